@@ -1,13 +1,16 @@
-pub type Ident = String;
+use token::intern::Sym;
+
+pub type Ident = Sym;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
     StructLiteral(Ident, Vec<(Ident, Expr)>),
+    EnumLiteral(Ident, Ident, Vec<Expr>),
     I32Literal(i32),
     I64Literal(i64),
     F32Literal(f32),
     F64Literal(f64),
-    StringLiteral(String),
+    StringLiteral(Sym),
     ArrayLiteral(Type, Box<Expr>),
     BoolLiteral(bool),
     CharLiteral(char),
@@ -28,6 +31,9 @@ pub enum Expr {
     BitAnd(Box<Expr>, Box<Expr>),
     BitOr(Box<Expr>, Box<Expr>),
     BitXor(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    Shr(Box<Expr>, Box<Expr>),
+    UShr(Box<Expr>, Box<Expr>),
     Pow(Box<Expr>, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
     Ne(Box<Expr>, Box<Expr>),
@@ -40,9 +46,36 @@ pub enum Expr {
     If(Box<(Expr, Expr)>, Vec<(Expr, Expr)>, Box<Option<Expr>>),
     While(Box<Expr>, Box<Expr>),
     Return(Box<Option<Expr>>),
+    Break(Option<Ident>),
+    Continue(Option<Ident>),
+    Labeled(Ident, Box<Expr>),
+    Match(Box<Expr>, Vec<(Ident, Vec<Ident>, Expr)>, Box<Option<Expr>>),
+    Cast(Box<Expr>, Type),
+    Bitcast(Box<Expr>, Type),
     Set(Box<Expr>, Box<Expr>),
+    CompoundSet(AssignOp, Box<Expr>, Box<Expr>),
     For(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>),
     Lambda(Vec<Ident>, Vec<(Ident, Type)>, Type, Box<Expr>),
+    // 以下はクロージャ変換の後にだけ現れる
+    // 関数表の関数と環境(キャプチャが無ければNone)の組
+    Closure(Ident, Box<Option<Expr>>),
+    CallIndirect(Box<Expr>, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AssignOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    UShr,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -60,18 +93,24 @@ pub enum Type {
 pub enum RefType {
     String,
     Array(Box<Type>),
+    // struct, enum, union の名前
     Struct(Ident),
     Func(Vec<Type>, Box<Option<Type>>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct FuncDef(Ident, Vec<(Ident, Type)>, Option<Type>);
+pub struct FuncDef(pub Ident, pub Vec<(Ident, Type)>, pub Option<Type>);
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Member {
     Struct(Ident, Vec<(Ident, Type)>),
-    Func(FuncDef, Expr),
+    Enum(Ident, Vec<(Ident, Vec<Type>)>),
+    Union(Ident, Vec<(Ident, Type)>),
+    // エクスポートされる場合はその名前を持つ
+    Func(FuncDef, Expr, Option<String>),
     ExternFun(FuncDef, String, String),
+    Let(Ident, Option<Type>, Expr),
+    Const(Ident, Option<Type>, Expr),
 }
 
 pub type Module = Vec<Member>;
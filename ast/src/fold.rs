@@ -0,0 +1,281 @@
+use crate::ast::{Expr, FuncDef, Member, Module, RefType, Type};
+
+// 各fold_*のデフォルト実装はwalk_*で子を置き換えて再構築する
+pub trait Fold {
+    fn fold_module(&mut self, m: Module) -> Module {
+        walk_module(self, m)
+    }
+
+    fn fold_member(&mut self, m: Member) -> Member {
+        walk_member(self, m)
+    }
+
+    fn fold_func_def(&mut self, d: FuncDef) -> FuncDef {
+        walk_func_def(self, d)
+    }
+
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        walk_expr(self, e)
+    }
+
+    fn fold_type(&mut self, t: Type) -> Type {
+        walk_type(self, t)
+    }
+
+    fn fold_ref_type(&mut self, t: RefType) -> RefType {
+        walk_ref_type(self, t)
+    }
+}
+
+// Boxの領域はそのまま使い回す
+fn fold_box<F: Fold + ?Sized>(f: &mut F, mut e: Box<Expr>) -> Box<Expr> {
+    *e = f.fold_expr(*e);
+    e
+}
+
+fn fold_opt<F: Fold + ?Sized>(f: &mut F, mut e: Box<Option<Expr>>) -> Box<Option<Expr>> {
+    *e = e.map(|e| f.fold_expr(e));
+    e
+}
+
+fn fold_exprs<F: Fold + ?Sized>(f: &mut F, es: Vec<Expr>) -> Vec<Expr> {
+    es.into_iter().map(|e| f.fold_expr(e)).collect()
+}
+
+fn fold_types<F: Fold + ?Sized>(f: &mut F, ts: Vec<Type>) -> Vec<Type> {
+    ts.into_iter().map(|t| f.fold_type(t)).collect()
+}
+
+fn fold_fields<F: Fold + ?Sized, K>(f: &mut F, fields: Vec<(K, Type)>) -> Vec<(K, Type)> {
+    fields
+        .into_iter()
+        .map(|(x, t)| (x, f.fold_type(t)))
+        .collect()
+}
+
+pub fn walk_module<F: Fold + ?Sized>(f: &mut F, m: Module) -> Module {
+    m.into_iter().map(|member| f.fold_member(member)).collect()
+}
+
+pub fn walk_member<F: Fold + ?Sized>(f: &mut F, m: Member) -> Member {
+    match m {
+        Member::Struct(name, fields) => Member::Struct(name, fold_fields(f, fields)),
+        Member::Enum(name, variants) => Member::Enum(
+            name,
+            variants
+                .into_iter()
+                .map(|(x, ts)| (x, fold_types(f, ts)))
+                .collect(),
+        ),
+        Member::Union(name, fields) => Member::Union(name, fold_fields(f, fields)),
+        Member::Func(def, body, export) => {
+            let def = f.fold_func_def(def);
+            Member::Func(def, f.fold_expr(body), export)
+        }
+        Member::ExternFun(def, module, field) => {
+            Member::ExternFun(f.fold_func_def(def), module, field)
+        }
+        Member::Let(name, t, e) => {
+            let t = t.map(|t| f.fold_type(t));
+            Member::Let(name, t, f.fold_expr(e))
+        }
+        Member::Const(name, t, e) => {
+            let t = t.map(|t| f.fold_type(t));
+            Member::Const(name, t, f.fold_expr(e))
+        }
+    }
+}
+
+pub fn walk_func_def<F: Fold + ?Sized>(f: &mut F, d: FuncDef) -> FuncDef {
+    let FuncDef(name, params, ret) = d;
+    let params = fold_fields(f, params);
+    FuncDef(name, params, ret.map(|t| f.fold_type(t)))
+}
+
+pub fn walk_expr<F: Fold + ?Sized>(f: &mut F, e: Expr) -> Expr {
+    match e {
+        Expr::StructLiteral(name, fields) => Expr::StructLiteral(
+            name,
+            fields
+                .into_iter()
+                .map(|(x, e)| (x, f.fold_expr(e)))
+                .collect(),
+        ),
+        Expr::EnumLiteral(name, variant, args) => {
+            Expr::EnumLiteral(name, variant, fold_exprs(f, args))
+        }
+        e @ Expr::I32Literal(_)
+        | e @ Expr::I64Literal(_)
+        | e @ Expr::F32Literal(_)
+        | e @ Expr::F64Literal(_)
+        | e @ Expr::StringLiteral(_)
+        | e @ Expr::BoolLiteral(_)
+        | e @ Expr::CharLiteral(_)
+        | e @ Expr::Var(_)
+        | e @ Expr::Break(_)
+        | e @ Expr::Continue(_) => e,
+        Expr::ArrayLiteral(t, len) => {
+            let t = f.fold_type(t);
+            Expr::ArrayLiteral(t, fold_box(f, len))
+        }
+        Expr::Not(x) => Expr::Not(fold_box(f, x)),
+        Expr::Plus(x) => Expr::Plus(fold_box(f, x)),
+        Expr::Minus(x) => Expr::Minus(fold_box(f, x)),
+        Expr::Member(x, field) => Expr::Member(fold_box(f, x), field),
+        Expr::Index(a, b) => Expr::Index(fold_box(f, a), fold_box(f, b)),
+        Expr::Call(x, args) => Expr::Call(fold_box(f, x), fold_exprs(f, args)),
+        Expr::CallIndirect(x, args) => Expr::CallIndirect(fold_box(f, x), fold_exprs(f, args)),
+        Expr::Add(a, b) => Expr::Add(fold_box(f, a), fold_box(f, b)),
+        Expr::Sub(a, b) => Expr::Sub(fold_box(f, a), fold_box(f, b)),
+        Expr::Mul(a, b) => Expr::Mul(fold_box(f, a), fold_box(f, b)),
+        Expr::Div(a, b) => Expr::Div(fold_box(f, a), fold_box(f, b)),
+        Expr::Mod(a, b) => Expr::Mod(fold_box(f, a), fold_box(f, b)),
+        Expr::And(a, b) => Expr::And(fold_box(f, a), fold_box(f, b)),
+        Expr::Or(a, b) => Expr::Or(fold_box(f, a), fold_box(f, b)),
+        Expr::BitAnd(a, b) => Expr::BitAnd(fold_box(f, a), fold_box(f, b)),
+        Expr::BitOr(a, b) => Expr::BitOr(fold_box(f, a), fold_box(f, b)),
+        Expr::BitXor(a, b) => Expr::BitXor(fold_box(f, a), fold_box(f, b)),
+        Expr::Shl(a, b) => Expr::Shl(fold_box(f, a), fold_box(f, b)),
+        Expr::Shr(a, b) => Expr::Shr(fold_box(f, a), fold_box(f, b)),
+        Expr::UShr(a, b) => Expr::UShr(fold_box(f, a), fold_box(f, b)),
+        Expr::Pow(a, b) => Expr::Pow(fold_box(f, a), fold_box(f, b)),
+        Expr::Eq(a, b) => Expr::Eq(fold_box(f, a), fold_box(f, b)),
+        Expr::Ne(a, b) => Expr::Ne(fold_box(f, a), fold_box(f, b)),
+        Expr::Lt(a, b) => Expr::Lt(fold_box(f, a), fold_box(f, b)),
+        Expr::Lte(a, b) => Expr::Lte(fold_box(f, a), fold_box(f, b)),
+        Expr::Gt(a, b) => Expr::Gt(fold_box(f, a), fold_box(f, b)),
+        Expr::Gte(a, b) => Expr::Gte(fold_box(f, a), fold_box(f, b)),
+        Expr::Block(stmts, last) => {
+            let stmts = fold_exprs(f, stmts);
+            Expr::Block(stmts, fold_opt(f, last))
+        }
+        Expr::Let(x, e) => Expr::Let(x, fold_box(f, e)),
+        Expr::If(first, elifs, els) => {
+            let (cond, then) = *first;
+            let first = (f.fold_expr(cond), f.fold_expr(then));
+            let elifs = elifs
+                .into_iter()
+                .map(|(cond, then)| (f.fold_expr(cond), f.fold_expr(then)))
+                .collect();
+            Expr::If(Box::new(first), elifs, fold_opt(f, els))
+        }
+        Expr::While(cond, body) => Expr::While(fold_box(f, cond), fold_box(f, body)),
+        Expr::Return(e) => Expr::Return(fold_opt(f, e)),
+        Expr::Closure(x, e) => Expr::Closure(x, fold_opt(f, e)),
+        Expr::Labeled(label, e) => Expr::Labeled(label, fold_box(f, e)),
+        Expr::Match(e, arms, default) => {
+            let e = fold_box(f, e);
+            let arms = arms
+                .into_iter()
+                .map(|(variant, binds, e)| (variant, binds, f.fold_expr(e)))
+                .collect();
+            Expr::Match(e, arms, fold_opt(f, default))
+        }
+        Expr::Cast(e, t) => {
+            let e = fold_box(f, e);
+            Expr::Cast(e, f.fold_type(t))
+        }
+        Expr::Bitcast(e, t) => {
+            let e = fold_box(f, e);
+            Expr::Bitcast(e, f.fold_type(t))
+        }
+        Expr::Set(a, b) => Expr::Set(fold_box(f, a), fold_box(f, b)),
+        Expr::CompoundSet(op, a, b) => Expr::CompoundSet(op, fold_box(f, a), fold_box(f, b)),
+        Expr::For(init, cond, step, body) => {
+            let init = fold_box(f, init);
+            let cond = fold_box(f, cond);
+            let step = fold_box(f, step);
+            Expr::For(init, cond, step, fold_box(f, body))
+        }
+        Expr::Lambda(caps, params, ret, body) => {
+            let params = fold_fields(f, params);
+            let ret = f.fold_type(ret);
+            Expr::Lambda(caps, params, ret, fold_box(f, body))
+        }
+    }
+}
+
+pub fn walk_type<F: Fold + ?Sized>(f: &mut F, t: Type) -> Type {
+    match t {
+        t @ Type::I32
+        | t @ Type::I64
+        | t @ Type::F32
+        | t @ Type::F64
+        | t @ Type::Bool
+        | t @ Type::Char => t,
+        Type::RefType(t) => Type::RefType(f.fold_ref_type(t)),
+    }
+}
+
+pub fn walk_ref_type<F: Fold + ?Sized>(f: &mut F, t: RefType) -> RefType {
+    match t {
+        t @ RefType::String | t @ RefType::Struct(_) => t,
+        RefType::Array(t) => RefType::Array(Box::new(f.fold_type(*t))),
+        RefType::Func(params, ret) => {
+            let params = fold_types(f, params);
+            RefType::Func(params, Box::new(ret.map(|t| f.fold_type(t))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use token::intern::Sym;
+
+    // 変数名と型名の両方を置き換える
+    struct Rename(Sym, Sym);
+
+    impl Fold for Rename {
+        fn fold_expr(&mut self, e: Expr) -> Expr {
+            match e {
+                Expr::Var(x) if x == self.0 => Expr::Var(self.1),
+                e => walk_expr(self, e),
+            }
+        }
+
+        fn fold_ref_type(&mut self, t: RefType) -> RefType {
+            match t {
+                RefType::Struct(x) if x == self.0 => RefType::Struct(self.1),
+                t => walk_ref_type(self, t),
+            }
+        }
+    }
+
+    #[test]
+    fn fold_test() {
+        let src = |x: &str| {
+            format!(
+                r#"
+                fun f(p: [{0}]): fun({0}) {{
+                    for (let i = 0; i < {0}; i += 1) {{ g({0}[i], \[{0}](y: {0}): i32 -> {0}); }}
+                    match ({0}) {{ A(z) => {0}, _ => 0 }}
+                }}
+            "#,
+                x
+            )
+        };
+        let m = parse(&src("a")).unwrap();
+        let renamed = Rename(Sym::intern("a"), Sym::intern("b")).fold_module(m);
+        let mut expected = parse(&src("b")).unwrap();
+        // ラムダのキャプチャリストは式ではないので置き換わらない
+        match &mut expected[0] {
+            Member::Func(_, Expr::Block(stmts, _), _) => match &mut stmts[0] {
+                Expr::For(_, _, _, body) => match &mut **body {
+                    Expr::Block(stmts, _) => match &mut stmts[0] {
+                        Expr::Call(_, args) => match &mut args[1] {
+                            Expr::Lambda(caps, _, _, _) => caps[0] = Sym::intern("a"),
+                            _ => panic!(),
+                        },
+                        _ => panic!(),
+                    },
+                    _ => panic!(),
+                },
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+        assert_eq!(renamed, expected);
+    }
+}
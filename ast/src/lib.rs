@@ -1,2 +1,7 @@
 pub mod ast;
-pub mod parser;
\ No newline at end of file
+pub mod fold;
+pub mod parser;
+pub mod printer;
+pub mod span;
+pub mod visit;
+pub mod visit_mut;
@@ -1,19 +1,1136 @@
+use crate::ast::{AssignOp, Expr, FuncDef, Ident, Member, Module, RefType, Type};
+use crate::span::{Span, Spans};
+use parser::parser::{
+    eof, expect, parser_func, token, ErrorExpect, Parser, ParserError, ParserResult,
+};
+use parser::stream::Stream;
+use std::error;
+use std::fmt;
+use token::intern::Sym;
+use token::lexer::{lex, LexError};
+use token::token::{Keyword, Kind, Literal, NumLiteral, Symbol};
 
-use crate::ast::Expr;
-use parser::parser::{token, val, Parser};
-use token::token::{Kind, Symbol};
+type BinFn = fn(Box<Expr>, Box<Expr>) -> Expr;
+
+// 式の範囲をトークンの添字で持ち、部分式の範囲を構文木と同じ順に並べる
+struct Tree {
+    range: (usize, usize),
+    children: Vec<Tree>,
+}
+
+impl Tree {
+    // 後行順に並べる
+    fn flatten(self, out: &mut Vec<(usize, usize)>) {
+        for t in self.children {
+            t.flatten(out);
+        }
+        out.push(self.range);
+    }
+}
+
+type Node = (Expr, Tree);
+
+// 定義とその範囲、定義の中の式の範囲
+type MemberNode = (Member, (usize, usize), Option<Tree>);
+
+fn node(st: &Stream<Kind>, start: usize, e: Expr, children: Vec<Tree>) -> Node {
+    let range = (start, st.pos());
+    (e, Tree { range, children })
+}
+
+fn unzip(nodes: Vec<Node>) -> (Vec<Expr>, Vec<Tree>) {
+    nodes.into_iter().unzip()
+}
+
+fn sym(s: Symbol) -> impl Parser<Input = Kind, Output = Kind> {
+    token(Kind::Symbol(s))
+}
+
+fn kw(k: Keyword) -> impl Parser<Input = Kind, Output = Kind> {
+    token(Kind::Keyword(k))
+}
+
+fn peek_is(st: &Stream<Kind>, kind: Kind) -> bool {
+    st.peak() == Some(kind)
+}
+
+fn error(st: &Stream<Kind>) -> ParserError<Kind> {
+    ParserError::new(st.pos(), st.peak(), ErrorExpect::Unknown)
+}
+
+pub fn ident() -> impl Parser<Input = Kind, Output = Ident> {
+    expect(|k| matches!(k, Kind::Ident(_))).map(|k| match k {
+        Kind::Ident(x) => x,
+        _ => unreachable!(),
+    })
+}
+
+pub fn string() -> impl Parser<Input = Kind, Output = Sym> {
+    expect(|k| matches!(k, Kind::Literal(Literal::String(_)))).map(|k| match k {
+        Kind::Literal(Literal::String(x)) => x,
+        _ => unreachable!(),
+    })
+}
+
+fn sep_by<P: Parser<Input = Kind>>(
+    p: P,
+    close: Symbol,
+) -> impl Parser<Input = Kind, Output = Vec<P::Output>> {
+    parser_func(move |st| {
+        let mut res = Vec::new();
+        while !peek_is(st, Kind::Symbol(close.clone())) {
+            res.push(p.parse(st)?);
+            if !peek_is(st, Kind::Symbol(close.clone())) {
+                sym(Symbol::Comma).parse(st)?;
+            }
+        }
+        sym(close.clone()).parse(st)?;
+        Ok(res)
+    })
+}
+
+pub fn typ() -> impl Parser<Input = Kind, Output = Type> {
+    parser_func(|st| {
+        let t = match st.peak() {
+            Some(Kind::Keyword(Keyword::I32)) => Type::I32,
+            Some(Kind::Keyword(Keyword::I64)) => Type::I64,
+            Some(Kind::Keyword(Keyword::F32)) => Type::F32,
+            Some(Kind::Keyword(Keyword::F64)) => Type::F64,
+            Some(Kind::Keyword(Keyword::Bool)) => Type::Bool,
+            Some(Kind::Keyword(Keyword::Char)) => Type::Char,
+            Some(Kind::Keyword(Keyword::String)) => Type::RefType(RefType::String),
+            Some(Kind::Ident(x)) => Type::RefType(RefType::Struct(x)),
+            Some(Kind::Symbol(Symbol::OpenBracket)) => {
+                st.next();
+                let t = typ().skip(sym(Symbol::CloseBracket)).parse(st)?;
+                return Ok(Type::RefType(RefType::Array(Box::new(t))));
+            }
+            Some(Kind::Keyword(Keyword::Fun)) => {
+                st.next();
+                let params = sym(Symbol::OpenParent)
+                    .with(sep_by(typ(), Symbol::CloseParent))
+                    .parse(st)?;
+                let ret = result_type().parse(st)?;
+                return Ok(Type::RefType(RefType::Func(params, Box::new(ret))));
+            }
+            _ => return Err(error(st)),
+        };
+        st.next();
+        Ok(t)
+    })
+}
+
+fn result_type() -> impl Parser<Input = Kind, Output = Option<Type>> {
+    sym(Symbol::Colon).with(typ()).optional()
+}
+
+fn param() -> impl Parser<Input = Kind, Output = (Ident, Type)> {
+    ident().skip(sym(Symbol::Colon)).and(typ())
+}
+
+fn params() -> impl Parser<Input = Kind, Output = Vec<(Ident, Type)>> {
+    sym(Symbol::OpenParent).with(sep_by(param(), Symbol::CloseParent))
+}
+
+pub fn func_def() -> impl Parser<Input = Kind, Output = FuncDef> {
+    kw(Keyword::Fun)
+        .with(ident())
+        .and(params())
+        .and(result_type())
+        .map(|((name, params), ret)| FuncDef(name, params, ret))
+}
+
+pub fn member() -> impl Parser<Input = Kind, Output = Member> {
+    member_node().map(|(m, _, _)| m)
+}
+
+fn member_node() -> impl Parser<Input = Kind, Output = MemberNode> {
+    parser_func(|st| {
+        let start = st.pos();
+        let (m, tree) = member_body().parse(st)?;
+        Ok((m, (start, st.pos()), tree))
+    })
+}
+
+fn member_body() -> impl Parser<Input = Kind, Output = (Member, Option<Tree>)> {
+    parser_func(|st| match st.peak() {
+        Some(Kind::Keyword(Keyword::Struct)) => {
+            st.next();
+            let name = ident().parse(st)?;
+            let fields = sym(Symbol::OpenBrace)
+                .with(sep_by(param(), Symbol::CloseBrace))
+                .parse(st)?;
+            Ok((Member::Struct(name, fields), None))
+        }
+        Some(Kind::Keyword(Keyword::Enum)) => {
+            st.next();
+            let name = ident().parse(st)?;
+            let variant = ident().and(parser_func(|st| {
+                if peek_is(st, Kind::Symbol(Symbol::OpenParent)) {
+                    st.next();
+                    sep_by(typ(), Symbol::CloseParent).parse(st)
+                } else {
+                    Ok(Vec::new())
+                }
+            }));
+            let variants = sym(Symbol::OpenBrace)
+                .with(sep_by(variant, Symbol::CloseBrace))
+                .parse(st)?;
+            Ok((Member::Enum(name, variants), None))
+        }
+        Some(Kind::Keyword(Keyword::Union)) => {
+            st.next();
+            let name = ident().parse(st)?;
+            let fields = sym(Symbol::OpenBrace)
+                .with(sep_by(param(), Symbol::CloseBrace))
+                .parse(st)?;
+            Ok((Member::Union(name, fields), None))
+        }
+        Some(Kind::Keyword(Keyword::Fun)) => {
+            let def = func_def().parse(st)?;
+            let (body, t) = block_node().parse(st)?;
+            Ok((Member::Func(def, body, None), Some(t)))
+        }
+        Some(Kind::Keyword(Keyword::Export)) => {
+            st.next();
+            let name = string().optional().parse(st)?;
+            let def = func_def().parse(st)?;
+            let (body, t) = block_node().parse(st)?;
+            let name = name.unwrap_or(def.0).as_str().to_string();
+            Ok((Member::Func(def, body, Some(name)), Some(t)))
+        }
+        Some(Kind::Keyword(Keyword::Extern)) => {
+            st.next();
+            let module = string().parse(st)?;
+            let field = string().parse(st)?;
+            let def = func_def().skip(sym(Symbol::Semicolon)).parse(st)?;
+            let m = Member::ExternFun(def, module.as_str().to_string(), field.as_str().to_string());
+            Ok((m, None))
+        }
+        Some(Kind::Keyword(k @ Keyword::Let)) | Some(Kind::Keyword(k @ Keyword::Const)) => {
+            st.next();
+            let name = ident().parse(st)?;
+            let t = result_type().parse(st)?;
+            let (e, tree) = sym(Symbol::Assign)
+                .with(expr_node())
+                .skip(sym(Symbol::Semicolon))
+                .parse(st)?;
+            let m = if k == Keyword::Let {
+                Member::Let(name, t, e)
+            } else {
+                Member::Const(name, t, e)
+            };
+            Ok((m, Some(tree)))
+        }
+        _ => Err(error(st)),
+    })
+}
+
+pub fn module() -> impl Parser<Input = Kind, Output = Module> {
+    member().many().skip(eof())
+}
+
+fn module_node() -> impl Parser<Input = Kind, Output = Vec<MemberNode>> {
+    member_node().many().skip(eof())
+}
 
 pub fn expr() -> impl Parser<Input = Kind, Output = Expr> {
-        unimplemented!();
-        val(Expr::I32Literal(1))
+    expr_node().map(|(e, _)| e)
+}
+
+// ラベル付きのループは式の先頭でだけ読む
+fn expr_node() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| match (st.peak(), st.peak_index(1), st.peak_index(2)) {
+        (
+            Some(Kind::Ident(x)),
+            Some(Kind::Symbol(Symbol::Colon)),
+            Some(Kind::Keyword(Keyword::While)) | Some(Kind::Keyword(Keyword::For)),
+        ) => {
+            let start = st.pos();
+            st.add_pos(2);
+            let (e, t) = expr_node().parse(st)?;
+            Ok(node(st, start, Expr::Labeled(x, Box::new(e)), vec![t]))
+        }
+        _ => assign().parse(st),
+    })
+}
+
+fn assign() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let lhs = ternary().parse(st)?;
+        let op = match st.peak() {
+            Some(Kind::Symbol(Symbol::Assign)) => None,
+            Some(Kind::Symbol(s)) => match assign_op(&s) {
+                Some(op) => Some(op),
+                None => return Ok(lhs),
+            },
+            _ => return Ok(lhs),
+        };
+        st.next();
+        let (lhs, lt) = lhs;
+        let (rhs, rt) = assign().parse(st)?;
+        let e = match op {
+            None => Expr::Set(Box::new(lhs), Box::new(rhs)),
+            Some(op) => Expr::CompoundSet(op, Box::new(lhs), Box::new(rhs)),
+        };
+        Ok(node(st, start, e, vec![lt, rt]))
+    })
+}
+
+fn assign_op(s: &Symbol) -> Option<AssignOp> {
+    Some(match s {
+        Symbol::AddAssign => AssignOp::Add,
+        Symbol::SubAssign => AssignOp::Sub,
+        Symbol::MulAssign => AssignOp::Mul,
+        Symbol::DivAssign => AssignOp::Div,
+        Symbol::ModAssign => AssignOp::Mod,
+        Symbol::PowAssign => AssignOp::Pow,
+        Symbol::BitAndAssign => AssignOp::BitAnd,
+        Symbol::BitOrAssign => AssignOp::BitOr,
+        Symbol::BitXorAssign => AssignOp::BitXor,
+        Symbol::ShlAssign => AssignOp::Shl,
+        Symbol::ShrAssign => AssignOp::Shr,
+        Symbol::UShrAssign => AssignOp::UShr,
+        _ => return None,
+    })
+}
+
+fn ternary() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let cond = or_expr().parse(st)?;
+        if !peek_is(st, Kind::Symbol(Symbol::Question)) {
+            return Ok(cond);
+        }
+        st.next();
+        let (cond, ct) = cond;
+        // 「c ? x : while ...」のxはラベルではない
+        let (then, tt) = assign().skip(sym(Symbol::Colon)).parse(st)?;
+        let (els, et) = ternary().parse(st)?;
+        let e = Expr::If(Box::new((cond, then)), Vec::new(), Box::new(Some(els)));
+        Ok(node(st, start, e, vec![ct, tt, et]))
+    })
+}
+
+fn binary<P: Parser<Input = Kind, Output = Node>>(
+    operand: P,
+    op: fn(&Symbol) -> Option<BinFn>,
+) -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(move |st| {
+        let start = st.pos();
+        let mut lhs = operand.parse(st)?;
+        while let Some(f) = match st.peak() {
+            Some(Kind::Symbol(s)) => op(&s),
+            _ => None,
+        } {
+            st.next();
+            let (l, lt) = lhs;
+            let (rhs, rt) = operand.parse(st)?;
+            lhs = node(st, start, f(Box::new(l), Box::new(rhs)), vec![lt, rt]);
+        }
+        Ok(lhs)
+    })
+}
+
+fn or_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(and_expr(), |s| match s {
+        Symbol::Or => Some(Expr::Or),
+        _ => None,
+    })
+}
+
+fn and_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(cmp_expr(), |s| match s {
+        Symbol::And => Some(Expr::And),
+        _ => None,
+    })
+}
+
+fn cmp_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(bit_or_expr(), |s| match s {
+        Symbol::Eq => Some(Expr::Eq),
+        Symbol::Ne => Some(Expr::Ne),
+        Symbol::Lt => Some(Expr::Lt),
+        Symbol::Lte => Some(Expr::Lte),
+        Symbol::Gt => Some(Expr::Gt),
+        Symbol::Gte => Some(Expr::Gte),
+        _ => None,
+    })
+}
+
+fn bit_or_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(bit_xor_expr(), |s| match s {
+        Symbol::BitOr => Some(Expr::BitOr),
+        _ => None,
+    })
+}
+
+fn bit_xor_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(bit_and_expr(), |s| match s {
+        Symbol::BitXor => Some(Expr::BitXor),
+        _ => None,
+    })
+}
+
+fn bit_and_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(shift_expr(), |s| match s {
+        Symbol::BitAnd => Some(Expr::BitAnd),
+        _ => None,
+    })
+}
+
+fn shift_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(add_expr(), |s| match s {
+        Symbol::Shl => Some(Expr::Shl),
+        Symbol::Shr => Some(Expr::Shr),
+        Symbol::UShr => Some(Expr::UShr),
+        _ => None,
+    })
+}
+
+fn add_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(mul_expr(), |s| match s {
+        Symbol::Add => Some(Expr::Add),
+        Symbol::Sub => Some(Expr::Sub),
+        _ => None,
+    })
+}
+
+fn mul_expr() -> impl Parser<Input = Kind, Output = Node> {
+    binary(cast_expr(), |s| match s {
+        Symbol::Mul => Some(Expr::Mul),
+        Symbol::Div => Some(Expr::Div),
+        Symbol::Mod => Some(Expr::Mod),
+        _ => None,
+    })
+}
+
+fn cast_expr() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let mut e = unary().parse(st)?;
+        loop {
+            let f: fn(Box<Expr>, Type) -> Expr = match st.peak() {
+                Some(Kind::Keyword(Keyword::As)) => Expr::Cast,
+                Some(Kind::Keyword(Keyword::Bitcast)) => Expr::Bitcast,
+                _ => return Ok(e),
+            };
+            st.next();
+            let t = typ().parse(st)?;
+            let (x, xt) = e;
+            e = node(st, start, f(Box::new(x), t), vec![xt]);
+        }
+    })
+}
+
+fn unary() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let f: fn(Box<Expr>) -> Expr = match st.peak() {
+            Some(Kind::Symbol(Symbol::Not)) => Expr::Not,
+            Some(Kind::Symbol(Symbol::Add)) => Expr::Plus,
+            Some(Kind::Symbol(Symbol::Sub)) => Expr::Minus,
+            _ => return pow().parse(st),
+        };
+        st.next();
+        let (x, xt) = unary().parse(st)?;
+        Ok(node(st, start, f(Box::new(x)), vec![xt]))
+    })
+}
+
+fn pow() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let lhs = postfix().parse(st)?;
+        if !peek_is(st, Kind::Symbol(Symbol::Pow)) {
+            return Ok(lhs);
+        }
+        st.next();
+        let (lhs, lt) = lhs;
+        let (rhs, rt) = unary().parse(st)?;
+        let e = Expr::Pow(Box::new(lhs), Box::new(rhs));
+        Ok(node(st, start, e, vec![lt, rt]))
+    })
+}
+
+fn postfix() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let mut e = primary().parse(st)?;
+        loop {
+            let (next, children) = match st.peak() {
+                Some(Kind::Symbol(Symbol::OpenParent)) => {
+                    st.next();
+                    let (f, ft) = e;
+                    let (args, ts) = unzip(sep_by(expr_node(), Symbol::CloseParent).parse(st)?);
+                    let ts = std::iter::once(ft).chain(ts).collect();
+                    (Expr::Call(Box::new(f), args), ts)
+                }
+                Some(Kind::Symbol(Symbol::OpenBracket)) => {
+                    st.next();
+                    let (a, at) = e;
+                    let (i, it) = expr_node().skip(sym(Symbol::CloseBracket)).parse(st)?;
+                    (Expr::Index(Box::new(a), Box::new(i)), vec![at, it])
+                }
+                Some(Kind::Symbol(Symbol::Dot)) => {
+                    st.next();
+                    let (x, xt) = e;
+                    (Expr::Member(Box::new(x), ident().parse(st)?), vec![xt])
+                }
+                _ => return Ok(e),
+            };
+            e = node(st, start, next, children);
+        }
+    })
+}
+
+fn paren_expr() -> impl Parser<Input = Kind, Output = Node> {
+    sym(Symbol::OpenParent)
+        .with(expr_node())
+        .skip(sym(Symbol::CloseParent))
+}
+
+fn primary() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        let e = match st.peak() {
+            Some(Kind::Literal(Literal::Num(n))) => match n {
+                NumLiteral::I32(x) => Expr::I32Literal(x),
+                NumLiteral::I64(x) => Expr::I64Literal(x),
+                NumLiteral::F32(x) => Expr::F32Literal(x),
+                NumLiteral::F64(x) => Expr::F64Literal(x),
+            },
+            Some(Kind::Literal(Literal::String(x))) => Expr::StringLiteral(x),
+            Some(Kind::Literal(Literal::Char(x))) => Expr::CharLiteral(x),
+            Some(Kind::Keyword(Keyword::True)) => Expr::BoolLiteral(true),
+            Some(Kind::Keyword(Keyword::False)) => Expr::BoolLiteral(false),
+            Some(Kind::Ident(x)) => {
+                st.next();
+                if peek_is(st, Kind::Symbol(Symbol::DoubleColon)) {
+                    st.next();
+                    let variant = ident().parse(st)?;
+                    let (args, ts) = if peek_is(st, Kind::Symbol(Symbol::OpenParent)) {
+                        st.next();
+                        unzip(sep_by(expr_node(), Symbol::CloseParent).parse(st)?)
+                    } else {
+                        (Vec::new(), Vec::new())
+                    };
+                    return Ok(node(st, start, Expr::EnumLiteral(x, variant, args), ts));
+                }
+                if !peek_is(st, Kind::Symbol(Symbol::OpenBrace)) {
+                    return Ok(node(st, start, Expr::Var(x), Vec::new()));
+                }
+                st.next();
+                let (fields, ts): (Vec<_>, Vec<_>) = sep_by(
+                    ident().skip(sym(Symbol::Colon)).and(expr_node()),
+                    Symbol::CloseBrace,
+                )
+                .parse(st)?
+                .into_iter()
+                .map(|(x, (e, t))| ((x, e), t))
+                .unzip();
+                return Ok(node(st, start, Expr::StructLiteral(x, fields), ts));
+            }
+            Some(Kind::Symbol(Symbol::OpenParent)) => return paren_expr().parse(st),
+            Some(Kind::Symbol(Symbol::OpenBrace)) => return block_node().parse(st),
+            Some(Kind::Symbol(Symbol::OpenBracket)) => {
+                st.next();
+                let t = typ().skip(sym(Symbol::Semicolon)).parse(st)?;
+                let (len, lt) = expr_node().skip(sym(Symbol::CloseBracket)).parse(st)?;
+                let e = Expr::ArrayLiteral(t, Box::new(len));
+                return Ok(node(st, start, e, vec![lt]));
+            }
+            Some(Kind::Symbol(Symbol::Backslash)) => return lambda().parse(st),
+            Some(Kind::Keyword(Keyword::Let)) => {
+                st.next();
+                let name = ident().skip(sym(Symbol::Assign)).parse(st)?;
+                let (e, t) = expr_node().parse(st)?;
+                return Ok(node(st, start, Expr::Let(name, Box::new(e)), vec![t]));
+            }
+            Some(Kind::Keyword(Keyword::If)) => return if_expr().parse(st),
+            Some(Kind::Keyword(Keyword::Match)) => return match_expr().parse(st),
+            Some(Kind::Keyword(Keyword::While)) => {
+                st.next();
+                let (cond, ct) = paren_expr().parse(st)?;
+                let (body, bt) = expr_node().parse(st)?;
+                let e = Expr::While(Box::new(cond), Box::new(body));
+                return Ok(node(st, start, e, vec![ct, bt]));
+            }
+            Some(Kind::Keyword(Keyword::For)) => {
+                st.next();
+                sym(Symbol::OpenParent).parse(st)?;
+                let (init, it) = expr_node().skip(sym(Symbol::Semicolon)).parse(st)?;
+                let (cond, ct) = expr_node().skip(sym(Symbol::Semicolon)).parse(st)?;
+                let (step, pt) = expr_node().skip(sym(Symbol::CloseParent)).parse(st)?;
+                let (body, bt) = expr_node().parse(st)?;
+                let e = Expr::For(
+                    Box::new(init),
+                    Box::new(cond),
+                    Box::new(step),
+                    Box::new(body),
+                );
+                return Ok(node(st, start, e, vec![it, ct, pt, bt]));
+            }
+            Some(Kind::Keyword(Keyword::Break)) => {
+                st.next();
+                let e = Expr::Break(ident().optional().parse(st)?);
+                return Ok(node(st, start, e, Vec::new()));
+            }
+            Some(Kind::Keyword(Keyword::Continue)) => {
+                st.next();
+                let e = Expr::Continue(ident().optional().parse(st)?);
+                return Ok(node(st, start, e, Vec::new()));
+            }
+            Some(Kind::Keyword(Keyword::Return)) => {
+                st.next();
+                let (e, ts) = match expr_node().optional().parse(st)? {
+                    Some((e, t)) => (Some(e), vec![t]),
+                    None => (None, Vec::new()),
+                };
+                return Ok(node(st, start, Expr::Return(Box::new(e)), ts));
+            }
+            _ => return Err(error(st)),
+        };
+        st.next();
+        Ok(node(st, start, e, Vec::new()))
+    })
+}
+
+fn if_expr() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        kw(Keyword::If).parse(st)?;
+        let (cond, ct) = paren_expr().parse(st)?;
+        let (then, tt) = expr_node().parse(st)?;
+        let mut children = vec![ct, tt];
+        let mut elifs = Vec::new();
+        while peek_is(st, Kind::Keyword(Keyword::Elif)) {
+            st.next();
+            let (cond, ct) = paren_expr().parse(st)?;
+            let (then, tt) = expr_node().parse(st)?;
+            elifs.push((cond, then));
+            children.push(ct);
+            children.push(tt);
+        }
+        let els = match kw(Keyword::Else).with(expr_node()).optional().parse(st)? {
+            Some((e, t)) => {
+                children.push(t);
+                Some(e)
+            }
+            None => None,
+        };
+        let e = Expr::If(Box::new((cond, then)), elifs, Box::new(els));
+        Ok(node(st, start, e, children))
+    })
+}
+
+fn match_expr() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        kw(Keyword::Match).parse(st)?;
+        let (e, et) = paren_expr().parse(st)?;
+        sym(Symbol::OpenBrace).parse(st)?;
+        let mut children = vec![et];
+        let mut arms = Vec::new();
+        let mut default = None;
+        while !peek_is(st, Kind::Symbol(Symbol::CloseBrace)) {
+            let variant = ident().parse(st)?;
+            if variant.as_str() == "_" {
+                default = Some(sym(Symbol::FatArrow).with(expr_node()).parse(st)?);
+            } else {
+                let binds = if peek_is(st, Kind::Symbol(Symbol::OpenParent)) {
+                    st.next();
+                    sep_by(ident(), Symbol::CloseParent).parse(st)?
+                } else {
+                    Vec::new()
+                };
+                let (body, t) = sym(Symbol::FatArrow).with(expr_node()).parse(st)?;
+                arms.push((variant, binds, body));
+                children.push(t);
+            }
+            if !peek_is(st, Kind::Symbol(Symbol::CloseBrace)) {
+                sym(Symbol::Comma).parse(st)?;
+            }
+        }
+        st.next();
+        // 構文木ではデフォルトの腕が最後に来る
+        let default = match default {
+            Some((e, t)) => {
+                children.push(t);
+                Some(e)
+            }
+            None => None,
+        };
+        let e = Expr::Match(Box::new(e), arms, Box::new(default));
+        Ok(node(st, start, e, children))
+    })
+}
+
+fn lambda() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        sym(Symbol::Backslash).parse(st)?;
+        let captures = if peek_is(st, Kind::Symbol(Symbol::OpenBracket)) {
+            st.next();
+            sep_by(ident(), Symbol::CloseBracket).parse(st)?
+        } else {
+            Vec::new()
+        };
+        let params = params().parse(st)?;
+        let ret = sym(Symbol::Colon).with(typ()).parse(st)?;
+        let (body, t) = sym(Symbol::Arrow).with(expr_node()).parse(st)?;
+        let e = Expr::Lambda(captures, params, ret, Box::new(body));
+        Ok(node(st, start, e, vec![t]))
+    })
+}
+
+pub fn ends_with_block(e: &Expr) -> bool {
+    match e {
+        Expr::Block(..) | Expr::Match(..) => true,
+        Expr::While(_, body) | Expr::For(_, _, _, body) | Expr::Labeled(_, body) => {
+            ends_with_block(body)
+        }
+        Expr::If(first, elifs, els) => match &**els {
+            Some(e) => ends_with_block(e),
+            None => ends_with_block(&elifs.last().unwrap_or(first).1),
+        },
+        _ => false,
+    }
 }
 
 pub fn block() -> impl Parser<Input = Kind, Output = Expr> {
-        token(Kind::Symbol(Symbol::OpenBrace))
-                .with(expr()
-                        .skip(token(Kind::Symbol(Symbol::Semicolon)))
-                        .attempt()
-                        .many())
-                .and(expr().optional())
-                .map(|(a, b)| Expr::Block(a, Box::new(b)))
+    block_node().map(|(e, _)| e)
+}
+
+fn block_node() -> impl Parser<Input = Kind, Output = Node> {
+    parser_func(|st| {
+        let start = st.pos();
+        sym(Symbol::OpenBrace).parse(st)?;
+        let mut stmts = Vec::new();
+        let mut children = Vec::new();
+        loop {
+            if peek_is(st, Kind::Symbol(Symbol::CloseBrace)) {
+                st.next();
+                let e = Expr::Block(stmts, Box::new(None));
+                return Ok(node(st, start, e, children));
+            }
+            let (e, t) = expr_node().parse(st)?;
+            children.push(t);
+            if peek_is(st, Kind::Symbol(Symbol::Semicolon)) {
+                st.next();
+                stmts.push(e);
+            } else if peek_is(st, Kind::Symbol(Symbol::CloseBrace)) {
+                st.next();
+                let e = Expr::Block(stmts, Box::new(Some(e)));
+                return Ok(node(st, start, e, children));
+            } else if ends_with_block(&e) {
+                stmts.push(e);
+            } else {
+                return Err(error(st));
+            }
+        }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    Lex(LexError),
+    Parse(usize, Option<Kind>),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Lex(e) => write!(f, "{}", e),
+            ParseError::Parse(pos, Some(k)) => write!(f, "unexpected {:?} at {}", k, pos),
+            ParseError::Parse(pos, None) => write!(f, "unexpected end of input at {}", pos),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+pub fn parse_with<P: Parser<Input = Kind>>(src: &str, p: P) -> Result<P::Output, ParseError> {
+    let chars = src.chars().collect::<Vec<_>>();
+    let tokens = lex(&chars).map_err(ParseError::Lex)?;
+    let mut st = Stream::new(tokens.iter().map(|t| t.kind.clone()).collect());
+    let res: ParserResult<_, _> = p.parse(&mut st);
+    res.map_err(|e| {
+        let pos = tokens
+            .get(e.pos())
+            .map(|t| t.pos)
+            .unwrap_or_else(|| chars.len());
+        ParseError::Parse(pos, e.unexpected().cloned())
+    })
+}
+
+pub fn parse(src: &str) -> Result<Module, ParseError> {
+    parse_with(src, module())
+}
+
+pub fn parse_expr(src: &str) -> Result<Expr, ParseError> {
+    parse_with(src, expr().skip(eof()))
+}
+
+// 式と定義の位置情報も返す
+pub fn parse_with_spans(src: &str) -> Result<(Module, Spans), ParseError> {
+    let chars = src.chars().collect::<Vec<_>>();
+    let tokens = lex(&chars).map_err(ParseError::Lex)?;
+    let members = parse_with(src, module_node())?;
+    let span = |&(start, end): &(usize, usize)| {
+        let pos = tokens[start].pos;
+        let last = &tokens[end - 1];
+        Span {
+            pos,
+            len: last.pos + last.len - pos,
+        }
+    };
+    let mut module = Vec::new();
+    let mut exprs = Vec::new();
+    let mut spans = Spans::default();
+    for (m, range, tree) in members {
+        if let Some(t) = tree {
+            t.flatten(&mut exprs);
+        }
+        module.push(m);
+        spans.members.push(span(&range));
+    }
+    spans.exprs = exprs.iter().map(span).collect();
+    Ok((module, spans))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(x: &str) -> Box<Expr> {
+        Box::new(Expr::Var(Sym::intern(x)))
+    }
+
+    fn i32(x: i32) -> Box<Expr> {
+        Box::new(Expr::I32Literal(x))
+    }
+
+    #[test]
+    fn precedence_test() {
+        assert_eq!(
+            parse_expr("a = 1 + 2 * 3 << 1 == b").unwrap(),
+            Expr::Set(
+                var("a"),
+                Box::new(Expr::Eq(
+                    Box::new(Expr::Shl(
+                        Box::new(Expr::Add(i32(1), Box::new(Expr::Mul(i32(2), i32(3))))),
+                        i32(1)
+                    )),
+                    var("b")
+                ))
+            )
+        );
+        assert_eq!(
+            parse_expr("-a ** 2 ** b").unwrap(),
+            Expr::Minus(Box::new(Expr::Pow(
+                var("a"),
+                Box::new(Expr::Pow(i32(2), var("b")))
+            )))
+        );
+        assert_eq!(
+            parse_expr("x >>>= y >> 1").unwrap(),
+            Expr::CompoundSet(
+                AssignOp::UShr,
+                var("x"),
+                Box::new(Expr::Shr(var("y"), i32(1)))
+            )
+        );
+        assert_eq!(
+            parse_expr("-x as i64 * y bitcast F64").unwrap(),
+            Expr::Mul(
+                Box::new(Expr::Cast(Box::new(Expr::Minus(var("x"))), Type::I64)),
+                Box::new(Expr::Bitcast(var("y"), Type::F64))
+            )
+        );
+        assert_eq!(
+            parse_expr("c ? 1 : 2").unwrap(),
+            Expr::If(
+                Box::new((Expr::Var(Sym::intern("c")), Expr::I32Literal(1))),
+                vec![],
+                Box::new(Some(Expr::I32Literal(2)))
+            )
+        );
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let b = |e: Box<Expr>| Expr::Block(vec![], Box::new(Some(*e)));
+        assert_eq!(
+            parse_expr("if (a) { 1 } elif (b) { 2 } elif (c) { 3 } else { 4 }").unwrap(),
+            Expr::If(
+                Box::new((*var("a"), b(i32(1)))),
+                vec![(*var("b"), b(i32(2))), (*var("c"), b(i32(3)))],
+                Box::new(Some(b(i32(4))))
+            )
+        );
+        assert_eq!(
+            parse_expr("outer: while (true) { while (x) { break outer; } continue }").unwrap(),
+            Expr::Labeled(
+                Sym::intern("outer"),
+                Box::new(Expr::While(
+                    Box::new(Expr::BoolLiteral(true)),
+                    Box::new(Expr::Block(
+                        vec![Expr::While(
+                            var("x"),
+                            Box::new(Expr::Block(
+                                vec![Expr::Break(Some(Sym::intern("outer")))],
+                                Box::new(None)
+                            ))
+                        )],
+                        Box::new(Some(Expr::Continue(None)))
+                    ))
+                ))
+            )
+        );
+        // 三項演算子の真の側の名前はラベルではない
+        let w = Expr::While(
+            Box::new(Expr::BoolLiteral(false)),
+            Box::new(Expr::Block(vec![], Box::new(None))),
+        );
+        assert_eq!(
+            parse_expr("c ? x : while (false) {}").unwrap(),
+            Expr::If(Box::new((*var("c"), *var("x"))), vec![], Box::new(Some(w)))
+        );
+        let src =
+            "fun f(c: bool): i32 { let y = c ? x : while (false) {}; l: while (c) { break l; } }";
+        assert!(parse(src).is_ok());
+    }
+
+    #[test]
+    fn postfix_test() {
+        assert_eq!(
+            parse_expr("f(a, 1)[0].x").unwrap(),
+            Expr::Member(
+                Box::new(Expr::Index(
+                    Box::new(Expr::Call(var("f"), vec![*var("a"), *i32(1)])),
+                    i32(0)
+                )),
+                Sym::intern("x")
+            )
+        );
+    }
+
+    #[test]
+    fn module_test() {
+        let src = r#"
+            extern "env" "print" fun print(x: i32);
+
+            struct Point {
+                x: F64,
+                y: F64,
+            }
+
+            fun main(): i32 {
+                let p = Point { x: 1.0, y: 2.0 };
+                let f = \[p](k: F64): F64 -> p.x * k;
+                let xs = [i32; 10];
+                for (let i = 0; i < 10; i += 1) {
+                    xs[i] = i;
+                }
+                while (false) {}
+                print(xs[3]);
+                return 0;
+            }
+        "#;
+        let m = parse(src).unwrap();
+        assert_eq!(m.len(), 3);
+        assert_eq!(
+            m[0],
+            Member::ExternFun(
+                FuncDef(
+                    Sym::intern("print"),
+                    vec![(Sym::intern("x"), Type::I32)],
+                    None
+                ),
+                "env".to_string(),
+                "print".to_string()
+            )
+        );
+        match &m[2] {
+            Member::Func(FuncDef(_, _, ret), Expr::Block(stmts, last), None) => {
+                assert_eq!(ret, &Some(Type::I32));
+                assert_eq!(stmts.len(), 7);
+                assert_eq!(**last, None);
+            }
+            _ => panic!(),
+        }
+        assert!(parse("fun f() { 1 2 }").is_err());
+    }
+
+    #[test]
+    fn enum_union_test() {
+        let src = r#"
+            enum Shape { Circle(F64), Rect(F64, F64), Empty }
+            union Bits { i: i32, f: F32 }
+            fun area(s: Shape): F64 {
+                match (s) {
+                    Circle(r) => 3.14 * r * r,
+                    Rect(w, h) => w * h,
+                    _ => 0.0,
+                }
+            }
+            fun main() {
+                area(Shape::Rect(1.0, 2.0));
+                area(Shape::Empty);
+                Bits { f: 1.0f32 }.i;
+            }
+        "#;
+        let m = parse(src).unwrap();
+        let t = |x: &str| Type::RefType(RefType::Struct(Sym::intern(x)));
+        assert_eq!(
+            m[0],
+            Member::Enum(
+                Sym::intern("Shape"),
+                vec![
+                    (Sym::intern("Circle"), vec![Type::F64]),
+                    (Sym::intern("Rect"), vec![Type::F64, Type::F64]),
+                    (Sym::intern("Empty"), vec![]),
+                ]
+            )
+        );
+        assert_eq!(
+            m[1],
+            Member::Union(
+                Sym::intern("Bits"),
+                vec![(Sym::intern("i"), Type::I32), (Sym::intern("f"), Type::F32)]
+            )
+        );
+        match &m[2] {
+            Member::Func(FuncDef(_, params, _), Expr::Block(_, last), None) => {
+                assert_eq!(params[0].1, t("Shape"));
+                match &**last {
+                    Some(Expr::Match(_, arms, default)) => {
+                        assert_eq!(arms.len(), 2);
+                        assert_eq!(arms[1].1, vec![Sym::intern("w"), Sym::intern("h")]);
+                        assert_eq!(**default, Some(Expr::F64Literal(0.0)));
+                    }
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+        match &m[3] {
+            Member::Func(_, Expr::Block(stmts, _), None) => {
+                assert_eq!(
+                    stmts[1],
+                    Expr::Call(
+                        var("area"),
+                        vec![Expr::EnumLiteral(
+                            Sym::intern("Shape"),
+                            Sym::intern("Empty"),
+                            vec![]
+                        )]
+                    )
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn global_test() {
+        let m = parse("const N: i32 = 1 << 4; let counter = N * 2;").unwrap();
+        assert_eq!(
+            m,
+            vec![
+                Member::Const(Sym::intern("N"), Some(Type::I32), Expr::Shl(i32(1), i32(4))),
+                Member::Let(Sym::intern("counter"), None, Expr::Mul(var("N"), i32(2))),
+            ]
+        );
+        assert!(parse("let x = 1").is_err());
+    }
+
+    #[test]
+    fn export_test() {
+        let m = parse(r#"export fun f() {} export "g2" fun g() {} fun h() {}"#).unwrap();
+        let exports = m
+            .iter()
+            .map(|x| match x {
+                Member::Func(_, _, name) => name.clone(),
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            exports,
+            vec![Some("f".to_string()), Some("g2".to_string()), None]
+        );
+        assert!(parse(r#"export "x" struct S {}"#).is_err());
+    }
+
+    #[test]
+    fn spans_test() {
+        use crate::visit::{walk_expr, Visitor};
+
+        // 後行順に式を集める
+        struct Postorder(Vec<Expr>);
+
+        impl Visitor for Postorder {
+            fn visit_expr(&mut self, e: &Expr) {
+                walk_expr(self, e);
+                self.0.push(e.clone());
+            }
+        }
+
+        let src = r#"
+            const N: i32 = 1 + 2;
+            fun f(a: i32): i32 {
+                let x = -a as i64;
+                match (e) { _ => (x), A(y) => y.z[0] }
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let mut p = Postorder(Vec::new());
+        p.visit_module(&m);
+        let chars = src.chars().collect::<Vec<_>>();
+        let text = |s: Span| chars[s.pos..s.end()].iter().collect::<String>();
+        let texts = spans.exprs.iter().map(|s| text(*s)).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "1",
+                "2",
+                "1 + 2",
+                "a",
+                "-a",
+                "-a as i64",
+                "let x = -a as i64",
+                "e",
+                "y",
+                "y.z",
+                "0",
+                "y.z[0]",
+                "x",
+                "match (e) { _ => (x), A(y) => y.z[0] }",
+                "{\n                let x = -a as i64;\n                match (e) { _ => (x), A(y) => y.z[0] }\n            }",
+            ]
+        );
+        for (e, s) in p.0.iter().zip(&texts) {
+            assert_eq!(*e, parse_expr(s).unwrap());
+        }
+        assert_eq!(
+            spans.members.iter().map(|s| text(*s)).collect::<Vec<_>>()[0],
+            "const N: i32 = 1 + 2;"
+        );
+        assert_eq!(parse_with_spans(src).unwrap().0, parse(src).unwrap());
+
+        // 途中で失敗した解析は次の解析の位置情報に残らない
+        assert!(parse_with_spans("fun g() { match (a) { _ => 1 + }").is_err());
+        let src = "fun g(): i32 { match (a) { _ => match (b) { _ => 1, B => 2 }, A => 3 } }";
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let mut p = Postorder(Vec::new());
+        p.visit_module(&m);
+        let chars = src.chars().collect::<Vec<_>>();
+        let text = |s: Span| chars[s.pos..s.end()].iter().collect::<String>();
+        assert_eq!(spans.exprs.len(), p.0.len());
+        for (e, s) in p.0.iter().zip(&spans.exprs) {
+            assert_eq!(*e, parse_expr(&text(*s)).unwrap());
+        }
+    }
 }
@@ -0,0 +1,986 @@
+use crate::ast::{AssignOp, Expr, FuncDef, Ident, Member, Module, RefType, Type};
+use std::fmt;
+
+// 優先順位(大きいほど強く結合する)
+// OPENは末尾で式を貪欲に読むので、被演算子になる時は常に括弧で囲む
+pub const OPEN: u8 = 0;
+pub const ASSIGN: u8 = 1;
+pub const OR: u8 = 2;
+pub const AND: u8 = 3;
+pub const CMP: u8 = 4;
+pub const BIT_OR: u8 = 5;
+pub const BIT_XOR: u8 = 6;
+pub const BIT_AND: u8 = 7;
+pub const SHIFT: u8 = 8;
+pub const ADD: u8 = 9;
+pub const MUL: u8 = 10;
+pub const CAST: u8 = 11;
+pub const UNARY: u8 = 12;
+pub const POW: u8 = 13;
+pub const POSTFIX: u8 = 14;
+pub const ATOM: u8 = 15;
+
+pub fn binary(e: &Expr) -> Option<(&'static str, u8, &Expr, &Expr)> {
+    let (op, prec, a, b) = match e {
+        Expr::Or(a, b) => ("||", OR, a, b),
+        Expr::And(a, b) => ("&&", AND, a, b),
+        Expr::Eq(a, b) => ("==", CMP, a, b),
+        Expr::Ne(a, b) => ("!=", CMP, a, b),
+        Expr::Lt(a, b) => ("<", CMP, a, b),
+        Expr::Lte(a, b) => ("<=", CMP, a, b),
+        Expr::Gt(a, b) => (">", CMP, a, b),
+        Expr::Gte(a, b) => (">=", CMP, a, b),
+        Expr::BitOr(a, b) => ("|", BIT_OR, a, b),
+        Expr::BitXor(a, b) => ("^", BIT_XOR, a, b),
+        Expr::BitAnd(a, b) => ("&", BIT_AND, a, b),
+        Expr::Shl(a, b) => ("<<", SHIFT, a, b),
+        Expr::Shr(a, b) => (">>", SHIFT, a, b),
+        Expr::UShr(a, b) => (">>>", SHIFT, a, b),
+        Expr::Add(a, b) => ("+", ADD, a, b),
+        Expr::Sub(a, b) => ("-", ADD, a, b),
+        Expr::Mul(a, b) => ("*", MUL, a, b),
+        Expr::Div(a, b) => ("/", MUL, a, b),
+        Expr::Mod(a, b) => ("%", MUL, a, b),
+        _ => return None,
+    };
+    Some((op, prec, a, b))
+}
+
+pub fn assign_op(op: AssignOp) -> &'static str {
+    match op {
+        AssignOp::Add => "+=",
+        AssignOp::Sub => "-=",
+        AssignOp::Mul => "*=",
+        AssignOp::Div => "/=",
+        AssignOp::Mod => "%=",
+        AssignOp::Pow => "**=",
+        AssignOp::BitAnd => "&=",
+        AssignOp::BitOr => "|=",
+        AssignOp::BitXor => "^=",
+        AssignOp::Shl => "<<=",
+        AssignOp::Shr => ">>=",
+        AssignOp::UShr => ">>>=",
+    }
+}
+
+pub fn prec(e: &Expr) -> u8 {
+    if let Some((_, p, _, _)) = binary(e) {
+        return p;
+    }
+    match e {
+        Expr::Let(..)
+        | Expr::If(..)
+        | Expr::While(..)
+        | Expr::For(..)
+        | Expr::Labeled(..)
+        | Expr::Return(..)
+        | Expr::Lambda(..) => OPEN,
+        Expr::Set(..) | Expr::CompoundSet(..) => ASSIGN,
+        Expr::Cast(..) | Expr::Bitcast(..) => CAST,
+        Expr::Not(_) | Expr::Plus(_) | Expr::Minus(_) => UNARY,
+        // 負の数は単項マイナスとして読み戻される
+        Expr::I32Literal(x) if *x < 0 && *x != i32::MIN => UNARY,
+        Expr::I64Literal(x) if *x < 0 && *x != i64::MIN => UNARY,
+        Expr::F32Literal(x) if x.is_finite() && x.is_sign_negative() => UNARY,
+        Expr::F64Literal(x) if x.is_finite() && x.is_sign_negative() => UNARY,
+        Expr::Pow(..) => POW,
+        Expr::Call(..) | Expr::CallIndirect(..) | Expr::Index(..) | Expr::Member(..) => POSTFIX,
+        _ => ATOM,
+    }
+}
+
+// 後ろに`else`や`elif`が続くと、それを取り込んでしまう式
+pub fn dangling(e: &Expr) -> bool {
+    match e {
+        Expr::If(_, _, els) => match &**els {
+            Some(e) => dangling(e),
+            None => true,
+        },
+        Expr::While(_, e)
+        | Expr::For(_, _, _, e)
+        | Expr::Labeled(_, e)
+        | Expr::Let(_, e)
+        | Expr::Lambda(_, _, _, e)
+        | Expr::Set(_, e)
+        | Expr::CompoundSet(_, _, e) => dangling(e),
+        Expr::Return(e) => matches!(&**e, Some(e) if dangling(e)),
+        _ => false,
+    }
+}
+
+pub fn escape(s: &str, quote: char, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\U{:08x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+pub fn float(s: String, finite: bool, negative: bool, suffix: &str) -> String {
+    if !finite {
+        let zero = format!("0.0{}", suffix);
+        let num = if s.contains("NaN") {
+            zero.clone()
+        } else if negative {
+            format!("-1.0{}", suffix)
+        } else {
+            format!("1.0{}", suffix)
+        };
+        return format!("({} / {})", num, zero);
+    }
+    let dot = if s.contains('.') { "" } else { ".0" };
+    format!("{}{}{}", s, dot, suffix)
+}
+
+pub fn literal(e: &Expr) -> Option<String> {
+    Some(match e {
+        Expr::I32Literal(i32::MIN) => "(-2147483647 - 1)".to_string(),
+        Expr::I32Literal(x) => x.to_string(),
+        Expr::I64Literal(i64::MIN) => "(-9223372036854775807i64 - 1i64)".to_string(),
+        Expr::I64Literal(x) => format!("{}i64", x),
+        Expr::F32Literal(x) => float(x.to_string(), x.is_finite(), x.is_sign_negative(), "f32"),
+        Expr::F64Literal(x) => float(x.to_string(), x.is_finite(), x.is_sign_negative(), ""),
+        Expr::StringLiteral(x) => {
+            let mut s = "\"".to_string();
+            escape(x.as_str(), '"', &mut s);
+            s.push('"');
+            s
+        }
+        Expr::CharLiteral(x) => {
+            let mut s = "'".to_string();
+            escape(&x.to_string(), '\'', &mut s);
+            s.push('\'');
+            s
+        }
+        Expr::BoolLiteral(x) => (if *x { "true" } else { "false" }).to_string(),
+        _ => return None,
+    })
+}
+
+pub struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn ident(&mut self, x: Ident) {
+        self.push(x.as_str());
+    }
+
+    fn sep<T>(&mut self, xs: &[T], mut f: impl FnMut(&mut Printer, &T)) {
+        for (i, x) in xs.iter().enumerate() {
+            if i != 0 {
+                self.push(", ");
+            }
+            f(self, x);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.push("\"");
+        escape(s, '"', &mut self.out);
+        self.push("\"");
+    }
+
+    pub fn typ(&mut self, t: &Type) {
+        match t {
+            Type::I32 => self.push("i32"),
+            Type::I64 => self.push("i64"),
+            Type::F32 => self.push("F32"),
+            Type::F64 => self.push("F64"),
+            Type::Bool => self.push("bool"),
+            Type::Char => self.push("char"),
+            Type::RefType(RefType::String) => self.push("string"),
+            Type::RefType(RefType::Struct(x)) => self.ident(*x),
+            Type::RefType(RefType::Array(t)) => {
+                self.push("[");
+                self.typ(t);
+                self.push("]");
+            }
+            Type::RefType(RefType::Func(params, ret)) => {
+                self.push("fun(");
+                self.sep(params, |p, t| p.typ(t));
+                self.push(")");
+                self.result_type(ret.as_ref().as_ref());
+            }
+        }
+    }
+
+    fn result_type(&mut self, t: Option<&Type>) {
+        if let Some(t) = t {
+            self.push(": ");
+            self.typ(t);
+        }
+    }
+
+    fn params(&mut self, params: &[(Ident, Type)]) {
+        self.push("(");
+        self.sep(params, |p, (x, t)| {
+            p.ident(*x);
+            p.push(": ");
+            p.typ(t);
+        });
+        self.push(")");
+    }
+
+    fn func_def(&mut self, FuncDef(name, params, ret): &FuncDef) {
+        self.push("fun ");
+        self.ident(*name);
+        self.params(params);
+        self.result_type(ret.as_ref());
+    }
+
+    fn fields(&mut self, fields: &[(Ident, Type)]) {
+        self.braces(fields, |p, (x, t)| {
+            p.ident(*x);
+            p.push(": ");
+            p.typ(t);
+        });
+    }
+
+    // 要素ごとに改行し、末尾にもカンマを付ける
+    fn braces<T>(&mut self, xs: &[T], mut f: impl FnMut(&mut Printer, &T)) {
+        if xs.is_empty() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        for x in xs {
+            self.newline();
+            f(self, x);
+            self.push(",");
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    pub fn member(&mut self, m: &Member) {
+        match m {
+            Member::Struct(name, fields) => {
+                self.push("struct ");
+                self.ident(*name);
+                self.push(" ");
+                self.fields(fields);
+            }
+            Member::Enum(name, variants) => {
+                self.push("enum ");
+                self.ident(*name);
+                self.push(" ");
+                self.braces(variants, |p, (x, ts)| {
+                    p.ident(*x);
+                    if !ts.is_empty() {
+                        p.push("(");
+                        p.sep(ts, |p, t| p.typ(t));
+                        p.push(")");
+                    }
+                });
+            }
+            Member::Union(name, fields) => {
+                self.push("union ");
+                self.ident(*name);
+                self.push(" ");
+                self.fields(fields);
+            }
+            Member::Func(def, body, export) => {
+                if let Some(export) = export {
+                    self.push("export ");
+                    if export != def.0.as_str() {
+                        self.string(export);
+                        self.push(" ");
+                    }
+                }
+                self.func_def(def);
+                self.push(" ");
+                match body {
+                    Expr::Block(..) => self.expr(body, ATOM),
+                    _ => self.block(&[], Some(body)),
+                }
+            }
+            Member::ExternFun(def, module, field) => {
+                self.push("extern ");
+                self.string(module);
+                self.push(" ");
+                self.string(field);
+                self.push(" ");
+                self.func_def(def);
+                self.push(";");
+            }
+            Member::Let(name, t, e) | Member::Const(name, t, e) => {
+                self.push(match m {
+                    Member::Let(..) => "let ",
+                    _ => "const ",
+                });
+                self.ident(*name);
+                self.result_type(t.as_ref());
+                self.push(" = ");
+                self.expr(e, OPEN);
+                self.push(";");
+            }
+        }
+    }
+
+    pub fn module(&mut self, m: &Module) {
+        for (i, member) in m.iter().enumerate() {
+            if i != 0 {
+                self.newline();
+            }
+            self.member(member);
+            self.newline();
+        }
+    }
+
+    fn block(&mut self, stmts: &[Expr], last: Option<&Expr>) {
+        if stmts.is_empty() && last.is_none() {
+            self.push("{}");
+            return;
+        }
+        self.push("{");
+        self.indent += 1;
+        for e in stmts {
+            self.newline();
+            self.expr(e, OPEN);
+            self.push(";");
+        }
+        if let Some(e) = last {
+            self.newline();
+            self.expr(e, OPEN);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.push("}");
+    }
+
+    // minより弱く結合する式は括弧で囲む
+    pub fn expr(&mut self, e: &Expr, min: u8) {
+        if prec(e) < min {
+            self.push("(");
+            self.expr(e, OPEN);
+            self.push(")");
+            return;
+        }
+        if let Some(s) = literal(e) {
+            self.push(&s);
+            return;
+        }
+        if let Some((op, p, a, b)) = binary(e) {
+            self.expr(a, p);
+            self.push(" ");
+            self.push(op);
+            self.push(" ");
+            self.expr(b, p + 1);
+            return;
+        }
+        match e {
+            Expr::Var(x) => self.ident(*x),
+            Expr::StructLiteral(name, fields) => {
+                self.ident(*name);
+                self.push(" {");
+                if !fields.is_empty() {
+                    self.push(" ");
+                    self.sep(fields, |p, (x, e)| {
+                        p.ident(*x);
+                        p.push(": ");
+                        p.expr(e, OPEN);
+                    });
+                    self.push(" ");
+                }
+                self.push("}");
+            }
+            Expr::EnumLiteral(name, variant, args) => {
+                self.ident(*name);
+                self.push("::");
+                self.ident(*variant);
+                if !args.is_empty() {
+                    self.push("(");
+                    self.sep(args, |p, e| p.expr(e, OPEN));
+                    self.push(")");
+                }
+            }
+            Expr::ArrayLiteral(t, len) => {
+                self.push("[");
+                self.typ(t);
+                self.push("; ");
+                self.expr(len, OPEN);
+                self.push("]");
+            }
+            Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) => {
+                self.push(match e {
+                    Expr::Not(_) => "!",
+                    Expr::Plus(_) => "+",
+                    _ => "-",
+                });
+                self.expr(x, UNARY);
+            }
+            Expr::Pow(a, b) => {
+                self.expr(a, POSTFIX);
+                self.push(" ** ");
+                self.expr(b, UNARY);
+            }
+            Expr::Cast(x, t) | Expr::Bitcast(x, t) => {
+                self.expr(x, CAST);
+                self.push(match e {
+                    Expr::Cast(..) => " as ",
+                    _ => " bitcast ",
+                });
+                self.typ(t);
+            }
+            // クロージャ変換後の式は読み戻せないが、確認用に出力する
+            Expr::Closure(f, env) => {
+                self.push("closure(");
+                self.ident(*f);
+                if let Some(env) = &**env {
+                    self.push(", ");
+                    self.expr(env, OPEN);
+                }
+                self.push(")");
+            }
+            Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+                // `E::A()`は引数無しの列挙子リテラルとして読まれる
+                match &**f {
+                    Expr::EnumLiteral(_, _, args) if args.is_empty() => {
+                        self.push("(");
+                        self.expr(f, OPEN);
+                        self.push(")");
+                    }
+                    _ => self.expr(f, POSTFIX),
+                }
+                self.push("(");
+                self.sep(args, |p, e| p.expr(e, OPEN));
+                self.push(")");
+            }
+            Expr::Index(x, i) => {
+                self.expr(x, POSTFIX);
+                self.push("[");
+                self.expr(i, OPEN);
+                self.push("]");
+            }
+            Expr::Member(x, field) => {
+                // `1.x`は浮動小数点数リテラルとして字句解析されてしまう
+                match &**x {
+                    Expr::I32Literal(_)
+                    | Expr::I64Literal(_)
+                    | Expr::F32Literal(_)
+                    | Expr::F64Literal(_) => {
+                        self.push("(");
+                        self.expr(x, OPEN);
+                        self.push(")");
+                    }
+                    _ => self.expr(x, POSTFIX),
+                }
+                self.push(".");
+                self.ident(*field);
+            }
+            Expr::Set(a, b) => {
+                self.expr(a, OR);
+                self.push(" = ");
+                self.expr(b, ASSIGN);
+            }
+            Expr::CompoundSet(op, a, b) => {
+                self.expr(a, OR);
+                self.push(" ");
+                self.push(assign_op(*op));
+                self.push(" ");
+                self.expr(b, ASSIGN);
+            }
+            Expr::Block(stmts, last) => self.block(stmts, last.as_ref().as_ref()),
+            Expr::Let(x, e) => {
+                self.push("let ");
+                self.ident(*x);
+                self.push(" = ");
+                self.expr(e, OPEN);
+            }
+            Expr::If(first, elifs, els) => {
+                let branches = std::iter::once(&**first).chain(elifs).collect::<Vec<_>>();
+                for (i, (cond, then)) in branches.iter().enumerate() {
+                    self.push(if i == 0 { "if (" } else { " elif (" });
+                    self.expr(cond, OPEN);
+                    self.push(") ");
+                    let followed = i + 1 < branches.len() || els.is_some();
+                    if followed && dangling(then) {
+                        self.push("(");
+                        self.expr(then, OPEN);
+                        self.push(")");
+                    } else {
+                        self.expr(then, OPEN);
+                    }
+                }
+                if let Some(els) = &**els {
+                    self.push(" else ");
+                    self.expr(els, OPEN);
+                }
+            }
+            Expr::While(cond, body) => {
+                self.push("while (");
+                self.expr(cond, OPEN);
+                self.push(") ");
+                self.expr(body, OPEN);
+            }
+            Expr::For(init, cond, step, body) => {
+                self.push("for (");
+                self.expr(init, OPEN);
+                self.push("; ");
+                self.expr(cond, OPEN);
+                self.push("; ");
+                self.expr(step, OPEN);
+                self.push(") ");
+                self.expr(body, OPEN);
+            }
+            Expr::Return(x) => {
+                self.push("return");
+                if let Some(x) = &**x {
+                    self.push(" ");
+                    self.expr(x, OPEN);
+                }
+            }
+            Expr::Break(label) | Expr::Continue(label) => {
+                self.push(match e {
+                    Expr::Break(_) => "break",
+                    _ => "continue",
+                });
+                if let Some(label) = label {
+                    self.push(" ");
+                    self.ident(*label);
+                }
+            }
+            Expr::Labeled(label, body) => {
+                self.ident(*label);
+                self.push(": ");
+                self.expr(body, OPEN);
+            }
+            Expr::Match(x, arms, default) => {
+                self.push("match (");
+                self.expr(x, OPEN);
+                self.push(") ");
+                let mut arms = arms
+                    .iter()
+                    .map(|(variant, binds, e)| (Some((variant, binds)), e))
+                    .collect::<Vec<_>>();
+                if let Some(default) = &**default {
+                    arms.push((None, default));
+                }
+                self.braces(&arms, |p, (pat, e)| {
+                    match pat {
+                        Some((variant, binds)) => {
+                            p.ident(**variant);
+                            if !binds.is_empty() {
+                                p.push("(");
+                                p.sep(binds, |p, x| p.ident(*x));
+                                p.push(")");
+                            }
+                        }
+                        None => p.push("_"),
+                    }
+                    p.push(" => ");
+                    p.expr(e, OPEN);
+                });
+            }
+            Expr::Lambda(caps, params, ret, body) => {
+                self.push("\\");
+                if !caps.is_empty() {
+                    self.push("[");
+                    self.sep(caps, |p, x| p.ident(*x));
+                    self.push("]");
+                }
+                self.params(params);
+                self.result_type(Some(ret));
+                self.push(" -> ");
+                self.expr(body, OPEN);
+            }
+            Expr::I32Literal(_)
+            | Expr::I64Literal(_)
+            | Expr::F32Literal(_)
+            | Expr::F64Literal(_)
+            | Expr::StringLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::BoolLiteral(_)
+            | Expr::Or(..)
+            | Expr::And(..)
+            | Expr::Eq(..)
+            | Expr::Ne(..)
+            | Expr::Lt(..)
+            | Expr::Lte(..)
+            | Expr::Gt(..)
+            | Expr::Gte(..)
+            | Expr::BitOr(..)
+            | Expr::BitXor(..)
+            | Expr::BitAnd(..)
+            | Expr::Shl(..)
+            | Expr::Shr(..)
+            | Expr::UShr(..)
+            | Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::Mod(..) => unreachable!(),
+        }
+    }
+}
+
+impl Default for Printer {
+    fn default() -> Printer {
+        Printer::new()
+    }
+}
+
+pub fn print_module(m: &Module) -> String {
+    let mut p = Printer::new();
+    p.module(m);
+    p.finish()
+}
+
+pub fn print_expr(e: &Expr) -> String {
+    let mut p = Printer::new();
+    p.expr(e, OPEN);
+    p.finish()
+}
+
+pub fn print_type(t: &Type) -> String {
+    let mut p = Printer::new();
+    p.typ(t);
+    p.finish()
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&print_expr(self))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&print_type(self))
+    }
+}
+
+impl fmt::Display for Member {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut p = Printer::new();
+        p.member(self);
+        f.write_str(&p.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, parse_expr};
+    use token::intern::Sym;
+
+    #[test]
+    fn print_expr_test() {
+        let cases = [
+            ("(a + b) * c - d", "(a + b) * c - d"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("a = b = c += 1", "a = b = c += 1"),
+            ("-(x ** 2) + (-x) ** 2", "-x ** 2 + (-x) ** 2"),
+            ("(1 + 2) as i64 as F64", "(1 + 2) as i64 as F64"),
+            ("(if (a) b else c) + 1", "(if (a) b else c) + 1"),
+            ("if (a) (if (b) c) else d", "if (a) (if (b) c) else d"),
+            ("a ? b : c ? d : e", "if (a) b else if (c) d else e"),
+            ("(1).x + 1.5f32 + 2.0", "(1).x + 1.5f32 + 2.0"),
+            (
+                "f(\\[a](x: i32): i32 -> x)[0]",
+                "f(\\[a](x: i32): i32 -> x)[0]",
+            ),
+            (r#"'\'' == "\"\n""#, r#"'\'' == "\"\n""#),
+        ];
+        for (src, expected) in cases.iter() {
+            assert_eq!(print_expr(&parse_expr(src).unwrap()), *expected);
+        }
+        assert_eq!(
+            print_expr(&Expr::Sub(
+                Box::new(Expr::I32Literal(i32::MIN)),
+                Box::new(Expr::F64Literal(-0.5))
+            )),
+            "(-2147483647 - 1) - -0.5"
+        );
+    }
+
+    #[test]
+    fn print_module_test() {
+        let src = r#"
+            extern "env" "print" fun print(x: i32);
+            struct P { x: F64, next: [P] }
+            enum E { A(i32, P), B }
+            const N: i32 = 10;
+            export "run" fun main(): i32 {
+                let p = P { x: 1.0, next: [P; N] };
+                outer: for (let i = 0; i < N; i += 1) { if (i == 3) { break outer; } }
+                match (E::B) { A(x, _p) => x, _ => 0 }
+            }
+        "#;
+        let expected = r#"extern "env" "print" fun print(x: i32);
+
+struct P {
+    x: F64,
+    next: [P],
+}
+
+enum E {
+    A(i32, P),
+    B,
+}
+
+const N: i32 = 10;
+
+export "run" fun main(): i32 {
+    let p = P { x: 1.0, next: [P; N] };
+    outer: for (let i = 0; i < N; i += 1) {
+        if (i == 3) {
+            break outer;
+        }
+    };
+    match (E::B) {
+        A(x, _p) => x,
+        _ => 0,
+    }
+}
+"#;
+        let m = parse(src).unwrap();
+        assert_eq!(print_module(&m), expected);
+        assert_eq!(parse(expected).unwrap(), m);
+    }
+
+    struct Gen(u64);
+
+    impl Gen {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn ident(&mut self) -> Ident {
+            let names = ["a", "b", "x1", "_y", "変数"];
+            Sym::intern(names[self.below(names.len() as u64) as usize])
+        }
+
+        fn idents(&mut self) -> Vec<Ident> {
+            (0..self.below(3)).map(|_| self.ident()).collect()
+        }
+
+        fn typ(&mut self, depth: u32) -> Type {
+            match self.below(if depth == 0 { 8 } else { 10 }) {
+                0 => Type::I32,
+                1 => Type::I64,
+                2 => Type::F32,
+                3 => Type::F64,
+                4 => Type::Bool,
+                5 => Type::Char,
+                6 => Type::RefType(RefType::String),
+                7 => Type::RefType(RefType::Struct(self.ident())),
+                8 => Type::RefType(RefType::Array(Box::new(self.typ(depth - 1)))),
+                _ => {
+                    let params = (0..self.below(3)).map(|_| self.typ(depth - 1)).collect();
+                    let ret = if self.below(2) == 0 {
+                        None
+                    } else {
+                        Some(self.typ(depth - 1))
+                    };
+                    Type::RefType(RefType::Func(params, Box::new(ret)))
+                }
+            }
+        }
+
+        fn b(&mut self, depth: u32) -> Box<Expr> {
+            Box::new(self.expr(depth))
+        }
+
+        fn opt(&mut self, depth: u32) -> Box<Option<Expr>> {
+            Box::new(if self.below(2) == 0 {
+                None
+            } else {
+                Some(self.expr(depth))
+            })
+        }
+
+        fn label(&mut self) -> Option<Ident> {
+            if self.below(2) == 0 {
+                None
+            } else {
+                Some(self.ident())
+            }
+        }
+
+        fn exprs(&mut self, depth: u32) -> Vec<Expr> {
+            (0..self.below(3)).map(|_| self.expr(depth)).collect()
+        }
+
+        // パーサーが生成し得る形の式のみを作る
+        fn expr(&mut self, depth: u32) -> Expr {
+            if depth == 0 {
+                return match self.below(9) {
+                    0 => Expr::I32Literal(self.below(1000) as i32),
+                    1 => Expr::I64Literal(self.below(1 << 40) as i64),
+                    2 => Expr::F32Literal(self.below(1000) as f32 / 8.0),
+                    3 => Expr::F64Literal(self.below(1 << 20) as f64 / 1024.0),
+                    4 => Expr::BoolLiteral(self.below(2) == 0),
+                    5 => Expr::CharLiteral(['a', '\'', '\\', '\0', 'あ'][self.below(5) as usize]),
+                    6 => Expr::StringLiteral(Sym::intern(
+                        ["", "\"q\"", "a\nb\t", "\u{7f}\u{301}", "文字列"][self.below(5) as usize],
+                    )),
+                    7 => Expr::Break(self.label()),
+                    _ => Expr::Var(self.ident()),
+                };
+            }
+            let d = depth - 1;
+            match self.below(48) {
+                0 => {
+                    let fields = (0..self.below(3))
+                        .map(|_| (self.ident(), self.expr(d)))
+                        .collect();
+                    Expr::StructLiteral(self.ident(), fields)
+                }
+                1 => Expr::EnumLiteral(self.ident(), self.ident(), self.exprs(d)),
+                2 => Expr::ArrayLiteral(self.typ(2), self.b(d)),
+                3 => Expr::Not(self.b(d)),
+                4 => Expr::Plus(self.b(d)),
+                5 => Expr::Minus(self.b(d)),
+                6 => Expr::Member(self.b(d), self.ident()),
+                7 => Expr::Index(self.b(d), self.b(d)),
+                8 => Expr::Call(self.b(d), self.exprs(d)),
+                9 => Expr::Add(self.b(d), self.b(d)),
+                10 => Expr::Sub(self.b(d), self.b(d)),
+                11 => Expr::Mul(self.b(d), self.b(d)),
+                12 => Expr::Div(self.b(d), self.b(d)),
+                13 => Expr::Mod(self.b(d), self.b(d)),
+                14 => Expr::And(self.b(d), self.b(d)),
+                15 => Expr::Or(self.b(d), self.b(d)),
+                16 => Expr::BitAnd(self.b(d), self.b(d)),
+                17 => Expr::BitOr(self.b(d), self.b(d)),
+                18 => Expr::BitXor(self.b(d), self.b(d)),
+                19 => Expr::Shl(self.b(d), self.b(d)),
+                20 => Expr::Shr(self.b(d), self.b(d)),
+                21 => Expr::UShr(self.b(d), self.b(d)),
+                22 => Expr::Pow(self.b(d), self.b(d)),
+                23 => Expr::Eq(self.b(d), self.b(d)),
+                24 => Expr::Ne(self.b(d), self.b(d)),
+                25 => Expr::Lt(self.b(d), self.b(d)),
+                26 => Expr::Lte(self.b(d), self.b(d)),
+                27 => Expr::Gt(self.b(d), self.b(d)),
+                28 => Expr::Gte(self.b(d), self.b(d)),
+                29 => Expr::Block(self.exprs(d), self.opt(d)),
+                30 => Expr::Let(self.ident(), self.b(d)),
+                31 => {
+                    let first = (self.expr(d), self.expr(d));
+                    let elifs = (0..self.below(3))
+                        .map(|_| (self.expr(d), self.expr(d)))
+                        .collect();
+                    Expr::If(Box::new(first), elifs, self.opt(d))
+                }
+                32 => Expr::While(self.b(d), self.b(d)),
+                33 => Expr::Return(self.opt(d)),
+                34 => Expr::Continue(self.label()),
+                35 => {
+                    let body = if self.below(2) == 0 {
+                        Expr::While(self.b(d), self.b(d))
+                    } else {
+                        Expr::For(self.b(d), self.b(d), self.b(d), self.b(d))
+                    };
+                    Expr::Labeled(self.ident(), Box::new(body))
+                }
+                36 => {
+                    let arms = (0..self.below(3))
+                        .map(|_| (self.ident(), self.idents(), self.expr(d)))
+                        .collect();
+                    Expr::Match(self.b(d), arms, self.opt(d))
+                }
+                37 => Expr::Cast(self.b(d), self.typ(2)),
+                38 => Expr::Bitcast(self.b(d), self.typ(2)),
+                39 => Expr::Set(self.b(d), self.b(d)),
+                40 => {
+                    let ops = [
+                        AssignOp::Add,
+                        AssignOp::Pow,
+                        AssignOp::UShr,
+                        AssignOp::BitXor,
+                    ];
+                    let op = ops[self.below(ops.len() as u64) as usize];
+                    Expr::CompoundSet(op, self.b(d), self.b(d))
+                }
+                41 => Expr::For(self.b(d), self.b(d), self.b(d), self.b(d)),
+                42 => {
+                    let params = (0..self.below(3))
+                        .map(|_| (self.ident(), self.typ(2)))
+                        .collect();
+                    Expr::Lambda(self.idents(), params, self.typ(2), self.b(d))
+                }
+                _ => self.expr(0),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut gen = Gen(0x2545_F491_4F6C_DD1D);
+        for _ in 0..3000 {
+            let e = gen.expr(5);
+            let src = print_expr(&e);
+            match parse_expr(&src) {
+                Ok(parsed) => assert_eq!(parsed, e, "{}", src),
+                Err(err) => panic!("{}: {}", err, src),
+            }
+            check_spans(&src);
+        }
+    }
+
+    // 各式の範囲を切り出して読み直すと、後行順に辿った式と一致する
+    fn check_spans(src: &str) {
+        use crate::parser::parse_with_spans;
+        use crate::visit::{walk_expr, Visitor};
+
+        struct Postorder(Vec<Expr>);
+
+        impl Visitor for Postorder {
+            fn visit_expr(&mut self, e: &Expr) {
+                walk_expr(self, e);
+                self.0.push(e.clone());
+            }
+        }
+
+        let src = format!("fun f() {{ {} }}", src);
+        let (m, spans) = parse_with_spans(&src).unwrap();
+        let mut p = Postorder(Vec::new());
+        p.visit_module(&m);
+        assert_eq!(p.0.len(), spans.exprs.len(), "{}", src);
+        let chars = src.chars().collect::<Vec<_>>();
+        for (e, span) in p.0.iter().zip(&spans.exprs) {
+            let text = chars[span.pos..span.end()].iter().collect::<String>();
+            assert_eq!(parse_expr(&text).as_ref(), Ok(e), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+// ソース上の範囲(文字単位)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub pos: usize,
+    pub len: usize,
+}
+
+impl Span {
+    pub fn end(&self) -> usize {
+        self.pos + self.len
+    }
+}
+
+// 式のIDはvisit::walk_exprの順で構文木を後行順に辿った時の番号
+pub type ExprId = usize;
+
+// 構文木の外に持つ位置情報
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spans {
+    pub exprs: Vec<Span>,
+    pub members: Vec<Span>,
+}
+
+impl Spans {
+    // 位置情報を持たない構文木では空の範囲を返す
+    pub fn expr(&self, id: ExprId) -> Span {
+        self.exprs.get(id).cloned().unwrap_or_default()
+    }
+
+    pub fn member(&self, i: usize) -> Span {
+        self.members.get(i).cloned().unwrap_or_default()
+    }
+}
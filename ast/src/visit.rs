@@ -0,0 +1,253 @@
+use crate::ast::{Expr, FuncDef, Member, Module, RefType, Type};
+
+// 各visit_*のデフォルト実装はwalk_*で子を辿る
+pub trait Visitor {
+    fn visit_module(&mut self, m: &Module) {
+        walk_module(self, m)
+    }
+
+    fn visit_member(&mut self, m: &Member) {
+        walk_member(self, m)
+    }
+
+    fn visit_func_def(&mut self, d: &FuncDef) {
+        walk_func_def(self, d)
+    }
+
+    fn visit_expr(&mut self, e: &Expr) {
+        walk_expr(self, e)
+    }
+
+    fn visit_type(&mut self, t: &Type) {
+        walk_type(self, t)
+    }
+
+    fn visit_ref_type(&mut self, t: &RefType) {
+        walk_ref_type(self, t)
+    }
+}
+
+pub fn walk_module<V: Visitor + ?Sized>(v: &mut V, m: &Module) {
+    for member in m {
+        v.visit_member(member);
+    }
+}
+
+pub fn walk_member<V: Visitor + ?Sized>(v: &mut V, m: &Member) {
+    match m {
+        Member::Struct(_, fields) | Member::Union(_, fields) => {
+            for (_, t) in fields {
+                v.visit_type(t);
+            }
+        }
+        Member::Enum(_, variants) => {
+            for (_, ts) in variants {
+                for t in ts {
+                    v.visit_type(t);
+                }
+            }
+        }
+        Member::Func(def, body, _) => {
+            v.visit_func_def(def);
+            v.visit_expr(body);
+        }
+        Member::ExternFun(def, _, _) => v.visit_func_def(def),
+        Member::Let(_, t, e) | Member::Const(_, t, e) => {
+            if let Some(t) = t {
+                v.visit_type(t);
+            }
+            v.visit_expr(e);
+        }
+    }
+}
+
+pub fn walk_func_def<V: Visitor + ?Sized>(v: &mut V, d: &FuncDef) {
+    for (_, t) in &d.1 {
+        v.visit_type(t);
+    }
+    if let Some(t) = &d.2 {
+        v.visit_type(t);
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, e: &Expr) {
+    match e {
+        Expr::StructLiteral(_, fields) => {
+            for (_, e) in fields {
+                v.visit_expr(e);
+            }
+        }
+        Expr::EnumLiteral(_, _, args) => {
+            for e in args {
+                v.visit_expr(e);
+            }
+        }
+        Expr::I32Literal(_)
+        | Expr::I64Literal(_)
+        | Expr::F32Literal(_)
+        | Expr::F64Literal(_)
+        | Expr::StringLiteral(_)
+        | Expr::BoolLiteral(_)
+        | Expr::CharLiteral(_)
+        | Expr::Var(_)
+        | Expr::Break(_)
+        | Expr::Continue(_) => {}
+        Expr::ArrayLiteral(t, len) => {
+            v.visit_type(t);
+            v.visit_expr(len);
+        }
+        Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) | Expr::Member(x, _) => v.visit_expr(x),
+        Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+            v.visit_expr(f);
+            for e in args {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Index(a, b)
+        | Expr::Add(a, b)
+        | Expr::Sub(a, b)
+        | Expr::Mul(a, b)
+        | Expr::Div(a, b)
+        | Expr::Mod(a, b)
+        | Expr::And(a, b)
+        | Expr::Or(a, b)
+        | Expr::BitAnd(a, b)
+        | Expr::BitOr(a, b)
+        | Expr::BitXor(a, b)
+        | Expr::Shl(a, b)
+        | Expr::Shr(a, b)
+        | Expr::UShr(a, b)
+        | Expr::Pow(a, b)
+        | Expr::Eq(a, b)
+        | Expr::Ne(a, b)
+        | Expr::Lt(a, b)
+        | Expr::Lte(a, b)
+        | Expr::Gt(a, b)
+        | Expr::Gte(a, b)
+        | Expr::While(a, b)
+        | Expr::Set(a, b)
+        | Expr::CompoundSet(_, a, b) => {
+            v.visit_expr(a);
+            v.visit_expr(b);
+        }
+        Expr::Block(stmts, last) => {
+            for e in stmts {
+                v.visit_expr(e);
+            }
+            if let Some(e) = &**last {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Let(_, e) | Expr::Labeled(_, e) => v.visit_expr(e),
+        Expr::If(first, elifs, els) => {
+            for (cond, then) in std::iter::once(&**first).chain(elifs) {
+                v.visit_expr(cond);
+                v.visit_expr(then);
+            }
+            if let Some(e) = &**els {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Return(e) | Expr::Closure(_, e) => {
+            if let Some(e) = &**e {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Match(e, arms, default) => {
+            v.visit_expr(e);
+            for (_, _, e) in arms {
+                v.visit_expr(e);
+            }
+            if let Some(e) = &**default {
+                v.visit_expr(e);
+            }
+        }
+        Expr::Cast(e, t) | Expr::Bitcast(e, t) => {
+            v.visit_expr(e);
+            v.visit_type(t);
+        }
+        Expr::For(init, cond, step, body) => {
+            v.visit_expr(init);
+            v.visit_expr(cond);
+            v.visit_expr(step);
+            v.visit_expr(body);
+        }
+        Expr::Lambda(_, params, ret, body) => {
+            for (_, t) in params {
+                v.visit_type(t);
+            }
+            v.visit_type(ret);
+            v.visit_expr(body);
+        }
+    }
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(v: &mut V, t: &Type) {
+    match t {
+        Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Bool | Type::Char => {}
+        Type::RefType(t) => v.visit_ref_type(t),
+    }
+}
+
+pub fn walk_ref_type<V: Visitor + ?Sized>(v: &mut V, t: &RefType) {
+    match t {
+        RefType::String | RefType::Struct(_) => {}
+        RefType::Array(t) => v.visit_type(t),
+        RefType::Func(params, ret) => {
+            for t in params {
+                v.visit_type(t);
+            }
+            if let Some(t) = &**ret {
+                v.visit_type(t);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Ident;
+    use crate::parser::parse;
+
+    #[derive(Default)]
+    struct Collect {
+        vars: Vec<Ident>,
+        structs: Vec<Ident>,
+    }
+
+    impl Visitor for Collect {
+        fn visit_expr(&mut self, e: &Expr) {
+            if let Expr::Var(x) = e {
+                self.vars.push(*x);
+            }
+            walk_expr(self, e)
+        }
+
+        fn visit_ref_type(&mut self, t: &RefType) {
+            if let RefType::Struct(x) = t {
+                self.structs.push(*x);
+            }
+            walk_ref_type(self, t)
+        }
+    }
+
+    #[test]
+    fn visitor_test() {
+        let m = parse(
+            r#"
+            struct P { next: [P] }
+            fun f(p: P): fun(Q): R {
+                if (a) { b } elif (c) { d } else { e };
+                \[g](x: S): T -> h(x as i32)
+            }
+        "#,
+        )
+        .unwrap();
+        let mut c = Collect::default();
+        c.visit_module(&m);
+        let names = |xs: Vec<Ident>| xs.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(names(c.vars), vec!["a", "b", "c", "d", "e", "h", "x"]);
+        assert_eq!(names(c.structs), vec!["P", "P", "Q", "R", "S", "T"]);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::ast::{Expr, FuncDef, Member, Module, RefType, Type};
+
+pub trait VisitorMut {
+    fn visit_module_mut(&mut self, m: &mut Module) {
+        walk_module_mut(self, m)
+    }
+
+    fn visit_member_mut(&mut self, m: &mut Member) {
+        walk_member_mut(self, m)
+    }
+
+    fn visit_func_def_mut(&mut self, d: &mut FuncDef) {
+        walk_func_def_mut(self, d)
+    }
+
+    fn visit_expr_mut(&mut self, e: &mut Expr) {
+        walk_expr_mut(self, e)
+    }
+
+    fn visit_type_mut(&mut self, t: &mut Type) {
+        walk_type_mut(self, t)
+    }
+
+    fn visit_ref_type_mut(&mut self, t: &mut RefType) {
+        walk_ref_type_mut(self, t)
+    }
+}
+
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(v: &mut V, m: &mut Module) {
+    for member in m {
+        v.visit_member_mut(member);
+    }
+}
+
+pub fn walk_member_mut<V: VisitorMut + ?Sized>(v: &mut V, m: &mut Member) {
+    match m {
+        Member::Struct(_, fields) | Member::Union(_, fields) => {
+            for (_, t) in fields {
+                v.visit_type_mut(t);
+            }
+        }
+        Member::Enum(_, variants) => {
+            for (_, ts) in variants {
+                for t in ts {
+                    v.visit_type_mut(t);
+                }
+            }
+        }
+        Member::Func(def, body, _) => {
+            v.visit_func_def_mut(def);
+            v.visit_expr_mut(body);
+        }
+        Member::ExternFun(def, _, _) => v.visit_func_def_mut(def),
+        Member::Let(_, t, e) | Member::Const(_, t, e) => {
+            if let Some(t) = t {
+                v.visit_type_mut(t);
+            }
+            v.visit_expr_mut(e);
+        }
+    }
+}
+
+pub fn walk_func_def_mut<V: VisitorMut + ?Sized>(v: &mut V, d: &mut FuncDef) {
+    for (_, t) in &mut d.1 {
+        v.visit_type_mut(t);
+    }
+    if let Some(t) = &mut d.2 {
+        v.visit_type_mut(t);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, e: &mut Expr) {
+    match e {
+        Expr::StructLiteral(_, fields) => {
+            for (_, e) in fields {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::EnumLiteral(_, _, args) => {
+            for e in args {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::I32Literal(_)
+        | Expr::I64Literal(_)
+        | Expr::F32Literal(_)
+        | Expr::F64Literal(_)
+        | Expr::StringLiteral(_)
+        | Expr::BoolLiteral(_)
+        | Expr::CharLiteral(_)
+        | Expr::Var(_)
+        | Expr::Break(_)
+        | Expr::Continue(_) => {}
+        Expr::ArrayLiteral(t, len) => {
+            v.visit_type_mut(t);
+            v.visit_expr_mut(len);
+        }
+        Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) | Expr::Member(x, _) => v.visit_expr_mut(x),
+        Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+            v.visit_expr_mut(f);
+            for e in args {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Index(a, b)
+        | Expr::Add(a, b)
+        | Expr::Sub(a, b)
+        | Expr::Mul(a, b)
+        | Expr::Div(a, b)
+        | Expr::Mod(a, b)
+        | Expr::And(a, b)
+        | Expr::Or(a, b)
+        | Expr::BitAnd(a, b)
+        | Expr::BitOr(a, b)
+        | Expr::BitXor(a, b)
+        | Expr::Shl(a, b)
+        | Expr::Shr(a, b)
+        | Expr::UShr(a, b)
+        | Expr::Pow(a, b)
+        | Expr::Eq(a, b)
+        | Expr::Ne(a, b)
+        | Expr::Lt(a, b)
+        | Expr::Lte(a, b)
+        | Expr::Gt(a, b)
+        | Expr::Gte(a, b)
+        | Expr::While(a, b)
+        | Expr::Set(a, b)
+        | Expr::CompoundSet(_, a, b) => {
+            v.visit_expr_mut(a);
+            v.visit_expr_mut(b);
+        }
+        Expr::Block(stmts, last) => {
+            for e in stmts {
+                v.visit_expr_mut(e);
+            }
+            if let Some(e) = &mut **last {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Let(_, e) | Expr::Labeled(_, e) => v.visit_expr_mut(e),
+        Expr::If(first, elifs, els) => {
+            for (cond, then) in std::iter::once(&mut **first).chain(elifs) {
+                v.visit_expr_mut(cond);
+                v.visit_expr_mut(then);
+            }
+            if let Some(e) = &mut **els {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Return(e) | Expr::Closure(_, e) => {
+            if let Some(e) = &mut **e {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Match(e, arms, default) => {
+            v.visit_expr_mut(e);
+            for (_, _, e) in arms {
+                v.visit_expr_mut(e);
+            }
+            if let Some(e) = &mut **default {
+                v.visit_expr_mut(e);
+            }
+        }
+        Expr::Cast(e, t) | Expr::Bitcast(e, t) => {
+            v.visit_expr_mut(e);
+            v.visit_type_mut(t);
+        }
+        Expr::For(init, cond, step, body) => {
+            v.visit_expr_mut(init);
+            v.visit_expr_mut(cond);
+            v.visit_expr_mut(step);
+            v.visit_expr_mut(body);
+        }
+        Expr::Lambda(_, params, ret, body) => {
+            for (_, t) in params {
+                v.visit_type_mut(t);
+            }
+            v.visit_type_mut(ret);
+            v.visit_expr_mut(body);
+        }
+    }
+}
+
+pub fn walk_type_mut<V: VisitorMut + ?Sized>(v: &mut V, t: &mut Type) {
+    match t {
+        Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Bool | Type::Char => {}
+        Type::RefType(t) => v.visit_ref_type_mut(t),
+    }
+}
+
+pub fn walk_ref_type_mut<V: VisitorMut + ?Sized>(v: &mut V, t: &mut RefType) {
+    match t {
+        RefType::String | RefType::Struct(_) => {}
+        RefType::Array(t) => v.visit_type_mut(t),
+        RefType::Func(params, ret) => {
+            for t in params {
+                v.visit_type_mut(t);
+            }
+            if let Some(t) = &mut **ret {
+                v.visit_type_mut(t);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expr;
+
+    struct Double;
+
+    impl VisitorMut for Double {
+        fn visit_expr_mut(&mut self, e: &mut Expr) {
+            if let Expr::I32Literal(x) = e {
+                *x *= 2;
+            }
+            walk_expr_mut(self, e)
+        }
+
+        fn visit_type_mut(&mut self, t: &mut Type) {
+            if *t == Type::I32 {
+                *t = Type::I64;
+            }
+            walk_type_mut(self, t)
+        }
+    }
+
+    #[test]
+    fn visitor_mut_test() {
+        let mut e = parse_expr("if (x) { [[i32]; 1] } else { f(2 as i32) + 3 }").unwrap();
+        Double.visit_expr_mut(&mut e);
+        assert_eq!(
+            e,
+            parse_expr("if (x) { [[i64]; 2] } else { f(4 as i64) + 6 }").unwrap()
+        );
+    }
+}
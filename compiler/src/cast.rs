@@ -0,0 +1,95 @@
+use ast::ast::Type;
+use wasm::ast::OperatorCode;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cast {
+    Noop,
+    Op(OperatorCode),
+}
+
+impl Cast {
+    pub fn codes(&self) -> Vec<OperatorCode> {
+        match self {
+            Cast::Noop => Vec::new(),
+            Cast::Op(op) => vec![op.clone()],
+        }
+    }
+}
+
+// `e as T`
+// 整数同士は符号付きとして拡張/切り捨て、浮動小数点数から整数へは0方向への切り捨て
+pub fn check_cast(from: &Type, to: &Type) -> Option<Cast> {
+    use OperatorCode::*;
+    use Type::*;
+    Some(match (from, to) {
+        (I32, I32) | (I64, I64) | (F32, F32) | (F64, F64) | (Bool, Bool) | (Char, Char) => {
+            Cast::Noop
+        }
+        (I32, I64) => Cast::Op(I64ExtendsI32),
+        (I32, F32) => Cast::Op(F32ConvertsI32),
+        (I32, F64) => Cast::Op(F64ConvertsI32),
+        (I64, I32) => Cast::Op(I32WrapI64),
+        (I64, F32) => Cast::Op(F32ConvertsI64),
+        (I64, F64) => Cast::Op(F64ConvertsI64),
+        (F32, I32) => Cast::Op(I32TruncsF32),
+        (F32, I64) => Cast::Op(I64TruncsF32),
+        (F32, F64) => Cast::Op(F64PromoteF32),
+        (F64, I32) => Cast::Op(I32TrancsF64),
+        (F64, I64) => Cast::Op(I64TrancsF64),
+        (F64, F32) => Cast::Op(F32DemoteF64),
+        (Bool, I32) | (Char, I32) => Cast::Noop,
+        (Bool, I64) | (Char, I64) => Cast::Op(I64ExtenduI32),
+        _ => return None,
+    })
+}
+
+// `e bitcast T`
+pub fn check_bitcast(from: &Type, to: &Type) -> Option<Cast> {
+    use OperatorCode::*;
+    use Type::*;
+    Some(match (from, to) {
+        (I32, I32) | (I64, I64) | (F32, F32) | (F64, F64) => Cast::Noop,
+        (I32, F32) => Cast::Op(F32ReinterpretI32),
+        (F32, I32) => Cast::Op(I32ReinterpretF32),
+        (I64, F64) => Cast::Op(F64ReinterpretI64),
+        (F64, I64) => Cast::Op(I64ReinterpretF64),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::ast::RefType;
+
+    #[test]
+    fn check_cast_test() {
+        let nums = [Type::I32, Type::I64, Type::F32, Type::F64];
+        for from in nums.iter() {
+            for to in nums.iter() {
+                assert!(check_cast(from, to).is_some());
+            }
+        }
+        assert_eq!(
+            check_cast(&Type::I64, &Type::I32),
+            Some(Cast::Op(OperatorCode::I32WrapI64))
+        );
+        assert_eq!(check_cast(&Type::Char, &Type::I32), Some(Cast::Noop));
+        assert_eq!(check_cast(&Type::I32, &Type::Bool), None);
+        assert_eq!(check_cast(&Type::I32, &Type::Char), None);
+        assert_eq!(
+            check_cast(&Type::RefType(RefType::String), &Type::I32),
+            None
+        );
+    }
+
+    #[test]
+    fn check_bitcast_test() {
+        assert_eq!(
+            check_bitcast(&Type::F64, &Type::I64),
+            Some(Cast::Op(OperatorCode::I64ReinterpretF64))
+        );
+        assert_eq!(check_bitcast(&Type::I32, &Type::F64), None);
+        assert_eq!(check_bitcast(&Type::Bool, &Type::I32), None);
+    }
+}
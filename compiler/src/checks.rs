@@ -0,0 +1,264 @@
+use crate::layout::TRAP_CODE;
+use crate::typeck::{Ty, Typed};
+use ast::ast::{AssignOp, Expr, FuncDef, Ident, Member, Module, Type};
+use ast::fold::{self, Fold};
+use ast::span::{ExprId, Span, Spans};
+use std::convert::TryInto;
+use token::intern::Sym;
+
+// 検査に失敗したときにトラップの前に書き込む符号の下位2ビット
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckKind {
+    OutOfBounds = 1,
+    DivisionByZero = 2,
+    // i32::MINやi64::MINを-1で割った
+    Overflow = 3,
+}
+
+// 上位ビットは検査した式の番号
+pub fn trap_code(site: i32, kind: CheckKind) -> i32 {
+    site << 2 | kind as i32
+}
+
+pub fn decode(code: i32) -> Option<(usize, CheckKind)> {
+    let kind = match code & 3 {
+        1 => CheckKind::OutOfBounds,
+        2 => CheckKind::DivisionByZero,
+        3 => CheckKind::Overflow,
+        _ => return None,
+    };
+    Some(((code >> 2) as usize, kind))
+}
+
+// トラップした後の線形メモリから失敗した検査とその位置を読む
+pub fn failed_check(memory: &[u8], sites: &[Span]) -> Option<(CheckKind, Span)> {
+    let at = TRAP_CODE as usize;
+    let code = i32::from_le_bytes(memory.get(at..at + 4)?.try_into().unwrap());
+    let (site, kind) = decode(code)?;
+    Some((kind, *sites.get(site)?))
+}
+
+fn site_global() -> Ident {
+    Sym::intern("site#")
+}
+
+fn site_func(t: &Type) -> Ident {
+    Sym::intern(match t {
+        Type::I64 => "site#i64",
+        _ => "site#i32",
+    })
+}
+
+// 検査する値を包んだ呼び出しなら、その値と式の番号を返す
+pub fn site(e: &Expr) -> Option<(&Expr, i32)> {
+    match e {
+        Expr::Call(f, args) => match (&**f, &args[..]) {
+            (Expr::Var(f), [x, Expr::I32Literal(site)])
+                if *f == site_func(&Type::I32) || *f == site_func(&Type::I64) =>
+            {
+                Some((x, *site))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 包んだ呼び出しの番号から検査する値の番号を求める
+// 子は関数, 値, 式の番号の順に並ぶので値は2つ前になる
+pub fn site_arg(id: ExprId) -> ExprId {
+    id - 2
+}
+
+// 最後に通った検査の番号を大域変数に残して値をそのまま返す
+// 最適化で展開されず、コード生成まで呼び出しの形が残る
+fn site_member(t: &Type) -> Member {
+    let x = Sym::intern("x");
+    let code = Sym::intern("code");
+    let set = Expr::Set(
+        Box::new(Expr::Var(site_global())),
+        Box::new(Expr::Var(code)),
+    );
+    Member::Func(
+        FuncDef(
+            site_func(t),
+            vec![(x, t.clone()), (code, Type::I32)],
+            Some(t.clone()),
+        ),
+        Expr::Block(vec![set], Box::new(Some(Expr::Var(x)))),
+        None,
+    )
+}
+
+fn int_type(t: &Ty) -> Option<&Type> {
+    match t {
+        Ty::Type(t @ Type::I32) | Ty::Type(t @ Type::I64) => Some(t),
+        _ => None,
+    }
+}
+
+// 0と-1以外の整数リテラルで割る場合は検査しない
+fn safe_divisor(e: &Expr) -> bool {
+    match e {
+        Expr::I32Literal(x) => *x != 0 && *x != -1,
+        Expr::I64Literal(x) => *x != 0 && *x != -1,
+        _ => false,
+    }
+}
+
+pub struct Checked {
+    pub module: Module,
+    // 検査した式の位置を番号の順に並べる
+    pub sites: Vec<Span>,
+}
+
+struct Inserter<'a> {
+    spans: &'a Spans,
+    typed: &'a Typed,
+    sites: Vec<Span>,
+    next_id: ExprId,
+    // 大域変数の初期値の中か
+    global: bool,
+}
+
+impl<'a> Inserter<'a> {
+    fn site(&mut self, id: ExprId, t: &Type, e: Expr) -> Box<Expr> {
+        let site = Expr::I32Literal(self.sites.len() as i32);
+        self.sites.push(self.spans.expr(id));
+        Box::new(Expr::Call(Box::new(Expr::Var(site_func(t))), vec![e, site]))
+    }
+
+    fn check(&mut self, id: ExprId, e: Expr) -> Expr {
+        match e {
+            Expr::Index(a, i) => Expr::Index(a, self.site(id, &Type::I32, *i)),
+            Expr::Div(a, b) if !safe_divisor(&b) => match int_type(&self.typed.exprs[id]) {
+                Some(t) => Expr::Div(a, self.site(id, t, *b)),
+                None => Expr::Div(a, b),
+            },
+            Expr::Mod(a, b) if !safe_divisor(&b) => match int_type(&self.typed.exprs[id]) {
+                Some(t) => Expr::Mod(a, self.site(id, t, *b)),
+                None => Expr::Mod(a, b),
+            },
+            // 右辺の型は左辺と同じ
+            Expr::CompoundSet(op @ AssignOp::Div, lhs, rhs)
+            | Expr::CompoundSet(op @ AssignOp::Mod, lhs, rhs)
+                if !safe_divisor(&rhs) =>
+            {
+                match int_type(&self.typed.exprs[id - 1]) {
+                    Some(t) => Expr::CompoundSet(op, lhs, self.site(id, t, *rhs)),
+                    None => Expr::CompoundSet(op, lhs, rhs),
+                }
+            }
+            e => e,
+        }
+    }
+}
+
+impl<'a> Fold for Inserter<'a> {
+    // 大域変数の初期値はコンパイル時に評価し、除算の誤りもそこで報告する
+    fn fold_member(&mut self, m: Member) -> Member {
+        self.global = matches!(m, Member::Let(..) | Member::Const(..));
+        let m = fold::walk_member(self, m);
+        self.global = false;
+        m
+    }
+
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        let e = fold::walk_expr(self, e);
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.global {
+            e
+        } else {
+            self.check(id, e)
+        }
+    }
+}
+
+// 添字と整数の除算の値を検査の印で包み、コード生成で範囲と除数の検査を出力させる
+// 印の関数と大域変数は末尾に追加する
+pub fn insert_checks(module: &Module, spans: &Spans, typed: &Typed) -> Checked {
+    let mut ins = Inserter {
+        spans,
+        typed,
+        sites: Vec::new(),
+        next_id: 0,
+        global: false,
+    };
+    let mut out = ins.fold_module(module.clone());
+    out.push(Member::Let(
+        site_global(),
+        Some(Type::I32),
+        Expr::I32Literal(0),
+    ));
+    out.push(site_member(&Type::I32));
+    out.push(site_member(&Type::I64));
+    Checked {
+        module: out,
+        sites: ins.sites,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use ast::printer::print_module;
+
+    fn text(src: &str, span: Span) -> String {
+        src.chars().skip(span.pos).take(span.len).collect()
+    }
+
+    #[test]
+    fn insert_checks_test() {
+        let src = r#"
+            fun f(a: [i32], i: i32, n: i64, x: F64): i32 {
+                let m = n % (n - 1i64);
+                let y = x / 0.0;
+                a[i] /= i;
+                a[0] / 2 + a[i + 1] % -1
+            }
+        "#;
+        let (module, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&module, &spans);
+        let typed = check(&module, &spans, &res);
+        let checked = insert_checks(&module, &spans, &typed);
+        let sites: Vec<_> = checked.sites.iter().map(|s| text(src, *s)).collect();
+        assert_eq!(
+            sites,
+            vec![
+                "n % (n - 1i64)",
+                "a[i]",
+                "a[i] /= i",
+                "a[0]",
+                "a[i + 1]",
+                "a[i + 1] % -1"
+            ]
+        );
+        let printed = print_module(&checked.module);
+        assert!(printed.contains("site#i64(n - 1i64, 0)"), "{}", printed);
+        assert!(printed.contains("x / 0.0"), "{}", printed);
+        assert!(printed.contains("a[site#i32(0, 3)] / 2"), "{}", printed);
+
+        let spans = Spans::default();
+        let res = resolve(&checked.module, &spans);
+        assert_eq!(res.errors, vec![]);
+        assert_eq!(check(&checked.module, &spans, &res).errors, vec![]);
+    }
+
+    #[test]
+    fn decode_test() {
+        let code = trap_code(5, CheckKind::Overflow);
+        assert_eq!(decode(code), Some((5, CheckKind::Overflow)));
+        assert_eq!(decode(0), None);
+        let mut memory = vec![0; 16];
+        memory[4..8].copy_from_slice(&trap_code(1, CheckKind::OutOfBounds).to_le_bytes());
+        let sites = vec![Span::default(), Span { pos: 3, len: 4 }];
+        assert_eq!(
+            failed_check(&memory, &sites),
+            Some((CheckKind::OutOfBounds, Span { pos: 3, len: 4 }))
+        );
+    }
+}
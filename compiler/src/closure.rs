@@ -0,0 +1,446 @@
+use crate::resolve::{DeclKind, Resolution};
+use crate::typeck::{Ty, Typed};
+use ast::ast::{Expr, FuncDef, Ident, Member, Module, RefType, Type};
+use ast::fold::{self, Fold};
+use ast::span::{ExprId, Span, Spans};
+use ast::visit::{self, Visitor};
+use std::collections::{HashMap, HashSet};
+use token::intern::Sym;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureErrorKind {
+    // 本体で使っているがキャプチャしていない
+    Missing(Ident),
+    // キャプチャしているが本体で使っていない
+    Unused(Ident),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureError {
+    pub kind: CaptureErrorKind,
+    pub span: Span,
+}
+
+// 式の中で束縛されていない名前を出現順に集める
+struct FreeVars {
+    bound: Vec<Ident>,
+    free: Vec<Ident>,
+}
+
+impl FreeVars {
+    fn use_var(&mut self, x: Ident) {
+        if !self.bound.contains(&x) && !self.free.contains(&x) {
+            self.free.push(x);
+        }
+    }
+
+    fn scoped(&mut self, e: &Expr) {
+        let n = self.bound.len();
+        self.visit_expr(e);
+        self.bound.truncate(n);
+    }
+}
+
+impl Visitor for FreeVars {
+    fn visit_expr(&mut self, e: &Expr) {
+        let n = self.bound.len();
+        match e {
+            Expr::Var(x) => self.use_var(*x),
+            Expr::Let(x, init) => {
+                self.visit_expr(init);
+                self.bound.push(*x);
+            }
+            Expr::Block(..) | Expr::For(..) => {
+                visit::walk_expr(self, e);
+                self.bound.truncate(n);
+            }
+            Expr::If(first, elifs, els) => {
+                for (cond, then) in std::iter::once(&**first).chain(elifs) {
+                    self.visit_expr(cond);
+                    self.scoped(then);
+                }
+                if let Some(e) = &**els {
+                    self.scoped(e);
+                }
+            }
+            Expr::While(cond, body) => {
+                self.visit_expr(cond);
+                self.scoped(body);
+            }
+            Expr::Match(x, arms, default) => {
+                self.visit_expr(x);
+                for (_, xs, body) in arms {
+                    self.bound.extend(xs);
+                    self.visit_expr(body);
+                    self.bound.truncate(n);
+                }
+                if let Some(e) = &**default {
+                    self.scoped(e);
+                }
+            }
+            // 内側のラムダはキャプチャした時点で変数を使う
+            Expr::Lambda(caps, _, _, _) => {
+                for x in caps {
+                    self.use_var(*x);
+                }
+            }
+            _ => visit::walk_expr(self, e),
+        }
+    }
+}
+
+struct CaptureChecker<'a> {
+    spans: &'a Spans,
+    globals: HashSet<Ident>,
+    next_id: ExprId,
+    errors: Vec<CaptureError>,
+}
+
+impl<'a> Visitor for CaptureChecker<'a> {
+    fn visit_expr(&mut self, e: &Expr) {
+        visit::walk_expr(self, e);
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Expr::Lambda(caps, params, _, body) = e {
+            let mut fv = FreeVars {
+                bound: params.iter().map(|(x, _)| *x).collect(),
+                free: Vec::new(),
+            };
+            fv.visit_expr(body);
+            let span = self.spans.expr(id);
+            for x in &fv.free {
+                if !caps.contains(x) && !self.globals.contains(x) {
+                    let kind = CaptureErrorKind::Missing(*x);
+                    self.errors.push(CaptureError { kind, span });
+                }
+            }
+            for x in caps {
+                if !fv.free.contains(x) {
+                    let kind = CaptureErrorKind::Unused(*x);
+                    self.errors.push(CaptureError { kind, span });
+                }
+            }
+        }
+    }
+}
+
+// キャプチャの宣言と本体で実際に使っている変数を照合する
+pub fn check_captures(module: &Module, spans: &Spans) -> Vec<CaptureError> {
+    let globals = module
+        .iter()
+        .filter_map(|m| match m {
+            Member::Func(def, _, _) | Member::ExternFun(def, _, _) => Some(def.0),
+            Member::Let(name, _, _) | Member::Const(name, _, _) => Some(*name),
+            _ => None,
+        })
+        .collect();
+    let mut c = CaptureChecker {
+        spans,
+        globals,
+        next_id: 0,
+        errors: Vec::new(),
+    };
+    c.visit_module(module);
+    c.errors
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Converted {
+    pub module: Module,
+    // Expr::Closureの関数の添字(CallIndirectで使う関数表)
+    pub table: Vec<Ident>,
+}
+
+fn env_param() -> Ident {
+    Sym::intern("env#")
+}
+
+fn struct_type(name: Ident) -> Type {
+    Type::RefType(RefType::Struct(name))
+}
+
+fn block(e: Expr) -> Expr {
+    match e {
+        e @ Expr::Block(..) => e,
+        e => Expr::Block(Vec::new(), Box::new(Some(e))),
+    }
+}
+
+struct Converter<'a> {
+    res: &'a Resolution,
+    typed: &'a Typed,
+    funcs: HashMap<Ident, &'a FuncDef>,
+    // 値として使った関数の包み
+    refs: HashMap<Ident, Ident>,
+    lifted: Vec<Member>,
+    lambdas: usize,
+    next_id: ExprId,
+}
+
+impl<'a> Converter<'a> {
+    fn func_ref(&mut self, f: Ident) -> Expr {
+        let r = Sym::intern(&format!("{}#ref", f.as_str()));
+        self.refs.insert(r, f);
+        Expr::Closure(r, Box::new(None))
+    }
+
+    fn lambda(&mut self, id: ExprId, params: Vec<(Ident, Type)>, ret: Type, body: Expr) -> Expr {
+        let n = self.lambdas;
+        self.lambdas += 1;
+        let name = Sym::intern(&format!("lambda#{}", n));
+        let binds = self.res.binds.get(&id).map_or(&[][..], |ds| &ds[..]);
+        let caps = &binds[..binds.len() - params.len()];
+        let mut fields = Vec::new();
+        let mut values = Vec::new();
+        for &d in caps {
+            let x = self.res.decls[d].name;
+            let t = match &self.typed.decls[d] {
+                Ty::Type(t) => t.clone(),
+                t => unreachable!("{:?}", t),
+            };
+            fields.push((x, t));
+            // 外側のラムダがキャプチャした変数はその環境から読む
+            let value = match self.res.decls[d].kind {
+                DeclKind::Capture(outer)
+                    if matches!(self.res.decls[outer].kind, DeclKind::Capture(_)) =>
+                {
+                    Expr::Member(Box::new(Expr::Var(env_param())), x)
+                }
+                _ => Expr::Var(x),
+            };
+            values.push((x, value));
+        }
+        let (env_type, env) = if caps.is_empty() {
+            (env_param(), None)
+        } else {
+            let env_name = Sym::intern(&format!("env#{}", n));
+            self.lifted.push(Member::Struct(env_name, fields));
+            (env_name, Some(Expr::StructLiteral(env_name, values)))
+        };
+        let params = std::iter::once((env_param(), struct_type(env_type)))
+            .chain(params)
+            .collect();
+        let def = FuncDef(name, params, Some(ret));
+        self.lifted.push(Member::Func(def, block(body), None));
+        Expr::Closure(name, Box::new(env))
+    }
+
+    fn convert(&mut self, id: ExprId, e: Expr) -> Expr {
+        match e {
+            Expr::Var(x) => match self.res.uses.get(&id).map(|d| self.res.decls[*d].kind) {
+                Some(DeclKind::Capture(_)) => Expr::Member(Box::new(Expr::Var(env_param())), x),
+                Some(DeclKind::Func) | Some(DeclKind::ExternFun) => self.func_ref(x),
+                _ => Expr::Var(x),
+            },
+            // 関数を直接呼ぶ場合は包みを使わない
+            Expr::Call(f, args) => match *f {
+                Expr::Closure(r, env) if env.is_none() && self.refs.contains_key(&r) => {
+                    Expr::Call(Box::new(Expr::Var(self.refs[&r])), args)
+                }
+                f => Expr::CallIndirect(Box::new(f), args),
+            },
+            Expr::Lambda(_, params, ret, body) => self.lambda(id, params, ret, *body),
+            e => e,
+        }
+    }
+}
+
+impl<'a> Fold for Converter<'a> {
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        let e = fold::walk_expr(self, e);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.convert(id, e)
+    }
+}
+
+// Expr::Closureの関数を出現順に集める
+struct Table(Vec<Ident>);
+
+impl Visitor for Table {
+    fn visit_expr(&mut self, e: &Expr) {
+        if let Expr::Closure(f, _) = e {
+            if !self.0.contains(f) {
+                self.0.push(*f);
+            }
+        }
+        visit::walk_expr(self, e);
+    }
+}
+
+// ラムダをトップレベルの関数に持ち上げ、関数の値を関数表の添字と環境の組で表す
+// 関数表の関数は第1引数で環境を受け取り、関数の値の呼び出しはCallIndirectになる
+pub fn convert(module: &Module, res: &Resolution, typed: &Typed) -> Converted {
+    let mut c = Converter {
+        res,
+        typed,
+        funcs: HashMap::new(),
+        refs: HashMap::new(),
+        lifted: Vec::new(),
+        lambdas: 0,
+        next_id: 0,
+    };
+    for m in module {
+        if let Member::Func(def, _, _) | Member::ExternFun(def, _, _) = m {
+            c.funcs.entry(def.0).or_insert(def);
+        }
+    }
+    let mut out = c.fold_module(module.clone());
+    out.append(&mut c.lifted);
+
+    let mut table = Table(Vec::new());
+    table.visit_module(&out);
+    for (r, f) in table
+        .0
+        .iter()
+        .filter_map(|r| c.refs.get(r).map(|f| (*r, *f)))
+    {
+        let def = c.funcs[&f];
+        let args = def.1.iter().map(|(x, _)| Expr::Var(*x)).collect();
+        let body = Expr::Call(Box::new(Expr::Var(f)), args);
+        let params = std::iter::once((env_param(), struct_type(env_param())))
+            .chain(def.1.iter().cloned())
+            .collect();
+        out.push(Member::Func(
+            FuncDef(r, params, def.2.clone()),
+            block(body),
+            None,
+        ));
+    }
+    // 環境を持たない関数の第1引数の型
+    let uses_shared = out.iter().any(|m| match m {
+        Member::Func(FuncDef(_, params, _), _, _) => {
+            params.first().map(|(x, t)| (*x, t)) == Some((env_param(), &struct_type(env_param())))
+        }
+        _ => false,
+    });
+    if uses_shared {
+        out.push(Member::Struct(env_param(), Vec::new()));
+    }
+    Converted {
+        module: out,
+        table: table.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use ast::printer::print_module;
+
+    fn text(src: &str, span: Span) -> String {
+        src.chars().skip(span.pos).take(span.len).collect()
+    }
+
+    #[test]
+    fn check_captures_test() {
+        let src = r#"
+            fun g(): i32 { 1 }
+            fun f(a: i32, b: i32) {
+                let h = \[a](x: i32): i32 -> x + b + g();
+                let k = \[a, b](): i32 -> { let a = 1; a };
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let errors = check_captures(&m, &spans)
+            .into_iter()
+            .map(|e| (e.kind, text(src, e.span)))
+            .collect::<Vec<_>>();
+        let h = r"\[a](x: i32): i32 -> x + b + g()";
+        let k = r"\[a, b](): i32 -> { let a = 1; a }";
+        assert_eq!(
+            errors,
+            vec![
+                (CaptureErrorKind::Missing(Sym::intern("b")), h.to_string()),
+                (CaptureErrorKind::Unused(Sym::intern("a")), h.to_string()),
+                (CaptureErrorKind::Unused(Sym::intern("a")), k.to_string()),
+                (CaptureErrorKind::Unused(Sym::intern("b")), k.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn convert_test() {
+        let src = r#"
+            fun twice(f: fun(i32): i32, x: i32): i32 { f(f(x)) }
+            fun inc(x: i32): i32 { x + 1 }
+            fun main(): i32 {
+                let n = 10;
+                let add = \[n](x: i32): i32 -> {
+                    let g = \[n](y: i32): i32 -> n + y;
+                    g(x)
+                };
+                let k = \(): i32 -> 1;
+                twice(add, inc(k())) + twice(inc, 0)
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        assert_eq!(check_captures(&m, &spans), vec![]);
+        assert_eq!(typed.errors, vec![]);
+        let out = convert(&m, &res, &typed);
+        assert_eq!(
+            print_module(&out.module),
+            r#"fun twice(f: fun(i32): i32, x: i32): i32 {
+    f(f(x))
+}
+
+fun inc(x: i32): i32 {
+    x + 1
+}
+
+fun main(): i32 {
+    let n = 10;
+    let add = closure(lambda#1, env#1 { n: n });
+    let k = closure(lambda#2);
+    twice(add, inc(k())) + twice(closure(inc#ref), 0)
+}
+
+struct env#0 {
+    n: i32,
+}
+
+fun lambda#0(env#: env#0, y: i32): i32 {
+    env#.n + y
+}
+
+struct env#1 {
+    n: i32,
+}
+
+fun lambda#1(env#: env#1, x: i32): i32 {
+    let g = closure(lambda#0, env#0 { n: env#.n });
+    g(x)
+}
+
+fun lambda#2(env#: env#): i32 {
+    1
+}
+
+fun inc#ref(env#: env#, x: i32): i32 {
+    inc(x)
+}
+
+struct env# {}
+"#
+        );
+        assert_eq!(
+            out.table,
+            ["lambda#1", "lambda#2", "inc#ref", "lambda#0"]
+                .iter()
+                .map(|x| Sym::intern(x))
+                .collect::<Vec<_>>()
+        );
+
+        // 変換後も名前解決と型検査を通る
+        let spans = Spans::default();
+        let res = resolve(&out.module, &spans);
+        assert_eq!(res.errors, vec![]);
+        let typed = check(&out.module, &spans, &res);
+        assert_eq!(typed.errors, vec![]);
+    }
+}
@@ -0,0 +1,1304 @@
+use crate::cast::{check_bitcast, check_cast};
+use crate::checks::{site, site_arg, trap_code, CheckKind};
+use crate::decls::{Decls, TypeDecl};
+use crate::export::{func_indices, Exports};
+use crate::global::{assign_op, bin_op, lower_globals_with, BinOp, Global};
+use crate::layout::{
+    elems_offset, load, size_of, store, value_type, TypeLayout, CLOSURE_ENV, CLOSURE_FUNC,
+    CLOSURE_SIZE, LENGTH_SIZE, TRAP_CODE,
+};
+use crate::resolve::{DeclId, DeclKind, Resolution};
+use crate::runtime::{runtime, Descriptors, Gc, ALLOC, FRAME, PAGE_SIZE, ROOT, STACK};
+use crate::typeck::{Sizes, Ty, Typed};
+use ast::ast::{Expr, FuncDef, Ident, Member, Module, RefType, Type};
+use ast::span::ExprId;
+use ast::visit::{self, Visitor};
+use std::collections::HashMap;
+use token::intern::Sym;
+use wasm::ast::*;
+
+pub const MEMORY_EXPORT: &str = "memory";
+
+// Expr::Closureの関数を出現順に集めて関数表とし、文字列リテラルを集める
+#[derive(Default)]
+struct Collect {
+    table: Vec<Ident>,
+    strings: Vec<Sym>,
+}
+
+impl Visitor for Collect {
+    fn visit_expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Closure(f, _) if !self.table.contains(f) => self.table.push(*f),
+            Expr::StringLiteral(s) if !self.strings.contains(s) => self.strings.push(*s),
+            _ => {}
+        }
+        visit::walk_expr(self, e);
+    }
+}
+
+fn func_type(def: &FuncDef) -> FuncType {
+    FuncType {
+        params: def.1.iter().map(|(_, t)| value_type(t)).collect(),
+        result: def.2.as_ref().map(value_type),
+    }
+}
+
+fn i32_load(offset: u32) -> OperatorCode {
+    load(&Type::I32, offset)
+}
+
+fn i32_store(offset: u32) -> OperatorCode {
+    store(&Type::I32, offset)
+}
+
+// 整数は符号付きとして扱う
+fn bin_opcode(op: BinOp, t: &Type) -> OperatorCode {
+    use OperatorCode::*;
+    match t {
+        Type::I64 => match op {
+            BinOp::Add => I64Add,
+            BinOp::Sub => I64Sub,
+            BinOp::Mul => I64Mul,
+            BinOp::Div => I64Divs,
+            BinOp::Mod => I64Rems,
+            BinOp::BitAnd => I64And,
+            BinOp::BitOr => I64Or,
+            BinOp::BitXor => I64Xor,
+            BinOp::Shl => I64Shl,
+            BinOp::Shr => I64Shrs,
+            BinOp::UShr => I64Shru,
+            BinOp::Eq => I64Eq,
+            BinOp::Ne => I64Ne,
+            BinOp::Lt => I64Lts,
+            BinOp::Lte => I64Les,
+            BinOp::Gt => I64Gts,
+            BinOp::Gte => I64Ges,
+        },
+        Type::F32 => match op {
+            BinOp::Add => F32Add,
+            BinOp::Sub => F32Sub,
+            BinOp::Mul => F32Mul,
+            BinOp::Div => F32Div,
+            BinOp::Eq => F32Eq,
+            BinOp::Ne => F32Ne,
+            BinOp::Lt => F32Lt,
+            BinOp::Lte => F32Le,
+            BinOp::Gt => F32Gt,
+            BinOp::Gte => F32Ge,
+            _ => unreachable!(),
+        },
+        Type::F64 => match op {
+            BinOp::Add => F64Add,
+            BinOp::Sub => F64Sub,
+            BinOp::Mul => F64Mul,
+            BinOp::Div => F64Div,
+            BinOp::Eq => F64Eq,
+            BinOp::Ne => F64Ne,
+            BinOp::Lt => F64Lt,
+            BinOp::Lte => F64Le,
+            BinOp::Gt => F64Gt,
+            BinOp::Gte => F64Ge,
+            _ => unreachable!(),
+        },
+        // i32, bool, char
+        _ => match op {
+            BinOp::Add => I32Add,
+            BinOp::Sub => I32Sub,
+            BinOp::Mul => I32Mul,
+            BinOp::Div => I32Divs,
+            BinOp::Mod => I32Rems,
+            BinOp::BitAnd => I32And,
+            BinOp::BitOr => I32Or,
+            BinOp::BitXor => I32Xor,
+            BinOp::Shl => I32Shl,
+            BinOp::Shr => I32Shrs,
+            BinOp::UShr => I32Shru,
+            BinOp::Eq => I32Eq,
+            BinOp::Ne => I32Ne,
+            BinOp::Lt => I32Lts,
+            BinOp::Lte => I32Les,
+            BinOp::Gt => I32Gts,
+            BinOp::Gte => I32Ges,
+        },
+    }
+}
+
+// 構造化制御命令が作るラベル
+#[derive(Clone, Copy, PartialEq)]
+enum Label {
+    Other,
+    Break(ExprId),
+    Continue(ExprId),
+}
+
+struct Gen<'a> {
+    res: &'a Resolution,
+    typed: &'a Typed,
+    decls: Decls<'a>,
+    sizes: Sizes,
+    funcs: HashMap<Ident, usize>,
+    globals: HashMap<Ident, usize>,
+    strings: HashMap<Sym, u32>,
+    table: HashMap<Ident, usize>,
+    types: Vec<FuncType>,
+    gc: Gc,
+    // 実行時ライブラリの最初の関数
+    runtime: usize,
+    // シャドウスタックの高さを持つグローバル変数
+    stack: usize,
+    descs: Descriptors,
+    // 関数ごとの状態
+    params: usize,
+    locals: Vec<ValueType>,
+    vars: HashMap<DeclId, usize>,
+    temps: Vec<usize>,
+    labels: Vec<Label>,
+    codes: Vec<OperatorCode>,
+    // シャドウスタックの枠の先頭と、参照型のローカル変数の枠の番号
+    fp: usize,
+    slots: HashMap<usize, u32>,
+}
+
+impl<'a> Gen<'a> {
+    fn type_index(&mut self, t: FuncType) -> usize {
+        match self.types.iter().position(|x| *x == t) {
+            Some(i) => i,
+            None => {
+                self.types.push(t);
+                self.types.len() - 1
+            }
+        }
+    }
+
+    fn ty(&self, id: ExprId) -> &'a Ty {
+        &self.typed.exprs[id]
+    }
+
+    fn typ(&self, id: ExprId) -> &'a Type {
+        match self.ty(id) {
+            Ty::Type(t) => t,
+            t => unreachable!("{:?}", t),
+        }
+    }
+
+    fn has_value(&self, id: ExprId) -> bool {
+        matches!(self.ty(id), Ty::Type(_))
+    }
+
+    fn block_type(&self, id: ExprId) -> BlockType {
+        match self.ty(id) {
+            Ty::Type(t) => BlockType(Some(value_type(t))),
+            _ => BlockType(None),
+        }
+    }
+
+    fn emit(&mut self, op: OperatorCode) {
+        self.codes.push(op);
+    }
+
+    fn local(&mut self, t: ValueType) -> usize {
+        self.locals.push(t);
+        self.params + self.locals.len() - 1
+    }
+
+    fn var(&mut self, d: DeclId) -> usize {
+        if let Some(&l) = self.vars.get(&d) {
+            return l;
+        }
+        let t = match &self.typed.decls[d] {
+            Ty::Type(t) => t,
+            t => unreachable!("{:?}", t),
+        };
+        let l = self.local(value_type(t));
+        self.vars.insert(d, l);
+        self.slot(l, t);
+        l
+    }
+
+    fn slot(&mut self, l: usize, t: &Type) {
+        if self.gc != Gc::Off && matches!(t, Type::RefType(_)) {
+            self.slots.insert(l, self.slots.len() as u32);
+        }
+    }
+
+    fn set_local(&mut self, l: usize) {
+        self.emit(OperatorCode::SetLocal(l));
+        self.store_slot(l);
+    }
+
+    // 参照型の変数は枠にも書き込む
+    fn store_slot(&mut self, l: usize) {
+        if let Some(&slot) = self.slots.get(&l) {
+            self.emit(OperatorCode::GetLocal(self.fp));
+            self.emit(OperatorCode::GetLocal(l));
+            self.emit(i32_store(slot * 4));
+        }
+    }
+
+    fn runtime(&self, f: usize) -> OperatorCode {
+        OperatorCode::Call(self.runtime + f)
+    }
+
+    // シャドウスタックの高さを覚えておき、文の終わりに戻す
+    fn save_stack(&mut self) -> Option<usize> {
+        if self.gc == Gc::Off {
+            return None;
+        }
+        let m = self.temp(ValueType::I32);
+        self.emit(OperatorCode::GetGlobal(self.stack));
+        self.emit(OperatorCode::SetLocal(m));
+        Some(m)
+    }
+
+    fn restore_stack(&mut self, m: Option<usize>) {
+        if let Some(m) = m {
+            self.emit(OperatorCode::GetLocal(m));
+            self.emit(OperatorCode::SetGlobal(self.stack));
+        }
+    }
+
+    fn leave_frame(&mut self) {
+        if self.gc != Gc::Off {
+            self.emit(OperatorCode::GetLocal(self.fp));
+            self.emit(OperatorCode::SetGlobal(self.stack));
+        }
+    }
+
+    // 一時変数は使い終わったら同じ型の別の用途に使い回す
+    fn temp(&mut self, t: ValueType) -> usize {
+        let params = self.params;
+        match self
+            .temps
+            .iter()
+            .position(|&l| self.locals[l - params] == t)
+        {
+            Some(i) => self.temps.swap_remove(i),
+            None => self.local(t),
+        }
+    }
+
+    fn free(&mut self, l: usize) {
+        self.temps.push(l);
+    }
+
+    fn depth(&self, label: Label) -> usize {
+        let i = self.labels.iter().rposition(|l| *l == label).unwrap();
+        self.labels.len() - 1 - i
+    }
+
+    fn block(&mut self, op: OperatorCode, label: Label) {
+        self.emit(op);
+        self.labels.push(label);
+    }
+
+    fn end(&mut self) {
+        self.emit(OperatorCode::End);
+        self.labels.pop();
+    }
+
+    fn field(&self, name: Ident, f: Ident) -> (u32, &'a Type) {
+        let decl = &self.decls.types[&name];
+        let fields = match decl {
+            TypeDecl::Struct(fields) | TypeDecl::Union(fields) => fields,
+            TypeDecl::Enum(_) => unreachable!(),
+        };
+        let i = fields.iter().position(|(x, _)| *x == f).unwrap();
+        (TypeLayout::new(decl).field(i), &fields[i].1)
+    }
+
+    fn struct_name(&self, id: ExprId) -> Ident {
+        match self.typ(id) {
+            Type::RefType(RefType::Struct(name)) => *name,
+            t => unreachable!("{:?}", t),
+        }
+    }
+
+    // 値を残さない式として評価する
+    fn stmt(&mut self, e: &Expr, id: ExprId) {
+        self.expr(e, id);
+        if self.has_value(id) {
+            self.emit(OperatorCode::Drop);
+        }
+    }
+
+    fn branch(&mut self, e: &Expr, id: ExprId, value: bool) {
+        if value {
+            self.expr(e, id);
+        } else {
+            self.stmt(e, id);
+        }
+    }
+
+    // 確保したオブジェクトはすぐにルートにする
+    fn alloc(&mut self, size: u32, desc: u32) -> usize {
+        self.emit(OperatorCode::I32Const(size as i32));
+        self.emit(OperatorCode::I32Const(desc as i32));
+        self.call_alloc();
+        let p = self.temp(ValueType::I32);
+        self.emit(OperatorCode::SetLocal(p));
+        p
+    }
+
+    fn call_alloc(&mut self) {
+        self.emit(self.runtime(ALLOC));
+        if self.gc != Gc::Off {
+            self.emit(self.runtime(ROOT));
+        }
+    }
+
+    // 確保したオブジェクトのoffsetにeを書き込む
+    fn init(&mut self, p: usize, t: &Type, offset: u32, e: &Expr, id: ExprId) {
+        self.emit(OperatorCode::GetLocal(p));
+        self.expr(e, id);
+        self.emit(store(t, offset));
+    }
+
+    // 検査の符号を書き込んでトラップする
+    fn trap(&mut self, site: i32, kind: CheckKind) {
+        use OperatorCode::*;
+        self.emit(I32Const(TRAP_CODE as i32));
+        self.emit(I32Const(trap_code(site, kind)));
+        self.emit(i32_store(0));
+        self.emit(Unreachable);
+    }
+
+    // 積まれた条件が成り立てばトラップする
+    fn trap_if(&mut self, site: i32, kind: CheckKind) {
+        self.block(OperatorCode::If(BlockType(None)), Label::Other);
+        self.trap(site, kind);
+        self.end();
+    }
+
+    // 積まれた配列か文字列と添字を、長さの範囲を検査して積み直す
+    fn check_bounds(&mut self, site: i32) {
+        use OperatorCode::*;
+        let i = self.temp(ValueType::I32);
+        let a = self.temp(ValueType::I32);
+        self.emit(SetLocal(i));
+        self.emit(SetLocal(a));
+        // 負の添字は符号なしで比べると長さ以上になる
+        self.emit(GetLocal(i));
+        self.emit(GetLocal(a));
+        self.emit(i32_load(0));
+        self.emit(I32Geu);
+        self.trap_if(site, CheckKind::OutOfBounds);
+        self.emit(GetLocal(a));
+        self.emit(GetLocal(i));
+        self.free(a);
+        self.free(i);
+    }
+
+    // 積まれた被除数を、検査の印で包んだ除数bで割る
+    fn divide(&mut self, op: BinOp, t: &Type, b: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        let (b, site) = site(b).unwrap();
+        self.expr(b, site_arg(id));
+        let y = self.temp(value_type(t));
+        let x = self.temp(value_type(t));
+        self.emit(SetLocal(y));
+        self.emit(SetLocal(x));
+        self.emit(GetLocal(y));
+        self.emit(if *t == Type::I64 { I64Eqz } else { I32Eqz });
+        self.trap_if(site, CheckKind::DivisionByZero);
+        // 剰余は溢れない
+        if matches!(op, BinOp::Div) {
+            let (min, minus_one, eq) = match t {
+                Type::I64 => (I64Const(i64::MIN), I64Const(-1), I64Eq),
+                _ => (I32Const(i32::MIN), I32Const(-1), I32Eq),
+            };
+            self.emit(GetLocal(x));
+            self.emit(min);
+            self.emit(eq.clone());
+            self.emit(GetLocal(y));
+            self.emit(minus_one);
+            self.emit(eq);
+            self.emit(I32And);
+            self.trap_if(site, CheckKind::Overflow);
+        }
+        self.emit(GetLocal(x));
+        self.emit(GetLocal(y));
+        self.emit(bin_opcode(op, t));
+        self.free(x);
+        self.free(y);
+    }
+
+    // 積まれた左辺の値と右辺で演算する
+    fn apply(&mut self, op: BinOp, t: &Type, rhs: &Expr, id: ExprId) {
+        match site(rhs) {
+            Some(_) => self.divide(op, t, rhs, id),
+            None => {
+                self.expr(rhs, id);
+                self.emit(bin_opcode(op, t));
+            }
+        }
+    }
+
+    fn literal(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        let ids = self.sizes.children(e, id);
+        match e {
+            Expr::StructLiteral(name, fields) => {
+                let decl = &self.decls.types[name];
+                let desc = self.descs.decl(decl);
+                let p = self.alloc(TypeLayout::new(decl).size(), desc);
+                for ((f, x), id) in fields.iter().zip(ids) {
+                    let (offset, t) = self.field(*name, *f);
+                    self.init(p, t, offset, x, id);
+                }
+                self.emit(GetLocal(p));
+                self.free(p);
+            }
+            Expr::EnumLiteral(name, variant, args) => {
+                let layout = match TypeLayout::new(&self.decls.types[name]) {
+                    TypeLayout::Enum(layout) => layout,
+                    _ => unreachable!(),
+                };
+                let (tag, types) = self.decls.variant(*name, *variant).unwrap();
+                let desc = self.descs.decl(&self.decls.types[name]);
+                let p = self.alloc(layout.size, desc);
+                self.emit(GetLocal(p));
+                self.emit(I32Const(tag as i32));
+                self.emit(i32_store(0));
+                let offsets = &layout.variants[tag].offsets;
+                for (((t, offset), x), id) in types.iter().zip(offsets).zip(args).zip(ids) {
+                    self.init(p, t, *offset, x, id);
+                }
+                self.emit(GetLocal(p));
+                self.free(p);
+            }
+            Expr::ArrayLiteral(t, len) => {
+                let n = self.temp(ValueType::I32);
+                self.expr(len, id - 1);
+                self.emit(SetLocal(n));
+                self.emit(I32Const(elems_offset(t) as i32));
+                self.emit(GetLocal(n));
+                self.emit(I32Const(size_of(t) as i32));
+                self.emit(I32Mul);
+                self.emit(I32Add);
+                let desc = self.descs.array(t);
+                self.emit(I32Const(desc as i32));
+                self.call_alloc();
+                let p = self.temp(ValueType::I32);
+                self.emit(TeeLocal(p));
+                self.emit(GetLocal(n));
+                self.emit(i32_store(0));
+                self.emit(GetLocal(p));
+                self.free(p);
+                self.free(n);
+            }
+            Expr::Closure(f, env) => {
+                let desc = self.descs.closure();
+                let p = self.alloc(CLOSURE_SIZE, desc);
+                self.emit(GetLocal(p));
+                self.emit(I32Const(self.table[f] as i32));
+                self.emit(i32_store(CLOSURE_FUNC));
+                self.emit(GetLocal(p));
+                match &**env {
+                    Some(env) => self.expr(env, id - 1),
+                    None => self.emit(I32Const(0)),
+                }
+                self.emit(i32_store(CLOSURE_ENV));
+                self.emit(GetLocal(p));
+                self.free(p);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn var_get(&mut self, id: ExprId) {
+        let d = self.res.uses[&id];
+        let decl = &self.res.decls[d];
+        match decl.kind {
+            DeclKind::Global | DeclKind::Const => {
+                self.emit(OperatorCode::GetGlobal(self.globals[&decl.name]))
+            }
+            _ => {
+                let l = self.var(d);
+                self.emit(OperatorCode::GetLocal(l));
+            }
+        }
+    }
+
+    fn var_set(&mut self, id: ExprId) {
+        let d = self.res.uses[&id];
+        let decl = &self.res.decls[d];
+        match decl.kind {
+            DeclKind::Global => self.emit(OperatorCode::SetGlobal(self.globals[&decl.name])),
+            _ => {
+                let l = self.var(d);
+                self.set_local(l);
+            }
+        }
+    }
+
+    // 代入先のアドレスを積み、読み書きに使う型とオフセットを返す
+    fn address(&mut self, lhs: &Expr, id: ExprId) -> (&'a Type, u32) {
+        use OperatorCode::*;
+        match lhs {
+            Expr::Member(x, f) => {
+                self.expr(x, id - 1);
+                let (offset, t) = self.field(self.struct_name(id - 1), *f);
+                (t, offset)
+            }
+            Expr::Index(a, i) => {
+                let ids = self.sizes.children(lhs, id);
+                self.expr(a, ids[0]);
+                match site(i) {
+                    Some((i, site)) => {
+                        self.expr(i, site_arg(ids[1]));
+                        self.check_bounds(site);
+                    }
+                    None => self.expr(i, ids[1]),
+                }
+                match self.typ(ids[0]) {
+                    Type::RefType(RefType::Array(t)) => {
+                        self.emit(I32Const(size_of(t) as i32));
+                        self.emit(I32Mul);
+                        self.emit(I32Add);
+                        (t, elems_offset(t))
+                    }
+                    // 文字列はバイト単位で読み、符号無しで拡張する
+                    _ => {
+                        self.emit(I32Add);
+                        (&Type::Bool, LENGTH_SIZE)
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn call(&mut self, e: &Expr, id: ExprId) {
+        let (f, args) = match e {
+            Expr::Call(f, args) => (f, args),
+            _ => unreachable!(),
+        };
+        let ids = self.sizes.children(e, id);
+        for (x, id) in args.iter().zip(&ids[1..]) {
+            self.expr(x, *id);
+        }
+        match &**f {
+            Expr::Var(name) => self.emit(OperatorCode::Call(self.funcs[name])),
+            f => panic!("{:?}", f),
+        }
+    }
+
+    // 閉包の環境を第1引数として関数表の関数を呼ぶ
+    fn call_indirect(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        let (f, args) = match e {
+            Expr::CallIndirect(f, args) => (f, args),
+            _ => unreachable!(),
+        };
+        let ids = self.sizes.children(e, id);
+        let t = match self.typ(ids[0]) {
+            Type::RefType(RefType::Func(params, ret)) => FuncType {
+                params: std::iter::once(ValueType::I32)
+                    .chain(params.iter().map(value_type))
+                    .collect(),
+                result: ret.as_ref().as_ref().map(value_type),
+            },
+            t => unreachable!("{:?}", t),
+        };
+        let c = self.temp(ValueType::I32);
+        self.expr(f, ids[0]);
+        self.emit(SetLocal(c));
+        self.emit(GetLocal(c));
+        self.emit(i32_load(CLOSURE_ENV));
+        for (x, id) in args.iter().zip(&ids[1..]) {
+            self.expr(x, *id);
+        }
+        self.emit(GetLocal(c));
+        self.emit(i32_load(CLOSURE_FUNC));
+        let t = self.type_index(t);
+        self.emit(CallIndirect(t));
+        self.free(c);
+    }
+
+    // armsは条件と本体の組とそれぞれのID、elsは最後の子
+    fn if_chain(&mut self, id: ExprId, arms: &[(&(Expr, Expr), &[ExprId])], els: &Option<Expr>) {
+        let value = self.has_value(id);
+        match arms.split_first() {
+            Some((((cond, then), ids), rest)) => {
+                self.expr(cond, ids[0]);
+                self.block(OperatorCode::If(self.block_type(id)), Label::Other);
+                self.branch(then, ids[1], value);
+                if !rest.is_empty() || els.is_some() {
+                    self.emit(OperatorCode::Else);
+                    self.if_chain(id, rest, els);
+                }
+                self.end();
+            }
+            None => {
+                if let Some(els) = els {
+                    self.branch(els, id - 1, value);
+                }
+            }
+        }
+    }
+
+    // idsは対象の式と各腕の本体のID
+    fn match_arms(
+        &mut self,
+        id: ExprId,
+        p: usize,
+        arms: &[(Ident, Vec<Ident>, Expr)],
+        ids: &[ExprId],
+        binds: &[DeclId],
+        default: &Option<Expr>,
+    ) {
+        use OperatorCode::*;
+        let value = self.has_value(id);
+        let ((variant, xs, body), rest) = match arms.split_first() {
+            Some(arm) => arm,
+            None => {
+                match default {
+                    Some(default) => self.branch(default, id - 1, value),
+                    // 網羅的なmatchの残りには来ない
+                    None if value => self.emit(Unreachable),
+                    None => {}
+                }
+                return;
+            }
+        };
+        let name = self.struct_name(ids[0]);
+        let layout = match TypeLayout::new(&self.decls.types[&name]) {
+            TypeLayout::Enum(layout) => layout,
+            _ => unreachable!(),
+        };
+        let (tag, types) = self.decls.variant(name, *variant).unwrap();
+        self.emit(GetLocal(p));
+        self.emit(i32_load(0));
+        self.emit(I32Const(tag as i32));
+        self.emit(I32Eq);
+        self.block(If(self.block_type(id)), Label::Other);
+        let offsets = &layout.variants[tag].offsets;
+        for ((t, offset), d) in types.iter().zip(offsets).zip(&binds[..xs.len()]) {
+            self.emit(GetLocal(p));
+            self.emit(load(t, *offset));
+            let l = self.var(*d);
+            self.set_local(l);
+        }
+        self.branch(body, ids[1], value);
+        self.emit(Else);
+        let ids = [&ids[..1], &ids[2..]].concat();
+        self.match_arms(id, p, rest, &ids, &binds[xs.len()..], default);
+        self.end();
+    }
+
+    fn control(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        match e {
+            Expr::Block(stmts, last) => {
+                let m = if stmts.is_empty() {
+                    None
+                } else {
+                    self.save_stack()
+                };
+                let ids = self.sizes.children(e, id);
+                for (x, id) in stmts.iter().zip(ids) {
+                    self.stmt(x, id);
+                    self.restore_stack(m);
+                }
+                if let Some(x) = &**last {
+                    self.expr(x, id - 1);
+                }
+                if let Some(m) = m {
+                    self.free(m);
+                }
+            }
+            Expr::If(first, elifs, els) => {
+                let ids = self.sizes.children(e, id);
+                let arms = std::iter::once(&**first)
+                    .chain(elifs)
+                    .zip(ids.chunks(2))
+                    .collect::<Vec<_>>();
+                self.if_chain(id, &arms, els);
+            }
+            // block { loop { br_if (!cond) 1; body; br 0 } }
+            Expr::While(cond, body) => {
+                let m = self.save_stack();
+                self.block(Block(BlockType(None)), Label::Break(id));
+                self.block(Loop(BlockType(None)), Label::Continue(id));
+                self.restore_stack(m);
+                let ids = self.sizes.children(e, id);
+                self.expr(cond, ids[0]);
+                self.emit(I32Eqz);
+                self.emit(BrIf(1));
+                self.stmt(body, ids[1]);
+                self.emit(Br(0));
+                self.end();
+                self.end();
+                if let Some(m) = m {
+                    self.free(m);
+                }
+            }
+            Expr::Labeled(_, x) => self.expr(x, id - 1),
+            Expr::Return(x) => {
+                if let Some(x) = &**x {
+                    self.expr(x, id - 1);
+                }
+                self.leave_frame();
+                self.emit(Return);
+            }
+            Expr::Break(_) => {
+                let depth = self.depth(Label::Break(self.res.loops[&id]));
+                self.emit(Br(depth));
+            }
+            Expr::Continue(_) => {
+                let depth = self.depth(Label::Continue(self.res.loops[&id]));
+                self.emit(Br(depth));
+            }
+            Expr::Match(x, arms, default) => {
+                let p = self.temp(ValueType::I32);
+                let ids = self.sizes.children(e, id);
+                self.expr(x, ids[0]);
+                self.emit(SetLocal(p));
+                let binds = self.res.binds.get(&id).map_or(&[][..], |ds| &ds[..]);
+                self.match_arms(id, p, arms, &ids, binds, default);
+                self.free(p);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn assign(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        match e {
+            Expr::Let(_, x) => {
+                self.expr(x, id - 1);
+                let l = self.var(self.res.binds[&id][0]);
+                self.set_local(l);
+            }
+            Expr::Set(lhs, rhs) => {
+                let ids = self.sizes.children(e, id);
+                match &**lhs {
+                    Expr::Var(_) => {
+                        self.expr(rhs, ids[1]);
+                        self.var_set(ids[0]);
+                    }
+                    _ => {
+                        let (t, offset) = self.address(lhs, ids[0]);
+                        self.expr(rhs, ids[1]);
+                        self.emit(store(t, offset));
+                    }
+                }
+            }
+            Expr::CompoundSet(op, lhs, rhs) => {
+                let op = assign_op(*op).unwrap();
+                let ids = self.sizes.children(e, id);
+                match &**lhs {
+                    Expr::Var(_) => {
+                        self.var_get(ids[0]);
+                        self.apply(op, self.typ(ids[0]), rhs, ids[1]);
+                        self.var_set(ids[0]);
+                    }
+                    _ => {
+                        // アドレスを一度だけ計算する
+                        let (t, offset) = self.address(lhs, ids[0]);
+                        let a = self.temp(ValueType::I32);
+                        self.emit(TeeLocal(a));
+                        self.emit(GetLocal(a));
+                        self.emit(load(t, offset));
+                        self.apply(op, t, rhs, ids[1]);
+                        self.emit(store(t, offset));
+                        self.free(a);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn operator(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        if let Some((op, a, b)) = bin_op(e) {
+            let ids = self.sizes.children(e, id);
+            self.expr(a, ids[0]);
+            if let (Some(_), Ty::Type(t)) = (site(b), self.ty(id)) {
+                self.divide(op, t, b, ids[1]);
+                return;
+            }
+            self.expr(b, ids[1]);
+            // 被演算子が戻ってこない場合は演算に到達しない
+            match (self.ty(ids[0]), self.ty(ids[1])) {
+                (Ty::Type(t), _) | (_, Ty::Type(t)) => self.emit(bin_opcode(op, t)),
+                _ => {}
+            }
+            return;
+        }
+        match e {
+            Expr::Not(x) => {
+                self.expr(x, id - 1);
+                self.emit(I32Eqz);
+            }
+            Expr::Plus(x) => self.expr(x, id - 1),
+            Expr::Minus(x) => match self.typ(id - 1) {
+                Type::I64 => {
+                    self.emit(I64Const(0));
+                    self.expr(x, id - 1);
+                    self.emit(I64Sub);
+                }
+                Type::F32 => {
+                    self.expr(x, id - 1);
+                    self.emit(F32Neg);
+                }
+                Type::F64 => {
+                    self.expr(x, id - 1);
+                    self.emit(F64Neg);
+                }
+                _ => {
+                    self.emit(I32Const(0));
+                    self.expr(x, id - 1);
+                    self.emit(I32Sub);
+                }
+            },
+            Expr::And(a, b) => {
+                let ids = self.sizes.children(e, id);
+                self.expr(a, ids[0]);
+                self.block(If(BlockType(Some(ValueType::I32))), Label::Other);
+                self.expr(b, ids[1]);
+                self.emit(Else);
+                self.emit(I32Const(0));
+                self.end();
+            }
+            Expr::Or(a, b) => {
+                let ids = self.sizes.children(e, id);
+                self.expr(a, ids[0]);
+                self.block(If(BlockType(Some(ValueType::I32))), Label::Other);
+                self.emit(I32Const(1));
+                self.emit(Else);
+                self.expr(b, ids[1]);
+                self.end();
+            }
+            Expr::Cast(x, to) | Expr::Bitcast(x, to) => {
+                self.expr(x, id - 1);
+                let from = self.typ(id - 1);
+                if from != to {
+                    let cast = match e {
+                        Expr::Cast(..) => check_cast(from, to),
+                        _ => check_bitcast(from, to),
+                    };
+                    for op in cast.unwrap().codes() {
+                        self.emit(op);
+                    }
+                }
+            }
+            Expr::Member(x, f) => {
+                self.expr(x, id - 1);
+                let (offset, t) = self.field(self.struct_name(id - 1), *f);
+                self.emit(load(t, offset));
+            }
+            Expr::Index(..) => {
+                let (t, offset) = self.address(e, id);
+                self.emit(load(t, offset));
+            }
+            e => panic!("{:?}", e),
+        }
+    }
+
+    fn expr(&mut self, e: &Expr, id: ExprId) {
+        use OperatorCode::*;
+        match e {
+            Expr::I32Literal(x) => self.emit(I32Const(*x)),
+            Expr::I64Literal(x) => self.emit(I64Const(*x)),
+            Expr::F32Literal(x) => self.emit(F32Const(*x)),
+            Expr::F64Literal(x) => self.emit(F64Const(*x)),
+            Expr::BoolLiteral(x) => self.emit(I32Const(*x as i32)),
+            Expr::CharLiteral(x) => self.emit(I32Const(*x as i32)),
+            Expr::StringLiteral(x) => self.emit(I32Const(self.strings[x] as i32)),
+            Expr::Var(_) => self.var_get(id),
+            Expr::StructLiteral(..)
+            | Expr::EnumLiteral(..)
+            | Expr::ArrayLiteral(..)
+            | Expr::Closure(..) => self.literal(e, id),
+            Expr::Call(..) => self.call(e, id),
+            Expr::CallIndirect(..) => self.call_indirect(e, id),
+            Expr::Block(..)
+            | Expr::If(..)
+            | Expr::While(..)
+            | Expr::Labeled(..)
+            | Expr::Return(_)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Match(..) => self.control(e, id),
+            Expr::Let(..) | Expr::Set(..) | Expr::CompoundSet(..) => self.assign(e, id),
+            _ => self.operator(e, id),
+        }
+        // 計算途中の参照をルートにする
+        if self.gc != Gc::Off
+            && !matches!(
+                e,
+                Expr::StringLiteral(_)
+                    | Expr::StructLiteral(..)
+                    | Expr::EnumLiteral(..)
+                    | Expr::ArrayLiteral(..)
+                    | Expr::Closure(..)
+            )
+            && matches!(self.ty(id), Ty::Type(Type::RefType(_)))
+        {
+            self.emit(self.runtime(ROOT));
+        }
+        // 戻ってこない式の後は値の有無を問わないようにする
+        if self.ty(id) == &Ty::Never
+            && !matches!(
+                self.codes.last(),
+                Some(Unreachable) | Some(Br(_)) | Some(Return)
+            )
+        {
+            self.emit(Unreachable);
+        }
+    }
+
+    fn function(
+        &mut self,
+        def: &FuncDef,
+        body: &Expr,
+        id: ExprId,
+        params: &[DeclId],
+    ) -> FunctionBody {
+        self.params = params.len();
+        self.locals = Vec::new();
+        self.vars = params.iter().enumerate().map(|(i, d)| (*d, i)).collect();
+        self.temps = Vec::new();
+        self.labels = Vec::new();
+        self.codes = Vec::new();
+        self.slots = HashMap::new();
+        // 枠の数は本体を生成した後に決まる
+        let frame = self.codes.len();
+        if self.gc != Gc::Off {
+            self.fp = self.local(ValueType::I32);
+            self.emit(OperatorCode::I32Const(0));
+            self.emit(self.runtime(FRAME));
+            self.emit(OperatorCode::SetLocal(self.fp));
+            for (i, d) in params.iter().enumerate() {
+                if let Ty::Type(t) = &self.typed.decls[*d] {
+                    self.slot(i, t);
+                    self.store_slot(i);
+                }
+            }
+        }
+        self.branch(body, id, def.2.is_some());
+        self.leave_frame();
+        self.emit(OperatorCode::End);
+        if self.gc != Gc::Off {
+            self.codes[frame] = OperatorCode::I32Const(self.slots.len() as i32);
+        }
+        let mut locals: Vec<LocalEntry> = Vec::new();
+        for t in std::mem::take(&mut self.locals) {
+            match locals.last_mut() {
+                Some(entry) if entry.typ == t => entry.count += 1,
+                _ => locals.push(LocalEntry { count: 1, typ: t }),
+            }
+        }
+        FunctionBody {
+            locals,
+            codes: std::mem::take(&mut self.codes),
+        }
+    }
+}
+
+// クロージャ変換と脱糖を済ませたモジュールをwasmのモジュールにする
+// 文字列, 配列, 構造体, 列挙型, 閉包は線形メモリに置き、その値はポインタになる
+pub fn codegen(
+    module: &Module,
+    res: &Resolution,
+    typed: &Typed,
+    globals: &[Global],
+    exports: Exports,
+    gc: Gc,
+) -> WasmASTRoot {
+    let mut collect = Collect::default();
+    collect.visit_module(module);
+    let (lowered, strings) = lower_globals_with(globals, &collect.strings);
+    let funcs = func_indices(module);
+    let runtime_funcs = funcs.len();
+    let runtime_globals = lowered.global_section.0.len();
+    let mut g = Gen {
+        res,
+        typed,
+        decls: Decls::new(module),
+        sizes: Sizes::new(module),
+        table: collect
+            .table
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (*f, i))
+            .collect(),
+        funcs,
+        globals: lowered.indices.clone(),
+        strings,
+        types: Vec::new(),
+        gc,
+        runtime: runtime_funcs,
+        stack: runtime_globals + STACK,
+        descs: Descriptors::new(lowered.heap_base),
+        params: 0,
+        locals: Vec::new(),
+        vars: HashMap::new(),
+        temps: Vec::new(),
+        labels: Vec::new(),
+        codes: Vec::new(),
+        fp: 0,
+        slots: HashMap::new(),
+    };
+
+    let mut imports = Vec::new();
+    for member in module {
+        if let Member::ExternFun(def, m, f) = member {
+            imports.push(ImportEntry {
+                module: m.clone(),
+                field: f.clone(),
+                kind: ExternalKindImport::Function(g.type_index(func_type(def))),
+            });
+        }
+    }
+    let mut sigs = Vec::new();
+    let mut bodies = Vec::new();
+    for (i, member) in module.iter().enumerate() {
+        if let Member::Func(def, body, _) = member {
+            sigs.push(g.type_index(func_type(def)));
+            bodies.push(g.function(def, body, g.sizes.member(i), &res.members[i][1..]));
+        }
+    }
+    let roots = globals
+        .iter()
+        .enumerate()
+        .filter(|(_, global)| matches!(global.value.typ(), Type::RefType(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let rt = runtime(gc, runtime_funcs, runtime_globals, roots, g.descs.end());
+    for (t, body) in rt.funcs {
+        sigs.push(g.type_index(t));
+        bodies.push(body);
+    }
+
+    let mut global_section = lowered.global_section;
+    global_section.0.extend(rt.globals);
+    let mut data_section = lowered.data_section;
+    if g.descs.end() > lowered.heap_base {
+        data_section.0.push(g.descs.segment());
+    }
+    let pages = rt.heap_base.div_ceil(PAGE_SIZE) + 1;
+    let mut export_section = exports.export_section;
+    export_section.0.push(ExportEntry {
+        field: MEMORY_EXPORT.to_string(),
+        kind: ExternalKind::Memory,
+        index: 0,
+    });
+    let (table_section, element_section) = if collect.table.is_empty() {
+        (None, None)
+    } else {
+        let n = collect.table.len() as i32;
+        let table = TableType {
+            element_type: ElemType::AnyFunc,
+            limits: ResizableLimits {
+                initial: n,
+                maximum: Some(n),
+            },
+        };
+        let elems = ElemSegment {
+            offset: InitExpr::I32(0),
+            elems: collect.table.iter().map(|f| g.funcs[f]).collect(),
+        };
+        (
+            Some(TableSection(vec![table])),
+            Some(ElementSection(vec![elems])),
+        )
+    };
+
+    WasmASTRoot {
+        type_section: Some(TypeSection(g.types)),
+        import_section: Some(ImportSection(imports)),
+        function_section: Some(FunctionSection(sigs)),
+        table_section,
+        memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+            initial: pages as i32,
+            maximum: None,
+        })])),
+        global_section: Some(global_section),
+        export_section: Some(export_section),
+        start_section: exports.start_section,
+        element_section,
+        code_section: Some(CodeSection(bodies)),
+        data_section: Some(data_section),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{exports, Entry};
+    use crate::global::eval_globals;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use ast::span::Spans;
+    use OperatorCode::*;
+
+    fn gen(src: &str) -> WasmASTRoot {
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        assert_eq!(typed.errors, vec![]);
+        let globals = eval_globals(&m).unwrap();
+        codegen(
+            &m,
+            &res,
+            &typed,
+            &globals,
+            exports(&m, Entry::Start).unwrap(),
+            Gc::Off,
+        )
+    }
+
+    #[test]
+    fn codegen_test() {
+        let wasm = gen(r#"
+            struct P { b: bool, x: F64 }
+            extern "env" "log" fun log(x: F64);
+            let count = 0;
+            export fun f(p: P, n: i32): i32 {
+                while (n > 0) {
+                    if (p.b) { break; } else {};
+                    n -= 1;
+                    count += 1;
+                };
+                log(p.x);
+                n
+            }
+        "#);
+        let m = |flags, offset| MemoryImmediate { flags, offset };
+        assert_eq!(
+            wasm.import_section,
+            Some(ImportSection(vec![ImportEntry {
+                module: "env".to_string(),
+                field: "log".to_string(),
+                kind: ExternalKindImport::Function(0),
+            }]))
+        );
+        // 実行時ライブラリの関数が後ろに続く
+        assert_eq!(
+            wasm.function_section,
+            Some(FunctionSection(vec![1, 1, 2, 3, 3, 3, 2, 4, 4, 3]))
+        );
+        let body = &wasm.code_section.as_ref().unwrap().0[0];
+        assert_eq!(body.locals, vec![]);
+        assert_eq!(
+            body.codes,
+            vec![
+                Block(BlockType(None)),
+                Loop(BlockType(None)),
+                GetLocal(1),
+                I32Const(0),
+                I32Gts,
+                I32Eqz,
+                BrIf(1),
+                GetLocal(0),
+                I32Load8u(m(0, 0)),
+                If(BlockType(None)),
+                Br(2),
+                Else,
+                End,
+                GetLocal(1),
+                I32Const(1),
+                I32Sub,
+                SetLocal(1),
+                GetGlobal(0),
+                I32Const(1),
+                I32Add,
+                SetGlobal(0),
+                Br(0),
+                End,
+                End,
+                GetLocal(0),
+                F64Load(m(3, 8)),
+                Call(0),
+                GetLocal(1),
+                End,
+            ]
+        );
+        // ユーザーのグローバル変数の後にヒープの先頭が続き、ヒープはシャドウスタックの後ろから始まる
+        assert_eq!(
+            wasm.global_section.unwrap().0[1],
+            GlobalVariable(
+                GlobalType {
+                    content_type: ValueType::I32,
+                    mutability: true,
+                },
+                InitExpr::I32((8 + crate::runtime::SHADOW_STACK_SIZE) as i32)
+            )
+        );
+    }
+
+    #[test]
+    fn closure_table_test() {
+        let (m, spans) = parse_with_spans(
+            r#"
+            fun f(): i32 {
+                let c = \(x: i32): i32 -> x;
+                c(1)
+            }
+        "#,
+        )
+        .unwrap();
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        let m = crate::closure::convert(&m, &res, &typed).module;
+        let spans = Spans::default();
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        let wasm = codegen(
+            &m,
+            &res,
+            &typed,
+            &[],
+            exports(&m, Entry::Start).unwrap(),
+            Gc::Off,
+        );
+        assert_eq!(
+            wasm.element_section,
+            Some(ElementSection(vec![ElemSegment {
+                offset: InitExpr::I32(0),
+                elems: vec![1],
+            }]))
+        );
+        let m = |offset| MemoryImmediate { flags: 2, offset };
+        let body = &wasm.code_section.as_ref().unwrap().0[0];
+        assert_eq!(
+            body.codes,
+            vec![
+                I32Const(8),
+                // 閉包の型記述子
+                I32Const(8),
+                Call(2),
+                SetLocal(0),
+                GetLocal(0),
+                I32Const(0),
+                I32Store(m(0)),
+                GetLocal(0),
+                I32Const(0),
+                I32Store(m(4)),
+                GetLocal(0),
+                SetLocal(1),
+                GetLocal(1),
+                SetLocal(0),
+                GetLocal(0),
+                I32Load(m(4)),
+                I32Const(1),
+                GetLocal(0),
+                I32Load(m(0)),
+                CallIndirect(1),
+                End,
+            ]
+        );
+        assert_eq!(
+            body.locals,
+            vec![LocalEntry {
+                count: 2,
+                typ: ValueType::I32,
+            }]
+        );
+    }
+}
@@ -0,0 +1,677 @@
+use crate::checks::insert_checks;
+use crate::closure::{check_captures, convert, CaptureError};
+use crate::codegen::{codegen, MEMORY_EXPORT};
+use crate::decls::{check_type_decls, DeclError};
+use crate::desugar::desugar;
+use crate::export::{exports, Entry, ExportError};
+use crate::global::{eval_globals, ConstError};
+use crate::layout::RUNTIME_MODULE;
+use crate::optimize::{optimize, OptLevel};
+use crate::prune::prune;
+use crate::resolve::{resolve, Resolution, ResolveError};
+use crate::runtime::Gc;
+use crate::typeck::{check, TypeError, Typed};
+use ast::ast::Module;
+use ast::span::{Span, Spans};
+use wasm::ast::WasmASTRoot;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    // 添字の範囲と整数の除数を実行時に検査する
+    Debug,
+    Release,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Options {
+    pub entry: Entry,
+    pub opt_level: OptLevel,
+    pub gc: Gc,
+    pub profile: Profile,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            entry: Entry::Start,
+            opt_level: OptLevel::O1,
+            gc: Gc::On,
+            profile: Profile::Debug,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileError {
+    Decl(Vec<DeclError>),
+    Resolve(Vec<ResolveError>),
+    Type(Vec<TypeError>),
+    Capture(Vec<CaptureError>),
+    Const(Vec<ConstError>),
+    Export(Vec<ExportError>),
+    // 変換後のモジュールが検査を通らなかった。コンパイラの誤り
+    Internal(String),
+}
+
+// 変換後のモジュールは位置情報を持たず、検査を通ることは各変換が保証する
+fn analyze(module: &Module) -> Result<(Resolution, Typed), CompileError> {
+    let spans = Spans::default();
+    let res = resolve(module, &spans);
+    if !res.errors.is_empty() {
+        return Err(CompileError::Internal(format!("{:?}", res.errors)));
+    }
+    let typed = check(module, &spans, &res);
+    if !typed.errors.is_empty() {
+        return Err(CompileError::Internal(format!("{:?}", typed.errors)));
+    }
+    Ok((res, typed))
+}
+
+pub fn compile(
+    module: &Module,
+    spans: &Spans,
+    options: &Options,
+) -> Result<WasmASTRoot, CompileError> {
+    compile_with_sites(module, spans, options).map(|(wasm, _)| wasm)
+}
+
+// 実行時検査の符号の番号から元の式の位置を引く表も返す
+pub fn compile_with_sites(
+    module: &Module,
+    spans: &Spans,
+    options: &Options,
+) -> Result<(WasmASTRoot, Vec<Span>), CompileError> {
+    let errors = check_type_decls(module);
+    if !errors.is_empty() {
+        return Err(CompileError::Decl(errors));
+    }
+    let res = resolve(module, spans);
+    if !res.errors.is_empty() {
+        return Err(CompileError::Resolve(res.errors));
+    }
+    let typed = check(module, spans, &res);
+    if !typed.errors.is_empty() {
+        return Err(CompileError::Type(typed.errors));
+    }
+    let errors = check_captures(module, spans);
+    if !errors.is_empty() {
+        return Err(CompileError::Capture(errors));
+    }
+
+    let (module, sites) = match options.profile {
+        Profile::Debug => {
+            let checked = insert_checks(module, spans, &typed);
+            (checked.module, checked.sites)
+        }
+        Profile::Release => (module.clone(), Vec::new()),
+    };
+    let globals = eval_globals(&module).map_err(CompileError::Const)?;
+    let (_, typed) = analyze(&module)?;
+    let module = prune(&module, &typed);
+    let (res, typed) = analyze(&module)?;
+    let converted = convert(&module, &res, &typed).module;
+    let (_, typed) = analyze(&converted)?;
+    let desugared = desugar(&converted, &typed);
+    let module = optimize(&desugared, options.opt_level);
+    let (res, typed) = analyze(&module)?;
+
+    let exports = exports(&module, options.entry).map_err(CompileError::Export)?;
+    // 線形メモリのエクスポートと名前が重なる
+    if exports
+        .export_section
+        .0
+        .iter()
+        .any(|e| e.field == MEMORY_EXPORT)
+    {
+        let error = ExportError::DuplicateExport(MEMORY_EXPORT.to_string());
+        return Err(CompileError::Export(vec![error]));
+    }
+    let wasm = codegen(&module, &res, &typed, &globals, exports, options.gc);
+    Ok((wasm, sites))
+}
+
+// ホストが"runtime"モジュールとして用意しなければならない関数の名前(layout.rsを参照)
+pub fn runtime_imports(wasm: &WasmASTRoot) -> Vec<&str> {
+    wasm.import_section
+        .iter()
+        .flat_map(|s| &s.0)
+        .filter(|e| e.module == RUNTIME_MODULE)
+        .map(|e| e.field.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{Host, Interpreter, Value};
+    use crate::layout::RUNTIME_POW;
+    use ast::parser::parse_with_spans;
+    use wasm::interp::{Imports, Instance, Val};
+
+    fn imports() -> Imports {
+        let mut imports = Imports::new();
+        imports.register(RUNTIME_MODULE, RUNTIME_POW, |_, args| match args {
+            [Val::F64(a), Val::F64(b)] => Some(Val::F64(a.powf(*b))),
+            _ => unreachable!(),
+        });
+        imports
+    }
+
+    fn to_val(v: &Value) -> Option<Val> {
+        match v {
+            Value::Unit => None,
+            Value::I32(x) => Some(Val::I32(*x)),
+            Value::I64(x) => Some(Val::I64(*x)),
+            Value::F32(x) => Some(Val::F32(*x)),
+            Value::F64(x) => Some(Val::F64(*x)),
+            Value::Bool(x) => Some(Val::I32(*x as i32)),
+            Value::Char(x) => Some(Val::I32(*x as i32)),
+            v => panic!("{:?}", v),
+        }
+    }
+
+    fn text(src: &str, span: Span) -> String {
+        src.chars().skip(span.pos).take(span.len).collect()
+    }
+
+    // 拡張を使わないMVPのwasmとして検証する
+    fn validate(bytes: &[u8]) {
+        let features = wasmparser::WasmFeatures {
+            mutable_global: false,
+            saturating_float_to_int: false,
+            sign_extension: false,
+            reference_types: false,
+            multi_value: false,
+            bulk_memory: false,
+            simd: false,
+            relaxed_simd: false,
+            threads: false,
+            tail_call: false,
+            floats: true,
+            multi_memory: false,
+            exceptions: false,
+            memory64: false,
+            extended_const: false,
+            component_model: false,
+            function_references: false,
+            memory_control: false,
+            gc: false,
+            component_model_values: false,
+            component_model_nested_names: false,
+        };
+        wasmparser::Validator::new_with_features(features)
+            .validate_all(bytes)
+            .unwrap();
+    }
+
+    // トラップの種類は比べない
+    fn show<E>(r: Result<Option<Val>, E>) -> String {
+        match r {
+            Ok(v) => format!("{:?}", v),
+            Err(_) => "trap".to_string(),
+        }
+    }
+
+    #[test]
+    fn differential_test() {
+        let src = r#"
+            struct P { x: i32, y: F64, ok: bool }
+            enum Shape { Circle(F64), Rect(F64, F64), Empty() }
+            union U { i: i32, f: F32 }
+            const N = 10;
+            const GREETING = "héllo";
+            let counter = 0i64;
+            let scale = 1.5;
+
+            fun fib(n: i32): i32 { if (n < 2) n else fib(n - 1) + fib(n - 2) }
+
+            fun area(s: Shape): F64 {
+                match (s) { Circle(r) => 3.0 * r * r, Rect(w, h) => w * h, _ => 0.0 }
+            }
+
+            fun apply(f: fun(i32): i32, x: i32): i32 { f(x) }
+
+            export fun shapes(k: i32): F64 {
+                let s = if (k == 0) Shape::Circle(2.0) elif (k == 1) Shape::Rect(2.0, 3.5) else Shape::Empty();
+                area(s) * scale
+            }
+
+            export fun points(n: i32): i32 {
+                let ps = [P; n];
+                for (let i = 0; i < n; i += 1) {
+                    ps[i] = P { x: i * i, y: i as F64 / 2.0, ok: i % 2 == 0 };
+                };
+                let s = 0;
+                for (let i = 0; i < n; i += 1) {
+                    let p = ps[i];
+                    p.x += 1;
+                    if (p.ok && p.y < 3.0) { s += p.x; } elif (!p.ok || false) { s -= 1; };
+                };
+                s + ps[n - 1].x
+            }
+
+            export fun loops(): i32 {
+                let s = 0;
+                outer: for (let i = 0; i < N; i += 1) {
+                    let j = 0;
+                    while (true) {
+                        j += 1;
+                        if (j > i) { continue outer; };
+                        if (i * j > 20) { break outer; };
+                        s += i * j;
+                    };
+                };
+                s
+            }
+
+            export fun strings(i: i32): i32 {
+                let c = GREETING[i];
+                if (c == 'h' as i32) 100 else c
+            }
+
+            export fun unions(x: F32): i32 {
+                let u = U { f: x };
+                let bits = u.i;
+                u.i = bits ^ -2147483647 - 1;
+                (u.f == -x) as i32 + ((bits >>> 23) & 255)
+            }
+
+            export fun closures(n: i32): i32 {
+                let add = \[n](x: i32): i32 -> x + n;
+                let twice = \(f: fun(i32): i32, x: i32): i32 -> f(f(x));
+                twice(add, 1) + apply(fib, 10) + apply(add, 0)
+            }
+
+            export fun counters(k: i64): i64 {
+                counter += k;
+                counter ** 2i64 + ((counter as i32) ** -1) as i64
+            }
+
+            export fun floats(x: F64): F64 {
+                let y = x % 0.75;
+                y %= 0.3;
+                x ** 0.5 + y + (x as F32 / 3.0f32) as F64
+            }
+
+            export fun ints(a: i32, b: i32): i32 {
+                let x = a as i64 * 1000000007i64;
+                (x >> 3i64) as i32 + a / b + a % b + (a << 30) + (a >>> 1) + -a + +b
+            }
+
+            export fun chars(c: char): bool { c >= 'a' && c <= 'z' }
+
+            export fun heap(n: i32): i64 {
+                let s = 0i64;
+                for (let k = 0; k < 4; k += 1) {
+                    let xs = [i64; n];
+                    for (let i = 0; i < n; i += 1) { xs[i] = (i * k) as i64; };
+                    s += xs[n - 1] + xs[n / 2];
+                };
+                s
+            }
+
+            export fun diverges(c: i32): i32 {
+                let x = { return c; };
+                0
+            }
+
+            // 戻ってこない式の後ろは型を持たないまま残る
+            export fun unreachables(c: i32): i32 {
+                if (c > 0) {
+                    let y = if (c > 1) { return 1; } else { return 2; };
+                    let f = \[y](): i32 -> y + 1;
+                    return f() + y;
+                };
+                let z = ({ return -c; }).x + -({ return c; });
+                z + 1
+            }
+        "#;
+        let calls = vec![
+            ("shapes", vec![Value::I32(0)]),
+            ("shapes", vec![Value::I32(1)]),
+            ("shapes", vec![Value::I32(2)]),
+            ("points", vec![Value::I32(1)]),
+            ("points", vec![Value::I32(9)]),
+            ("loops", vec![]),
+            ("strings", vec![Value::I32(0)]),
+            ("strings", vec![Value::I32(1)]),
+            ("strings", vec![Value::I32(2)]),
+            ("unions", vec![Value::F32(1.5)]),
+            ("closures", vec![Value::I32(7)]),
+            ("counters", vec![Value::I64(3)]),
+            ("counters", vec![Value::I64(-4)]),
+            ("floats", vec![Value::F64(2.0)]),
+            ("floats", vec![Value::F64(-5.25)]),
+            ("ints", vec![Value::I32(-7), Value::I32(2)]),
+            ("ints", vec![Value::I32(i32::MIN), Value::I32(-1)]),
+            ("ints", vec![Value::I32(1), Value::I32(0)]),
+            ("chars", vec![Value::Char('q')]),
+            ("chars", vec![Value::Char('Q')]),
+            // 初めのページに収まらないので線形メモリを広げる
+            ("heap", vec![Value::I32(20000)]),
+            ("diverges", vec![Value::I32(42)]),
+            ("unreachables", vec![Value::I32(1)]),
+            ("unreachables", vec![Value::I32(2)]),
+            ("unreachables", vec![Value::I32(-3)]),
+        ];
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let mut it = Interpreter::new(&m, &spans, &res, Host::new());
+        let expected = calls
+            .iter()
+            .map(|(f, args)| show(it.call(f, args.clone()).map(|v| to_val(&v))))
+            .collect::<Vec<_>>();
+        assert_eq!(expected[5], "Some(I32(115))");
+        assert_eq!(expected[17], "trap");
+        assert_eq!(expected[21], "Some(I32(42))");
+
+        // 範囲外の添字を読まないので検査を省いても結果は同じ
+        let configs = [
+            (OptLevel::O0, Gc::On, Profile::Debug),
+            (OptLevel::O1, Gc::On, Profile::Debug),
+            (OptLevel::O2, Gc::On, Profile::Debug),
+            (OptLevel::O1, Gc::Off, Profile::Debug),
+            (OptLevel::O1, Gc::Stress, Profile::Debug),
+            (OptLevel::O2, Gc::On, Profile::Release),
+        ];
+        for (opt_level, gc, profile) in configs {
+            let options = Options {
+                entry: Entry::Start,
+                opt_level,
+                gc,
+                profile,
+            };
+            let wasm = compile(&m, &spans, &options).unwrap();
+            validate(&wasm.to_bytes());
+            let mut inst = Instance::new(&wasm, imports()).unwrap();
+            let actual = calls
+                .iter()
+                .map(|(f, args)| {
+                    let args = args.iter().filter_map(to_val).collect::<Vec<_>>();
+                    show(inst.invoke(f, &args))
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(actual, expected, "{:?} {:?} {:?}", opt_level, gc, profile);
+        }
+    }
+
+    // 外部関数はABIに従って線形メモリ上の値を読む
+    #[test]
+    fn host_abi_test() {
+        use crate::layout::{elems_offset, LENGTH_SIZE};
+        use ast::ast::Type;
+        use std::convert::TryInto;
+        let src = r#"
+            struct P { tag: bool, x: F64 }
+            extern "env" "sum" fun sum(xs: [F64]): F64;
+            extern "env" "count" fun count(s: string, c: char): i32;
+            extern "env" "px" fun px(p: P): F64;
+            export fun run(n: i32): F64 {
+                let xs = [F64; n];
+                for (let i = 0; i < n; i += 1) { xs[i] = i as F64 * 0.5; };
+                sum(xs) + count("bañana", 'a') as F64 * 100.0 + px(P { tag: true, x: 7.0 })
+            }
+        "#;
+        let word = |mem: &[u8], p: usize| i32::from_le_bytes(mem[p..p + 4].try_into().unwrap());
+        let mut imports = Imports::new();
+        imports.register("env", "sum", move |mem, args| {
+            let p = match args {
+                [Val::I32(p)] => *p as usize,
+                _ => unreachable!(),
+            };
+            let base = p + elems_offset(&Type::F64) as usize;
+            let sum = (0..word(mem, p) as usize)
+                .map(|i| {
+                    f64::from_le_bytes(mem[base + i * 8..base + i * 8 + 8].try_into().unwrap())
+                })
+                .sum();
+            Some(Val::F64(sum))
+        });
+        imports.register("env", "count", move |mem, args| {
+            let (p, c) = match args {
+                [Val::I32(p), Val::I32(c)] => (*p as usize, char::from_u32(*c as u32).unwrap()),
+                _ => unreachable!(),
+            };
+            let base = p + LENGTH_SIZE as usize;
+            let s = std::str::from_utf8(&mem[base..base + word(mem, p) as usize]).unwrap();
+            Some(Val::I32(s.chars().filter(|x| *x == c).count() as i32))
+        });
+        imports.register("env", "px", |mem, args| {
+            let p = match args {
+                [Val::I32(p)] => *p as usize,
+                _ => unreachable!(),
+            };
+            assert_eq!(mem[p], 1);
+            Some(Val::F64(f64::from_le_bytes(
+                mem[p + 8..p + 16].try_into().unwrap(),
+            )))
+        });
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let wasm = compile(&m, &spans, &Options::default()).unwrap();
+        let mut inst = Instance::new(&wasm, imports).unwrap();
+        assert_eq!(
+            inst.invoke("run", &[Val::I32(4)]),
+            Ok(Some(Val::F64(310.0)))
+        );
+    }
+
+    #[test]
+    fn gc_test() {
+        let src = r#"
+            enum List { Cons(i32, List), Nil() }
+            union U { a: [i32], n: i32 }
+            let buf = [i32; 4];
+
+            fun sum(l: List): i32 { match (l) { Cons(x, rest) => x + sum(rest), _ => 0 } }
+
+            export fun churn(rounds: i32): i32 {
+                let l = List::Nil();
+                for (let i = 0; i < 100; i += 1) { l = List::Cons(i, l); };
+                let f = \[l](k: i32): i32 -> sum(l) + k;
+                let u = U { a: [i32; 3] };
+                u.a[1] = 7;
+                for (let r = 0; r < rounds; r += 1) {
+                    let garbage = [i32; 1000];
+                    garbage[r % 1000] = r;
+                    buf = [i32; 8];
+                    buf[0] = r;
+                };
+                f(buf[0]) + u.a[1]
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let mut it = Interpreter::new(&m, &spans, &res, Host::new());
+        assert_eq!(
+            it.call("churn", vec![Value::I32(200)]),
+            Ok(Value::I32(5156))
+        );
+
+        let mut pages = Vec::new();
+        for gc in [Gc::Off, Gc::On, Gc::Stress] {
+            let options = Options {
+                gc,
+                ..Options::default()
+            };
+            let wasm = compile(&m, &spans, &options).unwrap();
+            let mut inst = Instance::new(&wasm, imports()).unwrap();
+            let r = inst.invoke("churn", &[Val::I32(200)]);
+            assert_eq!(r, Ok(Some(Val::I32(5156))), "{:?}", gc);
+            pages.push(inst.memory().len() / wasm::interp::PAGE_SIZE);
+        }
+        // 回収すれば線形メモリはほとんど広がらない
+        assert!(pages[0] > 10, "{:?}", pages);
+        assert!(pages[1] <= 4 && pages[2] <= 4, "{:?}", pages);
+    }
+
+    #[test]
+    fn compile_error_test() {
+        let compile_src = |src: &str| {
+            let (m, spans) = parse_with_spans(src).unwrap();
+            compile(&m, &spans, &Options::default())
+        };
+        assert!(matches!(
+            compile_src("fun f(): i32 { true }"),
+            Err(CompileError::Type(_))
+        ));
+        assert!(matches!(
+            compile_src("fun f(): i32 { x }"),
+            Err(CompileError::Resolve(_))
+        ));
+        assert_eq!(
+            compile_src(r#"export "memory" fun f() {}"#),
+            Err(CompileError::Export(vec![ExportError::DuplicateExport(
+                "memory".to_string()
+            )]))
+        );
+        // 変換の誤りで型の合わないモジュールができても中断せずにエラーを返す
+        let (m, _) = parse_with_spans("fun f(): i32 { true }").unwrap();
+        assert!(matches!(analyze(&m), Err(CompileError::Internal(_))));
+    }
+
+    // 検査に失敗したトラップは符号から元の式の位置が分かる
+    #[test]
+    fn checks_test() {
+        use crate::checks::{failed_check, CheckKind};
+        let src = r#"
+            export fun get(n: i32, i: i32): i32 { let xs = [i32; n]; xs[i] }
+            export fun byte(i: i32): i32 { let s = "héllo"; s[i] }
+            export fun square(i: i32): i64 { let xs = [i64; 2]; xs[i] **= 2i64; xs[0] }
+            export fun div(a: i32, b: i32): i32 { a / b }
+            export fun rem(a: i64, b: i64): i64 { let x = a; x %= b; x }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let (wasm, sites) = compile_with_sites(&m, &spans, &Options::default()).unwrap();
+        let mut inst = Instance::new(&wasm, imports()).unwrap();
+        let cases = vec![
+            ("get", vec![Val::I32(3), Val::I32(2)], Ok(Some(Val::I32(0)))),
+            (
+                "get",
+                vec![Val::I32(3), Val::I32(3)],
+                Err((CheckKind::OutOfBounds, "xs[i]")),
+            ),
+            (
+                "get",
+                vec![Val::I32(3), Val::I32(-1)],
+                Err((CheckKind::OutOfBounds, "xs[i]")),
+            ),
+            ("byte", vec![Val::I32(2)], Ok(Some(Val::I32(0xa9)))),
+            (
+                "byte",
+                vec![Val::I32(6)],
+                Err((CheckKind::OutOfBounds, "s[i]")),
+            ),
+            (
+                "square",
+                vec![Val::I32(2)],
+                Err((CheckKind::OutOfBounds, "xs[i]")),
+            ),
+            (
+                "div",
+                vec![Val::I32(7), Val::I32(0)],
+                Err((CheckKind::DivisionByZero, "a / b")),
+            ),
+            (
+                "div",
+                vec![Val::I32(i32::MIN), Val::I32(-1)],
+                Err((CheckKind::Overflow, "a / b")),
+            ),
+            (
+                "rem",
+                vec![Val::I64(i64::MIN), Val::I64(-1)],
+                Ok(Some(Val::I64(0))),
+            ),
+            (
+                "rem",
+                vec![Val::I64(5), Val::I64(0)],
+                Err((CheckKind::DivisionByZero, "x %= b")),
+            ),
+        ];
+        for (f, args, expected) in cases {
+            let actual = inst
+                .invoke(f, &args)
+                .map_err(|_| failed_check(inst.memory(), &sites).unwrap())
+                .map_err(|(kind, span)| (kind, text(src, span)));
+            let expected = expected.map_err(|(kind, s)| (kind, s.to_string()));
+            assert_eq!(actual, expected, "{} {:?}", f, args);
+        }
+
+        // リリースでは検査を出力しない
+        let options = Options {
+            profile: Profile::Release,
+            ..Options::default()
+        };
+        let (wasm, sites) = compile_with_sites(&m, &spans, &options).unwrap();
+        assert_eq!(sites, vec![]);
+        let mut inst = Instance::new(&wasm, imports()).unwrap();
+        assert!(inst.invoke("get", &[Val::I32(3), Val::I32(3)]).is_ok());
+        assert!(inst.invoke("div", &[Val::I32(7), Val::I32(0)]).is_err());
+        assert_eq!(failed_check(inst.memory(), &sites), None);
+    }
+
+    // 浮動小数点数の剰余はランタイムに含め、累乗だけをホストから読み込む
+    #[test]
+    fn runtime_imports_test() {
+        let src = r#"
+            export fun rem(a: F64, b: F64): F64 { a % b }
+            export fun rem32(a: F32, b: F32): F32 { a % b }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let wasm = compile(&m, &spans, &Options::default()).unwrap();
+        assert_eq!(runtime_imports(&wasm), Vec::<&str>::new());
+        let mut inst = Instance::new(&wasm, Imports::new()).unwrap();
+        let cases = [
+            (5.5, 2.0),
+            (-5.5, 2.0),
+            (5.5, -2.0),
+            (-0.0, 3.0),
+            (1e300, 3.0),
+            (1e-310, 3e-320),
+            (f64::MAX, f64::MIN_POSITIVE),
+            (7.0, f64::INFINITY),
+            (0.1, 0.01),
+        ];
+        for (a, b) in cases.iter() {
+            let r = inst.invoke("rem", &[Val::F64(*a), Val::F64(*b)]);
+            match r {
+                Ok(Some(Val::F64(r))) => {
+                    assert_eq!(r.to_bits(), (a % b).to_bits(), "{} % {}", a, b)
+                }
+                r => panic!("{:?}", r),
+            }
+        }
+        for (a, b) in [(f64::NAN, 1.0), (f64::INFINITY, 1.0), (1.0, 0.0)].iter() {
+            let r = inst.invoke("rem", &[Val::F64(*a), Val::F64(*b)]);
+            assert!(matches!(r, Ok(Some(Val::F64(r))) if r.is_nan()), "{:?}", r);
+        }
+        let r = inst.invoke("rem32", &[Val::F32(7.5), Val::F32(-2.0)]);
+        assert_eq!(r, Ok(Some(Val::F32(1.5))));
+
+        let (m, spans) = parse_with_spans("export fun p(a: F64): F64 { a ** 0.5 }").unwrap();
+        let wasm = compile(&m, &spans, &Options::default()).unwrap();
+        assert_eq!(runtime_imports(&wasm), vec![RUNTIME_POW]);
+    }
+
+    // 大域変数の初期値の除算は検査せず、コンパイル時に評価する
+    #[test]
+    fn const_division_test() {
+        let src = r#"
+            const N = 2;
+            const C = 10 / N;
+            const D = 7 % -1 + 9 / -1;
+            let e = C / N;
+            export fun get(): i32 { C + D + e }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let (wasm, sites) = compile_with_sites(&m, &spans, &Options::default()).unwrap();
+        assert_eq!(sites, vec![]);
+        let mut inst = Instance::new(&wasm, imports()).unwrap();
+        assert_eq!(inst.invoke("get", &[]), Ok(Some(Val::I32(-2))));
+
+        let (m, spans) = parse_with_spans("const N = 0; const C = 1 / N;").unwrap();
+        assert!(matches!(
+            compile(&m, &spans, &Options::default()),
+            Err(CompileError::Const(_))
+        ));
+    }
+}
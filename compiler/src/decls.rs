@@ -0,0 +1,175 @@
+use ast::ast::{FuncDef, Ident, Member, Module, RefType, Type};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TypeDecl<'a> {
+    Struct(&'a [(Ident, Type)]),
+    Enum(&'a [(Ident, Vec<Type>)]),
+    Union(&'a [(Ident, Type)]),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeclError {
+    DuplicateType(Ident),
+    DuplicateField(Ident, Ident),
+    DuplicateVariant(Ident, Ident),
+    UnknownType(Ident, Ident),
+    EmptyUnion(Ident),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Decls<'a> {
+    pub types: HashMap<Ident, TypeDecl<'a>>,
+    pub funcs: HashMap<Ident, &'a FuncDef>,
+}
+
+impl<'a> Decls<'a> {
+    pub fn new(module: &'a Module) -> Decls<'a> {
+        let mut decls = Decls::default();
+        for member in module {
+            match member {
+                Member::Struct(name, fields) => {
+                    decls.types.entry(*name).or_insert(TypeDecl::Struct(fields));
+                }
+                Member::Enum(name, variants) => {
+                    decls.types.entry(*name).or_insert(TypeDecl::Enum(variants));
+                }
+                Member::Union(name, fields) => {
+                    decls.types.entry(*name).or_insert(TypeDecl::Union(fields));
+                }
+                Member::Func(def, _, _) | Member::ExternFun(def, _, _) => {
+                    decls.funcs.entry(def.0).or_insert(def);
+                }
+                Member::Let(_, _, _) | Member::Const(_, _, _) => {}
+            }
+        }
+        decls
+    }
+
+    pub fn field(&self, name: Ident, field: Ident) -> Option<&'a Type> {
+        match self.types.get(&name)? {
+            TypeDecl::Struct(fields) | TypeDecl::Union(fields) => {
+                fields.iter().find(|(x, _)| *x == field).map(|(_, t)| t)
+            }
+            TypeDecl::Enum(_) => None,
+        }
+    }
+
+    pub fn variant(&self, name: Ident, variant: Ident) -> Option<(usize, &'a [Type])> {
+        match self.types.get(&name)? {
+            TypeDecl::Enum(variants) => variants
+                .iter()
+                .position(|(x, _)| *x == variant)
+                .map(|i| (i, &variants[i].1[..])),
+            _ => None,
+        }
+    }
+
+    pub fn check_type(&self, t: &Type) -> Result<(), Ident> {
+        match t {
+            Type::RefType(RefType::Struct(name)) => {
+                if self.types.contains_key(name) {
+                    Ok(())
+                } else {
+                    Err(*name)
+                }
+            }
+            Type::RefType(RefType::Array(t)) => self.check_type(t),
+            Type::RefType(RefType::Func(params, ret)) => {
+                for t in params {
+                    self.check_type(t)?;
+                }
+                match &**ret {
+                    Some(t) => self.check_type(t),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_names<'a, I: Iterator<Item = &'a Ident>>(names: I) -> Vec<Ident> {
+    let mut seen = HashSet::new();
+    names.filter(|x| !seen.insert(**x)).cloned().collect()
+}
+
+pub fn check_type_decls(module: &Module) -> Vec<DeclError> {
+    let decls = Decls::new(module);
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for member in module {
+        let (name, types) = match member {
+            Member::Struct(name, fields) => {
+                for field in check_names(fields.iter().map(|(x, _)| x)) {
+                    errors.push(DeclError::DuplicateField(*name, field));
+                }
+                (name, fields.iter().map(|(_, t)| t).collect::<Vec<_>>())
+            }
+            Member::Union(name, fields) => {
+                if fields.is_empty() {
+                    errors.push(DeclError::EmptyUnion(*name));
+                }
+                for field in check_names(fields.iter().map(|(x, _)| x)) {
+                    errors.push(DeclError::DuplicateField(*name, field));
+                }
+                (name, fields.iter().map(|(_, t)| t).collect::<Vec<_>>())
+            }
+            Member::Enum(name, variants) => {
+                for variant in check_names(variants.iter().map(|(x, _)| x)) {
+                    errors.push(DeclError::DuplicateVariant(*name, variant));
+                }
+                (name, variants.iter().flat_map(|(_, ts)| ts).collect())
+            }
+            _ => continue,
+        };
+        if !seen.insert(*name) {
+            errors.push(DeclError::DuplicateType(*name));
+        }
+        for t in types {
+            if let Err(x) = decls.check_type(t) {
+                errors.push(DeclError::UnknownType(*name, x));
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parser::parse;
+    use token::intern::Sym;
+
+    #[test]
+    fn check_type_decls_test() {
+        let m = parse(
+            r#"
+            enum E { A(i32, P), B, A }
+            union U {}
+            union V { x: i32, x: Q }
+            struct P { e: E }
+            struct P { }
+        "#,
+        )
+        .unwrap();
+        let s = Sym::intern;
+        assert_eq!(
+            check_type_decls(&m),
+            vec![
+                DeclError::DuplicateVariant(s("E"), s("A")),
+                DeclError::EmptyUnion(s("U")),
+                DeclError::DuplicateField(s("V"), s("x")),
+                DeclError::UnknownType(s("V"), s("Q")),
+                DeclError::DuplicateType(s("P")),
+            ]
+        );
+
+        let decls = Decls::new(&m);
+        assert_eq!(decls.variant(s("E"), s("B")), Some((1, &[][..])));
+        assert_eq!(
+            decls.field(s("P"), s("e")),
+            Some(&Type::RefType(RefType::Struct(s("E"))))
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use crate::checks::site;
+use crate::layout::{RUNTIME_MODULE, RUNTIME_POW};
+use crate::typeck::{Ty, Typed};
+use ast::ast::{AssignOp, Expr, FuncDef, Ident, Member, Module, Type};
+use ast::fold::{self, Fold};
+use ast::parser::parse;
+use ast::span::ExprId;
+use ast::visit_mut::{self, VisitorMut};
+use token::intern::Sym;
+
+// 整数の累乗は繰り返し二乗法で、2の補数で折り返す
+// 負の指数は1/a^|b|を0方向に切り捨てた値とする
+const POW_INT: &str = r#"
+fun pow(a: T, b: T): T {
+    let r = 1S;
+    if (b < 0S) {
+        r = if (a == 1S) 1S else if (a == -1S) (if (b % 2S == 0S) 1S else -1S) else 0S;
+    } else {
+        while (b > 0S) {
+            if (b % 2S == 1S) { r *= a; } else {};
+            a *= a;
+            b /= 2S;
+        };
+    };
+    r
+}
+"#;
+
+// 浮動小数点数の剰余は、除数を2倍していった値を大きい方から引いて求める
+// どの引き算も丸めずに計算でき、結果は被除数と同じ符号を持つ
+const FMOD: &str = r#"
+fun fmod(a: F64, b: F64): F64 {
+    let x = if (a < 0.0) -a else a;
+    let y = if (b < 0.0) -b else b;
+    if (a - a != 0.0 || b != b || y == 0.0) {
+        0.0 / 0.0
+    } else if (x < y) {
+        a
+    } else {
+        let t = y;
+        while (t <= x / 2.0) {
+            t *= 2.0;
+        };
+        while (t >= y) {
+            if (x >= t) { x -= t; } else {};
+            t /= 2.0;
+        };
+        if (a < 0.0) -x else x
+    }
+}
+"#;
+
+// 演算子を置き換える補助関数
+#[derive(Clone, Copy, PartialEq)]
+enum Helper {
+    Pow,
+    // wasmには浮動小数点数の剰余が無い
+    Fmod,
+}
+
+fn helper_name(h: Helper, t: &Type) -> Ident {
+    Sym::intern(match (h, t) {
+        (Helper::Pow, Type::I32) => "pow#i32",
+        (Helper::Pow, Type::I64) => "pow#i64",
+        (Helper::Pow, _) => "pow#F64",
+        (Helper::Fmod, _) => "fmod#F64",
+    })
+}
+
+fn func(name: Ident, src: &str) -> Member {
+    match parse(src).unwrap().pop() {
+        Some(Member::Func(FuncDef(_, params, ret), body, None)) => {
+            Member::Func(FuncDef(name, params, ret), body, None)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn helper(h: Helper, t: &Type) -> Member {
+    let name = helper_name(h, t);
+    match (h, t) {
+        (Helper::Pow, Type::I32) | (Helper::Pow, Type::I64) => {
+            let (typ, suffix) = match t {
+                Type::I32 => ("i32", ""),
+                _ => ("i64", "i64"),
+            };
+            func(name, &POW_INT.replace('T', typ).replace('S', suffix))
+        }
+        (Helper::Fmod, _) => func(name, FMOD),
+        // 浮動小数点数の累乗はホストから読み込む
+        (Helper::Pow, _) => {
+            let a = Sym::intern("a");
+            let b = Sym::intern("b");
+            let def = FuncDef(name, vec![(a, Type::F64), (b, Type::F64)], Some(Type::F64));
+            Member::ExternFun(def, RUNTIME_MODULE.to_string(), RUNTIME_POW.to_string())
+        }
+    }
+}
+
+fn is_float(t: &Ty) -> bool {
+    matches!(t, Ty::Type(Type::F32) | Ty::Type(Type::F64))
+}
+
+// 対象のループへのcontinueを数え、書き換える場合は
+// continueを本体を囲むループからのbreakに、ラベル無しのbreakを外側のループへのbreakにする
+struct Jumps {
+    label: Option<Ident>,
+    rewrite: Option<(Ident, Ident)>,
+    depth: usize,
+    found: bool,
+}
+
+impl VisitorMut for Jumps {
+    fn visit_expr_mut(&mut self, e: &mut Expr) {
+        match e {
+            Expr::While(..) | Expr::For(..) => {
+                self.depth += 1;
+                visit_mut::walk_expr_mut(self, e);
+                self.depth -= 1;
+            }
+            // 関数の境界は越えない
+            Expr::Lambda(..) => {}
+            Expr::Continue(l)
+                if (l.is_none() && self.depth == 0) || (l.is_some() && *l == self.label) =>
+            {
+                self.found = true;
+                if let Some((cont, _)) = self.rewrite {
+                    *e = Expr::Break(Some(cont));
+                }
+            }
+            Expr::Break(None) if self.depth == 0 => {
+                if let Some((_, brk)) = self.rewrite {
+                    *e = Expr::Break(Some(brk));
+                }
+            }
+            _ => visit_mut::walk_expr_mut(self, e),
+        }
+    }
+}
+
+fn block(stmts: Vec<Expr>) -> Expr {
+    Expr::Block(stmts, Box::new(None))
+}
+
+fn labeled(label: Option<Ident>, e: Expr) -> Expr {
+    match label {
+        Some(l) => Expr::Labeled(l, Box::new(e)),
+        None => e,
+    }
+}
+
+struct Desugar<'a> {
+    typed: &'a Typed,
+    // forのラベル
+    label: Option<Ident>,
+    fresh: usize,
+    helpers: Vec<(Ident, Member)>,
+    next_id: ExprId,
+}
+
+impl<'a> Desugar<'a> {
+    fn fresh(&mut self, prefix: &str) -> Ident {
+        self.fresh += 1;
+        Sym::intern(&format!("{}#{}", prefix, self.fresh))
+    }
+
+    fn call(&mut self, h: Helper, t: &Ty, a: Expr, b: Expr) -> Expr {
+        let t = match t {
+            Ty::Type(t) => t,
+            t => panic!("{:?}", t),
+        };
+        let name = helper_name(h, t);
+        if self.helpers.iter().all(|(x, _)| *x != name) {
+            self.helpers.push((name, helper(h, t)));
+        }
+        let f = Box::new(Expr::Var(name));
+        match t {
+            Type::F32 => {
+                let a = Expr::Cast(Box::new(a), Type::F64);
+                let b = Expr::Cast(Box::new(b), Type::F64);
+                Expr::Cast(Box::new(Expr::Call(f, vec![a, b])), Type::F32)
+            }
+            _ => Expr::Call(f, vec![a, b]),
+        }
+    }
+
+    // `a **= b`と浮動小数点数の`a %= b`は左辺の式を一度だけ評価する
+    fn assign(&mut self, h: Helper, t: &Ty, lhs: Expr, rhs: Expr) -> Expr {
+        let mut stmts = Vec::new();
+        let mut temp = |d: &mut Desugar, e: Expr| {
+            let x = d.fresh("t");
+            stmts.push(Expr::Let(x, Box::new(e)));
+            Box::new(Expr::Var(x))
+        };
+        let place = match lhs {
+            Expr::Member(x, field) => Expr::Member(temp(self, *x), field),
+            Expr::Index(x, i) => {
+                let x = temp(self, *x);
+                // 検査の印は添字の外側に残す
+                match (site(&i).is_some(), *i) {
+                    (true, Expr::Call(f, mut args)) => {
+                        let v = args.remove(0);
+                        args.insert(0, *temp(self, v));
+                        Expr::Index(x, Box::new(Expr::Call(f, args)))
+                    }
+                    (_, i) => Expr::Index(x, temp(self, i)),
+                }
+            }
+            lhs => lhs,
+        };
+        let value = self.call(h, t, place.clone(), rhs);
+        let set = Expr::Set(Box::new(place), Box::new(value));
+        if stmts.is_empty() {
+            set
+        } else {
+            Expr::Block(stmts, Box::new(Some(set)))
+        }
+    }
+
+    fn for_loop(&mut self, init: Expr, cond: Expr, step: Expr, mut body: Expr) -> Expr {
+        let label = self.label.take();
+        let mut jumps = Jumps {
+            label,
+            rewrite: None,
+            depth: 0,
+            found: false,
+        };
+        jumps.visit_expr_mut(&mut body);
+        let cond = Box::new(cond);
+        let lp = if jumps.found {
+            // `continue`の後もstepを実行するため、本体を一度だけ回るループで囲む
+            let cont = self.fresh("continue");
+            let brk = label.unwrap_or_else(|| self.fresh("for"));
+            jumps.rewrite = Some((cont, brk));
+            jumps.visit_expr_mut(&mut body);
+            let once = Expr::While(
+                Box::new(Expr::BoolLiteral(true)),
+                Box::new(block(vec![body, Expr::Break(Some(cont))])),
+            );
+            let body = block(vec![Expr::Labeled(cont, Box::new(once)), step]);
+            Expr::Labeled(brk, Box::new(Expr::While(cond, Box::new(body))))
+        } else {
+            labeled(label, Expr::While(cond, Box::new(block(vec![body, step]))))
+        };
+        block(vec![init, lp])
+    }
+
+    fn if_chain(
+        &mut self,
+        first: (Expr, Expr),
+        elifs: Vec<(Expr, Expr)>,
+        els: Option<Expr>,
+    ) -> Expr {
+        // elseが無い場合は各分岐の値を捨てる
+        let discard = els.is_none();
+        let mut res = els.unwrap_or_else(|| block(Vec::new()));
+        for (cond, then) in elifs.into_iter().rev().chain(std::iter::once(first)) {
+            let then = match then {
+                Expr::Block(mut stmts, last) if discard => {
+                    stmts.extend(*last);
+                    block(stmts)
+                }
+                then if discard => block(vec![then]),
+                then => then,
+            };
+            res = Expr::If(Box::new((cond, then)), Vec::new(), Box::new(Some(res)));
+        }
+        res
+    }
+
+    fn desugar(&mut self, id: ExprId, e: Expr) -> Expr {
+        match e {
+            Expr::For(init, cond, step, body) => self.for_loop(*init, *cond, *step, *body),
+            // forから作ったブロックには既にラベルが付いている
+            Expr::Labeled(_, body) if matches!(*body, Expr::Block(..)) => *body,
+            Expr::If(first, elifs, els) => self.if_chain(*first, elifs, *els),
+            Expr::Pow(a, b) => {
+                let t = self.typed.exprs[id].clone();
+                self.call(Helper::Pow, &t, *a, *b)
+            }
+            Expr::Mod(a, b) if is_float(&self.typed.exprs[id]) => {
+                let t = self.typed.exprs[id].clone();
+                self.call(Helper::Fmod, &t, *a, *b)
+            }
+            // 右辺の型は左辺と同じ
+            Expr::CompoundSet(AssignOp::Pow, lhs, rhs) => {
+                let t = self.typed.exprs[id - 1].clone();
+                self.assign(Helper::Pow, &t, *lhs, *rhs)
+            }
+            Expr::CompoundSet(AssignOp::Mod, lhs, rhs) if is_float(&self.typed.exprs[id - 1]) => {
+                let t = self.typed.exprs[id - 1].clone();
+                self.assign(Helper::Fmod, &t, *lhs, *rhs)
+            }
+            e => e,
+        }
+    }
+}
+
+impl<'a> Fold for Desugar<'a> {
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        let e = match e {
+            Expr::Labeled(l, body) if matches!(*body, Expr::For(..)) => {
+                self.label = Some(l);
+                Expr::Labeled(l, Box::new(self.fold_expr(*body)))
+            }
+            Expr::For(..) => {
+                // ラベルはforの子を辿る間だけ退避する
+                let label = self.label.take();
+                let e = fold::walk_expr(self, e);
+                self.label = label;
+                e
+            }
+            e => fold::walk_expr(self, e),
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.desugar(id, e)
+    }
+}
+
+// For, elifの連鎖, 累乗と浮動小数点数の剰余をwhile, elseを持つ単一分岐のif, 補助関数の呼び出しに変換する
+// 補助関数は使ったものだけ末尾に追加する
+pub fn desugar(module: &Module, typed: &Typed) -> Module {
+    let mut d = Desugar {
+        typed,
+        label: None,
+        fresh: 0,
+        helpers: Vec::new(),
+        next_id: 0,
+    };
+    let mut out = d.fold_module(module.clone());
+    out.extend(d.helpers.into_iter().map(|(_, m)| m));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::global::eval_globals;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use ast::printer::print_module;
+    use ast::span::Spans;
+
+    fn run(src: &str) -> Module {
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        assert_eq!(res.errors, vec![]);
+        let typed = check(&m, &spans, &res);
+        assert_eq!(typed.errors, vec![]);
+        let out = desugar(&m, &typed);
+
+        // 変換後も名前解決と型検査を通る
+        let spans = Spans::default();
+        let res = resolve(&out, &spans);
+        assert_eq!(res.errors, vec![]);
+        assert_eq!(check(&out, &spans, &res).errors, vec![]);
+        out
+    }
+
+    #[test]
+    fn desugar_test() {
+        let out = run(r#"
+            fun f(n: i32, xs: [F32]): i32 {
+                let s = 0;
+                outer: for (let i = 0; i < n; i += 1) {
+                    for (let j = 0; j < i; j += 1) {
+                        if (j == 2) { continue outer; } elif (j == 3) { break; };
+                        s += j ** 2;
+                    };
+                    if (i == 5) { continue; };
+                };
+                xs[s] **= 2.0f32;
+                s
+            }
+        "#);
+        assert_eq!(
+            print_module(&out),
+            r#"fun f(n: i32, xs: [F32]): i32 {
+    let s = 0;
+    {
+        let i = 0;
+        outer: while (i < n) {
+            continue#1: while (true) {
+                {
+                    {
+                        let j = 0;
+                        while (j < i) {
+                            {
+                                if (j == 2) {
+                                    break continue#1;
+                                } else if (j == 3) {
+                                    break;
+                                } else {};
+                                s += pow#i32(j, 2);
+                            };
+                            j += 1;
+                        };
+                    };
+                    if (i == 5) {
+                        break continue#1;
+                    } else {};
+                };
+                break continue#1;
+            };
+            i += 1;
+        };
+    };
+    {
+        let t#2 = xs;
+        let t#3 = s;
+        t#2[t#3] = pow#F64(t#2[t#3] as F64, 2.0f32 as F64) as F32
+    };
+    s
+}
+
+fun pow#i32(a: i32, b: i32): i32 {
+    let r = 1;
+    if (b < 0) {
+        r = (if (a == 1) 1 else if (a == -1) if (b % 2 == 0) 1 else -1 else 0);
+    } else {
+        while (b > 0) {
+            if (b % 2 == 1) {
+                r *= a;
+            } else {};
+            a *= a;
+            b /= 2;
+        };
+    };
+    r
+}
+
+extern "runtime" "pow" fun pow#F64(a: F64, b: F64): F64;
+"#
+        );
+    }
+
+    #[test]
+    fn if_behaviour_test() {
+        let src = r#"
+            const A: i32 = 3;
+            const B: i32 = if (A == 1) 10 elif (A == 2) 20 elif (A == 3) 30 else 40;
+            const C: bool = if (B > 25) { if (A < 0) false elif (A > 0) true else false } else false;
+        "#;
+        let (m, _) = parse_with_spans(src).unwrap();
+        let out = run(src);
+        assert_ne!(m, out);
+        assert_eq!(eval_globals(&out), eval_globals(&m));
+    }
+}
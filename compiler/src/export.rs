@@ -0,0 +1,158 @@
+use ast::ast::{FuncDef, Ident, Member, Module};
+use std::collections::{HashMap, HashSet};
+use token::intern::Sym;
+use wasm::ast::{ExportEntry, ExportSection, ExternalKind, StartSection};
+
+pub const MAIN: &str = "main";
+pub const START_EXPORT: &str = "_start";
+
+// mainをStartSectionにするか`_start`としてエクスポートするか
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entry {
+    Start,
+    Export,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportError {
+    DuplicateExport(String),
+    InvalidMain,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exports {
+    pub export_section: ExportSection,
+    pub start_section: Option<StartSection>,
+}
+
+// wasmの関数インデックスはインポートした関数が先
+pub fn func_indices(module: &Module) -> HashMap<Ident, usize> {
+    let imports = module.iter().filter_map(|member| match member {
+        Member::ExternFun(def, _, _) => Some(def),
+        _ => None,
+    });
+    let funcs = module.iter().filter_map(|member| match member {
+        Member::Func(def, _, _) => Some(def),
+        _ => None,
+    });
+    let mut indices = HashMap::new();
+    for (i, def) in imports.chain(funcs).enumerate() {
+        indices.entry(def.0).or_insert(i);
+    }
+    indices
+}
+
+pub fn exports(module: &Module, entry: Entry) -> Result<Exports, Vec<ExportError>> {
+    let indices = func_indices(module);
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    let mut entries = Vec::new();
+    for member in module {
+        if let Member::Func(FuncDef(name, _, _), _, Some(field)) = member {
+            if !names.insert(field.clone()) {
+                errors.push(ExportError::DuplicateExport(field.clone()));
+                continue;
+            }
+            entries.push(ExportEntry {
+                field: field.clone(),
+                kind: ExternalKind::Function,
+                index: indices[name],
+            });
+        }
+    }
+
+    let main = module.iter().find_map(|member| match member {
+        Member::Func(def, _, _) if def.0 == Sym::intern(MAIN) => Some(def),
+        _ => None,
+    });
+    let mut start_section = None;
+    if let Some(FuncDef(name, params, ret)) = main {
+        if !params.is_empty() || ret.is_some() {
+            errors.push(ExportError::InvalidMain);
+        }
+        let index = indices[name];
+        match entry {
+            Entry::Start => start_section = Some(StartSection(index)),
+            Entry::Export if names.contains(START_EXPORT) => {
+                errors.push(ExportError::DuplicateExport(START_EXPORT.to_string()));
+            }
+            Entry::Export => entries.push(ExportEntry {
+                field: START_EXPORT.to_string(),
+                kind: ExternalKind::Function,
+                index,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Exports {
+            export_section: ExportSection(entries),
+            start_section,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parser::parse;
+
+    #[test]
+    fn exports_test() {
+        let m = parse(
+            r#"
+            fun helper() {}
+            export "add" fun add_i32(a: i32, b: i32): i32 { a + b }
+            extern "env" "print" fun print(x: i32);
+            export fun main() {}
+        "#,
+        )
+        .unwrap();
+        let entry = |field: &str, index| ExportEntry {
+            field: field.to_string(),
+            kind: ExternalKind::Function,
+            index,
+        };
+        assert_eq!(
+            exports(&m, Entry::Export),
+            Ok(Exports {
+                export_section: ExportSection(vec![
+                    entry("add", 2),
+                    entry("main", 3),
+                    entry("_start", 3),
+                ]),
+                start_section: None,
+            })
+        );
+        assert_eq!(
+            exports(&m, Entry::Start),
+            Ok(Exports {
+                export_section: ExportSection(vec![entry("add", 2), entry("main", 3)]),
+                start_section: Some(StartSection(3)),
+            })
+        );
+    }
+
+    #[test]
+    fn export_error_test() {
+        let m = parse(
+            r#"
+            export "f" fun f() {}
+            export "f" fun g() {}
+            export "_start" fun h() {}
+            fun main(): i32 { 0 }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            exports(&m, Entry::Export),
+            Err(vec![
+                ExportError::DuplicateExport("f".to_string()),
+                ExportError::InvalidMain,
+                ExportError::DuplicateExport("_start".to_string()),
+            ])
+        );
+    }
+}
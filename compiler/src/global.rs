@@ -0,0 +1,702 @@
+use crate::cast::{check_bitcast, check_cast};
+use crate::layout::{
+    align_of, align_to, elems_offset, size_of, value_type, DATA_BASE, DATA_LIMIT, LENGTH_SIZE,
+};
+use ast::ast::{AssignOp, Expr, Ident, Member, Module, RefType, Type};
+use std::collections::HashMap;
+use token::intern::Sym;
+use wasm::ast::{DataSection, DataSegment, GlobalSection, GlobalType, GlobalVariable, InitExpr};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Const {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    String(Sym),
+    // 要素がすべて0の配列の要素の型と要素数
+    Array(Type, u32),
+}
+
+impl Const {
+    pub fn typ(&self) -> Type {
+        match self {
+            Const::I32(_) => Type::I32,
+            Const::I64(_) => Type::I64,
+            Const::F32(_) => Type::F32,
+            Const::F64(_) => Type::F64,
+            Const::Bool(_) => Type::Bool,
+            Const::Char(_) => Type::Char,
+            Const::String(_) => Type::RefType(RefType::String),
+            Const::Array(t, _) => Type::RefType(RefType::Array(Box::new(t.clone()))),
+        }
+    }
+
+    // 参照型の初期値(null)は定数として表現しない
+    pub fn zero(t: &Type) -> Option<Const> {
+        Some(match t {
+            Type::I32 => Const::I32(0),
+            Type::I64 => Const::I64(0),
+            Type::F32 => Const::F32(0.0),
+            Type::F64 => Const::F64(0.0),
+            Type::Bool => Const::Bool(false),
+            Type::Char => Const::Char('\0'),
+            Type::RefType(_) => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstErrorKind {
+    DuplicateGlobal,
+    NotConstant,
+    UnknownName(Ident),
+    NotConstGlobal(Ident),
+    Cycle,
+    TypeMismatch(Type, Type),
+    InvalidOperand,
+    DivisionByZero,
+    Overflow,
+    // 静的データが大きすぎて線形メモリに置けない
+    TooLarge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConstError(pub Ident, pub ConstErrorKind);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Global {
+    pub name: Ident,
+    pub mutable: bool,
+    pub value: Const,
+}
+
+// Reportedは既に他のグローバル変数のエラーとして報告済み
+enum Fail {
+    Error(ConstErrorKind),
+    Reported,
+}
+
+impl From<ConstErrorKind> for Fail {
+    fn from(kind: ConstErrorKind) -> Fail {
+        Fail::Error(kind)
+    }
+}
+
+enum State {
+    Visiting,
+    Done(Option<Const>),
+}
+
+struct Evaluator<'a> {
+    defs: HashMap<Ident, (bool, &'a Option<Type>, &'a Expr)>,
+    states: HashMap<Ident, State>,
+    errors: Vec<ConstError>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    UShr,
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+pub(crate) fn bin_op(e: &Expr) -> Option<(BinOp, &Expr, &Expr)> {
+    let (op, a, b) = match e {
+        Expr::Add(a, b) => (BinOp::Add, a, b),
+        Expr::Sub(a, b) => (BinOp::Sub, a, b),
+        Expr::Mul(a, b) => (BinOp::Mul, a, b),
+        Expr::Div(a, b) => (BinOp::Div, a, b),
+        Expr::Mod(a, b) => (BinOp::Mod, a, b),
+        Expr::BitAnd(a, b) => (BinOp::BitAnd, a, b),
+        Expr::BitOr(a, b) => (BinOp::BitOr, a, b),
+        Expr::BitXor(a, b) => (BinOp::BitXor, a, b),
+        Expr::Shl(a, b) => (BinOp::Shl, a, b),
+        Expr::Shr(a, b) => (BinOp::Shr, a, b),
+        Expr::UShr(a, b) => (BinOp::UShr, a, b),
+        Expr::Eq(a, b) => (BinOp::Eq, a, b),
+        Expr::Ne(a, b) => (BinOp::Ne, a, b),
+        Expr::Lt(a, b) => (BinOp::Lt, a, b),
+        Expr::Lte(a, b) => (BinOp::Lte, a, b),
+        Expr::Gt(a, b) => (BinOp::Gt, a, b),
+        Expr::Gte(a, b) => (BinOp::Gte, a, b),
+        _ => return None,
+    };
+    Some((op, a, b))
+}
+
+// 累乗は対応する二項演算を持たない
+pub(crate) fn assign_op(op: AssignOp) -> Option<BinOp> {
+    Some(match op {
+        AssignOp::Add => BinOp::Add,
+        AssignOp::Sub => BinOp::Sub,
+        AssignOp::Mul => BinOp::Mul,
+        AssignOp::Div => BinOp::Div,
+        AssignOp::Mod => BinOp::Mod,
+        AssignOp::Pow => return None,
+        AssignOp::BitAnd => BinOp::BitAnd,
+        AssignOp::BitOr => BinOp::BitOr,
+        AssignOp::BitXor => BinOp::BitXor,
+        AssignOp::Shl => BinOp::Shl,
+        AssignOp::Shr => BinOp::Shr,
+        AssignOp::UShr => BinOp::UShr,
+    })
+}
+
+// 整数演算はwasmと同じく2の補数で折り返し、シフト量はビット幅で剰余を取る
+macro_rules! int_binary {
+    ($op:expr, $a:expr, $b:expr, $c:path, $u:ty) => {{
+        let (a, b) = ($a, $b);
+        match $op {
+            BinOp::Add => $c(a.wrapping_add(b)),
+            BinOp::Sub => $c(a.wrapping_sub(b)),
+            BinOp::Mul => $c(a.wrapping_mul(b)),
+            BinOp::Div if b == 0 => return Err(ConstErrorKind::DivisionByZero),
+            BinOp::Div => $c(a.checked_div(b).ok_or(ConstErrorKind::Overflow)?),
+            BinOp::Mod if b == 0 => return Err(ConstErrorKind::DivisionByZero),
+            BinOp::Mod => $c(a.wrapping_rem(b)),
+            BinOp::BitAnd => $c(a & b),
+            BinOp::BitOr => $c(a | b),
+            BinOp::BitXor => $c(a ^ b),
+            BinOp::Shl => $c(a.wrapping_shl(b as u32)),
+            BinOp::Shr => $c(a.wrapping_shr(b as u32)),
+            BinOp::UShr => $c((a as $u).wrapping_shr(b as u32) as _),
+            BinOp::Eq => Const::Bool(a == b),
+            BinOp::Ne => Const::Bool(a != b),
+            BinOp::Lt => Const::Bool(a < b),
+            BinOp::Lte => Const::Bool(a <= b),
+            BinOp::Gt => Const::Bool(a > b),
+            BinOp::Gte => Const::Bool(a >= b),
+        }
+    }};
+}
+
+macro_rules! float_binary {
+    ($op:expr, $a:expr, $b:expr, $c:path) => {{
+        let (a, b) = ($a, $b);
+        match $op {
+            BinOp::Add => $c(a + b),
+            BinOp::Sub => $c(a - b),
+            BinOp::Mul => $c(a * b),
+            BinOp::Div => $c(a / b),
+            BinOp::Mod => $c(a % b),
+            BinOp::Eq => Const::Bool(a == b),
+            BinOp::Ne => Const::Bool(a != b),
+            BinOp::Lt => Const::Bool(a < b),
+            BinOp::Lte => Const::Bool(a <= b),
+            BinOp::Gt => Const::Bool(a > b),
+            BinOp::Gte => Const::Bool(a >= b),
+            _ => return Err(ConstErrorKind::InvalidOperand),
+        }
+    }};
+}
+
+pub(crate) fn binary(op: BinOp, a: Const, b: Const) -> Result<Const, ConstErrorKind> {
+    Ok(match (a, b) {
+        (Const::I32(a), Const::I32(b)) => int_binary!(op, a, b, Const::I32, u32),
+        (Const::I64(a), Const::I64(b)) => int_binary!(op, a, b, Const::I64, u64),
+        (Const::F32(a), Const::F32(b)) => float_binary!(op, a, b, Const::F32),
+        (Const::F64(a), Const::F64(b)) => float_binary!(op, a, b, Const::F64),
+        (Const::Bool(a), Const::Bool(b)) => match op {
+            BinOp::Eq => Const::Bool(a == b),
+            BinOp::Ne => Const::Bool(a != b),
+            _ => return Err(ConstErrorKind::InvalidOperand),
+        },
+        (Const::Char(a), Const::Char(b)) => match op {
+            BinOp::Eq => Const::Bool(a == b),
+            BinOp::Ne => Const::Bool(a != b),
+            BinOp::Lt => Const::Bool(a < b),
+            BinOp::Lte => Const::Bool(a <= b),
+            BinOp::Gt => Const::Bool(a > b),
+            BinOp::Gte => Const::Bool(a >= b),
+            _ => return Err(ConstErrorKind::InvalidOperand),
+        },
+        _ => return Err(ConstErrorKind::InvalidOperand),
+    })
+}
+
+// wasmのtruncはNaNと範囲外でトラップする
+fn trunc(x: f64, min: f64, max: f64) -> Result<f64, ConstErrorKind> {
+    let x = x.trunc();
+    if x.is_nan() || x < min || x >= max {
+        Err(ConstErrorKind::Overflow)
+    } else {
+        Ok(x)
+    }
+}
+
+fn trunc_i32(x: f64) -> Result<Const, ConstErrorKind> {
+    Ok(Const::I32(trunc(x, -2147483648.0, 2147483648.0)? as i32))
+}
+
+fn trunc_i64(x: f64) -> Result<Const, ConstErrorKind> {
+    Ok(Const::I64(
+        trunc(x, -9223372036854775808.0, 9223372036854775808.0)? as i64,
+    ))
+}
+
+pub(crate) fn cast(v: Const, to: &Type) -> Result<Const, ConstErrorKind> {
+    check_cast(&v.typ(), to).ok_or(ConstErrorKind::InvalidOperand)?;
+    Ok(match (v, to) {
+        (Const::I32(x), Type::I64) => Const::I64(x as i64),
+        (Const::I32(x), Type::F32) => Const::F32(x as f32),
+        (Const::I32(x), Type::F64) => Const::F64(x as f64),
+        (Const::I64(x), Type::I32) => Const::I32(x as i32),
+        (Const::I64(x), Type::F32) => Const::F32(x as f32),
+        (Const::I64(x), Type::F64) => Const::F64(x as f64),
+        (Const::F32(x), Type::I32) => trunc_i32(x as f64)?,
+        (Const::F32(x), Type::I64) => trunc_i64(x as f64)?,
+        (Const::F32(x), Type::F64) => Const::F64(x as f64),
+        (Const::F64(x), Type::I32) => trunc_i32(x)?,
+        (Const::F64(x), Type::I64) => trunc_i64(x)?,
+        (Const::F64(x), Type::F32) => Const::F32(x as f32),
+        (Const::Bool(x), Type::I32) => Const::I32(x as i32),
+        (Const::Bool(x), Type::I64) => Const::I64(x as i64),
+        (Const::Char(x), Type::I32) => Const::I32(x as i32),
+        (Const::Char(x), Type::I64) => Const::I64(x as i64),
+        (v, _) => v,
+    })
+}
+
+pub(crate) fn bitcast(v: Const, to: &Type) -> Result<Const, ConstErrorKind> {
+    check_bitcast(&v.typ(), to).ok_or(ConstErrorKind::InvalidOperand)?;
+    Ok(match v {
+        Const::I32(x) if to == &Type::F32 => Const::F32(f32::from_bits(x as u32)),
+        Const::F32(x) if to == &Type::I32 => Const::I32(x.to_bits() as i32),
+        Const::I64(x) if to == &Type::F64 => Const::F64(f64::from_bits(x as u64)),
+        Const::F64(x) if to == &Type::I64 => Const::I64(x.to_bits() as i64),
+        v => v,
+    })
+}
+
+impl<'a> Evaluator<'a> {
+    fn global(&mut self, name: Ident) -> Option<Const> {
+        match self.states.get(&name) {
+            Some(State::Visiting) => {
+                self.errors.push(ConstError(name, ConstErrorKind::Cycle));
+                return None;
+            }
+            Some(State::Done(v)) => return v.clone(),
+            None => {}
+        }
+        self.states.insert(name, State::Visiting);
+        let (_, t, e) = self.defs[&name];
+        let v = match self.eval(e) {
+            Ok(v) => match t {
+                Some(t) if t != &v.typ() => {
+                    let kind = ConstErrorKind::TypeMismatch(t.clone(), v.typ());
+                    self.errors.push(ConstError(name, kind));
+                    None
+                }
+                _ => Some(v),
+            },
+            Err(Fail::Error(kind)) => {
+                self.errors.push(ConstError(name, kind));
+                None
+            }
+            Err(Fail::Reported) => None,
+        };
+        self.states.insert(name, State::Done(v.clone()));
+        v
+    }
+
+    fn eval(&mut self, e: &Expr) -> Result<Const, Fail> {
+        if let Some((op, a, b)) = bin_op(e) {
+            let a = self.eval(a)?;
+            let b = self.eval(b)?;
+            return Ok(binary(op, a, b)?);
+        }
+        Ok(match e {
+            Expr::I32Literal(x) => Const::I32(*x),
+            Expr::I64Literal(x) => Const::I64(*x),
+            Expr::F32Literal(x) => Const::F32(*x),
+            Expr::F64Literal(x) => Const::F64(*x),
+            Expr::BoolLiteral(x) => Const::Bool(*x),
+            Expr::CharLiteral(x) => Const::Char(*x),
+            Expr::StringLiteral(x) => Const::String(*x),
+            Expr::ArrayLiteral(t, len) => match self.eval(len)? {
+                Const::I32(n) if n >= 0 => {
+                    Const::zero(t).ok_or(ConstErrorKind::NotConstant)?;
+                    Const::Array(t.clone(), n as u32)
+                }
+                Const::I32(_) => return Err(ConstErrorKind::Overflow.into()),
+                _ => return Err(ConstErrorKind::InvalidOperand.into()),
+            },
+            Expr::Var(x) => match self.defs.get(x) {
+                None => return Err(ConstErrorKind::UnknownName(*x).into()),
+                Some((true, _, _)) => return Err(ConstErrorKind::NotConstGlobal(*x).into()),
+                Some(_) => self.global(*x).ok_or(Fail::Reported)?,
+            },
+            Expr::Not(x) => match self.eval(x)? {
+                Const::Bool(x) => Const::Bool(!x),
+                _ => return Err(ConstErrorKind::InvalidOperand.into()),
+            },
+            Expr::Plus(x) => match self.eval(x)? {
+                x @ Const::I32(_) | x @ Const::I64(_) | x @ Const::F32(_) | x @ Const::F64(_) => x,
+                _ => return Err(ConstErrorKind::InvalidOperand.into()),
+            },
+            Expr::Minus(x) => match self.eval(x)? {
+                Const::I32(x) => Const::I32(x.wrapping_neg()),
+                Const::I64(x) => Const::I64(x.wrapping_neg()),
+                Const::F32(x) => Const::F32(-x),
+                Const::F64(x) => Const::F64(-x),
+                _ => return Err(ConstErrorKind::InvalidOperand.into()),
+            },
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                let is_and = matches!(e, Expr::And(_, _));
+                match self.eval(a)? {
+                    Const::Bool(a) if a != is_and => Const::Bool(a),
+                    Const::Bool(_) => match self.eval(b)? {
+                        Const::Bool(b) => Const::Bool(b),
+                        _ => return Err(ConstErrorKind::InvalidOperand.into()),
+                    },
+                    _ => return Err(ConstErrorKind::InvalidOperand.into()),
+                }
+            }
+            Expr::Cast(x, t) => cast(self.eval(x)?, t)?,
+            Expr::Bitcast(x, t) => bitcast(self.eval(x)?, t)?,
+            Expr::If(first, elifs, els) => {
+                for (cond, then) in std::iter::once(&**first).chain(elifs.iter()) {
+                    match self.eval(cond)? {
+                        Const::Bool(true) => return self.eval(then),
+                        Const::Bool(false) => {}
+                        _ => return Err(ConstErrorKind::InvalidOperand.into()),
+                    }
+                }
+                match &**els {
+                    Some(els) => self.eval(els)?,
+                    None => return Err(ConstErrorKind::NotConstant.into()),
+                }
+            }
+            Expr::Block(stmts, last) if stmts.is_empty() => match &**last {
+                Some(last) => self.eval(last)?,
+                None => return Err(ConstErrorKind::NotConstant.into()),
+            },
+            _ => return Err(ConstErrorKind::NotConstant.into()),
+        })
+    }
+}
+
+// データセクションに置く大きさ(アラインメントのためのパディングを含む)
+fn data_size(v: &Const) -> u64 {
+    match v {
+        Const::String(s) => (LENGTH_SIZE as usize + s.as_str().len() + 3) as u64,
+        Const::Array(t, n) => {
+            let align = align_of(t).max(4);
+            (elems_offset(t) + align) as u64 + *n as u64 * size_of(t) as u64
+        }
+        _ => 0,
+    }
+}
+
+// `let`の初期化式からは`const`のみ参照できる
+pub fn eval_globals(module: &Module) -> Result<Vec<Global>, Vec<ConstError>> {
+    let mut ev = Evaluator {
+        defs: HashMap::new(),
+        states: HashMap::new(),
+        errors: Vec::new(),
+    };
+    let mut names = Vec::new();
+    for member in module {
+        let (mutable, name, t, e) = match member {
+            Member::Let(name, t, e) => (true, name, t, e),
+            Member::Const(name, t, e) => (false, name, t, e),
+            _ => continue,
+        };
+        if ev.defs.contains_key(name) {
+            ev.errors
+                .push(ConstError(*name, ConstErrorKind::DuplicateGlobal));
+            continue;
+        }
+        ev.defs.insert(*name, (mutable, t, e));
+        names.push((*name, mutable));
+    }
+    let mut globals = Vec::new();
+    let mut size = DATA_BASE as u64;
+    for (name, mutable) in names {
+        if let Some(value) = ev.global(name) {
+            size += data_size(&value);
+            if size > DATA_LIMIT {
+                ev.errors.push(ConstError(name, ConstErrorKind::TooLarge));
+                continue;
+            }
+            globals.push(Global {
+                name,
+                mutable,
+                value,
+            });
+        }
+    }
+    if ev.errors.is_empty() {
+        Ok(globals)
+    } else {
+        Err(ev.errors)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Globals {
+    pub indices: HashMap<Ident, usize>,
+    pub global_section: GlobalSection,
+    pub data_section: DataSection,
+    pub heap_base: u32,
+}
+
+struct DataBuilder {
+    offset: u32,
+    segments: Vec<DataSegment>,
+    strings: HashMap<Sym, u32>,
+}
+
+impl DataBuilder {
+    fn alloc(&mut self, data: Vec<u8>, align: u32) -> u32 {
+        let ptr = align_to(self.offset, align);
+        self.offset = ptr + data.len() as u32;
+        self.segments.push(DataSegment {
+            offset: InitExpr::I32(ptr as i32),
+            data,
+        });
+        ptr
+    }
+
+    // 文字列: [バイト長: i32][UTF-8]
+    // 配列: [要素数: i32][要素のアラインメントまでパディング][要素...]
+    fn object(&mut self, v: &Const) -> u32 {
+        match v {
+            Const::String(s) => {
+                if let Some(&ptr) = self.strings.get(s) {
+                    return ptr;
+                }
+                let mut data = (s.as_str().len() as u32).to_le_bytes().to_vec();
+                data.extend_from_slice(s.as_str().as_bytes());
+                let ptr = self.alloc(data, 4);
+                self.strings.insert(*s, ptr);
+                ptr
+            }
+            // 線形メモリは0で初期化されているので要素は書かずに領域だけ確保する
+            Const::Array(t, n) => {
+                let align = align_of(t).max(4);
+                let mut data = n.to_le_bytes().to_vec();
+                data.resize(elems_offset(t) as usize, 0);
+                let ptr = self.alloc(data, align);
+                self.offset += n * size_of(t);
+                ptr
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn init(&mut self, v: &Const) -> InitExpr {
+        match v {
+            Const::I32(x) => InitExpr::I32(*x),
+            Const::I64(x) => InitExpr::I64(*x),
+            Const::F32(x) => InitExpr::F32(*x),
+            Const::F64(x) => InitExpr::F64(*x),
+            Const::Bool(x) => InitExpr::I32(*x as i32),
+            Const::Char(x) => InitExpr::I32(*x as i32),
+            Const::String(_) | Const::Array(_, _) => InitExpr::I32(self.object(v) as i32),
+        }
+    }
+}
+
+// 数値はwasmのグローバル変数に、文字列と配列はデータセグメントに置きそのポインタをグローバル変数に持つ
+pub fn lower_globals(globals: &[Global]) -> Globals {
+    lower_globals_with(globals, &[]).0
+}
+
+// コード中の文字列リテラルも同じデータセクションに置き、そのポインタを返す
+pub(crate) fn lower_globals_with(
+    globals: &[Global],
+    strings: &[Sym],
+) -> (Globals, HashMap<Sym, u32>) {
+    let mut data = DataBuilder {
+        offset: DATA_BASE,
+        segments: Vec::new(),
+        strings: HashMap::new(),
+    };
+    let mut indices = HashMap::new();
+    let mut vars = Vec::new();
+    for (i, global) in globals.iter().enumerate() {
+        indices.insert(global.name, i);
+        let init = data.init(&global.value);
+        vars.push(GlobalVariable(
+            GlobalType {
+                content_type: value_type(&global.value.typ()),
+                mutability: global.mutable,
+            },
+            init,
+        ));
+    }
+    let ptrs = strings
+        .iter()
+        .map(|s| (*s, data.object(&Const::String(*s))))
+        .collect();
+    let globals = Globals {
+        indices,
+        global_section: GlobalSection(vars),
+        data_section: DataSection(data.segments),
+        heap_base: align_to(data.offset, 8),
+    };
+    (globals, ptrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parser::parse;
+    use wasm::ast::ValueType;
+
+    fn eval(src: &str) -> Result<Vec<Global>, Vec<ConstError>> {
+        eval_globals(&parse(src).unwrap())
+    }
+
+    #[test]
+    fn eval_globals_test() {
+        let globals = eval(
+            r#"
+            let counter: i64 = N as i64 * 3i64;
+            const N = -1 >>> 28 << 1;
+            const MIN = -2147483647 - 1;
+            const Q = MIN % -1 + (7 / -2) + (1 << 33);
+            const F = 2.5 as i32 + (1.0f32 bitcast i32 == 1065353216 ? 1 : 0);
+            const B = true || 1 / 0 == 0;
+        "#,
+        )
+        .unwrap();
+        let values = globals
+            .iter()
+            .map(|g| (g.name.as_str(), g.mutable, g.value.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                ("counter", true, Const::I64(90)),
+                ("N", false, Const::I32(30)),
+                ("MIN", false, Const::I32(i32::MIN)),
+                ("Q", false, Const::I32(-1)),
+                ("F", false, Const::I32(3)),
+                ("B", false, Const::Bool(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn const_error_test() {
+        let s = Sym::intern;
+        assert_eq!(
+            eval(
+                r#"
+                const A = B;
+                const B = A + 1;
+                let C = 1;
+                const D = C;
+                const E: i64 = 1;
+                const F = MIN / -1;
+                const MIN = -2147483647 - 1;
+                const G = 10000000000.0 as i32;
+                const H = f();
+                const I = J;
+                const E = 2;
+            "#
+            ),
+            Err(vec![
+                ConstError(s("E"), ConstErrorKind::DuplicateGlobal),
+                ConstError(s("A"), ConstErrorKind::Cycle),
+                ConstError(s("D"), ConstErrorKind::NotConstGlobal(s("C"))),
+                ConstError(s("E"), ConstErrorKind::TypeMismatch(Type::I64, Type::I32)),
+                ConstError(s("F"), ConstErrorKind::Overflow),
+                ConstError(s("G"), ConstErrorKind::Overflow),
+                ConstError(s("H"), ConstErrorKind::NotConstant),
+                ConstError(s("I"), ConstErrorKind::UnknownName(s("J"))),
+            ])
+        );
+        // 要素ごとに値を持たないので大きな配列でもすぐに失敗する
+        assert_eq!(
+            eval("let xs = [i64; 2000000000];"),
+            Err(vec![ConstError(s("xs"), ConstErrorKind::TooLarge)])
+        );
+        assert_eq!(
+            eval("let xs = [i64; 200000000]; let ys = [i32; 200000000];"),
+            Err(vec![ConstError(s("ys"), ConstErrorKind::TooLarge)])
+        );
+        assert!(eval("let xs = [i64; 200000000];").is_ok());
+    }
+
+    #[test]
+    fn lower_globals_test() {
+        let globals = eval(
+            r#"
+            const S = "hi";
+            let xs = [F64; 2];
+            let x = 1.5;
+            const T = "hi";
+        "#,
+        )
+        .unwrap();
+        let lowered = lower_globals(&globals);
+        assert_eq!(
+            lowered.global_section,
+            GlobalSection(vec![
+                GlobalVariable(
+                    GlobalType {
+                        content_type: ValueType::I32,
+                        mutability: false,
+                    },
+                    InitExpr::I32(8)
+                ),
+                GlobalVariable(
+                    GlobalType {
+                        content_type: ValueType::I32,
+                        mutability: true,
+                    },
+                    InitExpr::I32(16)
+                ),
+                GlobalVariable(
+                    GlobalType {
+                        content_type: ValueType::F64,
+                        mutability: true,
+                    },
+                    InitExpr::F64(1.5)
+                ),
+                GlobalVariable(
+                    GlobalType {
+                        content_type: ValueType::I32,
+                        mutability: false,
+                    },
+                    InitExpr::I32(8)
+                ),
+            ])
+        );
+        assert_eq!(
+            lowered.data_section,
+            DataSection(vec![
+                DataSegment {
+                    offset: InitExpr::I32(8),
+                    data: vec![2, 0, 0, 0, b'h', b'i'],
+                },
+                DataSegment {
+                    offset: InitExpr::I32(16),
+                    data: vec![2, 0, 0, 0, 0, 0, 0, 0],
+                },
+            ])
+        );
+        assert_eq!(lowered.indices[&Sym::intern("x")], 2);
+        assert_eq!(lowered.heap_base, 40);
+    }
+}
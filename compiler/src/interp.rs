@@ -0,0 +1,1181 @@
+use crate::decls::{Decls, TypeDecl};
+use crate::global::{assign_op, bin_op, binary, bitcast, cast, BinOp, Const, ConstErrorKind};
+use crate::layout::{RUNTIME_MODULE, RUNTIME_POW};
+use crate::resolve::{DeclId, DeclKind, Resolution};
+use crate::typeck::Sizes;
+use ast::ast::{Expr, Ident, Member, Module, Type};
+use ast::span::{ExprId, Span, Spans};
+use std::collections::HashMap;
+use token::intern::Sym;
+
+// 呼び出しの深さの上限
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    // 値を持たない式の結果
+    Unit,
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    String(Sym),
+    // 参照型の初期値
+    Null,
+    // ヒープ上のオブジェクトの番号
+    Ref(usize),
+    // 関数または外部関数の宣言
+    Func(DeclId),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Object {
+    Array(Vec<Value>),
+    // 共用体は最後に書き込んだフィールドだけを持つ
+    Struct(Ident, Vec<(Ident, Value)>),
+    Enum(Ident, Ident, Vec<Value>),
+    // ラムダの式とキャプチャした値
+    Lambda(ExprId, Vec<Value>),
+    // クロージャ変換後の関数表の関数と環境
+    Closure(Ident, Value),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrapKind {
+    DivisionByZero,
+    // 整数除算の溢れと、浮動小数点数から整数への変換の範囲外
+    Overflow,
+    // 添字と長さ
+    OutOfBounds(i32, usize),
+    NegativeLength(i32),
+    NullReference,
+    // 共用体のフィールドを別の型として読めない
+    InvalidUnionRead,
+    UnknownImport(String, String),
+    UnknownFunction(Ident),
+    // 初期化中の大域変数を参照した
+    Uninitialized(Ident),
+    StackOverflow,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub span: Span,
+}
+
+pub type HostFunc = Box<dyn FnMut(&[Value]) -> Value>;
+
+// 外部関数の(モジュール名, 名前)から実装へ
+pub struct Host {
+    funcs: HashMap<(String, String), HostFunc>,
+}
+
+impl Host {
+    // ランタイムの関数だけを持つ
+    pub fn new() -> Host {
+        let mut host = Host {
+            funcs: HashMap::new(),
+        };
+        host.register(RUNTIME_MODULE, RUNTIME_POW, |args| match args {
+            [Value::F64(a), Value::F64(b)] => Value::F64(a.powf(*b)),
+            _ => unreachable!(),
+        });
+        host
+    }
+
+    pub fn register<F: FnMut(&[Value]) -> Value + 'static>(
+        &mut self,
+        module: &str,
+        field: &str,
+        f: F,
+    ) {
+        self.funcs
+            .insert((module.to_string(), field.to_string()), Box::new(f));
+    }
+}
+
+impl Default for Host {
+    fn default() -> Host {
+        Host::new()
+    }
+}
+
+enum Flow {
+    Break(ExprId),
+    Continue(ExprId),
+    Return(Value),
+    Trap(Box<Trap>),
+}
+
+impl From<Trap> for Flow {
+    fn from(t: Trap) -> Flow {
+        Flow::Trap(Box::new(t))
+    }
+}
+
+type Eval = Result<Value, Flow>;
+
+// 代入先
+enum Place {
+    Var(DeclId),
+    Field(usize, Ident),
+    Element(Value, i32),
+}
+
+// 文字列の添字はバイト単位で、そのバイトを文字として返す
+fn element(a: &Value, i: i32, heap: &[Object]) -> Result<Value, TrapKind> {
+    let (len, x) = match a {
+        Value::String(s) => {
+            let bytes = s.as_str().as_bytes();
+            (
+                bytes.len(),
+                bytes.get(i as usize).map(|b| Value::I32(*b as i32)),
+            )
+        }
+        Value::Ref(r) => match &heap[*r] {
+            Object::Array(xs) => (xs.len(), xs.get(i as usize).cloned()),
+            _ => unreachable!(),
+        },
+        Value::Null => return Err(TrapKind::NullReference),
+        _ => unreachable!(),
+    };
+    match x {
+        Some(x) if i >= 0 => Ok(x),
+        _ => Err(TrapKind::OutOfBounds(i, len)),
+    }
+}
+
+fn to_const(v: Value) -> Const {
+    match v {
+        Value::I32(x) => Const::I32(x),
+        Value::I64(x) => Const::I64(x),
+        Value::F32(x) => Const::F32(x),
+        Value::F64(x) => Const::F64(x),
+        Value::Bool(x) => Const::Bool(x),
+        Value::Char(x) => Const::Char(x),
+        _ => unreachable!(),
+    }
+}
+
+fn from_const(c: Const) -> Value {
+    match c {
+        Const::I32(x) => Value::I32(x),
+        Const::I64(x) => Value::I64(x),
+        Const::F32(x) => Value::F32(x),
+        Const::F64(x) => Value::F64(x),
+        Const::Bool(x) => Value::Bool(x),
+        Const::Char(x) => Value::Char(x),
+        Const::String(x) => Value::String(x),
+        Const::Array(_, _) => unreachable!(),
+    }
+}
+
+fn is_scalar(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::I32(_)
+            | Value::I64(_)
+            | Value::F32(_)
+            | Value::F64(_)
+            | Value::Bool(_)
+            | Value::Char(_)
+    )
+}
+
+fn trap_kind(k: ConstErrorKind) -> TrapKind {
+    match k {
+        ConstErrorKind::DivisionByZero => TrapKind::DivisionByZero,
+        ConstErrorKind::Overflow => TrapKind::Overflow,
+        _ => unreachable!(),
+    }
+}
+
+fn zero(t: &Type) -> Value {
+    Const::zero(t).map_or(Value::Null, from_const)
+}
+
+// 整数の累乗は2の補数で折り返し、負の指数は0方向に切り捨てる
+macro_rules! int_pow {
+    ($a:expr, $b:expr) => {{
+        let (mut a, mut b) = ($a, $b);
+        if b < 0 {
+            match a {
+                1 => 1,
+                -1 if b % 2 == 0 => 1,
+                -1 => -1,
+                _ => 0,
+            }
+        } else {
+            let mut r = 1;
+            while b > 0 {
+                if b % 2 == 1 {
+                    r = a.wrapping_mul(r);
+                }
+                a = a.wrapping_mul(a);
+                b /= 2;
+            }
+            r
+        }
+    }};
+}
+
+fn pow(a: Value, b: Value) -> Value {
+    match (a, b) {
+        (Value::I32(a), Value::I32(b)) => Value::I32(int_pow!(a, b)),
+        (Value::I64(a), Value::I64(b)) => Value::I64(int_pow!(a, b)),
+        (Value::F32(a), Value::F32(b)) => Value::F32((a as f64).powf(b as f64) as f32),
+        (Value::F64(a), Value::F64(b)) => Value::F64(a.powf(b)),
+        _ => unreachable!(),
+    }
+}
+
+// 線形メモリ上と同じく、共用体の値をリトルエンディアンのバイト列として読み替える
+fn reinterpret(v: &Value, t: &Type) -> Option<Value> {
+    let mut bytes = match v {
+        Value::I32(x) => x.to_le_bytes().to_vec(),
+        Value::I64(x) => x.to_le_bytes().to_vec(),
+        Value::F32(x) => x.to_bits().to_le_bytes().to_vec(),
+        Value::F64(x) => x.to_bits().to_le_bytes().to_vec(),
+        Value::Bool(x) => vec![*x as u8],
+        Value::Char(x) => (*x as u32).to_le_bytes().to_vec(),
+        _ => return None,
+    };
+    bytes.resize(8, 0);
+    let mut b4 = [0; 4];
+    b4.copy_from_slice(&bytes[..4]);
+    let mut b8 = [0; 8];
+    b8.copy_from_slice(&bytes);
+    Some(match t {
+        Type::I32 => Value::I32(i32::from_le_bytes(b4)),
+        Type::I64 => Value::I64(i64::from_le_bytes(b8)),
+        Type::F32 => Value::F32(f32::from_bits(u32::from_le_bytes(b4))),
+        Type::F64 => Value::F64(f64::from_bits(u64::from_le_bytes(b8))),
+        Type::Bool => Value::Bool(bytes[0] != 0),
+        Type::Char => Value::Char(std::char::from_u32(u32::from_le_bytes(b4))?),
+        Type::RefType(_) => return None,
+    })
+}
+
+// 名前解決と型検査を通ったモジュールを構文木のまま実行する
+pub struct Interpreter<'a> {
+    module: &'a Module,
+    spans: &'a Spans,
+    res: &'a Resolution,
+    decls: Decls<'a>,
+    sizes: Sizes,
+    // 評価したラムダの式(呼び出せるのは評価した後だけ)
+    lambdas: HashMap<ExprId, &'a Expr>,
+    // トップレベルの宣言からその定義へ
+    owners: HashMap<DeclId, usize>,
+    // 関数の名前から宣言へ
+    funcs: HashMap<Ident, DeclId>,
+    // Noneは初期化中
+    globals: HashMap<DeclId, Option<Value>>,
+    frames: Vec<HashMap<DeclId, Value>>,
+    heap: Vec<Object>,
+    host: Host,
+    initialized: bool,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(module: &'a Module, spans: &'a Spans, res: &'a Resolution, host: Host) -> Self {
+        let mut owners = HashMap::new();
+        let mut funcs = HashMap::new();
+        for (i, member) in module.iter().enumerate() {
+            if let Some(&d) = res.members.get(i).and_then(|ds| ds.first()) {
+                owners.entry(d).or_insert(i);
+                if let Member::Func(def, _, _) = member {
+                    funcs.entry(def.0).or_insert(d);
+                }
+            }
+        }
+        Interpreter {
+            module,
+            spans,
+            res,
+            decls: Decls::new(module),
+            // 評価する式のIDは親のIDから求める
+            sizes: Sizes::new(module),
+            lambdas: HashMap::new(),
+            owners,
+            funcs,
+            globals: HashMap::new(),
+            frames: Vec::new(),
+            heap: Vec::new(),
+            host,
+            initialized: false,
+        }
+    }
+
+    // 大域変数をモジュールの順に初期化する
+    pub fn init(&mut self) -> Result<(), Trap> {
+        if self.initialized {
+            return Ok(());
+        }
+        self.initialized = true;
+        let res = self.res;
+        for (i, member) in self.module.iter().enumerate() {
+            if let Member::Let(..) | Member::Const(..) = member {
+                match res.members.get(i).and_then(|ds| ds.first()) {
+                    Some(&d) if self.owners.get(&d) == Some(&i) => {
+                        self.global(d, Span::default())?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Trap> {
+        self.init()?;
+        let name = Sym::intern(name);
+        match self.funcs.get(&name) {
+            Some(&d) => self.call_decl(d, args, Span::default()),
+            None => Err(Trap {
+                kind: TrapKind::UnknownFunction(name),
+                span: Span::default(),
+            }),
+        }
+    }
+
+    pub fn global_value(&mut self, name: &str) -> Result<Value, Trap> {
+        self.init()?;
+        let name = Sym::intern(name);
+        let res = self.res;
+        let d = res
+            .decls
+            .iter()
+            .enumerate()
+            .find(|(d, decl)| decl.name == name && self.owners.contains_key(d))
+            .map(|(d, _)| d);
+        match d {
+            Some(d) => self.read(d, Span::default()),
+            None => Err(Trap {
+                kind: TrapKind::UnknownFunction(name),
+                span: Span::default(),
+            }),
+        }
+    }
+
+    pub fn object(&self, v: &Value) -> Option<&Object> {
+        match v {
+            Value::Ref(r) => self.heap.get(*r),
+            _ => None,
+        }
+    }
+
+    // 参照を辿って値を表示する(参照の同一性は表さない)
+    pub fn show(&self, v: &Value) -> String {
+        let list = |xs: &[Value]| {
+            xs.iter()
+                .map(|x| self.show(x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match v {
+            Value::Unit => "()".to_string(),
+            Value::I32(x) => x.to_string(),
+            Value::I64(x) => format!("{}i64", x),
+            Value::F32(x) => format!("{:?}f32", x),
+            Value::F64(x) => format!("{:?}", x),
+            Value::Bool(x) => x.to_string(),
+            Value::Char(x) => format!("{:?}", x),
+            Value::String(x) => format!("{:?}", x.as_str()),
+            Value::Null => "null".to_string(),
+            Value::Func(d) => format!("fun {}", self.res.decls[*d].name.as_str()),
+            Value::Ref(r) => match &self.heap[*r] {
+                Object::Array(xs) => format!("[{}]", list(xs)),
+                Object::Struct(name, fields) => format!(
+                    "{} {{ {} }}",
+                    name.as_str(),
+                    fields
+                        .iter()
+                        .map(|(x, v)| format!("{}: {}", x.as_str(), self.show(v)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Object::Enum(name, variant, xs) => {
+                    format!("{}::{}({})", name.as_str(), variant.as_str(), list(xs))
+                }
+                Object::Lambda(_, _) | Object::Closure(_, _) => "closure".to_string(),
+            },
+        }
+    }
+
+    fn trap(&self, kind: TrapKind, id: ExprId) -> Trap {
+        Trap {
+            kind,
+            span: self.spans.expr(id),
+        }
+    }
+
+    fn alloc(&mut self, o: Object) -> Value {
+        self.heap.push(o);
+        Value::Ref(self.heap.len() - 1)
+    }
+
+    fn deref(&self, v: Value, id: ExprId) -> Result<usize, Trap> {
+        match v {
+            Value::Ref(r) => Ok(r),
+            Value::Null => Err(self.trap(TrapKind::NullReference, id)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn global(&mut self, d: DeclId, span: Span) -> Result<Value, Trap> {
+        match self.globals.get(&d) {
+            Some(Some(v)) => return Ok(v.clone()),
+            Some(None) => {
+                return Err(Trap {
+                    kind: TrapKind::Uninitialized(self.res.decls[d].name),
+                    span,
+                })
+            }
+            None => {}
+        }
+        self.globals.insert(d, None);
+        let module = self.module;
+        let i = self.owners[&d];
+        let e = match &module[i] {
+            Member::Let(_, _, e) | Member::Const(_, _, e) => e,
+            _ => unreachable!(),
+        };
+        // 初期化式は関数の外として評価する
+        let frames = std::mem::take(&mut self.frames);
+        let v = self.expr(e, self.sizes.member(i));
+        self.frames = frames;
+        let v = match v {
+            Ok(v) => v,
+            Err(Flow::Trap(t)) => return Err(*t),
+            Err(_) => unreachable!(),
+        };
+        self.globals.insert(d, Some(v.clone()));
+        Ok(v)
+    }
+
+    fn read(&mut self, d: DeclId, span: Span) -> Result<Value, Trap> {
+        match self.res.decls[d].kind {
+            DeclKind::Func | DeclKind::ExternFun => Ok(Value::Func(d)),
+            DeclKind::Global | DeclKind::Const => self.global(d, span),
+            _ => Ok(self.frames.last().unwrap()[&d].clone()),
+        }
+    }
+
+    fn write(&mut self, d: DeclId, v: Value, span: Span) -> Result<(), Trap> {
+        match self.res.decls[d].kind {
+            DeclKind::Global => {
+                self.global(d, span)?;
+                self.globals.insert(d, Some(v));
+            }
+            _ => {
+                self.frames.last_mut().unwrap().insert(d, v);
+            }
+        }
+        Ok(())
+    }
+
+    fn invoke(
+        &mut self,
+        frame: HashMap<DeclId, Value>,
+        body: &'a Expr,
+        id: ExprId,
+        span: Span,
+    ) -> Result<Value, Trap> {
+        if self.frames.len() >= MAX_DEPTH {
+            return Err(Trap {
+                kind: TrapKind::StackOverflow,
+                span,
+            });
+        }
+        self.frames.push(frame);
+        let probe = 0u8;
+        eprintln!("DEPTH {} {:p}", self.frames.len(), &probe);
+        let v = self.expr(body, id);
+        self.frames.pop();
+        match v {
+            Ok(v) | Err(Flow::Return(v)) => Ok(v),
+            Err(Flow::Trap(t)) => Err(*t),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    fn call_decl(&mut self, d: DeclId, args: Vec<Value>, span: Span) -> Result<Value, Trap> {
+        let module = self.module;
+        let i = self.owners[&d];
+        match &module[i] {
+            Member::Func(_, body, _) => {
+                let params = self.res.members[i][1..].iter().cloned();
+                let id = self.sizes.member(i);
+                self.invoke(params.zip(args).collect(), body, id, span)
+            }
+            Member::ExternFun(_, m, f) => match self.host.funcs.get_mut(&(m.clone(), f.clone())) {
+                Some(f) => Ok(f(&args)),
+                None => Err(Trap {
+                    kind: TrapKind::UnknownImport(m.clone(), f.clone()),
+                    span,
+                }),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn call_value(&mut self, f: Value, mut args: Vec<Value>, id: ExprId) -> Result<Value, Trap> {
+        let span = self.spans.expr(id);
+        let r = match f {
+            Value::Func(d) => return self.call_decl(d, args, span),
+            f => self.deref(f, id)?,
+        };
+        match self.heap[r].clone() {
+            Object::Lambda(lambda, caps) => {
+                let res = self.res;
+                let binds = res.binds.get(&lambda).map_or(&[][..], |ds| &ds[..]);
+                let frame = binds
+                    .iter()
+                    .cloned()
+                    .zip(caps.into_iter().chain(args))
+                    .collect();
+                match self.lambdas[&lambda] {
+                    Expr::Lambda(_, _, _, body) => self.invoke(frame, body, lambda - 1, span),
+                    _ => unreachable!(),
+                }
+            }
+            Object::Closure(f, env) => {
+                args.insert(0, env);
+                self.call_decl(self.funcs[&f], args, span)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&self, op: BinOp, a: Value, b: Value, id: ExprId) -> Result<Value, Trap> {
+        binary(op, to_const(a), to_const(b))
+            .map(from_const)
+            .map_err(|k| self.trap(trap_kind(k), id))
+    }
+
+    fn field(&self, r: usize, f: Ident, id: ExprId) -> Result<Value, Trap> {
+        match &self.heap[r] {
+            Object::Struct(name, fields) => match fields.iter().find(|(x, _)| *x == f) {
+                Some((_, v)) => Ok(v.clone()),
+                None => {
+                    let t = self.decls.field(*name, f).unwrap();
+                    reinterpret(&fields[0].1, t)
+                        .ok_or_else(|| self.trap(TrapKind::InvalidUnionRead, id))
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn place(&mut self, lhs: &'a Expr, id: ExprId) -> Result<Place, Flow> {
+        Ok(match lhs {
+            Expr::Var(_) => Place::Var(self.res.uses[&id]),
+            Expr::Member(x, f) => {
+                let v = self.expr(x, id - 1)?;
+                Place::Field(self.deref(v, id)?, *f)
+            }
+            Expr::Index(a, i) => {
+                let ids = self.sizes.children(lhs, id);
+                let a = self.expr(a, ids[0])?;
+                match self.expr(i, ids[1])? {
+                    Value::I32(i) => Place::Element(a, i),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn load(&mut self, p: &Place, id: ExprId) -> Result<Value, Trap> {
+        match p {
+            Place::Var(d) => self.read(*d, self.spans.expr(id)),
+            Place::Field(r, f) => self.field(*r, *f, id),
+            Place::Element(a, i) => element(a, *i, &self.heap).map_err(|k| self.trap(k, id)),
+        }
+    }
+
+    fn store(&mut self, p: Place, v: Value, id: ExprId) -> Result<(), Trap> {
+        match p {
+            Place::Var(d) => self.write(d, v, self.spans.expr(id)),
+            Place::Field(r, f) => {
+                let union = matches!(
+                    self.decls.types.get(&self.struct_name(r)),
+                    Some(TypeDecl::Union(_))
+                );
+                match &mut self.heap[r] {
+                    Object::Struct(_, fields) if union => *fields = vec![(f, v)],
+                    Object::Struct(_, fields) => {
+                        if let Some((_, x)) = fields.iter_mut().find(|(x, _)| *x == f) {
+                            *x = v;
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+                Ok(())
+            }
+            Place::Element(a, i) => {
+                element(&a, i, &self.heap).map_err(|k| self.trap(k, id))?;
+                match (a, &mut self.heap) {
+                    (Value::Ref(r), heap) => match &mut heap[r] {
+                        Object::Array(xs) => xs[i as usize] = v,
+                        _ => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn struct_name(&self, r: usize) -> Ident {
+        match &self.heap[r] {
+            Object::Struct(name, _) => *name,
+            _ => unreachable!(),
+        }
+    }
+
+    fn cond(&mut self, e: &'a Expr, id: ExprId) -> Result<bool, Flow> {
+        match self.expr(e, id)? {
+            Value::Bool(b) => Ok(b),
+            _ => unreachable!(),
+        }
+    }
+
+    // ループの本体を実行し、ループを抜けるならfalseを返す
+    fn loop_body(&mut self, body: &'a Expr, body_id: ExprId, id: ExprId) -> Result<bool, Flow> {
+        match self.expr(body, body_id) {
+            Ok(_) => Ok(true),
+            Err(Flow::Break(l)) if l == id => Ok(false),
+            Err(Flow::Continue(l)) if l == id => Ok(true),
+            Err(f) => Err(f),
+        }
+    }
+
+    fn literal(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        Ok(match e {
+            Expr::StructLiteral(name, fields) => {
+                let mut values = Vec::new();
+                for ((x, e), id) in fields.iter().zip(self.sizes.children(e, id)) {
+                    values.push((*x, self.expr(e, id)?));
+                }
+                self.alloc(Object::Struct(*name, values))
+            }
+            Expr::EnumLiteral(name, variant, args) => {
+                let mut values = Vec::new();
+                for (e, id) in args.iter().zip(self.sizes.children(e, id)) {
+                    values.push(self.expr(e, id)?);
+                }
+                self.alloc(Object::Enum(*name, *variant, values))
+            }
+            Expr::ArrayLiteral(t, len) => match self.expr(len, id - 1)? {
+                Value::I32(n) if n >= 0 => self.alloc(Object::Array(vec![zero(t); n as usize])),
+                Value::I32(n) => return Err(self.trap(TrapKind::NegativeLength(n), id).into()),
+                _ => unreachable!(),
+            },
+            Expr::Lambda(_, params, _, _) => {
+                let res = self.res;
+                let binds = res.binds.get(&id).map_or(&[][..], |ds| &ds[..]);
+                let mut caps = Vec::new();
+                for &d in &binds[..binds.len() - params.len()] {
+                    if let DeclKind::Capture(outer) = res.decls[d].kind {
+                        caps.push(self.read(outer, self.spans.expr(id))?);
+                    }
+                }
+                self.lambdas.insert(id, e);
+                self.alloc(Object::Lambda(id, caps))
+            }
+            Expr::Closure(f, env) => {
+                let env = match &**env {
+                    Some(e) => self.expr(e, id - 1)?,
+                    None => Value::Null,
+                };
+                self.alloc(Object::Closure(*f, env))
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn unary(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        Ok(match e {
+            Expr::Not(x) => match self.expr(x, id - 1)? {
+                Value::Bool(x) => Value::Bool(!x),
+                _ => unreachable!(),
+            },
+            Expr::Plus(x) => self.expr(x, id - 1)?,
+            Expr::Minus(x) => match self.expr(x, id - 1)? {
+                Value::I32(x) => Value::I32(x.wrapping_neg()),
+                Value::I64(x) => Value::I64(x.wrapping_neg()),
+                Value::F32(x) => Value::F32(-x),
+                Value::F64(x) => Value::F64(-x),
+                _ => unreachable!(),
+            },
+            Expr::Cast(x, t) | Expr::Bitcast(x, t) => {
+                let v = self.expr(x, id - 1)?;
+                if !is_scalar(&v) {
+                    return Ok(v);
+                }
+                let c = match e {
+                    Expr::Cast(..) => cast(to_const(v), t),
+                    _ => bitcast(to_const(v), t),
+                };
+                c.map(from_const).map_err(|k| self.trap(trap_kind(k), id))?
+            }
+            Expr::Member(x, f) => {
+                let v = self.expr(x, id - 1)?;
+                let r = self.deref(v, id)?;
+                self.field(r, *f, id)?
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn binary_expr(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        let ids = self.sizes.children(e, id);
+        if let Some((op, a, b)) = bin_op(e) {
+            let a = self.expr(a, ids[0])?;
+            let b = self.expr(b, ids[1])?;
+            return Ok(self.binary(op, a, b, id)?);
+        }
+        Ok(match e {
+            Expr::And(a, b) => match self.cond(a, ids[0])? {
+                true => self.expr(b, ids[1])?,
+                false => Value::Bool(false),
+            },
+            Expr::Or(a, b) => match self.cond(a, ids[0])? {
+                true => Value::Bool(true),
+                false => self.expr(b, ids[1])?,
+            },
+            Expr::Pow(a, b) => {
+                let a = self.expr(a, ids[0])?;
+                pow(a, self.expr(b, ids[1])?)
+            }
+            Expr::Index(a, i) => {
+                let a = self.expr(a, ids[0])?;
+                match self.expr(i, ids[1])? {
+                    Value::I32(i) => element(&a, i, &self.heap).map_err(|k| self.trap(k, id))?,
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn call_expr(&mut self, e: &'a Expr, f: &'a Expr, args: &'a [Expr], id: ExprId) -> Eval {
+        let ids = self.sizes.children(e, id);
+        let f = self.expr(f, ids[0])?;
+        let mut values = Vec::new();
+        for (e, id) in args.iter().zip(&ids[1..]) {
+            values.push(self.expr(e, *id)?);
+        }
+        Ok(self.call_value(f, values, id)?)
+    }
+
+    fn block(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        let (stmts, last) = match e {
+            Expr::Block(stmts, last) => (stmts, last),
+            _ => unreachable!(),
+        };
+        for (e, id) in stmts.iter().zip(self.sizes.children(e, id)) {
+            self.expr(e, id)?;
+        }
+        match &**last {
+            Some(e) => self.expr(e, id - 1),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    fn control(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        let res = self.res;
+        let ids = self.sizes.children(e, id);
+        Ok(match e {
+            Expr::If(first, elifs, els) => {
+                let arms = std::iter::once(&**first).chain(elifs);
+                for ((cond, then), ids) in arms.zip(ids.chunks(2)) {
+                    if self.cond(cond, ids[0])? {
+                        let v = self.expr(then, ids[1])?;
+                        return Ok(if els.is_some() { v } else { Value::Unit });
+                    }
+                }
+                match &**els {
+                    Some(e) => self.expr(e, id - 1)?,
+                    None => Value::Unit,
+                }
+            }
+            Expr::While(cond, body) => {
+                while self.cond(cond, ids[0])? && self.loop_body(body, ids[1], id)? {}
+                Value::Unit
+            }
+            Expr::For(init, cond, step, body) => {
+                self.expr(init, ids[0])?;
+                while self.cond(cond, ids[1])? && self.loop_body(body, ids[3], id)? {
+                    self.expr(step, ids[2])?;
+                }
+                Value::Unit
+            }
+            Expr::Return(x) => {
+                let v = match &**x {
+                    Some(x) => self.expr(x, id - 1)?,
+                    None => Value::Unit,
+                };
+                return Err(Flow::Return(v));
+            }
+            Expr::Break(_) => return Err(Flow::Break(res.loops[&id])),
+            Expr::Continue(_) => return Err(Flow::Continue(res.loops[&id])),
+            Expr::Match(x, arms, default) => {
+                let v = self.expr(x, ids[0])?;
+                let r = self.deref(v, id)?;
+                let (variant, values) = match &self.heap[r] {
+                    Object::Enum(_, variant, values) => (*variant, values.clone()),
+                    _ => unreachable!(),
+                };
+                let binds = res.binds.get(&id).map_or(&[][..], |ds| &ds[..]);
+                let mut offset = 0;
+                for ((v, xs, body), id) in arms.iter().zip(&ids[1..]) {
+                    if *v == variant {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.extend(binds[offset..offset + xs.len()].iter().cloned().zip(values));
+                        return self.expr(body, *id);
+                    }
+                    offset += xs.len();
+                }
+                match &**default {
+                    Some(e) => self.expr(e, id - 1)?,
+                    None => Value::Unit,
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn assign(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        let ids = self.sizes.children(e, id);
+        match e {
+            Expr::Let(_, x) => {
+                let v = self.expr(x, ids[0])?;
+                let d = self.res.binds[&id][0];
+                self.frames.last_mut().unwrap().insert(d, v);
+            }
+            Expr::Set(lhs, rhs) => {
+                let p = self.place(lhs, ids[0])?;
+                let v = self.expr(rhs, ids[1])?;
+                self.store(p, v, id)?;
+            }
+            Expr::CompoundSet(op, lhs, rhs) => {
+                let p = self.place(lhs, ids[0])?;
+                let a = self.load(&p, id)?;
+                let b = self.expr(rhs, ids[1])?;
+                let v = match assign_op(*op) {
+                    Some(op) => self.binary(op, a, b, id)?,
+                    None => pow(a, b),
+                };
+                self.store(p, v, id)?;
+            }
+            _ => unreachable!(),
+        }
+        Ok(Value::Unit)
+    }
+
+    // 再帰の度に積まれるので、各式の処理は別の関数に分けてスタックフレームを小さく保つ
+    fn expr(&mut self, e: &'a Expr, id: ExprId) -> Eval {
+        match e {
+            Expr::I32Literal(x) => Ok(Value::I32(*x)),
+            Expr::I64Literal(x) => Ok(Value::I64(*x)),
+            Expr::F32Literal(x) => Ok(Value::F32(*x)),
+            Expr::F64Literal(x) => Ok(Value::F64(*x)),
+            Expr::BoolLiteral(x) => Ok(Value::Bool(*x)),
+            Expr::CharLiteral(x) => Ok(Value::Char(*x)),
+            Expr::StringLiteral(x) => Ok(Value::String(*x)),
+            Expr::Var(_) => Ok(self.read(self.res.uses[&id], self.spans.expr(id))?),
+            Expr::StructLiteral(..)
+            | Expr::EnumLiteral(..)
+            | Expr::ArrayLiteral(..)
+            | Expr::Lambda(..)
+            | Expr::Closure(..) => self.literal(e, id),
+            Expr::Not(_)
+            | Expr::Plus(_)
+            | Expr::Minus(_)
+            | Expr::Cast(..)
+            | Expr::Bitcast(..)
+            | Expr::Member(..) => self.unary(e, id),
+            Expr::Call(f, args) | Expr::CallIndirect(f, args) => self.call_expr(e, f, args, id),
+            Expr::Block(..) => self.block(e, id),
+            Expr::If(..)
+            | Expr::While(..)
+            | Expr::For(..)
+            | Expr::Return(_)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Match(..) => self.control(e, id),
+            Expr::Labeled(_, x) => self.expr(x, id - 1),
+            Expr::Let(..) | Expr::Set(..) | Expr::CompoundSet(..) => self.assign(e, id),
+            _ => self.binary_expr(e, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::closure::convert;
+    use crate::desugar::desugar;
+    use crate::optimize::{optimize, OptLevel};
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // 関数を呼び出した結果を表示した文字列
+    fn run(m: &Module, spans: &Spans, host: Host, calls: &[(&str, Vec<Value>)]) -> Vec<String> {
+        let res = resolve(m, spans);
+        assert_eq!(res.errors, vec![]);
+        assert_eq!(check(m, spans, &res).errors, vec![]);
+        let mut it = Interpreter::new(m, spans, &res, host);
+        calls
+            .iter()
+            .map(|(f, args)| match it.call(f, args.clone()) {
+                Ok(v) => it.show(&v),
+                Err(t) => format!("{:?}", t.kind),
+            })
+            .collect()
+    }
+
+    fn run_src(src: &str, calls: &[(&str, Vec<Value>)]) -> Vec<String> {
+        let (m, spans) = parse_with_spans(src).unwrap();
+        run(&m, &spans, Host::new(), calls)
+    }
+
+    #[test]
+    fn run_test() {
+        let src = r#"
+            struct P { x: i32, y: F64 }
+            enum Shape { Circle(F64), Rect(F64, F64), Empty() }
+            union U { i: i32, f: F32 }
+            const N: i32 = 10;
+            let counter = 0;
+
+            fun fib(n: i32): i32 { if (n < 2) n else fib(n - 1) + fib(n - 2) }
+
+            fun area(s: Shape): F64 {
+                match (s) { Circle(r) => 3.0 * r * r, Rect(w, h) => w * h, _ => 0.0 }
+            }
+
+            fun loops(): i32 {
+                let s = 0;
+                outer: for (let i = 0; i < N; i += 1) {
+                    let j = 0;
+                    while (true) {
+                        j += 1;
+                        if (j > i) { continue outer; };
+                        if (i * j > 20) { break outer; };
+                        s += i * j;
+                    };
+                };
+                s
+            }
+
+            fun arrays(): [i32] {
+                let xs = [i32; 5];
+                for (let i = 0; i < 5; i += 1) { xs[i] = i ** 2; };
+                xs[4] **= 2;
+                xs
+            }
+
+            fun adder(k: i32): fun(i32): i32 { \[k](x: i32): i32 -> x + k }
+
+            fun apply(f: fun(i32): i32, x: i32): i32 { f(x) }
+
+            fun tick(): i32 { counter += 1; counter }
+
+            fun misc(): P {
+                let u = U { f: 1.0f32 };
+                let s = "héllo";
+                let p = P { y: area(Shape::Rect(2.0, 1.5)), x: u.i };
+                if (s[1] as i32 == 195) { p.y = p.y + 0.5; };
+                p
+            }
+        "#;
+        let out = run_src(
+            src,
+            &[
+                ("fib", vec![Value::I32(15)]),
+                ("area", vec![Value::Null]),
+                ("loops", vec![]),
+                ("arrays", vec![]),
+                ("tick", vec![]),
+                ("tick", vec![]),
+                ("misc", vec![]),
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                "610",
+                "NullReference",
+                "115",
+                "[0, 1, 4, 9, 256]",
+                "1",
+                "2",
+                "P { y: 3.5, x: 1065353216 }",
+            ]
+        );
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let mut it = Interpreter::new(&m, &spans, &res, Host::new());
+        let add = it.call("adder", vec![Value::I32(7)]).unwrap();
+        assert_eq!(
+            it.call("apply", vec![add, Value::I32(5)]),
+            Ok(Value::I32(12))
+        );
+        assert_eq!(it.global_value("N"), Ok(Value::I32(10)));
+    }
+
+    #[test]
+    fn trap_test() {
+        let src = r#"
+            fun div(a: i32, b: i32): i32 { a / b }
+            fun rem(a: i64, b: i64): i64 { a % b }
+            fun index(i: i32): i32 { let xs = [i32; 3]; xs[i] }
+            fun trunc(x: F64): i32 { x as i32 }
+            fun len(n: i32): [bool] { [bool; n] }
+            fun deep(n: i32): i32 { deep(n + 1) }
+        "#;
+        let min = Value::I32(i32::MIN);
+        let out = run_src(
+            src,
+            &[
+                ("div", vec![Value::I32(7), Value::I32(0)]),
+                ("div", vec![min.clone(), Value::I32(-1)]),
+                ("div", vec![Value::I32(-7), Value::I32(2)]),
+                ("rem", vec![Value::I64(i64::MIN), Value::I64(-1)]),
+                ("index", vec![Value::I32(3)]),
+                ("index", vec![Value::I32(-1)]),
+                ("trunc", vec![Value::F64(2147483648.0)]),
+                ("trunc", vec![Value::F64(-2.9)]),
+                ("len", vec![Value::I32(-1)]),
+                ("deep", vec![Value::I32(0)]),
+                ("missing", vec![]),
+            ],
+        );
+        assert_eq!(
+            out,
+            vec![
+                "DivisionByZero",
+                "Overflow",
+                "-3",
+                "0i64",
+                "OutOfBounds(3, 3)",
+                "OutOfBounds(-1, 3)",
+                "Overflow",
+                "-2",
+                "NegativeLength(-1)",
+                "StackOverflow",
+                "UnknownFunction(\"missing\")",
+            ]
+        );
+
+        // トラップの位置は原因の式の範囲
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let mut it = Interpreter::new(&m, &spans, &res, Host::new());
+        let trap = it
+            .call("div", vec![Value::I32(1), Value::I32(0)])
+            .unwrap_err();
+        assert_eq!(&src[trap.span.pos..trap.span.end()], "a / b");
+    }
+
+    #[test]
+    fn host_test() {
+        let src = r#"
+            extern "env" "log" fun log(x: i32);
+            extern "env" "missing" fun missing(): i32;
+            fun main(): i32 {
+                for (let i = 0; i < 3; i += 1) { log(i * 10); };
+                2.0 ** 10.0 as i32
+            }
+            fun fail(): i32 { missing() }
+        "#;
+        let logs = Rc::new(RefCell::new(Vec::new()));
+        let mut host = Host::new();
+        let l = logs.clone();
+        host.register("env", "log", move |args| {
+            l.borrow_mut().push(args[0].clone());
+            Value::Unit
+        });
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let out = run(&m, &spans, host, &[("main", vec![]), ("fail", vec![])]);
+        assert_eq!(out, vec!["1024", "UnknownImport(\"env\", \"missing\")"]);
+        assert_eq!(
+            *logs.borrow(),
+            vec![Value::I32(0), Value::I32(10), Value::I32(20)]
+        );
+    }
+
+    // 各変換の前後で同じ結果になることを確かめる
+    #[test]
+    fn differential_test() {
+        let src = r#"
+            struct Acc { sum: i64, n: i32 }
+            enum Op { Add(i32), Mul(i32), Skip() }
+
+            fun step(acc: Acc, op: Op): Acc {
+                match (op) {
+                    Add(x) => Acc { sum: acc.sum + x as i64, n: acc.n + 1 },
+                    Mul(x) => Acc { sum: acc.sum * x as i64, n: acc.n + 1 },
+                    _ => acc
+                }
+            }
+
+            fun sq(x: i32): i32 { x * x }
+
+            fun compose(f: fun(i32): i32, g: fun(i32): i32): fun(i32): i32 {
+                \[f, g](x: i32): i32 -> g(f(x))
+            }
+
+            fun main(n: i32): i64 {
+                let acc = Acc { sum: 1i64, n: 0 };
+                let h = compose(sq, \[n](x: i32): i32 -> x + n);
+                for (let i = 0; i < n; i += 1) {
+                    if (i % 3 == 0) { continue; } elif (i > 20) { break; };
+                    acc = step(acc, if (i % 2 == 0) Op::Add(h(i)) else Op::Mul(sq(2) - 1 + 0 * i));
+                };
+                acc.sum ** 2i64 + (acc.n as i64) + (n ** -1) as i64
+            }
+
+            fun floats(x: F32): F32 {
+                let y = x % 0.75f32;
+                y %= 0.3f32;
+                x ** 0.5f32 + y + (1.0f32 / 0.0f32 - 1.0f32) * 0.0f32
+            }
+        "#;
+        let calls = [
+            ("main", vec![Value::I32(0)]),
+            ("main", vec![Value::I32(1)]),
+            ("main", vec![Value::I32(10)]),
+            ("main", vec![Value::I32(40)]),
+            ("floats", vec![Value::F32(2.0)]),
+        ];
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let expected = run(&m, &spans, Host::new(), &calls);
+
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        let converted = convert(&m, &res, &typed).module;
+        let spans = Spans::default();
+        assert_eq!(run(&converted, &spans, Host::new(), &calls), expected);
+
+        let res = resolve(&converted, &spans);
+        let typed = check(&converted, &spans, &res);
+        let desugared = desugar(&converted, &typed);
+        assert_eq!(run(&desugared, &spans, Host::new(), &calls), expected);
+
+        for level in [OptLevel::O1, OptLevel::O2] {
+            let optimized = optimize(&desugared, level);
+            assert_eq!(run(&optimized, &spans, Host::new(), &calls), expected);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// 線形メモリ上の値の表現(外部関数との間のABI)
+//
+// 値の型とwasmの型
+//   i32, bool, char, 参照型 -> i32 / i64 -> i64 / F32 -> f32 / F64 -> f64
+//   boolは0か1、charはUnicodeのスカラー値
+//   参照型の値はオブジェクトの先頭を指すポインタで、nullは0
+//
+// メモリ上の大きさとアラインメント(リトルエンディアン)
+//   bool: 1, i32, F32, char, 参照型: 4, i64, F64: 8
+//
+// オブジェクト
+//   string: [バイト長: i32][UTF-8のバイト列]
+//     添字はバイト単位で、そのバイトを0から255のi32として読む
+//   [T]: [要素数: i32][Tのアラインメントまでパディング][T; 要素数]
+//     要素はsize_of(T)ごとに詰めて並ぶ
+//   struct: フィールドを宣言順に、各フィールドのアラインメントに揃えて並べる
+//     全体の大きさは最大のアラインメントの倍数
+//   enum: [タグ: i32][ペイロード]
+//     タグは宣言順のヴァリアントの番号、ペイロードはオフセット4から始まる構造体と同じ並び
+//   union: 全てのフィールドがオフセット0に重なる
+//   関数の値: [関数表の添字: i32][環境へのポインタ: i32]
+//     関数表の関数は環境を第1引数に取る
+//
+// オブジェクトの先頭は8バイト境界に揃う
+// ヒープのオブジェクトの直前8バイトはゴミ集めのヘッダ(runtime.rsを参照)
+//
+// 実行時検査に失敗するとトラップの前に4番地へ符号(i32)を書く(checks.rsを参照)
+//
+// ホストが用意する関数
+//   "runtime" "pow": (f64, f64) -> f64  F64とF32の累乗。使うモジュールだけが読み込む
+//   読み込む関数はcompile::runtime_importsで分かる
+use crate::decls::TypeDecl;
+use ast::ast::{Ident, Type};
+use wasm::ast::{MemoryImmediate, OperatorCode, ValueType};
+
+pub const TAG_SIZE: u32 = 4;
+// 文字列と配列の長さ
+pub const LENGTH_SIZE: u32 = 4;
+pub const CLOSURE_FUNC: u32 = 0;
+pub const CLOSURE_ENV: u32 = 4;
+pub const CLOSURE_SIZE: u32 = 8;
+
+// 0番地はnullとして使うので静的データはその後ろから置く
+pub const DATA_BASE: u32 = 8;
+// ポインタがi32の正の値に収まるよう、静的データは2GiBまでにする
+pub const DATA_LIMIT: u64 = 1 << 31;
+pub const TRAP_CODE: u32 = 4;
+
+pub const RUNTIME_MODULE: &str = "runtime";
+pub const RUNTIME_POW: &str = "pow";
+
+pub fn value_type(t: &Type) -> ValueType {
+    match t {
+        Type::I32 | Type::Bool | Type::Char | Type::RefType(_) => ValueType::I32,
+        Type::I64 => ValueType::I64,
+        Type::F32 => ValueType::F32,
+        Type::F64 => ValueType::F64,
+    }
+}
+
+pub fn size_of(t: &Type) -> u32 {
+    match t {
+        Type::Bool => 1,
+        Type::I32 | Type::F32 | Type::Char => 4,
+        Type::I64 | Type::F64 => 8,
+        Type::RefType(_) => 4,
+    }
+}
+
+pub fn align_of(t: &Type) -> u32 {
+    size_of(t)
+}
+
+pub fn align_to(x: u32, align: u32) -> u32 {
+    x.div_ceil(align) * align
+}
+
+pub fn elems_offset(t: &Type) -> u32 {
+    align_to(LENGTH_SIZE, align_of(t))
+}
+
+// flagsはアラインメントの2の対数
+pub fn memory_immediate(t: &Type, offset: u32) -> MemoryImmediate {
+    MemoryImmediate {
+        flags: align_of(t).trailing_zeros(),
+        offset,
+    }
+}
+
+pub fn load(t: &Type, offset: u32) -> OperatorCode {
+    let m = memory_immediate(t, offset);
+    match t {
+        Type::Bool => OperatorCode::I32Load8u(m),
+        Type::I64 => OperatorCode::I64Load(m),
+        Type::F32 => OperatorCode::F32Load(m),
+        Type::F64 => OperatorCode::F64Load(m),
+        Type::I32 | Type::Char | Type::RefType(_) => OperatorCode::I32Load(m),
+    }
+}
+
+pub fn store(t: &Type, offset: u32) -> OperatorCode {
+    let m = memory_immediate(t, offset);
+    match t {
+        Type::Bool => OperatorCode::I32Store8(m),
+        Type::I64 => OperatorCode::I64Store(m),
+        Type::F32 => OperatorCode::F32Store(m),
+        Type::F64 => OperatorCode::F64Store(m),
+        Type::I32 | Type::Char | Type::RefType(_) => OperatorCode::I32Store(m),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordLayout {
+    pub offsets: Vec<u32>,
+    pub size: u32,
+    pub align: u32,
+}
+
+impl RecordLayout {
+    pub fn new<'a, I: IntoIterator<Item = &'a Type>>(types: I, start: u32) -> RecordLayout {
+        let mut offsets = Vec::new();
+        let mut size = start;
+        let mut align = 1;
+        for t in types {
+            let a = align_of(t);
+            size = align_to(size, a);
+            offsets.push(size);
+            size += size_of(t);
+            align = align.max(a);
+        }
+        RecordLayout {
+            offsets,
+            size: align_to(size, align),
+            align,
+        }
+    }
+}
+
+// タグ(i32)の後にペイロードが続く
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnumLayout {
+    pub variants: Vec<RecordLayout>,
+    pub size: u32,
+    pub align: u32,
+}
+
+impl EnumLayout {
+    pub fn new(variants: &[(Ident, Vec<Type>)]) -> EnumLayout {
+        let variants = variants
+            .iter()
+            .map(|(_, ts)| RecordLayout::new(ts, TAG_SIZE))
+            .collect::<Vec<_>>();
+        let align = variants.iter().map(|v| v.align).fold(TAG_SIZE, u32::max);
+        let size = variants.iter().map(|v| v.size).fold(TAG_SIZE, u32::max);
+        EnumLayout {
+            variants,
+            size: align_to(size, align),
+            align,
+        }
+    }
+}
+
+// 全てのフィールドがオフセット0に重なる
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnionLayout {
+    pub size: u32,
+    pub align: u32,
+}
+
+impl UnionLayout {
+    pub fn new(fields: &[(Ident, Type)]) -> UnionLayout {
+        let align = fields.iter().map(|(_, t)| align_of(t)).fold(1, u32::max);
+        let size = fields.iter().map(|(_, t)| size_of(t)).fold(0, u32::max);
+        UnionLayout {
+            size: align_to(size, align),
+            align,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeLayout {
+    Struct(RecordLayout),
+    Enum(EnumLayout),
+    Union(UnionLayout),
+}
+
+impl TypeLayout {
+    pub fn new(decl: &TypeDecl) -> TypeLayout {
+        match decl {
+            TypeDecl::Struct(fields) => {
+                TypeLayout::Struct(RecordLayout::new(fields.iter().map(|(_, t)| t), 0))
+            }
+            TypeDecl::Enum(variants) => TypeLayout::Enum(EnumLayout::new(variants)),
+            TypeDecl::Union(fields) => TypeLayout::Union(UnionLayout::new(fields)),
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        match self {
+            TypeLayout::Struct(l) => l.size,
+            TypeLayout::Enum(l) => l.size,
+            TypeLayout::Union(l) => l.size,
+        }
+    }
+
+    // 構造体と共用体のi番目のフィールドのオフセット
+    pub fn field(&self, i: usize) -> u32 {
+        match self {
+            TypeLayout::Struct(l) => l.offsets[i],
+            TypeLayout::Union(_) => 0,
+            TypeLayout::Enum(_) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::ast::RefType;
+    use token::intern::Sym;
+
+    #[test]
+    fn enum_layout_test() {
+        let s = Sym::intern;
+        let layout = EnumLayout::new(&[
+            (s("A"), vec![Type::Bool, Type::F64, Type::I32]),
+            (s("B"), vec![]),
+            (s("C"), vec![Type::Char]),
+        ]);
+        assert_eq!(layout.variants[0].offsets, vec![4, 8, 16]);
+        assert_eq!(layout.variants[1].offsets, Vec::<u32>::new());
+        assert_eq!(layout.variants[2].offsets, vec![4]);
+        assert_eq!((layout.size, layout.align), (24, 8));
+    }
+
+    #[test]
+    fn union_layout_test() {
+        let s = Sym::intern;
+        let layout = UnionLayout::new(&[(s("a"), Type::Bool), (s("b"), Type::I64)]);
+        assert_eq!((layout.size, layout.align), (8, 8));
+    }
+
+    #[test]
+    fn load_store_test() {
+        let m = |flags, offset| MemoryImmediate { flags, offset };
+        assert_eq!(load(&Type::Bool, 4), OperatorCode::I32Load8u(m(0, 4)));
+        assert_eq!(load(&Type::F32, 0), OperatorCode::F32Load(m(2, 0)));
+        assert_eq!(store(&Type::I64, 8), OperatorCode::I64Store(m(3, 8)));
+        assert_eq!(
+            store(&Type::RefType(RefType::String), 12),
+            OperatorCode::I32Store(m(2, 12))
+        );
+        assert_eq!(elems_offset(&Type::Char), 4);
+        assert_eq!(elems_offset(&Type::F64), 8);
+    }
+}
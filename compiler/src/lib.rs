@@ -0,0 +1,16 @@
+pub mod cast;
+pub mod checks;
+pub mod closure;
+pub mod codegen;
+pub mod compile;
+pub mod decls;
+pub mod desugar;
+pub mod export;
+pub mod global;
+pub mod interp;
+pub mod layout;
+pub mod optimize;
+pub mod prune;
+pub mod resolve;
+pub mod runtime;
+pub mod typeck;
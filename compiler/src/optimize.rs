@@ -0,0 +1,518 @@
+use crate::global::{bin_op, binary, bitcast, cast, Const};
+use ast::ast::{Expr, FuncDef, Ident, Member, Module, Type};
+use ast::fold::{self, Fold};
+use ast::visit::{self, Visitor};
+use std::collections::{HashMap, HashSet};
+
+// O1は定数畳み込み、到達しない分岐の除去、代数的簡約
+// O2はそれに加えて小さな純粋関数をインライン展開する
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+// インライン展開する関数本体の最大ノード数
+const INLINE_SIZE: usize = 16;
+
+// 文字列リテラルは評価の度に新しい参照を作るので畳み込まない
+fn constant(e: &Expr) -> Option<Const> {
+    Some(match e {
+        Expr::I32Literal(x) => Const::I32(*x),
+        Expr::I64Literal(x) => Const::I64(*x),
+        Expr::F32Literal(x) => Const::F32(*x),
+        Expr::F64Literal(x) => Const::F64(*x),
+        Expr::BoolLiteral(x) => Const::Bool(*x),
+        Expr::CharLiteral(x) => Const::Char(*x),
+        _ => return None,
+    })
+}
+
+fn literal(c: Const) -> Expr {
+    match c {
+        Const::I32(x) => Expr::I32Literal(x),
+        Const::I64(x) => Expr::I64Literal(x),
+        Const::F32(x) => Expr::F32Literal(x),
+        Const::F64(x) => Expr::F64Literal(x),
+        Const::Bool(x) => Expr::BoolLiteral(x),
+        Const::Char(x) => Expr::CharLiteral(x),
+        Const::String(_) | Const::Array(_, _) => unreachable!(),
+    }
+}
+
+// 整数リテラルの値
+// 浮動小数点数は符号付きゼロやNaNがあるので代数的簡約の対象にしない
+fn int(e: &Expr) -> Option<i64> {
+    match e {
+        Expr::I32Literal(x) => Some(*x as i64),
+        Expr::I64Literal(x) => Some(*x),
+        _ => None,
+    }
+}
+
+// シフト量はビット幅で剰余を取る
+fn zero_shift(e: &Expr) -> bool {
+    match e {
+        Expr::I32Literal(x) => x & 31 == 0,
+        Expr::I64Literal(x) => x & 63 == 0,
+        _ => false,
+    }
+}
+
+// 副作用もトラップも無いので、評価を省いたり複製したりできる式
+// 整数の除算と浮動小数点数から整数へのキャストはトラップし得る
+fn pure(e: &Expr) -> bool {
+    match e {
+        Expr::I32Literal(_)
+        | Expr::I64Literal(_)
+        | Expr::F32Literal(_)
+        | Expr::F64Literal(_)
+        | Expr::StringLiteral(_)
+        | Expr::BoolLiteral(_)
+        | Expr::CharLiteral(_)
+        | Expr::Var(_) => true,
+        Expr::Closure(_, env) => env.is_none(),
+        Expr::Block(stmts, last) => stmts.is_empty() && last.is_none(),
+        Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) | Expr::Bitcast(x, _) => pure(x),
+        Expr::Add(a, b)
+        | Expr::Sub(a, b)
+        | Expr::Mul(a, b)
+        | Expr::And(a, b)
+        | Expr::Or(a, b)
+        | Expr::BitAnd(a, b)
+        | Expr::BitOr(a, b)
+        | Expr::BitXor(a, b)
+        | Expr::Shl(a, b)
+        | Expr::Shr(a, b)
+        | Expr::UShr(a, b)
+        | Expr::Eq(a, b)
+        | Expr::Ne(a, b)
+        | Expr::Lt(a, b)
+        | Expr::Lte(a, b)
+        | Expr::Gt(a, b)
+        | Expr::Gte(a, b) => pure(a) && pure(b),
+        _ => false,
+    }
+}
+
+fn unit() -> Expr {
+    Expr::Block(Vec::new(), Box::new(None))
+}
+
+// 取り出した分岐の`let`が外側のスコープに漏れないようにする
+fn scoped(e: Expr) -> Expr {
+    match e {
+        e @ Expr::Let(_, _) => Expr::Block(vec![e], Box::new(None)),
+        e => e,
+    }
+}
+
+// elseの無いifの分岐は値を捨てる
+fn discard(e: Expr) -> Expr {
+    match e {
+        Expr::Block(stmts, last) if last.is_none() => Expr::Block(stmts, last),
+        e => Expr::Block(vec![e], Box::new(None)),
+    }
+}
+
+fn diverges(e: &Expr) -> bool {
+    matches!(e, Expr::Return(_) | Expr::Break(_) | Expr::Continue(_))
+}
+
+// パラメータとリテラルと演算だけからなる式
+// 束縛を含まないので実引数を代入しても名前が捕獲されない
+fn inlinable(e: &Expr, params: &[(Ident, Type)], size: &mut usize) -> bool {
+    *size += 1;
+    if *size > INLINE_SIZE {
+        return false;
+    }
+    match e {
+        Expr::Var(x) => params.iter().any(|(p, _)| p == x),
+        Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) | Expr::Cast(x, _) | Expr::Bitcast(x, _) => {
+            inlinable(x, params, size)
+        }
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            inlinable(a, params, size) && inlinable(b, params, size)
+        }
+        Expr::If(first, elifs, els) => match &**els {
+            Some(els) => {
+                std::iter::once(&**first)
+                    .chain(elifs.iter())
+                    .all(|(cond, then)| {
+                        inlinable(cond, params, size) && inlinable(then, params, size)
+                    })
+                    && inlinable(els, params, size)
+            }
+            None => false,
+        },
+        e => match bin_op(e) {
+            Some((_, a, b)) => inlinable(a, params, size) && inlinable(b, params, size),
+            None => constant(e).is_some(),
+        },
+    }
+}
+
+struct Subst(HashMap<Ident, Expr>);
+
+impl Fold for Subst {
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        match e {
+            Expr::Var(x) if self.0.contains_key(&x) => self.0[&x].clone(),
+            e => fold::walk_expr(self, e),
+        }
+    }
+}
+
+// 関数内で束縛される名前
+// これらと同名の関数の呼び出しは展開しない
+struct Binders(HashSet<Ident>);
+
+impl Visitor for Binders {
+    fn visit_expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Let(x, _) => {
+                self.0.insert(*x);
+            }
+            Expr::Match(_, arms, _) => {
+                for (_, binds, _) in arms {
+                    self.0.extend(binds.iter().cloned());
+                }
+            }
+            Expr::Lambda(caps, params, _, _) => {
+                self.0.extend(caps.iter().cloned());
+                self.0.extend(params.iter().map(|(x, _)| *x));
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, e)
+    }
+}
+
+struct Optimizer {
+    inline: HashMap<Ident, (Vec<Ident>, Expr)>,
+    bound: HashSet<Ident>,
+}
+
+impl Optimizer {
+    fn inline_call(&self, f: &Expr, args: &[Expr]) -> Option<Expr> {
+        let (params, body) = match f {
+            Expr::Var(x) if !self.bound.contains(x) => self.inline.get(x)?,
+            _ => return None,
+        };
+        // 実引数が純粋なら評価の省略や複製をしても意味が変わらない
+        if params.len() != args.len() || !args.iter().all(pure) {
+            return None;
+        }
+        let mut subst = Subst(params.iter().cloned().zip(args.iter().cloned()).collect());
+        Some(subst.fold_expr(body.clone()))
+    }
+
+    fn branches(
+        &mut self,
+        first: (Expr, Expr),
+        elifs: Vec<(Expr, Expr)>,
+        els: Option<Expr>,
+    ) -> Expr {
+        let has_else = els.is_some();
+        let mut arms = Vec::new();
+        let mut els = els;
+        for (cond, then) in std::iter::once(first).chain(elifs) {
+            match cond {
+                Expr::BoolLiteral(false) => {}
+                Expr::BoolLiteral(true) if has_else => {
+                    els = Some(then);
+                    break;
+                }
+                Expr::BoolLiteral(true) if arms.is_empty() => return discard(then),
+                // 値を持たないifのままにするため条件は残す
+                cond @ Expr::BoolLiteral(true) => {
+                    arms.push((cond, then));
+                    break;
+                }
+                cond => arms.push((cond, then)),
+            }
+        }
+        if arms.is_empty() {
+            return match els {
+                Some(els) if has_else => scoped(els),
+                _ => unit(),
+            };
+        }
+        let first = arms.remove(0);
+        Expr::If(Box::new(first), arms, Box::new(els))
+    }
+
+    fn block(&mut self, stmts: Vec<Expr>, last: Option<Expr>) -> Expr {
+        let mut out = Vec::new();
+        for stmt in stmts {
+            let diverges = diverges(&stmt);
+            if !pure(&stmt) {
+                out.push(stmt);
+            }
+            // 脱出する文より後ろには到達しない
+            if diverges {
+                return Expr::Block(out, Box::new(None));
+            }
+        }
+        Expr::Block(out, Box::new(last))
+    }
+
+    fn simplify(&mut self, e: Expr) -> Expr {
+        if let Some((op, a, b)) = bin_op(&e) {
+            if let (Some(a), Some(b)) = (constant(a), constant(b)) {
+                // トラップする演算は実行時まで残す
+                if let Ok(c) = binary(op, a, b) {
+                    return literal(c);
+                }
+            }
+        }
+        match e {
+            Expr::Not(x) => match *x {
+                Expr::BoolLiteral(x) => Expr::BoolLiteral(!x),
+                Expr::Not(x) => *x,
+                x => Expr::Not(Box::new(x)),
+            },
+            Expr::Plus(x) => *x,
+            Expr::Minus(x) => match *x {
+                Expr::I32Literal(x) => Expr::I32Literal(x.wrapping_neg()),
+                Expr::I64Literal(x) => Expr::I64Literal(x.wrapping_neg()),
+                Expr::F32Literal(x) => Expr::F32Literal(-x),
+                Expr::F64Literal(x) => Expr::F64Literal(-x),
+                Expr::Minus(x) => *x,
+                x => Expr::Minus(Box::new(x)),
+            },
+            Expr::Cast(x, t) => match constant(&x).map(|c| cast(c, &t)) {
+                Some(Ok(c)) => literal(c),
+                _ => Expr::Cast(x, t),
+            },
+            Expr::Bitcast(x, t) => match constant(&x).map(|c| bitcast(c, &t)) {
+                Some(Ok(c)) => literal(c),
+                _ => Expr::Bitcast(x, t),
+            },
+            Expr::And(a, b) => match (*a, *b) {
+                (Expr::BoolLiteral(true), b) => b,
+                (a @ Expr::BoolLiteral(false), _) => a,
+                (a, Expr::BoolLiteral(true)) => a,
+                (a, b @ Expr::BoolLiteral(false)) if pure(&a) => b,
+                (a, b) => Expr::And(Box::new(a), Box::new(b)),
+            },
+            Expr::Or(a, b) => match (*a, *b) {
+                (Expr::BoolLiteral(false), b) => b,
+                (a @ Expr::BoolLiteral(true), _) => a,
+                (a, Expr::BoolLiteral(false)) => a,
+                (a, b @ Expr::BoolLiteral(true)) if pure(&a) => b,
+                (a, b) => Expr::Or(Box::new(a), Box::new(b)),
+            },
+            Expr::Add(a, b) => match (int(&a), int(&b)) {
+                (_, Some(0)) => *a,
+                (Some(0), _) => *b,
+                _ => Expr::Add(a, b),
+            },
+            Expr::Sub(a, b) if int(&b) == Some(0) => *a,
+            Expr::Mul(a, b) => match (int(&a), int(&b)) {
+                (_, Some(1)) => *a,
+                (Some(1), _) => *b,
+                (_, Some(0)) if pure(&a) => *b,
+                (Some(0), _) if pure(&b) => *a,
+                _ => Expr::Mul(a, b),
+            },
+            Expr::Div(a, b) if int(&b) == Some(1) => *a,
+            Expr::Mod(a, b) if matches!(int(&b), Some(1) | Some(-1)) && pure(&a) => match *b {
+                Expr::I32Literal(_) => Expr::I32Literal(0),
+                _ => Expr::I64Literal(0),
+            },
+            Expr::BitAnd(a, b) => match (int(&a), int(&b)) {
+                (_, Some(-1)) => *a,
+                (Some(-1), _) => *b,
+                (_, Some(0)) if pure(&a) => *b,
+                (Some(0), _) if pure(&b) => *a,
+                _ => Expr::BitAnd(a, b),
+            },
+            Expr::BitOr(a, b) => match (int(&a), int(&b)) {
+                (_, Some(0)) => *a,
+                (Some(0), _) => *b,
+                (_, Some(-1)) if pure(&a) => *b,
+                (Some(-1), _) if pure(&b) => *a,
+                _ => Expr::BitOr(a, b),
+            },
+            Expr::BitXor(a, b) => match (int(&a), int(&b)) {
+                (_, Some(0)) => *a,
+                (Some(0), _) => *b,
+                _ => Expr::BitXor(a, b),
+            },
+            Expr::Shl(a, b) | Expr::Shr(a, b) | Expr::UShr(a, b) if zero_shift(&b) => *a,
+            Expr::If(first, elifs, els) => self.branches(*first, elifs, *els),
+            Expr::While(cond, _) if *cond == Expr::BoolLiteral(false) => unit(),
+            Expr::Block(stmts, last) => self.block(stmts, *last),
+            Expr::Call(f, args) => match self.inline_call(&f, &args) {
+                // 展開した本体は呼び出しを含まないので再帰は停止する
+                Some(e) => self.fold_expr(e),
+                None => Expr::Call(f, args),
+            },
+            e => e,
+        }
+    }
+}
+
+impl Fold for Optimizer {
+    fn fold_member(&mut self, m: Member) -> Member {
+        self.bound = match &m {
+            Member::Func(FuncDef(_, params, _), body, _) => {
+                let mut binders = Binders(params.iter().map(|(x, _)| *x).collect());
+                binders.visit_expr(body);
+                binders.0
+            }
+            _ => HashSet::new(),
+        };
+        fold::walk_member(self, m)
+    }
+
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        match e {
+            // ループを取り除くときはラベルごと取り除く
+            Expr::Labeled(label, body) => match fold::walk_expr(self, *body) {
+                Expr::While(cond, _) if *cond == Expr::BoolLiteral(false) => unit(),
+                body => Expr::Labeled(label, Box::new(body)),
+            },
+            e => {
+                let e = fold::walk_expr(self, e);
+                self.simplify(e)
+            }
+        }
+    }
+}
+
+pub fn optimize(module: &Module, level: OptLevel) -> Module {
+    if level == OptLevel::O0 {
+        return module.clone();
+    }
+    let mut opt = Optimizer {
+        inline: HashMap::new(),
+        bound: HashSet::new(),
+    };
+    let module = opt.fold_module(module.clone());
+    if level < OptLevel::O2 {
+        return module;
+    }
+    // 簡約済みの本体を展開する
+    for member in &module {
+        if let Member::Func(FuncDef(name, params, Some(_)), body, _) = member {
+            let body = match body {
+                Expr::Block(stmts, last) if stmts.is_empty() => match &**last {
+                    Some(e) => e,
+                    None => continue,
+                },
+                e => e,
+            };
+            if inlinable(body, params, &mut 0) {
+                let params = params.iter().map(|(x, _)| *x).collect();
+                opt.inline.insert(*name, (params, body.clone()));
+            }
+        }
+    }
+    opt.fold_module(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse;
+    use ast::printer::print_module;
+    use ast::span::Spans;
+
+    fn run(src: &str, level: OptLevel) -> String {
+        let out = optimize(&parse(src).unwrap(), level);
+
+        // 最適化後も名前解決と型検査を通る
+        let spans = Spans::default();
+        let res = resolve(&out, &spans);
+        assert_eq!(res.errors, vec![]);
+        assert_eq!(check(&out, &spans, &res).errors, vec![]);
+        print_module(&out)
+    }
+
+    fn normalize(src: &str) -> String {
+        print_module(&parse(src).unwrap())
+    }
+
+    #[test]
+    fn fold_test() {
+        let src = r#"
+            const a: i32 = 1 + 2 * 3;
+            const b: i32 = 2147483647 * 2;
+            const c: i64 = 1i64 << 65i64;
+            const d: i32 = 1 / 0;
+            const e: F64 = 0.1 + 0.2;
+            const f: F32 = 16777216.0f32 + 1.0f32;
+            const g: bool = !true || 3 < 2;
+            const h: i32 = -(3.9 as i32) >>> 28;
+            const i: i32 = 2.5 as i32 + (1.0 / 0.0) as i32;
+        "#;
+        let expected = r#"
+            const a: i32 = 7;
+            const b: i32 = -2;
+            const c: i64 = 2i64;
+            const d: i32 = 1 / 0;
+            const e: F64 = 0.30000000000000004;
+            const f: F32 = 16777216.0f32;
+            const g: bool = false;
+            const h: i32 = 15;
+            const i: i32 = 2 + (1.0 / 0.0) as i32;
+        "#;
+        assert_eq!(run(src, OptLevel::O1), normalize(expected));
+        assert_eq!(run(src, OptLevel::O0), normalize(src));
+    }
+
+    #[test]
+    fn simplify_test() {
+        let src = r#"
+            fun f(x: i32, y: i32): i32 {
+                let a = x * 1 + 0;
+                let b = y * 0 | 0;
+                let c = g() * 0;
+                let d = x > 0 && true || !!(y < 0);
+                if (false) { g(); } elif (x > 0) { a; } else { g(); };
+                if (a > 0) { g(); } elif (true) { g(); } elif (b > 0) { g(); };
+                while (false) { g(); };
+                l: while (false) { break l; };
+                1;
+                if (true) a else b
+            }
+            fun g(): i32 { return 1; g(); 2 }
+        "#;
+        let expected = r#"
+            fun f(x: i32, y: i32): i32 {
+                let a = x;
+                let b = 0;
+                let c = g() * 0;
+                let d = x > 0 || y < 0;
+                if (x > 0) {} else { g(); };
+                if (a > 0) { g(); } elif (true) { g(); };
+                a
+            }
+            fun g(): i32 { return 1; }
+        "#;
+        assert_eq!(run(src, OptLevel::O1), normalize(expected));
+    }
+
+    #[test]
+    fn inline_test() {
+        let src = r#"
+            fun sq(x: i32): i32 { x * x }
+            fun dist(x: i32, y: i32): i32 { if (x > y) x - y else y - x }
+            fun main(n: i32): i32 { sq(3) + dist(n, 1) + sq(main(n)) }
+            fun h(sq: fun(i32): i32): i32 { sq(2) }
+        "#;
+        let expected = r#"
+            fun sq(x: i32): i32 { x * x }
+            fun dist(x: i32, y: i32): i32 { if (x > y) x - y else y - x }
+            fun main(n: i32): i32 { 9 + (if (n > 1) n - 1 else 1 - n) + sq(main(n)) }
+            fun h(sq: fun(i32): i32): i32 { sq(2) }
+        "#;
+        assert_eq!(run(src, OptLevel::O2), normalize(expected));
+        assert_eq!(run(src, OptLevel::O1), normalize(src));
+    }
+}
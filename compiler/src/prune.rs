@@ -0,0 +1,167 @@
+use crate::typeck::{Ty, Typed};
+use ast::ast::{Expr, Module};
+use ast::fold::{self, Fold};
+use ast::span::ExprId;
+
+fn block(stmts: Vec<Expr>) -> Expr {
+    Expr::Block(stmts, Box::new(None))
+}
+
+struct Pruner<'a> {
+    typed: &'a Typed,
+    next_id: ExprId,
+    // 処理済みの式ごとに、戻ってこないかどうか
+    diverges: Vec<bool>,
+}
+
+impl<'a> Pruner<'a> {
+    // 最初の子だけを必ず評価する式
+    fn lazy(e: &Expr) -> bool {
+        matches!(
+            e,
+            Expr::And(..)
+                | Expr::Or(..)
+                | Expr::If(..)
+                | Expr::While(..)
+                | Expr::Match(..)
+                | Expr::For(..)
+        )
+    }
+
+    // 必ず評価する子の式を評価順に並べる
+    fn children(e: Expr) -> Vec<Expr> {
+        match e {
+            Expr::StructLiteral(_, fields) => fields.into_iter().map(|(_, e)| e).collect(),
+            Expr::EnumLiteral(_, _, args) => args,
+            Expr::ArrayLiteral(_, x)
+            | Expr::Not(x)
+            | Expr::Plus(x)
+            | Expr::Minus(x)
+            | Expr::Member(x, _)
+            | Expr::Let(_, x)
+            | Expr::Cast(x, _)
+            | Expr::Bitcast(x, _) => vec![*x],
+            Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+                std::iter::once(*f).chain(args).collect()
+            }
+            Expr::Index(a, b)
+            | Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Mod(a, b)
+            | Expr::BitAnd(a, b)
+            | Expr::BitOr(a, b)
+            | Expr::BitXor(a, b)
+            | Expr::Shl(a, b)
+            | Expr::Shr(a, b)
+            | Expr::UShr(a, b)
+            | Expr::Pow(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Ne(a, b)
+            | Expr::Lt(a, b)
+            | Expr::Lte(a, b)
+            | Expr::Gt(a, b)
+            | Expr::Gte(a, b)
+            | Expr::Set(a, b)
+            | Expr::CompoundSet(_, a, b) => vec![*a, *b],
+            Expr::Return(x) => (*x).into_iter().collect(),
+            Expr::Closure(_, env) => (*env).into_iter().collect(),
+            Expr::And(a, _) | Expr::Or(a, _) | Expr::While(a, _) | Expr::Match(a, _, _) => {
+                vec![*a]
+            }
+            Expr::If(first, _, _) => vec![first.0],
+            Expr::For(init, _, _, _) => vec![*init],
+            e => unreachable!("{:?}", e),
+        }
+    }
+
+    // 戻ってこない子があれば、その子までを文として評価するブロックに置き換える
+    fn prune(&self, id: ExprId, e: Expr, diverges: &[bool]) -> (Expr, bool) {
+        let never = self.typed.exprs[id] == Ty::Never;
+        match e {
+            Expr::Block(mut stmts, last) => match diverges.iter().position(|d| *d) {
+                Some(i) if i < stmts.len() && (i + 1 < stmts.len() || last.is_some()) => {
+                    stmts.truncate(i + 1);
+                    (block(stmts), true)
+                }
+                _ => (Expr::Block(stmts, last), never),
+            },
+            // 本体は別の関数になる
+            e @ Expr::Lambda(..) => (e, false),
+            e @ Expr::Labeled(..) => (e, never),
+            e => match diverges.iter().position(|d| *d) {
+                Some(i) if i == 0 || !Self::lazy(&e) => {
+                    let mut children = Self::children(e);
+                    children.truncate(i + 1);
+                    (block(children), true)
+                }
+                _ => (e, never),
+            },
+        }
+    }
+}
+
+impl<'a> Fold for Pruner<'a> {
+    fn fold_expr(&mut self, e: Expr) -> Expr {
+        let n = self.diverges.len();
+        let e = fold::walk_expr(self, e);
+        let id = self.next_id;
+        self.next_id += 1;
+        let diverges = self.diverges.split_off(n);
+        let (e, d) = self.prune(id, e, &diverges);
+        self.diverges.push(d);
+        e
+    }
+}
+
+// 戻ってこない式より後ろの、到達しない式を取り除く
+// 型検査はそこで使われる変数や演算の型をNeverやErrorのまま残すので、クロージャ変換とコード生成の前に除く
+pub fn prune(module: &Module, typed: &Typed) -> Module {
+    let mut p = Pruner {
+        typed,
+        next_id: 0,
+        diverges: Vec::new(),
+    };
+    p.fold_module(module.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use ast::parser::parse_with_spans;
+    use ast::printer::print_module;
+    use ast::span::Spans;
+
+    #[test]
+    fn prune_test() {
+        let src = r#"
+            fun f(c: i32): i32 {
+                let x = { return c; };
+                x + 1
+            }
+            fun g(c: bool): i32 {
+                if (c || { return 1; }) { return 2; };
+                (3 + { return 4; }) * 5
+            }
+        "#;
+        let (module, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&module, &spans);
+        let typed = check(&module, &spans, &res);
+        let pruned = prune(&module, &typed);
+        let printed = print_module(&pruned);
+        assert!(!printed.contains("let x"), "{}", printed);
+        assert!(!printed.contains("x + 1"), "{}", printed);
+        assert!(printed.contains("c || "), "{}", printed);
+        assert!(!printed.contains("5"), "{}", printed);
+
+        let spans = Spans::default();
+        let res = resolve(&pruned, &spans);
+        assert_eq!(res.errors, vec![]);
+        let typed = check(&pruned, &spans, &res);
+        assert_eq!(typed.errors, vec![]);
+        assert!(!typed.decls.contains(&Ty::Never));
+    }
+}
@@ -0,0 +1,655 @@
+use crate::decls::Decls;
+use ast::ast::{Expr, Ident, Member, Module, RefType, Type};
+use ast::span::{ExprId, Span, Spans};
+use std::collections::{HashMap, HashSet};
+use token::lint::confusables;
+
+pub type DeclId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeclKind {
+    Func,
+    ExternFun,
+    Global,
+    Const,
+    Param,
+    Local,
+    MatchBind,
+    LambdaParam,
+    // ラムダがキャプチャした外側の変数
+    Capture(DeclId),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decl {
+    pub name: Ident,
+    pub kind: DeclKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResolveErrorKind {
+    Undefined(Ident),
+    // ラムダの中からキャプチャしていない外側の局所変数を参照した
+    NotCaptured(Ident),
+    UndefinedType(Ident),
+    UndefinedLabel(Ident),
+    OutsideLoop,
+    DuplicateFunc(Ident),
+    DuplicateParam(Ident),
+    DuplicateField(Ident, Ident),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    // declが同じ名前のshadowedを隠している
+    Shadowing { decl: DeclId, shadowed: DeclId },
+    // 見た目の紛らわしい別々の名前
+    Confusable { first: DeclId, second: DeclId },
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resolution {
+    pub decls: Vec<Decl>,
+    // 定義ごとに宣言した名前(関数は自身の後に引数が続く)
+    pub members: Vec<Vec<DeclId>>,
+    // Var式から参照している宣言へ
+    pub uses: HashMap<ExprId, DeclId>,
+    // Let, Match, Lambdaの式から宣言した名前へ
+    pub binds: HashMap<ExprId, Vec<DeclId>>,
+    // break, continueから対象のループへ
+    pub loops: HashMap<ExprId, ExprId>,
+    pub errors: Vec<ResolveError>,
+    pub warnings: Vec<Warning>,
+}
+
+struct Loop {
+    label: Option<Ident>,
+    // ループの式のIDが決まってから対応付ける
+    jumps: Vec<ExprId>,
+}
+
+// 関数またはラムダの本体ごとの状態
+#[derive(Default)]
+struct Frame {
+    scopes: Vec<HashMap<Ident, DeclId>>,
+    loops: Vec<Loop>,
+}
+
+struct Resolver<'a> {
+    spans: &'a Spans,
+    decls: Decls<'a>,
+    globals: HashMap<Ident, DeclId>,
+    frame: Frame,
+    // ラムダの外側の関数
+    outer: Vec<Frame>,
+    label: Option<Ident>,
+    next_id: ExprId,
+    res: Resolution,
+}
+
+impl<'a> Resolver<'a> {
+    fn new_decl(&mut self, name: Ident, kind: DeclKind, span: Span) -> DeclId {
+        self.res.decls.push(Decl { name, kind, span });
+        self.res.decls.len() - 1
+    }
+
+    fn error(&mut self, kind: ResolveErrorKind, span: Span) {
+        self.res.errors.push(ResolveError { kind, span });
+    }
+
+    fn lookup(&self, x: Ident) -> Option<DeclId> {
+        self.frame
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(&x))
+            .or_else(|| self.globals.get(&x))
+            .cloned()
+    }
+
+    // 同じスコープで重複する場合はその名前を返す
+    fn declare(&mut self, name: Ident, kind: DeclKind, span: Span) -> (DeclId, Option<Ident>) {
+        let shadowed = self.lookup(name);
+        let decl = self.new_decl(name, kind, span);
+        if let Some(shadowed) = shadowed {
+            self.res
+                .warnings
+                .push(Warning::Shadowing { decl, shadowed });
+        }
+        let scope = self.frame.scopes.last_mut().unwrap();
+        let dup = scope.insert(name, decl).map(|_| name);
+        (decl, dup)
+    }
+
+    // 引数のように、同じスコープでの重複がエラーになる名前
+    fn declare_all(
+        &mut self,
+        names: impl Iterator<Item = (Ident, DeclKind)>,
+        span: Span,
+        errors: &mut Vec<ResolveErrorKind>,
+    ) -> Vec<DeclId> {
+        names
+            .map(|(x, kind)| {
+                let (decl, dup) = self.declare(x, kind, span);
+                if let Some(x) = dup {
+                    errors.push(ResolveErrorKind::DuplicateParam(x));
+                }
+                decl
+            })
+            .collect()
+    }
+
+    fn check_type(&self, t: &Type, errors: &mut Vec<ResolveErrorKind>) {
+        if let Err(x) = self.decls.check_type(t) {
+            errors.push(ResolveErrorKind::UndefinedType(x));
+        }
+    }
+
+    fn scoped(&mut self, e: &Expr) {
+        self.frame.scopes.push(HashMap::new());
+        self.expr(e);
+        self.frame.scopes.pop();
+    }
+
+    fn var(&mut self, x: Ident) -> Result<DeclId, ResolveErrorKind> {
+        if let Some(decl) = self.frame.scopes.iter().rev().find_map(|s| s.get(&x)) {
+            return Ok(*decl);
+        }
+        let outer = self
+            .outer
+            .iter()
+            .flat_map(|f| &f.scopes)
+            .any(|s| s.contains_key(&x));
+        if outer {
+            return Err(ResolveErrorKind::NotCaptured(x));
+        }
+        self.globals
+            .get(&x)
+            .cloned()
+            .ok_or(ResolveErrorKind::Undefined(x))
+    }
+
+    fn loop_body(&mut self, body: &Expr) -> Vec<ExprId> {
+        let label = self.label.take();
+        self.frame.loops.push(Loop {
+            label,
+            jumps: Vec::new(),
+        });
+        self.scoped(body);
+        self.frame.loops.pop().unwrap().jumps
+    }
+
+    // 子を辿ってから後行順でIDを振る
+    fn expr(&mut self, e: &Expr) -> ExprId {
+        let mut errors = Vec::new();
+        let mut jumps = Vec::new();
+        // IDが決まってから範囲を埋める宣言
+        let mut binds = Vec::new();
+        match e {
+            Expr::StructLiteral(name, fields) => {
+                for (_, e) in fields {
+                    self.expr(e);
+                }
+                self.check_type(&Type::RefType(RefType::Struct(*name)), &mut errors);
+                let mut seen = HashSet::new();
+                for (x, _) in fields {
+                    if !seen.insert(*x) {
+                        errors.push(ResolveErrorKind::DuplicateField(*name, *x));
+                    }
+                }
+            }
+            Expr::EnumLiteral(name, _, args) => {
+                for e in args {
+                    self.expr(e);
+                }
+                self.check_type(&Type::RefType(RefType::Struct(*name)), &mut errors);
+            }
+            Expr::I32Literal(_)
+            | Expr::I64Literal(_)
+            | Expr::F32Literal(_)
+            | Expr::F64Literal(_)
+            | Expr::StringLiteral(_)
+            | Expr::BoolLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::Var(_)
+            | Expr::Break(_)
+            | Expr::Continue(_) => {}
+            Expr::ArrayLiteral(t, len) => {
+                self.expr(len);
+                self.check_type(t, &mut errors);
+            }
+            Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) | Expr::Member(x, _) => {
+                self.expr(x);
+            }
+            Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+                self.expr(f);
+                for e in args {
+                    self.expr(e);
+                }
+            }
+            Expr::Index(a, b)
+            | Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Mod(a, b)
+            | Expr::And(a, b)
+            | Expr::Or(a, b)
+            | Expr::BitAnd(a, b)
+            | Expr::BitOr(a, b)
+            | Expr::BitXor(a, b)
+            | Expr::Shl(a, b)
+            | Expr::Shr(a, b)
+            | Expr::UShr(a, b)
+            | Expr::Pow(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Ne(a, b)
+            | Expr::Lt(a, b)
+            | Expr::Lte(a, b)
+            | Expr::Gt(a, b)
+            | Expr::Gte(a, b)
+            | Expr::Set(a, b)
+            | Expr::CompoundSet(_, a, b) => {
+                self.expr(a);
+                self.expr(b);
+            }
+            Expr::While(cond, body) => {
+                let label = self.label.take();
+                self.expr(cond);
+                self.label = label;
+                jumps = self.loop_body(body);
+            }
+            Expr::Block(stmts, last) => {
+                self.frame.scopes.push(HashMap::new());
+                for e in stmts.iter().chain(&**last) {
+                    self.expr(e);
+                }
+                self.frame.scopes.pop();
+            }
+            Expr::Let(_, e) => {
+                self.expr(e);
+            }
+            Expr::If(first, elifs, els) => {
+                for (cond, then) in std::iter::once(&**first).chain(elifs) {
+                    self.expr(cond);
+                    self.scoped(then);
+                }
+                if let Some(e) = &**els {
+                    self.scoped(e);
+                }
+            }
+            Expr::Return(e) | Expr::Closure(_, e) => {
+                if let Some(e) = &**e {
+                    self.expr(e);
+                }
+            }
+            Expr::Labeled(label, e) => {
+                self.label = Some(*label);
+                self.expr(e);
+            }
+            Expr::Match(e, arms, default) => {
+                self.expr(e);
+                for (_, xs, e) in arms {
+                    self.frame.scopes.push(HashMap::new());
+                    let xs = xs.iter().map(|x| (*x, DeclKind::MatchBind));
+                    binds.extend(self.declare_all(xs, Span::default(), &mut errors));
+                    self.expr(e);
+                    self.frame.scopes.pop();
+                }
+                if let Some(e) = &**default {
+                    self.scoped(e);
+                }
+            }
+            Expr::Cast(e, t) | Expr::Bitcast(e, t) => {
+                self.expr(e);
+                self.check_type(t, &mut errors);
+            }
+            Expr::For(init, cond, step, body) => {
+                let label = self.label.take();
+                self.frame.scopes.push(HashMap::new());
+                self.expr(init);
+                self.expr(cond);
+                self.expr(step);
+                self.label = label;
+                jumps = self.loop_body(body);
+                self.frame.scopes.pop();
+            }
+            Expr::Lambda(caps, params, ret, body) => {
+                let caps = caps
+                    .iter()
+                    .filter_map(|x| match self.var(*x) {
+                        Ok(decl) => Some((*x, DeclKind::Capture(decl))),
+                        Err(err) => {
+                            errors.push(err);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let frame = std::mem::take(&mut self.frame);
+                self.outer.push(frame);
+                self.frame.scopes.push(HashMap::new());
+                binds.extend(self.declare_all(caps.into_iter(), Span::default(), &mut errors));
+                let xs = params.iter().map(|(x, _)| (*x, DeclKind::LambdaParam));
+                binds.extend(self.declare_all(xs, Span::default(), &mut errors));
+                self.expr(body);
+                self.frame = self.outer.pop().unwrap();
+                for (_, t) in params {
+                    self.check_type(t, &mut errors);
+                }
+                self.check_type(ret, &mut errors);
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let span = self.spans.expr(id);
+        match e {
+            Expr::Var(x) => match self.var(*x) {
+                Ok(decl) => {
+                    self.res.uses.insert(id, decl);
+                }
+                Err(err) => errors.push(err),
+            },
+            Expr::Let(x, _) => {
+                let (decl, _) = self.declare(*x, DeclKind::Local, span);
+                binds.push(decl);
+            }
+            Expr::Closure(f, _) => {
+                let func = self.globals.get(f).map(|d| self.res.decls[*d].kind);
+                if func != Some(DeclKind::Func) {
+                    errors.push(ResolveErrorKind::Undefined(*f));
+                }
+            }
+            Expr::Break(label) | Expr::Continue(label) => {
+                let target = match label {
+                    Some(l) => self
+                        .frame
+                        .loops
+                        .iter_mut()
+                        .rev()
+                        .find(|x| x.label == Some(*l)),
+                    None => self.frame.loops.last_mut(),
+                };
+                match (target, label) {
+                    (Some(target), _) => target.jumps.push(id),
+                    (None, Some(l)) => errors.push(ResolveErrorKind::UndefinedLabel(*l)),
+                    (None, None) => errors.push(ResolveErrorKind::OutsideLoop),
+                }
+            }
+            _ => {}
+        }
+        for jump in jumps {
+            self.res.loops.insert(jump, id);
+        }
+        if !binds.is_empty() {
+            for decl in &binds {
+                self.res.decls[*decl].span = span;
+            }
+            self.res.binds.insert(id, binds);
+        }
+        for kind in errors {
+            self.error(kind, span);
+        }
+        id
+    }
+
+    fn member(&mut self, m: &Member, span: Span) -> Vec<DeclId> {
+        let mut errors = Vec::new();
+        let mut decls = Vec::new();
+        self.frame = Frame::default();
+        self.frame.scopes.push(HashMap::new());
+        match m {
+            Member::Struct(name, fields) | Member::Union(name, fields) => {
+                let mut seen = HashSet::new();
+                for (x, _) in fields {
+                    if !seen.insert(*x) {
+                        errors.push(ResolveErrorKind::DuplicateField(*name, *x));
+                    }
+                }
+            }
+            Member::Enum(..) => {}
+            Member::Func(def, body, _) => {
+                decls.extend(self.globals.get(&def.0));
+                let params = def.1.iter().map(|(x, _)| (*x, DeclKind::Param));
+                decls.extend(self.declare_all(params, span, &mut errors));
+                for t in def.1.iter().map(|(_, t)| t).chain(&def.2) {
+                    self.check_type(t, &mut errors);
+                }
+                self.expr(body);
+            }
+            Member::ExternFun(def, _, _) => {
+                decls.extend(self.globals.get(&def.0));
+                for t in def.1.iter().map(|(_, t)| t).chain(&def.2) {
+                    self.check_type(t, &mut errors);
+                }
+            }
+            Member::Let(name, t, e) | Member::Const(name, t, e) => {
+                decls.extend(self.globals.get(name));
+                if let Some(t) = t {
+                    self.check_type(t, &mut errors);
+                }
+                self.expr(e);
+            }
+        }
+        for kind in errors {
+            self.error(kind, span);
+        }
+        decls
+    }
+}
+
+// 名前をその宣言に結び付ける
+pub fn resolve(module: &Module, spans: &Spans) -> Resolution {
+    let mut r = Resolver {
+        spans,
+        decls: Decls::new(module),
+        globals: HashMap::new(),
+        frame: Frame::default(),
+        outer: Vec::new(),
+        label: None,
+        next_id: 0,
+        res: Resolution::default(),
+    };
+    // トップレベルの名前は定義の順序によらず見える
+    for (i, member) in module.iter().enumerate() {
+        let (name, kind) = match member {
+            Member::Func(def, _, _) => (def.0, DeclKind::Func),
+            Member::ExternFun(def, _, _) => (def.0, DeclKind::ExternFun),
+            Member::Let(name, _, _) => (*name, DeclKind::Global),
+            Member::Const(name, _, _) => (*name, DeclKind::Const),
+            _ => continue,
+        };
+        let span = spans.member(i);
+        match r.globals.get(&name) {
+            Some(&prev) => {
+                // 大域変数同士の重複はeval_globalsで報告する
+                let func = |k| matches!(k, DeclKind::Func | DeclKind::ExternFun);
+                if func(kind) || func(r.res.decls[prev].kind) {
+                    r.error(ResolveErrorKind::DuplicateFunc(name), span);
+                }
+            }
+            None => {
+                let decl = r.new_decl(name, kind, span);
+                r.globals.insert(name, decl);
+            }
+        }
+    }
+    for (i, member) in module.iter().enumerate() {
+        let decls = r.member(member, spans.member(i));
+        r.res.members.push(decls);
+    }
+    let names = r
+        .res
+        .decls
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| !matches!(d.kind, DeclKind::Capture(_)))
+        .map(|(i, d)| (d.name, i));
+    for (first, second) in confusables(names) {
+        r.res.warnings.push(Warning::Confusable { first, second });
+    }
+    r.res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ast::parser::parse_with_spans;
+
+    fn text(src: &str, span: Span) -> String {
+        src.chars().skip(span.pos).take(span.len).collect()
+    }
+
+    #[test]
+    fn resolve_test() {
+        let src = r#"
+            const N: i32 = 10;
+            fun f(a: i32): i32 {
+                let x = a + N;
+                for (let i = 0; i < x; i += 1) {
+                    let x = i;
+                    outer: while (true) { while (true) { break outer; } continue; };
+                };
+                let g = \[x](y: i32): i32 -> x + y + f(y);
+                match (E::A(x)) { A(z) => z, _ => x }
+            }
+            enum E { A(i32) }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        assert_eq!(res.errors, vec![]);
+
+        let mut uses = res.uses.iter().collect::<Vec<_>>();
+        uses.sort();
+        let uses = uses
+            .into_iter()
+            .map(|(e, d)| {
+                let d = &res.decls[*d];
+                (text(src, spans.expr(*e)), d.name.as_str(), d.kind)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            vec![
+                ("a".to_string(), "a", DeclKind::Param),
+                ("N".to_string(), "N", DeclKind::Const),
+                ("i".to_string(), "i", DeclKind::Local),
+                ("x".to_string(), "x", DeclKind::Local),
+                ("i".to_string(), "i", DeclKind::Local),
+                ("i".to_string(), "i", DeclKind::Local),
+                ("x".to_string(), "x", DeclKind::Capture(3)),
+                ("y".to_string(), "y", DeclKind::LambdaParam),
+                ("f".to_string(), "f", DeclKind::Func),
+                ("y".to_string(), "y", DeclKind::LambdaParam),
+                ("x".to_string(), "x", DeclKind::Local),
+                ("z".to_string(), "z", DeclKind::MatchBind),
+                ("x".to_string(), "x", DeclKind::Local),
+            ]
+        );
+        assert_eq!(text(src, res.decls[3].span), "let x = a + N");
+        assert_eq!(res.members[1], vec![1, 2]);
+
+        // 内側のxが外側のxを隠している
+        let [Warning::Shadowing { decl, shadowed }] = res.warnings[..] else {
+            panic!("{:?}", res.warnings);
+        };
+        assert_eq!(text(src, res.decls[decl].span), "let x = i");
+        assert_eq!(shadowed, 3);
+
+        let mut loops = res
+            .loops
+            .iter()
+            .map(|(j, l)| (text(src, spans.expr(*j)), text(src, spans.expr(*l))))
+            .collect::<Vec<_>>();
+        loops.sort();
+        assert_eq!(
+            loops,
+            vec![
+                (
+                    "break outer".to_string(),
+                    "while (true) { while (true) { break outer; } continue; }".to_string()
+                ),
+                (
+                    "continue".to_string(),
+                    "while (true) { while (true) { break outer; } continue; }".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_error_test() {
+        let src = r#"
+            struct P { x: i32, x: i32 }
+            fun f(a: i32, a: Q) {
+                let p = P { y: b, y: 1 };
+                let h = \(z: i32): i32 -> p;
+                break;
+                while (true) { continue l; };
+            }
+            extern "m" "f" fun f();
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let errors = res
+            .errors
+            .iter()
+            .map(|e| (e.kind.clone(), text(src, e.span)))
+            .collect::<Vec<_>>();
+        let sym = token::intern::Sym::intern;
+        let f = "fun f(a: i32, a: Q) {\n                let p = P { y: b, y: 1 };\n                let h = \\(z: i32): i32 -> p;\n                break;\n                while (true) { continue l; };\n            }";
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    ResolveErrorKind::DuplicateFunc(sym("f")),
+                    r#"extern "m" "f" fun f();"#.to_string()
+                ),
+                (
+                    ResolveErrorKind::DuplicateField(sym("P"), sym("x")),
+                    "struct P { x: i32, x: i32 }".to_string()
+                ),
+                (ResolveErrorKind::Undefined(sym("b")), "b".to_string()),
+                (
+                    ResolveErrorKind::DuplicateField(sym("P"), sym("y")),
+                    "P { y: b, y: 1 }".to_string()
+                ),
+                (ResolveErrorKind::NotCaptured(sym("p")), "p".to_string()),
+                (ResolveErrorKind::OutsideLoop, "break".to_string()),
+                (
+                    ResolveErrorKind::UndefinedLabel(sym("l")),
+                    "continue l".to_string()
+                ),
+                (ResolveErrorKind::DuplicateParam(sym("a")), f.to_string()),
+                (ResolveErrorKind::UndefinedType(sym("Q")), f.to_string()),
+            ]
+        );
+    }
+
+    // 2つ目のaはキリル文字
+    #[test]
+    fn confusable_test() {
+        let src = r#"
+            fun paypal(x: i32): i32 { x }
+            fun f(): i32 {
+                let pаypal = 1;
+                paypal(pаypal)
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        assert_eq!(res.errors, vec![]);
+        let [Warning::Confusable { first, second }] = res.warnings[..] else {
+            panic!("{:?}", res.warnings);
+        };
+        assert_eq!(res.decls[first].kind, DeclKind::Func);
+        assert_eq!(text(src, res.decls[second].span), "let pаypal = 1");
+    }
+}
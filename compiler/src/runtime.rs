@@ -0,0 +1,1005 @@
+// コンパイルしたモジュールに必ず付け加える実行時ライブラリ
+// 関数はモジュールの関数の後ろに、グローバル変数はモジュールのグローバル変数の後ろに並べる
+//
+// 線形メモリ
+//   [静的データ][型記述子][シャドウスタック][ヒープ]
+//   静的データのオブジェクトは参照を含まないので回収しない
+//
+// ヒープのブロック: [ヘッダ: 8バイト][オブジェクト]
+//   ヘッダ: [型記述子のアドレス: i32][ブロックの大きさ | フラグ: i32]
+//   空きブロックは型記述子の代わりに空きリストの次のブロックを持つ
+//   型記述子が0のオブジェクトは参照を含まない
+//
+// 型記述子: [種類: i32][n: i32][i32; n]
+//   FIELDS: n個のオフセットに参照がある
+//   ARRAY: 要素が参照の配列
+//   ENUM: タグ番目のヴァリアントのFIELDSの記述子のアドレス(参照がなければ0)がn個並ぶ
+//   UNION: オフセット0の値がヒープのオブジェクトを指していれば参照とみなす
+//
+// シャドウスタック
+//   関数は参照型の変数ごとに枠を確保し、代入のたびに書き込む
+//   計算途中の参照もルートとして積み、文の終わりで積む前の高さに戻す
+//
+// ゴミ集めはマーク・スイープで、マーク中の灰色のオブジェクトはシャドウスタックの上に積む
+// 積みきれなければ印の付いたブロックを走査し直す
+use crate::decls::TypeDecl;
+use crate::layout::{align_to, TypeLayout, CLOSURE_ENV, LENGTH_SIZE};
+use ast::ast::Type;
+use std::collections::HashMap;
+use wasm::ast::*;
+
+pub const PAGE_SIZE: u32 = 65536;
+pub const HEADER_SIZE: u32 = 8;
+pub const SHADOW_STACK_SIZE: u32 = 65536;
+
+// ブロックの大きさの下位ビット
+pub const MARK: i32 = 1;
+pub const FREE: i32 = 2;
+
+pub const FIELDS: i32 = 0;
+pub const ARRAY: i32 = 1;
+pub const ENUM: i32 = 2;
+pub const UNION: i32 = 3;
+
+// 外から呼ぶ関数の番号
+// alloc#(size: i32, desc: i32): i32
+pub const ALLOC: usize = 0;
+// collect#()
+pub const COLLECT: usize = 1;
+// frame#(n: i32): i32 n個の枠を確保して元の高さを返す
+pub const FRAME: usize = 2;
+// root#(p: i32): i32
+pub const ROOT: usize = 3;
+const FIND: usize = 4;
+const DRAIN: usize = 5;
+const MARK_FN: usize = 6;
+const SCAN: usize = 7;
+const IS_OBJECT: usize = 8;
+
+// グローバル変数の番号
+const HEAP: usize = 0;
+const FREE_LIST: usize = 1;
+pub const STACK: usize = 2;
+const GRAY: usize = 3;
+const OVERFLOW: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Gc {
+    // 回収しない
+    Off,
+    // 線形メモリを広げる前に回収する
+    #[default]
+    On,
+    // 確保のたびに回収する
+    Stress,
+}
+
+// 型記述子を並べたデータ
+pub struct Descriptors {
+    base: u32,
+    data: Vec<u8>,
+    memo: HashMap<Vec<i32>, u32>,
+}
+
+impl Descriptors {
+    pub fn new(base: u32) -> Descriptors {
+        Descriptors {
+            base,
+            data: Vec::new(),
+            memo: HashMap::new(),
+        }
+    }
+
+    pub fn end(&self) -> u32 {
+        self.base + self.data.len() as u32
+    }
+
+    fn add(&mut self, kind: i32, entries: Vec<i32>) -> u32 {
+        let mut words = vec![kind, entries.len() as i32];
+        words.extend(entries);
+        if let Some(&ptr) = self.memo.get(&words) {
+            return ptr;
+        }
+        let ptr = self.end();
+        for w in &words {
+            self.data.extend_from_slice(&w.to_le_bytes());
+        }
+        self.memo.insert(words, ptr);
+        ptr
+    }
+
+    // 参照型のフィールドのオフセットを記録する
+    fn fields<'b>(&mut self, fields: impl Iterator<Item = (&'b Type, u32)>) -> u32 {
+        let offsets = fields
+            .filter(|(t, _)| matches!(t, Type::RefType(_)))
+            .map(|(_, offset)| offset as i32)
+            .collect::<Vec<_>>();
+        if offsets.is_empty() {
+            0
+        } else {
+            self.add(FIELDS, offsets)
+        }
+    }
+
+    pub fn decl(&mut self, decl: &TypeDecl) -> u32 {
+        match (decl, TypeLayout::new(decl)) {
+            (TypeDecl::Struct(fields), TypeLayout::Struct(layout)) => {
+                self.fields(fields.iter().map(|(_, t)| t).zip(layout.offsets))
+            }
+            (TypeDecl::Enum(variants), TypeLayout::Enum(layout)) => {
+                let entries = variants
+                    .iter()
+                    .zip(layout.variants)
+                    .map(|((_, types), l)| self.fields(types.iter().zip(l.offsets)) as i32)
+                    .collect::<Vec<_>>();
+                if entries.iter().all(|&d| d == 0) {
+                    0
+                } else {
+                    self.add(ENUM, entries)
+                }
+            }
+            (TypeDecl::Union(fields), _) => {
+                if fields.iter().any(|(_, t)| matches!(t, Type::RefType(_))) {
+                    self.add(UNION, Vec::new())
+                } else {
+                    0
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // 要素の型がtの配列
+    pub fn array(&mut self, t: &Type) -> u32 {
+        match t {
+            Type::RefType(_) => self.add(ARRAY, Vec::new()),
+            _ => 0,
+        }
+    }
+
+    pub fn closure(&mut self) -> u32 {
+        self.add(FIELDS, vec![CLOSURE_ENV as i32])
+    }
+
+    pub fn segment(&self) -> DataSegment {
+        DataSegment {
+            offset: InitExpr::I32(self.base as i32),
+            data: self.data.clone(),
+        }
+    }
+}
+
+pub struct Runtime {
+    pub funcs: Vec<(FuncType, FunctionBody)>,
+    pub globals: Vec<GlobalVariable>,
+    pub heap_base: u32,
+}
+
+struct Builder {
+    gc: Gc,
+    funcs: usize,
+    globals: usize,
+    roots: Vec<usize>,
+    stack_base: u32,
+    heap_base: u32,
+}
+
+fn i32_load(offset: u32) -> OperatorCode {
+    OperatorCode::I32Load(MemoryImmediate { flags: 2, offset })
+}
+
+fn i32_store(offset: u32) -> OperatorCode {
+    OperatorCode::I32Store(MemoryImmediate { flags: 2, offset })
+}
+
+fn body(locals: usize, codes: Vec<OperatorCode>) -> FunctionBody {
+    FunctionBody {
+        locals: if locals == 0 {
+            vec![]
+        } else {
+            vec![LocalEntry {
+                count: locals,
+                typ: ValueType::I32,
+            }]
+        },
+        codes,
+    }
+}
+
+impl Builder {
+    fn call(&self, f: usize) -> OperatorCode {
+        OperatorCode::Call(self.funcs + f)
+    }
+
+    fn get(&self, g: usize) -> OperatorCode {
+        OperatorCode::GetGlobal(self.globals + g)
+    }
+
+    fn set(&self, g: usize) -> OperatorCode {
+        OperatorCode::SetGlobal(self.globals + g)
+    }
+
+    fn heap_base(&self) -> OperatorCode {
+        OperatorCode::I32Const(self.heap_base as i32)
+    }
+
+    // ヒープの全てのブロックについてeachを実行する。ブロックはローカル変数b、ヘッダの2語目はwに入る
+    fn blocks(&self, b: usize, w: usize, each: Vec<OperatorCode>) -> Vec<OperatorCode> {
+        use OperatorCode::*;
+        let mut codes = vec![
+            self.heap_base(),
+            SetLocal(b),
+            Block(BlockType(None)),
+            Loop(BlockType(None)),
+            GetLocal(b),
+            self.get(HEAP),
+            I32Geu,
+            BrIf(1),
+            GetLocal(b),
+            i32_load(4),
+            SetLocal(w),
+        ];
+        codes.extend(each);
+        codes.extend(vec![
+            GetLocal(b),
+            GetLocal(w),
+            I32Const(-8),
+            I32And,
+            I32Add,
+            SetLocal(b),
+            Br(0),
+            End,
+            End,
+        ]);
+        codes
+    }
+
+    // alloc#(size, desc): 空きリストから探し、なければヒープの末尾を進める
+    fn alloc(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let log2_page = PAGE_SIZE.trailing_zeros() as i32;
+        let (size, desc, need, b, end) = (0, 1, 2, 3, 4);
+        let mut codes = vec![
+            GetLocal(size),
+            I32Const(HEADER_SIZE as i32 + 7),
+            I32Add,
+            I32Const(-8),
+            I32And,
+            SetLocal(need),
+        ];
+        if self.gc == Gc::Stress {
+            codes.push(self.call(COLLECT));
+        }
+        codes.extend(vec![
+            Block(BlockType(None)),
+            GetLocal(need),
+            self.call(FIND),
+            TeeLocal(b),
+            BrIf(0),
+            self.get(HEAP),
+            TeeLocal(b),
+            GetLocal(need),
+            I32Add,
+            TeeLocal(end),
+            CurrentMemory,
+            I32Const(log2_page),
+            I32Shl,
+            I32Gtu,
+            If(BlockType(None)),
+        ]);
+        if self.gc == Gc::On {
+            codes.extend(vec![
+                self.call(COLLECT),
+                GetLocal(need),
+                self.call(FIND),
+                TeeLocal(b),
+                BrIf(1),
+                self.get(HEAP),
+                SetLocal(b),
+            ]);
+        }
+        codes.extend(vec![
+            GetLocal(end),
+            I32Const(PAGE_SIZE as i32 - 1),
+            I32Add,
+            I32Const(log2_page),
+            I32Shru,
+            CurrentMemory,
+            I32Sub,
+            GrowMemory,
+            I32Const(-1),
+            I32Eq,
+            If(BlockType(None)),
+            Unreachable,
+            End,
+            End,
+            GetLocal(end),
+            self.set(HEAP),
+            End,
+            // ヘッダを書いて中身を0で埋める
+            GetLocal(b),
+            GetLocal(desc),
+            i32_store(0),
+            GetLocal(b),
+            GetLocal(need),
+            i32_store(4),
+            GetLocal(b),
+            GetLocal(need),
+            I32Add,
+            SetLocal(end),
+            GetLocal(b),
+            I32Const(HEADER_SIZE as i32),
+            I32Add,
+            SetLocal(size),
+            Block(BlockType(None)),
+            Loop(BlockType(None)),
+            GetLocal(size),
+            GetLocal(end),
+            I32Geu,
+            BrIf(1),
+            GetLocal(size),
+            I64Const(0),
+            I64Store(MemoryImmediate {
+                flags: 3,
+                offset: 0,
+            }),
+            GetLocal(size),
+            I32Const(8),
+            I32Add,
+            SetLocal(size),
+            Br(0),
+            End,
+            End,
+            GetLocal(b),
+            I32Const(HEADER_SIZE as i32),
+            I32Add,
+            End,
+        ]);
+        body(3, codes)
+    }
+
+    // find#(need): 大きさneed以上の空きブロックを末尾から切り出す。なければ0
+    fn find(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (need, prev, cur, size) = (0, 1, 2, 3);
+        body(
+            3,
+            vec![
+                I32Const(0),
+                SetLocal(prev),
+                self.get(FREE_LIST),
+                SetLocal(cur),
+                Block(BlockType(None)),
+                Loop(BlockType(None)),
+                GetLocal(cur),
+                I32Eqz,
+                BrIf(1),
+                GetLocal(cur),
+                i32_load(4),
+                I32Const(-8),
+                I32And,
+                TeeLocal(size),
+                GetLocal(need),
+                I32Geu,
+                If(BlockType(None)),
+                GetLocal(size),
+                GetLocal(need),
+                I32Eq,
+                If(BlockType(None)),
+                GetLocal(prev),
+                I32Eqz,
+                If(BlockType(None)),
+                GetLocal(cur),
+                i32_load(0),
+                self.set(FREE_LIST),
+                Else,
+                GetLocal(prev),
+                GetLocal(cur),
+                i32_load(0),
+                i32_store(0),
+                End,
+                GetLocal(cur),
+                Return,
+                End,
+                GetLocal(cur),
+                GetLocal(size),
+                GetLocal(need),
+                I32Sub,
+                I32Const(FREE),
+                I32Or,
+                i32_store(4),
+                GetLocal(cur),
+                GetLocal(size),
+                I32Add,
+                GetLocal(need),
+                I32Sub,
+                Return,
+                End,
+                GetLocal(cur),
+                SetLocal(prev),
+                GetLocal(cur),
+                i32_load(0),
+                SetLocal(cur),
+                Br(0),
+                End,
+                End,
+                I32Const(0),
+                End,
+            ],
+        )
+    }
+
+    // collect#(): ルートから印を付け、印のないブロックを空きリストに戻す
+    fn collect(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (p, w, size) = (0, 1, 2);
+        let mut codes = vec![self.get(STACK), self.set(GRAY)];
+        for g in &self.roots {
+            codes.push(GetGlobal(*g));
+            codes.push(self.call(MARK_FN));
+        }
+        codes.extend(vec![
+            I32Const(self.stack_base as i32),
+            SetLocal(p),
+            Block(BlockType(None)),
+            Loop(BlockType(None)),
+            GetLocal(p),
+            self.get(STACK),
+            I32Geu,
+            BrIf(1),
+            GetLocal(p),
+            i32_load(0),
+            self.call(MARK_FN),
+            GetLocal(p),
+            I32Const(4),
+            I32Add,
+            SetLocal(p),
+            Br(0),
+            End,
+            End,
+            self.call(DRAIN),
+            // 灰色のオブジェクトを積みきれなかった
+            Block(BlockType(None)),
+            Loop(BlockType(None)),
+            self.get(OVERFLOW),
+            I32Eqz,
+            BrIf(1),
+            I32Const(0),
+            self.set(OVERFLOW),
+        ]);
+        codes.extend(self.blocks(
+            p,
+            w,
+            vec![
+                GetLocal(w),
+                I32Const(MARK | FREE),
+                I32And,
+                I32Const(MARK),
+                I32Eq,
+                If(BlockType(None)),
+                GetLocal(p),
+                I32Const(HEADER_SIZE as i32),
+                I32Add,
+                self.call(SCAN),
+                self.call(DRAIN),
+                End,
+            ],
+        ));
+        codes.extend(vec![Br(0), End, End, I32Const(0), self.set(FREE_LIST)]);
+        // 隣り合う空きブロックはつなげる
+        codes.extend(self.blocks(
+            p,
+            w,
+            vec![
+                GetLocal(w),
+                I32Const(-8),
+                I32And,
+                SetLocal(size),
+                GetLocal(w),
+                I32Const(MARK),
+                I32And,
+                If(BlockType(None)),
+                GetLocal(p),
+                GetLocal(size),
+                i32_store(4),
+                Else,
+                self.get(FREE_LIST),
+                I32Eqz,
+                If(BlockType(Some(ValueType::I32))),
+                I32Const(0),
+                Else,
+                self.get(FREE_LIST),
+                self.get(FREE_LIST),
+                i32_load(4),
+                I32Const(-8),
+                I32And,
+                I32Add,
+                GetLocal(p),
+                I32Eq,
+                End,
+                If(BlockType(None)),
+                self.get(FREE_LIST),
+                self.get(FREE_LIST),
+                i32_load(4),
+                GetLocal(size),
+                I32Add,
+                i32_store(4),
+                Else,
+                GetLocal(p),
+                self.get(FREE_LIST),
+                i32_store(0),
+                GetLocal(p),
+                GetLocal(size),
+                I32Const(FREE),
+                I32Or,
+                i32_store(4),
+                GetLocal(p),
+                self.set(FREE_LIST),
+                End,
+                End,
+            ],
+        ));
+        codes.push(End);
+        body(3, codes)
+    }
+
+    // drain#(): 灰色のオブジェクトがなくなるまで走査する
+    fn drain(&self) -> FunctionBody {
+        use OperatorCode::*;
+        body(
+            1,
+            vec![
+                Block(BlockType(None)),
+                Loop(BlockType(None)),
+                self.get(GRAY),
+                self.get(STACK),
+                I32Leu,
+                BrIf(1),
+                self.get(GRAY),
+                I32Const(4),
+                I32Sub,
+                TeeLocal(0),
+                self.set(GRAY),
+                GetLocal(0),
+                i32_load(0),
+                self.call(SCAN),
+                Br(0),
+                End,
+                End,
+                End,
+            ],
+        )
+    }
+
+    // mark#(p): ヒープのオブジェクトに印を付けて灰色にする
+    fn mark(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (p, h, w) = (0, 1, 2);
+        body(
+            2,
+            vec![
+                GetLocal(p),
+                I32Const((self.heap_base + HEADER_SIZE) as i32),
+                I32Ltu,
+                If(BlockType(None)),
+                Return,
+                End,
+                GetLocal(p),
+                I32Const(4),
+                I32Sub,
+                TeeLocal(h),
+                i32_load(0),
+                TeeLocal(w),
+                I32Const(MARK | FREE),
+                I32And,
+                If(BlockType(None)),
+                Return,
+                End,
+                GetLocal(h),
+                GetLocal(w),
+                I32Const(MARK),
+                I32Or,
+                i32_store(0),
+                self.get(GRAY),
+                self.heap_base(),
+                I32Ltu,
+                If(BlockType(None)),
+                self.get(GRAY),
+                GetLocal(p),
+                i32_store(0),
+                self.get(GRAY),
+                I32Const(4),
+                I32Add,
+                self.set(GRAY),
+                Else,
+                I32Const(1),
+                self.set(OVERFLOW),
+                End,
+                End,
+            ],
+        )
+    }
+
+    // scan#(p): 型記述子に従ってオブジェクトの参照に印を付ける
+    fn scan(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (p, d, n, i, v) = (0, 1, 2, 3, 4);
+        let each = |elem: Vec<OperatorCode>| {
+            let mut codes = vec![
+                I32Const(0),
+                SetLocal(i),
+                Block(BlockType(None)),
+                Loop(BlockType(None)),
+                GetLocal(i),
+                GetLocal(n),
+                I32Ges,
+                BrIf(1),
+            ];
+            codes.extend(elem);
+            codes.extend(vec![
+                self.call(MARK_FN),
+                GetLocal(i),
+                I32Const(1),
+                I32Add,
+                SetLocal(i),
+                Br(0),
+                End,
+                End,
+            ]);
+            codes
+        };
+        let mut codes = vec![
+            GetLocal(p),
+            I32Const(HEADER_SIZE as i32),
+            I32Sub,
+            i32_load(0),
+            TeeLocal(d),
+            I32Eqz,
+            If(BlockType(None)),
+            Return,
+            End,
+            GetLocal(d),
+            i32_load(0),
+            I32Const(ENUM),
+            I32Eq,
+            If(BlockType(None)),
+            GetLocal(d),
+            GetLocal(p),
+            i32_load(0),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            i32_load(8),
+            TeeLocal(d),
+            I32Eqz,
+            If(BlockType(None)),
+            Return,
+            End,
+            End,
+            GetLocal(d),
+            i32_load(0),
+            I32Const(ARRAY),
+            I32Eq,
+            If(BlockType(None)),
+            GetLocal(p),
+            i32_load(0),
+            SetLocal(n),
+        ];
+        codes.extend(each(vec![
+            GetLocal(p),
+            GetLocal(i),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            i32_load(LENGTH_SIZE),
+        ]));
+        codes.extend(vec![
+            Return,
+            End,
+            GetLocal(d),
+            i32_load(0),
+            I32Const(UNION),
+            I32Eq,
+            If(BlockType(None)),
+            GetLocal(p),
+            i32_load(0),
+            TeeLocal(v),
+            self.call(IS_OBJECT),
+            If(BlockType(None)),
+            GetLocal(v),
+            self.call(MARK_FN),
+            End,
+            Return,
+            End,
+            GetLocal(d),
+            i32_load(4),
+            SetLocal(n),
+        ]);
+        codes.extend(each(vec![
+            GetLocal(p),
+            GetLocal(d),
+            GetLocal(i),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            i32_load(8),
+            I32Add,
+            i32_load(0),
+        ]));
+        codes.push(End);
+        body(4, codes)
+    }
+
+    // is_object#(v): vがヒープのオブジェクトの先頭を指すか
+    fn is_object(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (v, b, w) = (0, 1, 2);
+        let mut codes = vec![
+            GetLocal(v),
+            I32Const((self.heap_base + HEADER_SIZE) as i32),
+            I32Ltu,
+            If(BlockType(None)),
+            I32Const(0),
+            Return,
+            End,
+        ];
+        codes.extend(self.blocks(
+            b,
+            w,
+            vec![
+                GetLocal(b),
+                I32Const(HEADER_SIZE as i32),
+                I32Add,
+                GetLocal(v),
+                I32Eq,
+                If(BlockType(None)),
+                I32Const(1),
+                Return,
+                End,
+            ],
+        ));
+        codes.extend(vec![I32Const(0), End]);
+        body(2, codes)
+    }
+
+    // frame#(n): 0で埋めたn個の枠をシャドウスタックに積む
+    fn frame(&self) -> FunctionBody {
+        use OperatorCode::*;
+        let (n, fp, end) = (0, 1, 2);
+        body(
+            2,
+            vec![
+                self.get(STACK),
+                TeeLocal(fp),
+                GetLocal(n),
+                I32Const(2),
+                I32Shl,
+                I32Add,
+                TeeLocal(end),
+                self.heap_base(),
+                I32Gtu,
+                If(BlockType(None)),
+                Unreachable,
+                End,
+                Block(BlockType(None)),
+                Loop(BlockType(None)),
+                self.get(STACK),
+                GetLocal(end),
+                I32Geu,
+                BrIf(1),
+                self.get(STACK),
+                I32Const(0),
+                i32_store(0),
+                self.get(STACK),
+                I32Const(4),
+                I32Add,
+                self.set(STACK),
+                Br(0),
+                End,
+                End,
+                GetLocal(fp),
+                End,
+            ],
+        )
+    }
+
+    // root#(p): pをシャドウスタックに積んでそのまま返す
+    fn root(&self) -> FunctionBody {
+        use OperatorCode::*;
+        body(
+            0,
+            vec![
+                self.get(STACK),
+                self.heap_base(),
+                I32Geu,
+                If(BlockType(None)),
+                Unreachable,
+                End,
+                self.get(STACK),
+                GetLocal(0),
+                i32_store(0),
+                self.get(STACK),
+                I32Const(4),
+                I32Add,
+                self.set(STACK),
+                GetLocal(0),
+                End,
+            ],
+        )
+    }
+}
+
+// funcs, globalsは実行時ライブラリの最初の関数とグローバル変数の番号
+// rootsは参照型のグローバル変数、stack_baseは型記述子の後ろ
+pub fn runtime(
+    gc: Gc,
+    funcs: usize,
+    globals: usize,
+    roots: Vec<usize>,
+    stack_base: u32,
+) -> Runtime {
+    let stack_base = align_to(stack_base, 8);
+    let heap_base = stack_base + SHADOW_STACK_SIZE;
+    let b = Builder {
+        gc,
+        funcs,
+        globals,
+        roots,
+        stack_base,
+        heap_base,
+    };
+    let sig = |params: usize, result: bool| FuncType {
+        params: vec![ValueType::I32; params],
+        result: if result { Some(ValueType::I32) } else { None },
+    };
+    let funcs = vec![
+        (sig(2, true), b.alloc()),
+        (sig(0, false), b.collect()),
+        (sig(1, true), b.frame()),
+        (sig(1, true), b.root()),
+        (sig(1, true), b.find()),
+        (sig(0, false), b.drain()),
+        (sig(1, false), b.mark()),
+        (sig(1, false), b.scan()),
+        (sig(1, true), b.is_object()),
+    ];
+    let init = [heap_base, 0, stack_base, stack_base, 0];
+    let globals = init
+        .iter()
+        .map(|&x| {
+            GlobalVariable(
+                GlobalType {
+                    content_type: ValueType::I32,
+                    mutability: true,
+                },
+                InitExpr::I32(x as i32),
+            )
+        })
+        .collect::<Vec<_>>();
+    Runtime {
+        funcs,
+        globals,
+        heap_base,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm::interp::{Imports, Instance, Trap, Val};
+
+    // 実行時ライブラリとメモリに書き込む関数store(addr, v)だけのモジュール
+    fn module(gc: Gc, descs: &Descriptors, maximum: Option<i32>) -> WasmASTRoot {
+        use OperatorCode::*;
+        let rt = runtime(gc, 0, 0, vec![], descs.end());
+        let mut types: Vec<FuncType> = Vec::new();
+        let mut sigs = Vec::new();
+        let mut bodies = Vec::new();
+        let store = FuncType {
+            params: vec![ValueType::I32; 2],
+            result: None,
+        };
+        let store_body = body(0, vec![GetLocal(0), GetLocal(1), i32_store(0), End]);
+        for (t, body) in rt.funcs.into_iter().chain(Some((store, store_body))) {
+            sigs.push(types.iter().position(|x| *x == t).unwrap_or_else(|| {
+                types.push(t);
+                types.len() - 1
+            }));
+            bodies.push(body);
+        }
+        let export = |field: &str, index| ExportEntry {
+            field: field.to_string(),
+            kind: ExternalKind::Function,
+            index,
+        };
+        WasmASTRoot {
+            type_section: Some(TypeSection(types)),
+            import_section: None,
+            function_section: Some(FunctionSection(sigs)),
+            table_section: None,
+            memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 2,
+                maximum,
+            })])),
+            global_section: Some(GlobalSection(rt.globals)),
+            export_section: Some(ExportSection(vec![
+                export("alloc", ALLOC),
+                export("collect", COLLECT),
+                export("frame", FRAME),
+                export("root", ROOT),
+                export("store", bodies.len() - 1),
+            ])),
+            start_section: None,
+            element_section: None,
+            code_section: Some(CodeSection(bodies)),
+            data_section: Some(DataSection(vec![descs.segment()])),
+        }
+    }
+
+    fn call(inst: &mut Instance, f: &str, args: &[i32]) -> Result<Option<Val>, Trap> {
+        let args = args.iter().map(|x| Val::I32(*x)).collect::<Vec<_>>();
+        inst.invoke(f, &args)
+    }
+
+    fn alloc(inst: &mut Instance, size: i32, desc: u32) -> i32 {
+        match call(inst, "alloc", &[size, desc as i32]) {
+            Ok(Some(Val::I32(p))) => p,
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn alloc_test() {
+        let descs = Descriptors::new(8);
+        let wasm = module(Gc::Off, &descs, Some(3));
+        let mut inst = Instance::new(&wasm, Imports::new()).unwrap();
+        let heap = (8 + SHADOW_STACK_SIZE + HEADER_SIZE) as i32;
+        assert_eq!(alloc(&mut inst, 10, 0), heap);
+        assert_eq!(alloc(&mut inst, 0, 0), heap + 24);
+        assert_eq!(alloc(&mut inst, 100000, 0), heap + 32);
+        assert_eq!(inst.memory().len(), 3 * PAGE_SIZE as usize);
+        assert_eq!(
+            call(&mut inst, "alloc", &[100000, 0]),
+            Err(Trap::Unreachable)
+        );
+    }
+
+    #[test]
+    fn collect_test() {
+        let mut descs = Descriptors::new(8);
+        let closure = descs.closure();
+        assert_eq!(descs.closure(), closure);
+        let wasm = module(Gc::On, &descs, None);
+        let mut inst = Instance::new(&wasm, Imports::new()).unwrap();
+        assert_eq!(call(&mut inst, "frame", &[2]), Ok(Some(Val::I32(24))));
+        // 閉包から環境をたどれる
+        let c = alloc(&mut inst, 8, closure);
+        let env = alloc(&mut inst, 16, 0);
+        let garbage = alloc(&mut inst, 16, 0);
+        call(&mut inst, "store", &[c + 4, env]).unwrap();
+        call(&mut inst, "root", &[c]).unwrap();
+        call(&mut inst, "collect", &[]).unwrap();
+        assert_eq!(alloc(&mut inst, 16, 0), garbage);
+        assert_eq!(alloc(&mut inst, 16, 0), garbage + 24);
+
+        // シャドウスタックは有限
+        assert_eq!(
+            call(&mut inst, "frame", &[SHADOW_STACK_SIZE as i32 / 4]),
+            Err(Trap::Unreachable)
+        );
+    }
+
+    #[test]
+    fn stress_test() {
+        let wasm = module(Gc::Stress, &Descriptors::new(8), None);
+        let mut inst = Instance::new(&wasm, Imports::new()).unwrap();
+        let p = alloc(&mut inst, 40, 0);
+        // 隣り合う空きブロックはつながる
+        assert_eq!(alloc(&mut inst, 16, 0), p + 24);
+        call(&mut inst, "root", &[p + 24]).unwrap();
+        assert_eq!(alloc(&mut inst, 8, 0), p + 8);
+        let q = alloc(&mut inst, 4000, 0);
+        // 空きブロックの末尾から切り出す
+        assert_eq!(alloc(&mut inst, 1000, 0), q + 3000);
+    }
+}
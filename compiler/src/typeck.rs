@@ -0,0 +1,998 @@
+use crate::cast::{check_bitcast, check_cast};
+use crate::decls::{Decls, TypeDecl};
+use crate::resolve::{DeclId, DeclKind, Resolution};
+use ast::ast::{AssignOp, Expr, Ident, Member, Module, RefType, Type};
+use ast::span::{ExprId, Span, Spans};
+use ast::visit::{walk_expr, Visitor};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ty {
+    // 値を持たない
+    Unit,
+    // 制御が戻ってこない
+    Never,
+    // 既にエラーを報告した
+    Error,
+    Type(Type),
+}
+
+impl Ty {
+    pub fn of(t: Option<&Type>) -> Ty {
+        t.cloned().map_or(Ty::Unit, Ty::Type)
+    }
+
+    // NeverとErrorはどの型としても扱える
+    pub fn is_any(&self) -> bool {
+        matches!(self, Ty::Never | Ty::Error)
+    }
+}
+
+fn ref_type(t: RefType) -> Ty {
+    Ty::Type(Type::RefType(t))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeErrorKind {
+    // 期待した型と実際の型
+    Mismatch(Ty, Ty),
+    InvalidOperand(Ty),
+    InvalidOperands(Ty, Ty),
+    InvalidCast(Ty, Type),
+    NotCallable(Ty),
+    // 期待した個数と実際の個数
+    ArgCount(usize, usize),
+    NotStruct(Ty),
+    NotStructType(Ident),
+    UnknownField(Ident, Ident),
+    MissingField(Ident, Ident),
+    // 共用体のリテラルはちょうど1つのフィールドを持つ
+    UnionLiteral(Ident),
+    NotEnum(Ty),
+    NotEnumType(Ident),
+    UnknownVariant(Ident, Ident),
+    NotIndexable(Ty),
+    NotAssignable,
+    NoValue,
+    ReturnOutsideFunction,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    pub span: Span,
+}
+
+// 構文木と合わせて型付きの構文木として扱う
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Typed {
+    // 式のIDごとの型
+    pub exprs: Vec<Ty>,
+    // 宣言ごとの型
+    pub decls: Vec<Ty>,
+    pub errors: Vec<TypeError>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operands {
+    Arith,
+    Int,
+    Logic,
+    Eq,
+    Ord,
+}
+
+impl Operands {
+    fn of(e: &Expr) -> Option<(Operands, &Expr, &Expr)> {
+        Some(match e {
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Mod(a, b)
+            | Expr::Pow(a, b) => (Operands::Arith, a, b),
+            Expr::BitAnd(a, b)
+            | Expr::BitOr(a, b)
+            | Expr::BitXor(a, b)
+            | Expr::Shl(a, b)
+            | Expr::Shr(a, b)
+            | Expr::UShr(a, b) => (Operands::Int, a, b),
+            Expr::And(a, b) | Expr::Or(a, b) => (Operands::Logic, a, b),
+            Expr::Eq(a, b) | Expr::Ne(a, b) => (Operands::Eq, a, b),
+            Expr::Lt(a, b) | Expr::Lte(a, b) | Expr::Gt(a, b) | Expr::Gte(a, b) => {
+                (Operands::Ord, a, b)
+            }
+            _ => return None,
+        })
+    }
+
+    fn assign(op: &AssignOp) -> Operands {
+        match op {
+            AssignOp::Add
+            | AssignOp::Sub
+            | AssignOp::Mul
+            | AssignOp::Div
+            | AssignOp::Mod
+            | AssignOp::Pow => Operands::Arith,
+            _ => Operands::Int,
+        }
+    }
+
+    fn accepts(self, t: &Type) -> bool {
+        use Type::*;
+        match self {
+            Operands::Arith => matches!(t, I32 | I64 | F32 | F64),
+            Operands::Int => matches!(t, I32 | I64),
+            Operands::Logic => matches!(t, Bool),
+            Operands::Eq => matches!(t, I32 | I64 | F32 | F64 | Bool | Char),
+            Operands::Ord => matches!(t, I32 | I64 | F32 | F64 | Char),
+        }
+    }
+
+    fn result(self, t: Option<&Type>) -> Ty {
+        match self {
+            Operands::Arith | Operands::Int => t.map_or(Ty::Error, |t| Ty::Type(t.clone())),
+            Operands::Logic | Operands::Eq | Operands::Ord => Ty::Type(Type::Bool),
+        }
+    }
+}
+
+// 後行順に数えた部分木の式の個数
+struct Count(usize);
+
+impl Visitor for Count {
+    fn visit_expr(&mut self, e: &Expr) {
+        walk_expr(self, e);
+        self.0 += 1;
+    }
+}
+
+fn size(e: &Expr) -> usize {
+    let mut c = Count(0);
+    c.visit_expr(e);
+    c.0
+}
+
+// 実行のように構文木を順に辿らない場合のために、後行順のIDごとに部分木の式の個数を数える
+// 子の式のIDは親のIDから後ろ向きに求まる
+pub(crate) struct Sizes {
+    sizes: Vec<usize>,
+    // トップレベルの定義ごとの、次の定義の最初の式のID
+    ends: Vec<ExprId>,
+}
+
+impl Visitor for Sizes {
+    fn visit_expr(&mut self, e: &Expr) {
+        let start = self.sizes.len();
+        walk_expr(self, e);
+        self.sizes.push(self.sizes.len() - start + 1);
+    }
+}
+
+// 直接の子の式の個数
+struct Direct(usize);
+
+impl Visitor for Direct {
+    fn visit_expr(&mut self, _: &Expr) {
+        self.0 += 1;
+    }
+}
+
+impl Sizes {
+    pub(crate) fn new(module: &Module) -> Sizes {
+        let mut sizes = Sizes {
+            sizes: Vec::new(),
+            ends: Vec::new(),
+        };
+        for member in module {
+            sizes.visit_member(member);
+            sizes.ends.push(sizes.sizes.len());
+        }
+        sizes
+    }
+
+    // 関数の本体や大域変数の初期値のID
+    pub(crate) fn member(&self, i: usize) -> ExprId {
+        self.ends[i] - 1
+    }
+
+    // 直接の子の式のIDを並び順に返す
+    pub(crate) fn children(&self, e: &Expr, id: ExprId) -> Vec<ExprId> {
+        let mut direct = Direct(0);
+        walk_expr(&mut direct, e);
+        let mut ids = vec![0; direct.0];
+        let mut next = id;
+        for x in ids.iter_mut().rev() {
+            *x = next - 1;
+            next -= self.sizes[next - 1];
+        }
+        ids
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Todo,
+    Visiting,
+    Done,
+}
+
+struct Checker<'a> {
+    module: &'a Module,
+    spans: &'a Spans,
+    res: &'a Resolution,
+    decls: Decls<'a>,
+    // 定義ごとの最初の式のID
+    starts: Vec<ExprId>,
+    states: Vec<State>,
+    // トップレベルの宣言からその定義へ
+    owners: HashMap<DeclId, usize>,
+    // 関数とラムダの戻り値の型
+    rets: Vec<Option<Type>>,
+    next_id: ExprId,
+    out: Typed,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, kind: TypeErrorKind, span: Span) {
+        self.out.errors.push(TypeError { kind, span });
+    }
+
+    // 直前に検査した式の範囲
+    fn last_span(&self) -> Span {
+        self.spans.expr(self.next_id - 1)
+    }
+
+    fn expect(&mut self, found: &Ty, want: &Type, span: Span) {
+        match found {
+            Ty::Type(t) if t == want => {}
+            t if t.is_any() => {}
+            _ => self.error(
+                TypeErrorKind::Mismatch(Ty::Type(want.clone()), found.clone()),
+                span,
+            ),
+        }
+    }
+
+    fn decl_type(&mut self, d: DeclId) -> Ty {
+        match self.res.decls[d].kind {
+            DeclKind::Global | DeclKind::Const => {
+                // 型注釈のない大域変数は初期化式の型を先に調べる
+                let i = self.owners[&d];
+                if self.states[i] == State::Visiting {
+                    return Ty::Error;
+                }
+                self.member(i);
+                self.out.decls[d].clone()
+            }
+            _ => self.out.decls[d].clone(),
+        }
+    }
+
+    // 分岐の型を揃える
+    fn join(&mut self, branches: Vec<(Ty, Span)>) -> Ty {
+        let mut res = Ty::Never;
+        for (t, span) in branches {
+            if t.is_any() {
+                continue;
+            }
+            if res.is_any() {
+                res = t;
+            } else if t != res {
+                self.error(TypeErrorKind::Mismatch(res.clone(), t), span);
+            }
+        }
+        res
+    }
+
+    fn operands(&mut self, op: Operands, a: Ty, b: Ty, errors: &mut Vec<TypeErrorKind>) -> Ty {
+        let known = [&a, &b]
+            .iter()
+            .filter_map(|t| match t {
+                Ty::Type(t) => Some(t),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let unit = a == Ty::Unit || b == Ty::Unit;
+        if unit
+            || known.iter().any(|t| !op.accepts(t))
+            || (known.len() == 2 && known[0] != known[1])
+        {
+            errors.push(TypeErrorKind::InvalidOperands(a, b));
+            return Ty::Error;
+        }
+        op.result(known.first().cloned())
+    }
+
+    fn args(&mut self, params: &[Type], args: &[Expr], errors: &mut Vec<TypeErrorKind>) {
+        if params.len() != args.len() {
+            errors.push(TypeErrorKind::ArgCount(params.len(), args.len()));
+        }
+        for (i, e) in args.iter().enumerate() {
+            let t = self.expr(e);
+            if let Some(p) = params.get(i) {
+                self.expect(&t, p, self.last_span());
+            }
+        }
+    }
+
+    // 代入できる式か(lhsは検査済み)
+    fn assignable(&self, lhs: &Expr) -> bool {
+        let id = self.next_id - 1;
+        match lhs {
+            Expr::Var(_) => match self.res.uses.get(&id) {
+                Some(d) => !matches!(
+                    self.res.decls[*d].kind,
+                    DeclKind::Const | DeclKind::Func | DeclKind::ExternFun | DeclKind::Capture(_)
+                ),
+                None => true,
+            },
+            Expr::Member(..) => true,
+            Expr::Index(_, i) => {
+                let array = id - size(i) - 1;
+                !matches!(
+                    &self.out.exprs[array],
+                    Ty::Type(Type::RefType(RefType::String))
+                )
+            }
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> Ty {
+        let mut errors = Vec::new();
+        let t = self.expr_type(e, &mut errors);
+        let id = self.next_id;
+        self.next_id += 1;
+        let span = self.spans.expr(id);
+        for kind in errors {
+            self.error(kind, span);
+        }
+        self.out.exprs[id] = t.clone();
+        t
+    }
+
+    fn expr_type(&mut self, e: &Expr, errors: &mut Vec<TypeErrorKind>) -> Ty {
+        if let Some((op, a, b)) = Operands::of(e) {
+            let a = self.expr(a);
+            let b = self.expr(b);
+            return self.operands(op, a, b, errors);
+        }
+        match e {
+            Expr::I32Literal(_) => Ty::Type(Type::I32),
+            Expr::I64Literal(_) => Ty::Type(Type::I64),
+            Expr::F32Literal(_) => Ty::Type(Type::F32),
+            Expr::F64Literal(_) => Ty::Type(Type::F64),
+            Expr::BoolLiteral(_) => Ty::Type(Type::Bool),
+            Expr::CharLiteral(_) => Ty::Type(Type::Char),
+            Expr::StringLiteral(_) => ref_type(RefType::String),
+            Expr::StructLiteral(name, fields) => {
+                let given = fields
+                    .iter()
+                    .map(|(x, e)| (*x, self.expr(e), self.last_span()))
+                    .collect::<Vec<_>>();
+                match self.decls.types.get(name).cloned() {
+                    Some(TypeDecl::Struct(decl)) | Some(TypeDecl::Union(decl)) => {
+                        for (x, t, span) in &given {
+                            match self.decls.field(*name, *x) {
+                                Some(want) => self.expect(t, want, *span),
+                                None => errors.push(TypeErrorKind::UnknownField(*name, *x)),
+                            }
+                        }
+                        if let Some(TypeDecl::Union(_)) = self.decls.types.get(name) {
+                            if given.len() != 1 {
+                                errors.push(TypeErrorKind::UnionLiteral(*name));
+                            }
+                        } else {
+                            let given = given.iter().map(|(x, _, _)| *x).collect::<HashSet<_>>();
+                            for (x, _) in decl {
+                                if !given.contains(x) {
+                                    errors.push(TypeErrorKind::MissingField(*name, *x));
+                                }
+                            }
+                        }
+                    }
+                    Some(TypeDecl::Enum(_)) => errors.push(TypeErrorKind::NotStructType(*name)),
+                    None => {}
+                }
+                ref_type(RefType::Struct(*name))
+            }
+            Expr::EnumLiteral(name, variant, args) => {
+                match self.decls.types.get(name) {
+                    Some(TypeDecl::Enum(_)) => match self.decls.variant(*name, *variant) {
+                        Some((_, params)) => self.args(params, args, errors),
+                        None => {
+                            self.args(&[], args, &mut Vec::new());
+                            errors.push(TypeErrorKind::UnknownVariant(*name, *variant));
+                        }
+                    },
+                    decl => {
+                        if decl.is_some() {
+                            errors.push(TypeErrorKind::NotEnumType(*name));
+                        }
+                        self.args(&[], args, &mut Vec::new());
+                    }
+                }
+                ref_type(RefType::Struct(*name))
+            }
+            Expr::ArrayLiteral(t, len) => {
+                let n = self.expr(len);
+                self.expect(&n, &Type::I32, self.last_span());
+                ref_type(RefType::Array(Box::new(t.clone())))
+            }
+            Expr::Var(_) => match self.res.uses.get(&self.next_id) {
+                Some(d) => self.decl_type(*d),
+                None => Ty::Error,
+            },
+            Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) => {
+                let t = self.expr(x);
+                let op = match e {
+                    Expr::Not(_) => Operands::Logic,
+                    _ => Operands::Arith,
+                };
+                match &t {
+                    Ty::Type(x) if op.accepts(x) => t,
+                    t if t.is_any() => op.result(None),
+                    _ => {
+                        errors.push(TypeErrorKind::InvalidOperand(t));
+                        Ty::Error
+                    }
+                }
+            }
+            Expr::Member(x, field) => match self.expr(x) {
+                Ty::Type(Type::RefType(RefType::Struct(name))) => {
+                    match self.decls.field(name, *field) {
+                        Some(t) => Ty::Type(t.clone()),
+                        None => {
+                            errors.push(TypeErrorKind::UnknownField(name, *field));
+                            Ty::Error
+                        }
+                    }
+                }
+                t if t.is_any() => Ty::Error,
+                t => {
+                    errors.push(TypeErrorKind::NotStruct(t));
+                    Ty::Error
+                }
+            },
+            Expr::Index(a, i) => {
+                let ta = self.expr(a);
+                let ti = self.expr(i);
+                self.expect(&ti, &Type::I32, self.last_span());
+                match ta {
+                    Ty::Type(Type::RefType(RefType::Array(t))) => Ty::Type(*t),
+                    // 文字列の添字はUTF-8のバイトを0から255のi32で返す
+                    Ty::Type(Type::RefType(RefType::String)) => Ty::Type(Type::I32),
+                    t if t.is_any() => Ty::Error,
+                    t => {
+                        errors.push(TypeErrorKind::NotIndexable(t));
+                        Ty::Error
+                    }
+                }
+            }
+            Expr::Closure(f, env) => {
+                let t = (**env).as_ref().map(|e| (self.expr(e), self.last_span()));
+                match self.decls.funcs.get(f).cloned() {
+                    // 関数表の関数は第1引数で環境を受け取る
+                    Some(def) if !def.1.is_empty() => {
+                        if let Some((t, span)) = t {
+                            self.expect(&t, &def.1[0].1, span);
+                        }
+                        let params = def.1[1..].iter().map(|(_, t)| t.clone()).collect();
+                        ref_type(RefType::Func(params, Box::new(def.2.clone())))
+                    }
+                    _ => {
+                        errors.push(TypeErrorKind::NotCallable(Ty::Error));
+                        Ty::Error
+                    }
+                }
+            }
+            Expr::Call(f, args) | Expr::CallIndirect(f, args) => {
+                let tf = self.expr(f);
+                let span = self.last_span();
+                match tf {
+                    Ty::Type(Type::RefType(RefType::Func(params, ret))) => {
+                        self.args(&params, args, errors);
+                        Ty::of(ret.as_ref().as_ref())
+                    }
+                    t => {
+                        if !t.is_any() {
+                            self.error(TypeErrorKind::NotCallable(t), span);
+                        }
+                        self.args(&[], args, &mut Vec::new());
+                        Ty::Error
+                    }
+                }
+            }
+            Expr::Block(stmts, last) => {
+                let mut diverges = false;
+                for e in stmts {
+                    diverges |= self.expr(e) == Ty::Never;
+                }
+                match &**last {
+                    Some(e) => self.expr(e),
+                    None if diverges => Ty::Never,
+                    None => Ty::Unit,
+                }
+            }
+            Expr::Let(_, x) => {
+                let t = self.expr(x);
+                if t == Ty::Unit {
+                    self.error(TypeErrorKind::NoValue, self.last_span());
+                }
+                let diverges = t == Ty::Never;
+                if let Some(binds) = self.res.binds.get(&self.next_id) {
+                    self.out.decls[binds[0]] = match t {
+                        Ty::Unit => Ty::Error,
+                        // 初期化式から戻ってこない変数は値を持たず、どの型としても使える
+                        Ty::Never => Ty::Never,
+                        t => t,
+                    };
+                }
+                // 初期化式から戻ってこなければletも戻ってこない
+                if diverges {
+                    Ty::Never
+                } else {
+                    Ty::Unit
+                }
+            }
+            Expr::If(first, elifs, els) => {
+                let mut branches = Vec::new();
+                for (cond, then) in std::iter::once(&**first).chain(elifs) {
+                    let t = self.expr(cond);
+                    self.expect(&t, &Type::Bool, self.last_span());
+                    branches.push((self.expr(then), self.last_span()));
+                }
+                match &**els {
+                    Some(e) => {
+                        branches.push((self.expr(e), self.last_span()));
+                        self.join(branches)
+                    }
+                    None => Ty::Unit,
+                }
+            }
+            Expr::While(cond, body) => {
+                let t = self.expr(cond);
+                self.expect(&t, &Type::Bool, self.last_span());
+                self.expr(body);
+                Ty::Unit
+            }
+            Expr::For(init, cond, step, body) => {
+                self.expr(init);
+                let t = self.expr(cond);
+                self.expect(&t, &Type::Bool, self.last_span());
+                self.expr(step);
+                self.expr(body);
+                Ty::Unit
+            }
+            Expr::Return(x) => {
+                let t = (**x).as_ref().map(|x| (self.expr(x), self.last_span()));
+                match (self.rets.last().cloned(), t) {
+                    (None, _) => errors.push(TypeErrorKind::ReturnOutsideFunction),
+                    (Some(Some(ret)), Some((t, span))) => self.expect(&t, &ret, span),
+                    (Some(Some(ret)), None) => {
+                        errors.push(TypeErrorKind::Mismatch(Ty::Type(ret), Ty::Unit))
+                    }
+                    (Some(None), Some((t, span))) => {
+                        if !t.is_any() && t != Ty::Unit {
+                            self.error(TypeErrorKind::Mismatch(Ty::Unit, t), span);
+                        }
+                    }
+                    (Some(None), None) => {}
+                }
+                Ty::Never
+            }
+            Expr::Break(_) | Expr::Continue(_) => Ty::Never,
+            Expr::Labeled(_, x) => self.expr(x),
+            Expr::Match(x, arms, default) => {
+                let id = self.next_id + size(e) - 1;
+                let t = self.expr(x);
+                let span = self.last_span();
+                let variants = match &t {
+                    Ty::Type(Type::RefType(RefType::Struct(name))) => {
+                        match self.decls.types.get(name) {
+                            Some(TypeDecl::Enum(vs)) => Some((*name, *vs)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                if variants.is_none() && !t.is_any() {
+                    self.error(TypeErrorKind::NotEnum(t), span);
+                }
+                let binds = self.res.binds.get(&id).cloned().unwrap_or_default();
+                let mut binds = binds.into_iter();
+                let mut covered = HashSet::new();
+                let mut branches = Vec::new();
+                for (variant, xs, body) in arms {
+                    let params = match variants {
+                        Some((name, _)) => match self.decls.variant(name, *variant) {
+                            Some((_, params)) => {
+                                covered.insert(*variant);
+                                if params.len() != xs.len() {
+                                    errors.push(TypeErrorKind::ArgCount(params.len(), xs.len()));
+                                }
+                                params
+                            }
+                            None => {
+                                errors.push(TypeErrorKind::UnknownVariant(name, *variant));
+                                &[]
+                            }
+                        },
+                        None => &[],
+                    };
+                    for (i, d) in binds.by_ref().take(xs.len()).enumerate() {
+                        self.out.decls[d] = params.get(i).cloned().map_or(Ty::Error, Ty::Type);
+                    }
+                    branches.push((self.expr(body), self.last_span()));
+                }
+                if let Some(e) = &**default {
+                    branches.push((self.expr(e), self.last_span()));
+                }
+                let exhaustive = default.is_some()
+                    || variants.is_none_or(|(_, vs)| vs.iter().all(|(v, _)| covered.contains(v)));
+                if exhaustive {
+                    self.join(branches)
+                } else {
+                    Ty::Unit
+                }
+            }
+            Expr::Cast(x, to) | Expr::Bitcast(x, to) => {
+                let t = self.expr(x);
+                let ok = match &t {
+                    Ty::Type(from) => match e {
+                        Expr::Cast(..) => check_cast(from, to).is_some(),
+                        _ => check_bitcast(from, to).is_some(),
+                    },
+                    t => t.is_any(),
+                };
+                if !ok {
+                    errors.push(TypeErrorKind::InvalidCast(t, to.clone()));
+                }
+                Ty::Type(to.clone())
+            }
+            Expr::Set(lhs, rhs) | Expr::CompoundSet(_, lhs, rhs) => {
+                let tl = self.expr(lhs);
+                if !self.assignable(lhs) {
+                    self.error(TypeErrorKind::NotAssignable, self.last_span());
+                }
+                let tr = self.expr(rhs);
+                match e {
+                    Expr::CompoundSet(op, _, _) => {
+                        let t = self.operands(Operands::assign(op), tl.clone(), tr, errors);
+                        if let (Ty::Type(want), Ty::Type(_)) = (&tl, &t) {
+                            self.expect(&t, want, self.last_span());
+                        }
+                    }
+                    _ => {
+                        if let Ty::Type(want) = &tl {
+                            self.expect(&tr, want, self.last_span());
+                        }
+                    }
+                }
+                Ty::Unit
+            }
+            Expr::Lambda(_, params, ret, body) => {
+                let id = self.next_id + size(e) - 1;
+                let binds = self.res.binds.get(&id).cloned().unwrap_or_default();
+                let caps = binds.len() - params.len();
+                for &d in &binds[..caps] {
+                    if let DeclKind::Capture(outer) = self.res.decls[d].kind {
+                        self.out.decls[d] = self.decl_type(outer);
+                    }
+                }
+                for (&d, (_, t)) in binds[caps..].iter().zip(params) {
+                    self.out.decls[d] = Ty::Type(t.clone());
+                }
+                self.rets.push(Some(ret.clone()));
+                let t = self.expr(body);
+                self.expect(&t, ret, self.last_span());
+                self.rets.pop();
+                let params = params.iter().map(|(_, t)| t.clone()).collect();
+                ref_type(RefType::Func(params, Box::new(Some(ret.clone()))))
+            }
+            Expr::Add(..)
+            | Expr::Sub(..)
+            | Expr::Mul(..)
+            | Expr::Div(..)
+            | Expr::Mod(..)
+            | Expr::Pow(..)
+            | Expr::BitAnd(..)
+            | Expr::BitOr(..)
+            | Expr::BitXor(..)
+            | Expr::Shl(..)
+            | Expr::Shr(..)
+            | Expr::UShr(..)
+            | Expr::And(..)
+            | Expr::Or(..)
+            | Expr::Eq(..)
+            | Expr::Ne(..)
+            | Expr::Lt(..)
+            | Expr::Lte(..)
+            | Expr::Gt(..)
+            | Expr::Gte(..) => unreachable!(),
+        }
+    }
+
+    fn member(&mut self, i: usize) {
+        if self.states[i] != State::Todo {
+            return;
+        }
+        self.states[i] = State::Visiting;
+        let next_id = std::mem::replace(&mut self.next_id, self.starts[i]);
+        let rets = std::mem::take(&mut self.rets);
+        let decls = &self.res.members[i];
+        match &self.module[i] {
+            Member::Func(def, body, _) => {
+                for (&d, (_, t)) in decls[1..].iter().zip(&def.1) {
+                    self.out.decls[d] = Ty::Type(t.clone());
+                }
+                self.rets.push(def.2.clone());
+                let t = self.expr(body);
+                if let Some(ret) = &def.2 {
+                    self.expect(&t, ret, self.last_span());
+                }
+            }
+            Member::Let(_, annot, e) | Member::Const(_, annot, e) => {
+                let t = self.expr(e);
+                let span = self.last_span();
+                if t == Ty::Unit {
+                    self.error(TypeErrorKind::NoValue, span);
+                }
+                let t = match annot {
+                    Some(annot) => {
+                        self.expect(&t, annot, span);
+                        Ty::Type(annot.clone())
+                    }
+                    None if t == Ty::Unit => Ty::Error,
+                    None => t,
+                };
+                if let Some(&d) = decls.first() {
+                    if self.owners.get(&d) == Some(&i) {
+                        self.out.decls[d] = t;
+                    }
+                }
+            }
+            Member::Struct(..) | Member::Enum(..) | Member::Union(..) | Member::ExternFun(..) => {}
+        }
+        self.next_id = next_id;
+        self.rets = rets;
+        self.states[i] = State::Done;
+    }
+}
+
+// 型推論は行わず、変数の型は宣言または初期化式の型とする
+pub fn check(module: &Module, spans: &Spans, res: &Resolution) -> Typed {
+    let mut starts = Vec::new();
+    let mut count = Count(0);
+    for member in module {
+        starts.push(count.0);
+        count.visit_member(member);
+    }
+    let mut c = Checker {
+        module,
+        spans,
+        res,
+        decls: Decls::new(module),
+        starts,
+        states: vec![State::Todo; module.len()],
+        owners: HashMap::new(),
+        rets: Vec::new(),
+        next_id: 0,
+        out: Typed {
+            exprs: vec![Ty::Error; count.0],
+            decls: vec![Ty::Error; res.decls.len()],
+            errors: Vec::new(),
+        },
+    };
+    for (i, member) in module.iter().enumerate() {
+        let d = match res.members.get(i).and_then(|ds| ds.first()) {
+            Some(&d) if !c.owners.contains_key(&d) => d,
+            _ => continue,
+        };
+        match (member, res.decls[d].kind) {
+            (Member::Func(def, _, _), DeclKind::Func)
+            | (Member::ExternFun(def, _, _), DeclKind::ExternFun) => {
+                let params = def.1.iter().map(|(_, t)| t.clone()).collect();
+                let ret = Box::new(def.2.clone());
+                c.out.decls[d] = ref_type(RefType::Func(params, ret));
+                c.owners.insert(d, i);
+            }
+            (Member::Let(..), DeclKind::Global) | (Member::Const(..), DeclKind::Const) => {
+                c.owners.insert(d, i);
+            }
+            _ => {}
+        }
+    }
+    for i in 0..module.len() {
+        c.member(i);
+    }
+    c.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use ast::parser::parse_with_spans;
+    use token::intern::Sym;
+
+    fn check_src(src: &str) -> (Spans, Typed) {
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        assert_eq!(res.errors, vec![]);
+        let typed = check(&m, &spans, &res);
+        (spans, typed)
+    }
+
+    fn text(src: &str, span: Span) -> String {
+        src.chars().skip(span.pos).take(span.len).collect()
+    }
+
+    #[test]
+    fn check_test() {
+        let src = r#"
+            struct P { x: i32, y: F64 }
+            enum E { A(i32), B }
+            let G = N * 2;
+            const N: i32 = 3;
+            fun f(p: P, e: E): F64 {
+                let a = [i32; G];
+                a[0] += p.x;
+                let s = "abc";
+                let c = s[1] as i32;
+                let g = \[a](i: i32): i32 -> a[i] + 1;
+                let n = match (e) { A(x) => g(x), B => { return 0.0; } };
+                if (n < c && true) { p.y } else { (n as F64) ** 2.0 }
+            }
+        "#;
+        let (spans, typed) = check_src(src);
+        assert_eq!(typed.errors, vec![]);
+        let find = |s: &str| {
+            let id = (0..typed.exprs.len())
+                .find(|&id| text(src, spans.expr(id)) == s)
+                .unwrap();
+            typed.exprs[id].clone()
+        };
+        assert_eq!(find("G"), Ty::Type(Type::I32));
+        assert_eq!(find("s[1]"), Ty::Type(Type::I32));
+        assert_eq!(find("a[0] += p.x"), Ty::Unit);
+        assert_eq!(find("return 0.0"), Ty::Never);
+        assert_eq!(find("{ return 0.0; }"), Ty::Never);
+        assert_eq!(
+            find("\\[a](i: i32): i32 -> a[i] + 1"),
+            ref_type(RefType::Func(vec![Type::I32], Box::new(Some(Type::I32))))
+        );
+        assert_eq!(
+            find("match (e) { A(x) => g(x), B => { return 0.0; } }"),
+            Ty::Type(Type::I32)
+        );
+    }
+
+    #[test]
+    fn check_error_test() {
+        let src = r#"
+            struct P { x: i32 }
+            union U { a: i32, b: F64 }
+            const C: i32 = 1;
+            fun f(p: P): i32 {
+                let a = 1 + 1.0;
+                let b = true && 1;
+                let q = P { x: 'a' };
+                let u = U { a: 1, b: 1.0 };
+                p.z;
+                "s"[0] = 97;
+                C = 2;
+                f(p, 1);
+                let v = f(p) as bool;
+                if (b) { 1 } else { 'c' };
+                return;
+            }
+        "#;
+        let (_, typed) = check_src(src);
+        let errors = typed
+            .errors
+            .iter()
+            .map(|e| (e.kind.clone(), text(src, e.span)))
+            .collect::<Vec<_>>();
+        let sym = Sym::intern;
+        let ty = Ty::Type;
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    TypeErrorKind::InvalidOperands(ty(Type::I32), ty(Type::F64)),
+                    "1 + 1.0".to_string()
+                ),
+                (
+                    TypeErrorKind::InvalidOperands(ty(Type::Bool), ty(Type::I32)),
+                    "true && 1".to_string()
+                ),
+                (
+                    TypeErrorKind::Mismatch(ty(Type::I32), ty(Type::Char)),
+                    "'a'".to_string()
+                ),
+                (
+                    TypeErrorKind::UnionLiteral(sym("U")),
+                    "U { a: 1, b: 1.0 }".to_string()
+                ),
+                (
+                    TypeErrorKind::UnknownField(sym("P"), sym("z")),
+                    "p.z".to_string()
+                ),
+                (TypeErrorKind::NotAssignable, "\"s\"[0]".to_string()),
+                (TypeErrorKind::NotAssignable, "C".to_string()),
+                (TypeErrorKind::ArgCount(1, 2), "f(p, 1)".to_string()),
+                (
+                    TypeErrorKind::InvalidCast(ty(Type::I32), Type::Bool),
+                    "f(p) as bool".to_string()
+                ),
+                (
+                    TypeErrorKind::Mismatch(ty(Type::I32), ty(Type::Char)),
+                    "{ 'c' }".to_string()
+                ),
+                (
+                    TypeErrorKind::Mismatch(ty(Type::I32), Ty::Unit),
+                    "return".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn diverging_let_test() {
+        let src = r#"
+            fun f(c: i32): i32 {
+                let x = { return 1; };
+                x + c
+            }
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let res = resolve(&m, &spans);
+        let typed = check(&m, &spans, &res);
+        assert_eq!(typed.errors, vec![]);
+        let id = (0..typed.exprs.len())
+            .find(|&id| text(src, spans.expr(id)) == "let x = { return 1; }")
+            .unwrap();
+        assert_eq!(typed.exprs[id], Ty::Never);
+        let x = res
+            .decls
+            .iter()
+            .position(|d| d.name == Sym::intern("x"))
+            .unwrap();
+        assert_eq!(typed.decls[x], Ty::Never);
+    }
+
+    #[test]
+    fn sizes_test() {
+        let src = r#"
+            struct P { x: i32 }
+            fun f(a: i32): i32 { let x = a * 2 + 1; if (x > 0) x elif (x < -5) 0 else -x }
+            const C = P { x: 1 };
+        "#;
+        let (m, spans) = parse_with_spans(src).unwrap();
+        let sizes = Sizes::new(&m);
+        let texts = |e: &Expr, id| {
+            sizes
+                .children(e, id)
+                .into_iter()
+                .map(|id| text(src, spans.expr(id)))
+                .collect::<Vec<_>>()
+        };
+        let body = match &m[1] {
+            Member::Func(_, body, _) => body,
+            _ => unreachable!(),
+        };
+        let id = sizes.member(1);
+        let cond = "if (x > 0) x elif (x < -5) 0 else -x";
+        assert_eq!(texts(body, id), vec!["let x = a * 2 + 1", cond]);
+        match body {
+            Expr::Block(_, last) => assert_eq!(
+                texts(last.as_ref().as_ref().unwrap(), id - 1),
+                vec!["x > 0", "x", "x < -5", "0", "-x"]
+            ),
+            _ => unreachable!(),
+        }
+        assert_eq!(text(src, spans.expr(sizes.member(2))), "P { x: 1 }");
+    }
+}
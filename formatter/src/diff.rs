@@ -0,0 +1,117 @@
+// Myersの差分アルゴリズムで最長共通部分列を求める
+// 戻り値はbの各要素に対応するaの添字
+pub fn align<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Option<usize>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + max + 1) as usize;
+    // trace[d]はd回目の探索前のvのうち、k=-d-1..=d+1の範囲
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v[at(-d - 1)..=at(d + 1)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut res = vec![None; b.len()];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            res[y as usize] = Some(x as usize);
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lcs(a: &str, b: &str) -> usize {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        let mut dp = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                dp[i + 1][j + 1] = if a[i] == b[j] {
+                    dp[i][j] + 1
+                } else {
+                    dp[i][j + 1].max(dp[i + 1][j])
+                };
+            }
+        }
+        dp[a.len()][b.len()]
+    }
+
+    fn check(a: &str, b: &str) {
+        let res = align(a.as_bytes(), b.as_bytes());
+        let pairs = res
+            .iter()
+            .enumerate()
+            .filter_map(|(j, i)| i.map(|i| (i, j)))
+            .collect::<Vec<_>>();
+        for w in pairs.windows(2) {
+            assert!(w[0].0 < w[1].0, "{:?} {:?}", a, b);
+        }
+        for &(i, j) in &pairs {
+            assert_eq!(a.as_bytes()[i], b.as_bytes()[j], "{:?} {:?}", a, b);
+        }
+        assert_eq!(pairs.len(), lcs(a, b), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn align_test() {
+        assert_eq!(
+            align(b"a?b:c;", b"if(a)b else c"),
+            vec![
+                None,
+                None,
+                None,
+                Some(0),
+                None,
+                Some(2),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(4)
+            ]
+        );
+        let words = ["", "a", "abc", "abcabba", "cbabac", "xyz", "aaaa", "ba"];
+        for a in &words {
+            for b in &words {
+                check(a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+// Wadlerの"A prettier printer"に基づくレイアウト
+#[derive(Clone, Debug, PartialEq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    // 平坦なら空白1つ、改行するなら改行
+    Line,
+    // 平坦なら何も出力しない
+    SoftLine,
+    // 常に改行する
+    HardLine,
+    // 行の途中なら改行する
+    FreshLine,
+    // 囲んでいるグループを必ず改行させる
+    BreakParent,
+    // 出力した後、次の文字列の前に必ず改行する
+    Comment(String),
+    Nest(usize, Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+pub const INDENT: usize = 4;
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn nest(d: Doc) -> Doc {
+    Doc::Nest(INDENT, Box::new(d))
+}
+
+pub fn group(d: Doc) -> Doc {
+    Doc::Group(Box::new(d))
+}
+
+pub fn concat(ds: Vec<Doc>) -> Doc {
+    Doc::Concat(ds)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+// 残り幅wに、次の改行までが収まるか
+fn fits(next: (usize, Mode, &Doc), rest: &[(usize, Mode, &Doc)], mut w: isize) -> bool {
+    let mut stack = vec![next];
+    let mut rest = rest.iter().rev();
+    loop {
+        let (i, mode, d) = match stack.pop() {
+            Some(x) => x,
+            None => match rest.next() {
+                Some(&x) => x,
+                None => return true,
+            },
+        };
+        match d {
+            Doc::Nil | Doc::FreshLine => {}
+            Doc::Text(s) => {
+                if s.contains('\n') {
+                    return false;
+                }
+                w -= width(s) as isize;
+            }
+            Doc::Line | Doc::SoftLine => {
+                if mode == Mode::Break {
+                    return true;
+                }
+                if let Doc::Line = d {
+                    w -= 1;
+                }
+            }
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::BreakParent => {
+                if mode == Mode::Flat {
+                    return false;
+                }
+            }
+            // コメントの後は改行される
+            Doc::Comment(_) => return mode == Mode::Break,
+            Doc::Nest(j, d) => stack.push((i + j, mode, d)),
+            Doc::Group(d) => stack.push((i, mode, d)),
+            Doc::Concat(ds) => stack.extend(ds.iter().rev().map(|d| (i, mode, d))),
+        }
+        if w < 0 {
+            return false;
+        }
+    }
+}
+
+struct Renderer {
+    out: String,
+    col: usize,
+    // 行頭から空白しか出力していない
+    fresh: bool,
+    // コメントの後で改行が必要
+    pending: bool,
+}
+
+impl Renderer {
+    fn newline(&mut self, indent: usize) {
+        while self.out.ends_with(' ') {
+            self.out.pop();
+        }
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.col = indent;
+        self.fresh = true;
+        self.pending = false;
+    }
+
+    fn text(&mut self, s: &str, indent: usize) {
+        if self.pending {
+            // コメントの直後の空白は改行に置き換える
+            if s.chars().all(|c| c == ' ') {
+                return;
+            }
+            self.newline(indent);
+        }
+        self.out.push_str(s);
+        match s.rfind('\n') {
+            Some(i) => self.col = width(&s[i + 1..]),
+            None => self.col += width(s),
+        }
+        if !s.is_empty() {
+            self.fresh = false;
+        }
+    }
+}
+
+pub fn render(doc: &Doc, line_width: usize) -> String {
+    let mut r = Renderer {
+        out: String::new(),
+        col: 0,
+        fresh: true,
+        pending: false,
+    };
+    let mut stack = vec![(0, Mode::Break, doc)];
+    while let Some((i, mode, d)) = stack.pop() {
+        match d {
+            Doc::Nil | Doc::BreakParent => {}
+            Doc::Text(s) => r.text(s, i),
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = d {
+                    r.text(" ", i);
+                }
+            }
+            Doc::Line | Doc::SoftLine | Doc::HardLine => r.newline(i),
+            Doc::FreshLine => {
+                if !r.fresh {
+                    r.newline(i);
+                }
+            }
+            Doc::Comment(s) => {
+                r.text(s, i);
+                r.pending = true;
+            }
+            Doc::Nest(j, d) => stack.push((i + j, mode, d)),
+            Doc::Group(d) => {
+                let w = line_width as isize - r.col as isize;
+                let mode = if mode == Mode::Flat || fits((i, Mode::Flat, d), &stack, w) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((i, mode, d));
+            }
+            Doc::Concat(ds) => stack.extend(ds.iter().rev().map(|d| (i, mode, d))),
+        }
+    }
+    while r.out.ends_with(' ') {
+        r.out.pop();
+    }
+    r.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(f: &str, args: &[&str]) -> Doc {
+        let mut inner = vec![Doc::SoftLine];
+        for (i, x) in args.iter().enumerate() {
+            if i != 0 {
+                inner.push(text(","));
+                inner.push(Doc::Line);
+            }
+            inner.push(text(*x));
+        }
+        group(concat(vec![
+            text(f),
+            text("("),
+            nest(concat(inner)),
+            Doc::SoftLine,
+            text(")"),
+        ]))
+    }
+
+    #[test]
+    fn render_test() {
+        let d = call("f", &["aaa", "bbb"]);
+        assert_eq!(render(&d, 12), "f(aaa, bbb)");
+        assert_eq!(render(&d, 10), "f(\n    aaa,\n    bbb\n)");
+
+        // 後続の文字列も幅に含める
+        let d = concat(vec![call("f", &["a"]), text(";;;;")]);
+        assert_eq!(render(&d, 8), "f(a);;;;");
+        assert_eq!(render(&d, 7), "f(\n    a\n);;;;");
+
+        let d = call("f", &["a", "b"]);
+        let d = concat(vec![
+            text("x"),
+            Doc::Comment(" // c".to_string()),
+            text(" "),
+            d,
+        ]);
+        assert_eq!(render(&d, 80), "x // c\nf(a, b)");
+
+        let d = group(concat(vec![
+            text("a"),
+            Doc::Comment(" // c".to_string()),
+            Doc::BreakParent,
+            Doc::Line,
+            text("b"),
+        ]));
+        assert_eq!(render(&d, 80), "a // c\nb");
+
+        let d = concat(vec![text("a ="), Doc::FreshLine, text("b"), Doc::FreshLine]);
+        assert_eq!(render(&d, 80), "a =\nb\n");
+    }
+}
@@ -0,0 +1,967 @@
+mod diff;
+pub mod doc;
+
+use ast::ast::{Expr, FuncDef, Ident, Member, Module, Type};
+use ast::parser::{parse, ParseError};
+use ast::printer::{
+    assign_op, binary, dangling, literal, prec, ASSIGN, ATOM, CAST, OPEN, OR, POSTFIX, UNARY,
+};
+use doc::{concat, group, nest, text, Doc};
+use std::collections::{HashMap, HashSet};
+use token::intern::Sym;
+use token::lexer::{lex, lex_with_comments};
+use token::token::{Comment, Kind, Symbol, Token};
+
+pub const DEFAULT_WIDTH: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Trivia {
+    text: String,
+    // 後ろで改行が必要
+    line: bool,
+    // 行頭にある
+    own_line: bool,
+    // 直前に空行がある
+    blank: bool,
+}
+
+// 出力するトークンの添字ごとのコメント
+#[derive(Debug, Default)]
+struct Attached {
+    leading: HashMap<usize, Vec<Trivia>>,
+    trailing: HashMap<usize, Vec<Trivia>>,
+    // コメントを除いて直前に空行がある
+    blank: HashSet<usize>,
+    end: Vec<Trivia>,
+}
+
+fn newlines(chars: &[char]) -> usize {
+    chars.iter().filter(|&&c| c == '\n').count()
+}
+
+// コメントを元のソースのトークンに割り当ててから、出力するトークンへ移す
+fn attach(chars: &[char], tokens: &[Token], comments: &[Comment], emitted: &[Kind]) -> Attached {
+    let mut leading = vec![Vec::new(); tokens.len() + 1];
+    let mut trailing = vec![Vec::new(); tokens.len()];
+    let mut blank = vec![false; tokens.len()];
+
+    let mut ti = 0;
+    let mut prev_end = None;
+    // 直前のコメントが次のトークンに付いたか
+    let mut prev_leading = false;
+    for (ci, c) in comments.iter().enumerate() {
+        while ti < tokens.len() && tokens[ti].pos < c.pos {
+            let t = &tokens[ti];
+            blank[ti] = prev_end.is_some_and(|end| newlines(&chars[end..t.pos]) >= 2);
+            prev_end = Some(t.pos + t.len);
+            prev_leading = false;
+            ti += 1;
+        }
+        let end = c.pos + c.len;
+        let before = prev_end.map_or(1, |e| newlines(&chars[e..c.pos]));
+        let next_token = tokens.get(ti).map_or(chars.len(), |t| t.pos);
+        let next = comments
+            .get(ci + 1)
+            .map_or(next_token, |c| c.pos.min(next_token));
+        let trivia = Trivia {
+            text: chars[c.pos..end].iter().collect(),
+            line: !c.block || newlines(&chars[end..next]) > 0 || next == chars.len(),
+            own_line: before > 0,
+            blank: before >= 2 && prev_end.is_some(),
+        };
+        let inline = c.block && ti < tokens.len() && newlines(&chars[end..next_token]) == 0;
+        prev_end = Some(end);
+        if trivia.own_line || prev_leading || inline {
+            leading[ti].push(trivia);
+            prev_leading = true;
+        } else {
+            trailing[ti - 1].push(trivia);
+        }
+    }
+    while ti < tokens.len() {
+        let t = &tokens[ti];
+        blank[ti] = prev_end.is_some_and(|end| newlines(&chars[end..t.pos]) >= 2);
+        prev_end = Some(t.pos + t.len);
+        ti += 1;
+    }
+
+    let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>();
+    let mut to_emitted = vec![None; tokens.len()];
+    for (j, i) in diff::align(&kinds, emitted).into_iter().enumerate() {
+        if let Some(i) = i {
+            to_emitted[i] = Some(j);
+        }
+    }
+
+    let mut res = Attached::default();
+    // 出力されないトークンのコメントは、順序を保ったまま前後のトークンに移す
+    let mut pending = Vec::new();
+    let mut last = None;
+    let mut leading = leading.into_iter();
+    for (i, (ts, cs)) in trailing.into_iter().zip(leading.by_ref()).enumerate() {
+        match to_emitted[i] {
+            Some(j) => {
+                pending.extend(cs);
+                res.leading.insert(j, std::mem::take(&mut pending));
+                res.trailing.insert(j, ts);
+                last = Some(j);
+            }
+            None => {
+                pending.extend(cs);
+                match last {
+                    Some(j) if pending.is_empty() => res.trailing.entry(j).or_default().extend(ts),
+                    _ => pending.extend(ts),
+                }
+            }
+        }
+    }
+    pending.extend(leading.flatten());
+    res.end = pending;
+    for (i, b) in blank.into_iter().enumerate() {
+        if let (true, Some(j)) = (b, to_emitted[i]) {
+            res.blank.insert(j);
+        }
+    }
+    normalize(&mut res, emitted);
+    res
+}
+
+fn is_close(k: Option<&Kind>) -> bool {
+    matches!(
+        k,
+        Some(Kind::Symbol(Symbol::CloseParent)) | Some(Kind::Symbol(Symbol::CloseBracket))
+    )
+}
+
+fn is_open(k: Option<&Kind>) -> bool {
+    matches!(
+        k,
+        Some(Kind::Symbol(Symbol::OpenParent)) | Some(Kind::Symbol(Symbol::OpenBracket))
+    )
+}
+
+// 区切りの前後のコメントは区切りの後ろに置く。「a /* c */, b」は「a, /* c */ b」になる
+fn normalize(res: &mut Attached, emitted: &[Kind]) {
+    for (j, k) in emitted.iter().enumerate().skip(1) {
+        if let Kind::Symbol(Symbol::Comma) | Kind::Symbol(Symbol::Semicolon) = k {
+            let mut cs = res.trailing.remove(&(j - 1)).unwrap_or_default();
+            cs.extend(res.leading.remove(&j).unwrap_or_default());
+            cs.extend(res.trailing.remove(&j).unwrap_or_default());
+            res.trailing.insert(j, cs);
+        }
+    }
+}
+
+fn comment(c: &Trivia, ds: &mut Vec<Doc>) {
+    if c.line {
+        ds.push(Doc::Comment(c.text.clone()));
+        ds.push(Doc::BreakParent);
+    } else {
+        ds.push(text(c.text.clone()));
+    }
+}
+
+struct Builder<'a> {
+    attached: &'a Attached,
+    // これまでに出力したトークン
+    kinds: Vec<Kind>,
+    // 前置コメントを出力済みのトークン
+    hoisted: HashSet<usize>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(attached: &'a Attached) -> Builder<'a> {
+        Builder {
+            attached,
+            kinds: Vec::new(),
+            hoisted: HashSet::new(),
+        }
+    }
+
+    // 次のトークンの前に空行があるか
+    fn blank_before(&self) -> bool {
+        let i = self.kinds.len();
+        match self.attached.leading.get(&i).and_then(|cs| cs.first()) {
+            Some(c) => c.blank,
+            None => self.attached.blank.contains(&i),
+        }
+    }
+
+    fn push_kinds(&mut self, s: &str) -> std::ops::Range<usize> {
+        let start = self.kinds.len();
+        let chars = s.chars().collect::<Vec<_>>();
+        let tokens = lex(&chars).expect("printed token must be lexable");
+        self.kinds.extend(tokens.into_iter().map(|t| t.kind));
+        start..self.kinds.len()
+    }
+
+    fn leading(&self, i: usize, ds: &mut Vec<Doc>) {
+        let cs = match self.attached.leading.get(&i) {
+            Some(cs) if !cs.is_empty() && !self.hoisted.contains(&i) => cs,
+            _ => return,
+        };
+        for (k, c) in cs.iter().enumerate() {
+            if c.own_line {
+                ds.push(Doc::FreshLine);
+                if k != 0 && c.blank {
+                    ds.push(Doc::HardLine);
+                }
+            }
+            comment(c, ds);
+            // 閉じ括弧の直前のコメントは括弧に詰める
+            let close = k + 1 == cs.len() && is_close(self.kinds.get(i));
+            if !c.line && !close {
+                ds.push(text(" "));
+            }
+        }
+        if self.attached.blank.contains(&i) {
+            ds.push(Doc::FreshLine);
+            ds.push(Doc::HardLine);
+        }
+    }
+
+    // 次のトークンの前置コメントを先に出力し、式のグループの外に出す
+    fn hoist(&mut self) -> Doc {
+        let i = self.kinds.len();
+        let mut ds = Vec::new();
+        self.leading(i, &mut ds);
+        self.hoisted.insert(i);
+        concat(ds)
+    }
+
+    fn trailing(&self, i: usize, ds: &mut Vec<Doc>) {
+        for c in self.attached.trailing.get(&i).into_iter().flatten() {
+            ds.push(text(" "));
+            comment(c, ds);
+        }
+    }
+
+    // 閉じ括弧と同じ行に前置コメントがあり、その前に空白が要る
+    fn inline_close(&self, i: usize) -> bool {
+        let inline = match self.attached.leading.get(&i) {
+            Some(cs) if !self.hoisted.contains(&i) => cs.first().is_some_and(|c| !c.own_line),
+            _ => false,
+        };
+        inline
+            && is_close(self.kinds.get(i))
+            && !is_open(i.checked_sub(1).and_then(|j| self.kinds.get(j)))
+    }
+
+    fn token(&mut self, s: &str) -> Doc {
+        self.token_with(s, true)
+    }
+
+    // padなら閉じ括弧の前のコメントとの間に空白を入れる
+    fn token_with(&mut self, s: &str, pad: bool) -> Doc {
+        let range = self.push_kinds(s);
+        let mut ds = Vec::new();
+        if pad && self.inline_close(range.start) {
+            ds.push(text(" "));
+        }
+        for i in range.clone() {
+            self.leading(i, &mut ds);
+        }
+        ds.push(text(s));
+        for i in range {
+            self.trailing(i, &mut ds);
+        }
+        concat(ds)
+    }
+
+    // 閉じ括弧の前のコメントは括弧の内側に字下げして置く
+    fn close(&mut self, s: &str) -> (Vec<Doc>, Doc) {
+        let mut inner = Vec::new();
+        for (k, c) in self
+            .attached
+            .leading
+            .get(&self.kinds.len())
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            inner.push(Doc::FreshLine);
+            if k != 0 && c.blank {
+                inner.push(Doc::HardLine);
+            }
+            comment(c, &mut inner);
+        }
+        let range = self.push_kinds(s);
+        let mut ds = vec![text(s)];
+        for i in range {
+            self.trailing(i, &mut ds);
+        }
+        (inner, concat(ds))
+    }
+
+    fn ident(&mut self, x: Ident) -> Doc {
+        self.token(x.as_str())
+    }
+
+    fn string(&mut self, s: &str) -> Doc {
+        let s = literal(&Expr::StringLiteral(Sym::intern(s))).unwrap();
+        self.token(&s)
+    }
+
+    // 収まらなければ要素ごとに改行する
+    fn list<T>(
+        &mut self,
+        open: &str,
+        xs: &[T],
+        close: &str,
+        mut f: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Doc {
+        let open = self.token(open);
+        let mut inner = vec![Doc::SoftLine];
+        for (i, x) in xs.iter().enumerate() {
+            if i != 0 {
+                inner.push(self.token(","));
+                inner.push(Doc::Line);
+            }
+            inner.push(self.hoist());
+            inner.push(f(self, x));
+        }
+        // 閉じ括弧の前のコメントは、同じ行にあれば平坦なとき空白を空けて、行頭にあれば必ず改行する
+        let i = self.kinds.len();
+        let close = self.token_with(close, false);
+        if xs.is_empty() {
+            return concat(vec![open, close]);
+        }
+        let line = match self.attached.leading.get(&i).and_then(|cs| cs.first()) {
+            Some(c) if c.own_line => concat(vec![Doc::BreakParent, Doc::SoftLine]),
+            Some(_) => Doc::Line,
+            None => Doc::SoftLine,
+        };
+        group(concat(vec![open, nest(concat(inner)), line, close]))
+    }
+
+    // 要素ごとに改行し、末尾にもカンマを付ける
+    fn braces<T>(&mut self, xs: &[T], mut f: impl FnMut(&mut Self, &T) -> Doc) -> Doc {
+        let open = self.token("{");
+        let mut inner = Vec::new();
+        for (i, x) in xs.iter().enumerate() {
+            inner.push(Doc::HardLine);
+            if i != 0 && self.blank_before() {
+                inner.push(Doc::HardLine);
+            }
+            inner.push(self.hoist());
+            inner.push(f(self, x));
+            inner.push(self.token(","));
+        }
+        self.close_braces(open, inner)
+    }
+
+    fn close_braces(&mut self, open: Doc, mut inner: Vec<Doc>) -> Doc {
+        let (comments, close) = self.close("}");
+        if inner.is_empty() && comments.is_empty() {
+            return concat(vec![open, close]);
+        }
+        inner.extend(comments);
+        concat(vec![open, nest(concat(inner)), Doc::HardLine, close])
+    }
+
+    fn typ(&mut self, t: &Type) -> Doc {
+        use ast::ast::RefType;
+        match t {
+            Type::I32 => self.token("i32"),
+            Type::I64 => self.token("i64"),
+            Type::F32 => self.token("F32"),
+            Type::F64 => self.token("F64"),
+            Type::Bool => self.token("bool"),
+            Type::Char => self.token("char"),
+            Type::RefType(RefType::String) => self.token("string"),
+            Type::RefType(RefType::Struct(x)) => self.ident(*x),
+            Type::RefType(RefType::Array(t)) => {
+                concat(vec![self.token("["), self.typ(t), self.token("]")])
+            }
+            Type::RefType(RefType::Func(params, ret)) => concat(vec![
+                self.token("fun"),
+                self.list("(", params, ")", |b, t| b.typ(t)),
+                self.result_type(ret.as_ref().as_ref()),
+            ]),
+        }
+    }
+
+    fn result_type(&mut self, t: Option<&Type>) -> Doc {
+        match t {
+            Some(t) => concat(vec![self.token(":"), text(" "), self.typ(t)]),
+            None => Doc::Nil,
+        }
+    }
+
+    fn field(&mut self, (x, t): &(Ident, Type)) -> Doc {
+        concat(vec![
+            self.ident(*x),
+            self.token(":"),
+            text(" "),
+            self.typ(t),
+        ])
+    }
+
+    fn func_def(&mut self, FuncDef(name, params, ret): &FuncDef) -> Doc {
+        concat(vec![
+            self.token("fun"),
+            text(" "),
+            self.ident(*name),
+            self.list("(", params, ")", Builder::field),
+            self.result_type(ret.as_ref()),
+        ])
+    }
+
+    fn member(&mut self, m: &Member) -> Doc {
+        match m {
+            Member::Struct(name, fields) | Member::Union(name, fields) => concat(vec![
+                self.token(match m {
+                    Member::Struct(..) => "struct",
+                    _ => "union",
+                }),
+                text(" "),
+                self.ident(*name),
+                text(" "),
+                self.braces(fields, Builder::field),
+            ]),
+            Member::Enum(name, variants) => concat(vec![
+                self.token("enum"),
+                text(" "),
+                self.ident(*name),
+                text(" "),
+                self.braces(variants, |b, (x, ts)| {
+                    let x = b.ident(*x);
+                    if ts.is_empty() {
+                        x
+                    } else {
+                        concat(vec![x, b.list("(", ts, ")", |b, t| b.typ(t))])
+                    }
+                }),
+            ]),
+            Member::Func(def, body, export) => {
+                let mut ds = Vec::new();
+                if let Some(export) = export {
+                    ds.push(self.token("export"));
+                    ds.push(text(" "));
+                    if export != def.0.as_str() {
+                        ds.push(self.string(export));
+                        ds.push(text(" "));
+                    }
+                }
+                ds.push(self.func_def(def));
+                ds.push(text(" "));
+                ds.push(match body {
+                    Expr::Block(..) => self.expr(body, ATOM),
+                    _ => self.block(&[], Some(body)),
+                });
+                concat(ds)
+            }
+            Member::ExternFun(def, module, field) => concat(vec![
+                self.token("extern"),
+                text(" "),
+                self.string(module),
+                text(" "),
+                self.string(field),
+                text(" "),
+                self.func_def(def),
+                self.token(";"),
+            ]),
+            Member::Let(name, t, e) | Member::Const(name, t, e) => concat(vec![
+                self.token(match m {
+                    Member::Let(..) => "let",
+                    _ => "const",
+                }),
+                text(" "),
+                self.ident(*name),
+                self.result_type(t.as_ref()),
+                text(" "),
+                self.token("="),
+                text(" "),
+                self.expr(e, OPEN),
+                self.token(";"),
+            ]),
+        }
+    }
+
+    fn module(&mut self, m: &Module) -> Doc {
+        let mut ds = Vec::new();
+        for (i, member) in m.iter().enumerate() {
+            if i != 0 {
+                ds.push(Doc::HardLine);
+                ds.push(Doc::HardLine);
+            }
+            ds.push(self.hoist());
+            ds.push(self.member(member));
+        }
+        for c in &self.attached.end {
+            ds.push(Doc::FreshLine);
+            if c.blank {
+                ds.push(Doc::HardLine);
+            }
+            comment(c, &mut ds);
+        }
+        concat(ds)
+    }
+
+    fn block(&mut self, stmts: &[Expr], last: Option<&Expr>) -> Doc {
+        let open = self.token("{");
+        let mut inner = Vec::new();
+        for (i, e) in stmts.iter().chain(last).enumerate() {
+            inner.push(Doc::HardLine);
+            if i != 0 && self.blank_before() {
+                inner.push(Doc::HardLine);
+            }
+            inner.push(self.hoist());
+            inner.push(self.expr(e, OPEN));
+            if i < stmts.len() {
+                inner.push(self.token(";"));
+            }
+        }
+        self.close_braces(open, inner)
+    }
+
+    fn paren(&mut self, e: &Expr) -> Doc {
+        concat(vec![self.token("("), self.expr(e, OPEN), self.token(")")])
+    }
+
+    // 同じ優先順位の二項演算子の連鎖は1つのグループにする
+    fn binary(&mut self, e: &Expr, p: u8) -> Doc {
+        let mut rest = Vec::new();
+        let mut first = e;
+        while let Some((op, q, a, b)) = binary(first) {
+            if q != p {
+                break;
+            }
+            rest.push((op, b));
+            first = a;
+        }
+        let first = self.expr(first, p);
+        let mut tail = Vec::new();
+        for (op, b) in rest.into_iter().rev() {
+            tail.push(Doc::Line);
+            tail.push(self.token(op));
+            tail.push(text(" "));
+            tail.push(self.expr(b, p + 1));
+        }
+        group(concat(vec![first, nest(concat(tail))]))
+    }
+
+    // minより弱く結合する式は括弧で囲む
+    fn expr(&mut self, e: &Expr, min: u8) -> Doc {
+        if prec(e) < min {
+            return self.paren(e);
+        }
+        if let Some(s) = literal(e) {
+            return self.token(&s);
+        }
+        if let Some((_, p, _, _)) = binary(e) {
+            return self.binary(e, p);
+        }
+        match e {
+            Expr::Var(x) => self.ident(*x),
+            Expr::StructLiteral(name, fields) => {
+                let name = self.ident(*name);
+                let open = self.token("{");
+                let mut inner = vec![Doc::Line];
+                for (i, (x, e)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        inner.push(self.token(","));
+                        inner.push(Doc::Line);
+                    }
+                    inner.push(self.ident(*x));
+                    inner.push(self.token(":"));
+                    inner.push(text(" "));
+                    inner.push(self.expr(e, OPEN));
+                }
+                let close = self.token("}");
+                if fields.is_empty() {
+                    return concat(vec![name, text(" "), open, close]);
+                }
+                group(concat(vec![
+                    name,
+                    text(" "),
+                    open,
+                    nest(concat(inner)),
+                    Doc::Line,
+                    close,
+                ]))
+            }
+            Expr::EnumLiteral(name, variant, args) => {
+                let mut ds = vec![self.ident(*name), self.token("::"), self.ident(*variant)];
+                if !args.is_empty() {
+                    ds.push(self.list("(", args, ")", |b, e| b.expr(e, OPEN)));
+                }
+                concat(ds)
+            }
+            Expr::ArrayLiteral(t, len) => concat(vec![
+                self.token("["),
+                self.typ(t),
+                self.token(";"),
+                text(" "),
+                self.expr(len, OPEN),
+                self.token("]"),
+            ]),
+            Expr::Not(x) | Expr::Plus(x) | Expr::Minus(x) => concat(vec![
+                self.token(match e {
+                    Expr::Not(_) => "!",
+                    Expr::Plus(_) => "+",
+                    _ => "-",
+                }),
+                self.expr(x, UNARY),
+            ]),
+            Expr::Pow(a, b) => concat(vec![
+                self.expr(a, POSTFIX),
+                text(" "),
+                self.token("**"),
+                text(" "),
+                self.expr(b, UNARY),
+            ]),
+            Expr::Cast(x, t) | Expr::Bitcast(x, t) => concat(vec![
+                self.expr(x, CAST),
+                text(" "),
+                self.token(match e {
+                    Expr::Cast(..) => "as",
+                    _ => "bitcast",
+                }),
+                text(" "),
+                self.typ(t),
+            ]),
+            Expr::Call(f, args) => {
+                // `E::A()`は引数無しの列挙子リテラルとして読まれる
+                let f = match &**f {
+                    Expr::EnumLiteral(_, _, args) if args.is_empty() => self.paren(f),
+                    _ => self.expr(f, POSTFIX),
+                };
+                concat(vec![f, self.list("(", args, ")", |b, e| b.expr(e, OPEN))])
+            }
+            Expr::Index(x, i) => concat(vec![
+                self.expr(x, POSTFIX),
+                self.token("["),
+                self.expr(i, OPEN),
+                self.token("]"),
+            ]),
+            Expr::Member(x, field) => {
+                // `1.x`は浮動小数点数リテラルとして字句解析されてしまう
+                let x = match &**x {
+                    Expr::I32Literal(_)
+                    | Expr::I64Literal(_)
+                    | Expr::F32Literal(_)
+                    | Expr::F64Literal(_) => self.paren(x),
+                    _ => self.expr(x, POSTFIX),
+                };
+                concat(vec![x, self.token("."), self.ident(*field)])
+            }
+            Expr::Set(a, b) => concat(vec![
+                self.expr(a, OR),
+                text(" "),
+                self.token("="),
+                text(" "),
+                self.expr(b, ASSIGN),
+            ]),
+            Expr::CompoundSet(op, a, b) => concat(vec![
+                self.expr(a, OR),
+                text(" "),
+                self.token(assign_op(*op)),
+                text(" "),
+                self.expr(b, ASSIGN),
+            ]),
+            Expr::Block(stmts, last) => self.block(stmts, last.as_ref().as_ref()),
+            Expr::Let(x, e) => concat(vec![
+                self.token("let"),
+                text(" "),
+                self.ident(*x),
+                text(" "),
+                self.token("="),
+                text(" "),
+                self.expr(e, OPEN),
+            ]),
+            Expr::If(first, elifs, els) => {
+                let branches = std::iter::once(&**first).chain(elifs).collect::<Vec<_>>();
+                let mut ds = Vec::new();
+                for (i, (cond, then)) in branches.iter().enumerate() {
+                    if i != 0 {
+                        ds.push(text(" "));
+                    }
+                    ds.push(self.token(if i == 0 { "if" } else { "elif" }));
+                    ds.push(text(" "));
+                    ds.push(self.token("("));
+                    ds.push(self.expr(cond, OPEN));
+                    ds.push(self.token(")"));
+                    ds.push(text(" "));
+                    let followed = i + 1 < branches.len() || els.is_some();
+                    ds.push(if followed && dangling(then) {
+                        self.paren(then)
+                    } else {
+                        self.expr(then, OPEN)
+                    });
+                }
+                if let Some(els) = &**els {
+                    ds.push(text(" "));
+                    ds.push(self.token("else"));
+                    ds.push(text(" "));
+                    ds.push(self.expr(els, OPEN));
+                }
+                concat(ds)
+            }
+            Expr::While(cond, body) => concat(vec![
+                self.token("while"),
+                text(" "),
+                self.token("("),
+                self.expr(cond, OPEN),
+                self.token(")"),
+                text(" "),
+                self.expr(body, OPEN),
+            ]),
+            Expr::For(init, cond, step, body) => concat(vec![
+                self.token("for"),
+                text(" "),
+                self.token("("),
+                self.expr(init, OPEN),
+                self.token(";"),
+                text(" "),
+                self.expr(cond, OPEN),
+                self.token(";"),
+                text(" "),
+                self.expr(step, OPEN),
+                self.token(")"),
+                text(" "),
+                self.expr(body, OPEN),
+            ]),
+            Expr::Return(x) => {
+                let mut ds = vec![self.token("return")];
+                if let Some(x) = &**x {
+                    ds.push(text(" "));
+                    ds.push(self.expr(x, OPEN));
+                }
+                concat(ds)
+            }
+            Expr::Break(label) | Expr::Continue(label) => {
+                let mut ds = vec![self.token(match e {
+                    Expr::Break(_) => "break",
+                    _ => "continue",
+                })];
+                if let Some(label) = label {
+                    ds.push(text(" "));
+                    ds.push(self.ident(*label));
+                }
+                concat(ds)
+            }
+            Expr::Labeled(label, body) => concat(vec![
+                self.ident(*label),
+                self.token(":"),
+                text(" "),
+                self.expr(body, OPEN),
+            ]),
+            Expr::Match(x, arms, default) => {
+                let head = concat(vec![
+                    self.token("match"),
+                    text(" "),
+                    self.token("("),
+                    self.expr(x, OPEN),
+                    self.token(")"),
+                    text(" "),
+                ]);
+                let mut arms = arms
+                    .iter()
+                    .map(|(variant, binds, e)| (Some((variant, binds)), e))
+                    .collect::<Vec<_>>();
+                if let Some(default) = &**default {
+                    arms.push((None, default));
+                }
+                let body = self.braces(&arms, |b, (pat, e)| {
+                    let mut ds = Vec::new();
+                    match pat {
+                        Some((variant, binds)) => {
+                            ds.push(b.ident(**variant));
+                            if !binds.is_empty() {
+                                ds.push(b.list("(", binds, ")", |b, x| b.ident(*x)));
+                            }
+                        }
+                        None => ds.push(b.token("_")),
+                    }
+                    ds.push(text(" "));
+                    ds.push(b.token("=>"));
+                    ds.push(text(" "));
+                    ds.push(b.expr(e, OPEN));
+                    concat(ds)
+                });
+                concat(vec![head, body])
+            }
+            Expr::Lambda(caps, params, ret, body) => {
+                let mut ds = vec![self.token("\\")];
+                if !caps.is_empty() {
+                    ds.push(self.list("[", caps, "]", |b, x| b.ident(*x)));
+                }
+                ds.push(self.list("(", params, ")", Builder::field));
+                ds.push(self.result_type(Some(ret)));
+                ds.push(text(" "));
+                ds.push(self.token("->"));
+                ds.push(text(" "));
+                ds.push(self.expr(body, OPEN));
+                concat(ds)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// 構文木を変えずに整形する。コメントは近くのトークンに付けたまま残す
+pub fn format(src: &str, width: usize) -> Result<String, ParseError> {
+    let module = parse(src)?;
+    let chars = src.chars().collect::<Vec<_>>();
+    let (tokens, comments) = lex_with_comments(&chars).map_err(ParseError::Lex)?;
+
+    // 一度組み立てて出力するトークン列を求め、元のトークン列と対応付ける
+    let empty = Attached::default();
+    let mut b = Builder::new(&empty);
+    b.module(&module);
+    let attached = attach(&chars, &tokens, &comments, &b.kinds);
+
+    let mut b = Builder::new(&attached);
+    let doc = b.module(&module);
+    let mut out = doc::render(&doc, width);
+    while out.ends_with('\n') {
+        out.pop();
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(src: &str) -> Vec<String> {
+        let chars = src.chars().collect::<Vec<_>>();
+        let (_, comments) = lex_with_comments(&chars).unwrap();
+        comments
+            .iter()
+            .map(|c| chars[c.pos..c.pos + c.len].iter().collect())
+            .collect()
+    }
+
+    fn check(src: &str, width: usize) -> String {
+        let out = format(src, width).unwrap();
+        assert_eq!(parse(&out).unwrap(), parse(src).unwrap(), "{}", out);
+        assert_eq!(comments(&out), comments(src), "{}", out);
+        assert_eq!(format(&out, width).unwrap(), out);
+        out
+    }
+
+    const SRC: &str = r#"
+// 座標
+struct P { x: i32, /* 横 */ y: i32 }
+extern "env" "print" fun print(x: i32); // 出力
+fun dist(p: P, q: P): i32 {
+    let dx = p.x - q.x; // x方向
+
+
+    /* y方向 */ let dy = p.y - q.y;
+    // 二乗和
+    dx * dx + dy * dy
+    // 終わり
+}
+export "run" fun main() {
+    print(dist(P { x: 1, y: 2 }, P { x: 300000000, y: 400000000 }) + dist(P { x: 0, y: 0 }, P { x: 3, y: 4 }));
+    let f = \[a](x: i32): i32 -> a ? x : /* 0 */ 0;
+    match (E::A(1)) { A(x) => x, /* 他 */ _ => 0 }
+}
+/* 末尾 /* 入れ子 */ */
+"#;
+
+    #[test]
+    fn format_test() {
+        let expected = r#"// 座標
+struct P {
+    x: i32,
+    /* 横 */ y: i32,
+}
+
+extern "env" "print" fun print(x: i32); // 出力
+
+fun dist(p: P, q: P): i32 {
+    let dx = p.x - q.x; // x方向
+
+    /* y方向 */ let dy = p.y - q.y;
+    // 二乗和
+    dx * dx + dy * dy
+    // 終わり
+}
+
+export "run" fun main() {
+    print(
+        dist(P { x: 1, y: 2 }, P { x: 300000000, y: 400000000 })
+            + dist(P { x: 0, y: 0 }, P { x: 3, y: 4 })
+    );
+    let f = \[a](x: i32): i32 -> if (a) x else /* 0 */ 0;
+    match (E::A(1)) {
+        A(x) => x,
+        /* 他 */ _ => 0,
+    }
+}
+/* 末尾 /* 入れ子 */ */
+"#;
+        assert_eq!(check(SRC, 80), expected);
+        check(SRC, 20);
+        check(SRC, 200);
+        assert_eq!(format("", 80).unwrap(), "");
+        assert_eq!(
+            check("fun f() { // a\n}\nfun g(/* b */) {/* c */}", 80),
+            "fun f() { // a\n}\n\nfun g(/* b */) {\n    /* c */\n}\n"
+        );
+        assert_eq!(format("// a\n\n\n// b", 80).unwrap(), "// a\n\n// b\n");
+    }
+
+    // 区切りの前のコメントは区切りの後ろに移す
+    #[test]
+    fn separator_comment_test() {
+        assert_eq!(
+            check("struct P { x: i32, /*2*/ y: i32 //3\n}", 80),
+            "struct P {\n    x: i32,\n    /*2*/ y: i32, //3\n}\n"
+        );
+        assert_eq!(
+            check("fun f(a: i32 /*4*/, b: i32) { g(a /*5*/) }", 80),
+            "fun f(a: i32, /*4*/ b: i32) {\n    g(a /*5*/)\n}\n"
+        );
+        assert_eq!(
+            check("fun f() { let x = 1 /*6*/; }", 80),
+            "fun f() {\n    let x = 1; /*6*/\n}\n"
+        );
+    }
+
+    #[test]
+    fn width_test() {
+        let src = "fun f() { g(aaaa, bbbb, cccc + dddd * eeee) }";
+        assert_eq!(
+            check(src, 26),
+            "fun f() {\n    g(\n        aaaa,\n        bbbb,\n        cccc + dddd * eeee\n    )\n}\n"
+        );
+        assert_eq!(
+            check(src, 25),
+            "fun f() {\n    g(\n        aaaa,\n        bbbb,\n        cccc\n            + dddd * eeee\n    )\n}\n"
+        );
+    }
+
+    // トークンの間のあらゆる位置にコメントを入れても壊れない
+    #[test]
+    fn comments_everywhere_test() {
+        let src = SRC.chars().collect::<Vec<_>>();
+        let tokens = lex(&src).unwrap();
+        for width in [10, 40, 100].iter() {
+            for step in 1..4 {
+                let mut s = String::new();
+                let mut prev = 0;
+                for (i, t) in tokens.iter().enumerate() {
+                    s.extend(&src[prev..t.pos]);
+                    if i % step == 0 {
+                        match i % 3 {
+                            0 => s.push_str(&format!("/* c{} */", i)),
+                            1 => s.push_str(&format!("// c{}\n", i)),
+                            _ => s.push_str(&format!("\n/* c{} */ ", i)),
+                        }
+                    }
+                    s.extend(&src[t.pos..t.pos + t.len]);
+                    prev = t.pos + t.len;
+                }
+                check(&s, *width);
+            }
+        }
+    }
+}
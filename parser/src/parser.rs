@@ -37,6 +37,18 @@ impl<T> ParserError<T> {
             expecting,
         }
     }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn unexpected(&self) -> Option<&T> {
+        self.unexpected.as_ref()
+    }
+
+    pub fn expecting(&self) -> &ErrorExpect<T> {
+        &self.expecting
+    }
 }
 
 impl<T: Debug> fmt::Display for ParserError<T> {
@@ -229,6 +241,12 @@ impl<T: Clone> AnyOne<T> {
     }
 }
 
+impl<T: Clone> Default for AnyOne<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone> Parser for AnyOne<T> {
     type Input = T;
     type Output = T;
@@ -256,7 +274,7 @@ impl<T: Parser> Parser for Attempt<T> {
     fn parse(&self, st: &mut Stream<T::Input>) -> ParserResult<T::Output, T::Input> {
         let pos = st.pos();
         let res = self.0.parse(st);
-        if let Err(_) = res {
+        if res.is_err() {
             st.set_pos(pos);
         }
         res
@@ -456,6 +474,12 @@ impl<T: Clone> Eof<T> {
     }
 }
 
+impl<T: Clone> Default for Eof<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Clone> Parser for Eof<T> {
     type Input = T;
     type Output = ();
@@ -637,6 +661,12 @@ impl<A: Clone, B> Fail<A, B> {
     }
 }
 
+impl<A: Clone, B> Default for Fail<A, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<A: Clone, B> Parser for Fail<A, B> {
     type Input = A;
     type Output = B;
@@ -670,10 +700,15 @@ impl<A: Parser, B: Parser<Input = A::Input, Output = A::Output>> Parser for Eith
 mod tests {
     use super::*;
 
-    fn helper<A: Parser>(
-        analyzer: A,
-        cases: Vec<(Vec<A::Input>, ParserResult<A::Output, A::Input>, usize)>,
-    ) where
+    // 入力と期待する結果、解析後の位置
+    type Case<A> = (
+        Vec<<A as Parser>::Input>,
+        ParserResult<<A as Parser>::Output, <A as Parser>::Input>,
+        usize,
+    );
+
+    fn helper<A: Parser>(analyzer: A, cases: Vec<Case<A>>)
+    where
         A::Input: PartialEq + Debug,
         A::Output: PartialEq + Debug,
     {
@@ -33,6 +33,8 @@ impl<T> Stream<T> {
         self.set_pos(self.pos() + x)
     }
 
+    // 値は返さず位置だけ進めるのでIteratorにはしない
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<()> {
         self.add_pos(1)
     }
@@ -0,0 +1,68 @@
+use parser::parser::Parser;
+use parser::stream::Stream;
+use std::time::{Duration, Instant};
+use token::lexer::lex;
+use token::parser::lexer;
+
+const SOURCE: &str = r#"
+/* 構造体 /* ネスト */ */
+struct Point {
+    x: F64,
+    y: F64,
+}
+
+// 距離の二乗
+fun dist2(a: Point, b: Point): F64 {
+    let dx = a.x - b.x;
+    let dy = a.y - b.y;
+    dx * dx + dy * dy
+}
+
+fun fib(n: i32): i64 {
+    let a = 0i64;
+    let b = 1i64;
+    for (let i = 0; i < n; i = i + 1) {
+        let t = a + b;
+        a = b;
+        b = t;
+    }
+    if (a != 0i64 && n >= 1 || !true) { a } else { b }
+}
+
+fun 挨拶(名前: string): string {
+    "こんにちは\tあ\x41\n" + 名前 + '!' + 3.14f32 + 2 ** 10 % 7
+}
+"#;
+
+fn measure<F: FnMut()>(mut f: F) -> Duration {
+    let mut best = Duration::from_secs(u64::MAX);
+    for _ in 0..5 {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    best
+}
+
+fn main() {
+    let src = SOURCE.repeat(500).chars().collect::<Vec<_>>();
+    assert_eq!(
+        lexer().parse(&mut Stream::new(src.clone())),
+        lex(&src).map_err(|_| unreachable!())
+    );
+
+    let combinator = measure(|| {
+        lexer().parse(&mut Stream::new(src.clone())).unwrap();
+    });
+    let hand_written = measure(|| {
+        lex(&src).unwrap();
+    });
+
+    println!("input: {} chars", src.len());
+    println!("combinator:   {:?}", combinator);
+    println!("hand-written: {:?}", hand_written);
+    println!(
+        "speedup: {:.1}x",
+        combinator.as_secs_f64() / hand_written.as_secs_f64()
+    );
+}
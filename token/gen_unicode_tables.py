@@ -0,0 +1,137 @@
+#!/usr/bin/env python3
+# token/src/unicode/tables.rs を生成する
+# python3 gen_unicode_tables.py > src/unicode/tables.rs
+
+import sys
+import unicodedata
+
+MAX = 0x110000
+
+# UTS #39 の confusables.txt の一部。識別子で紛らわしい文字をプロトタイプに寄せる
+CONFUSABLES = {
+    # Cyrillic
+    "а": "a", "в": "b", "е": "e", "һ": "h", "і": "i", "ј": "j", "к": "k",
+    "м": "m", "н": "h", "о": "o", "р": "p", "с": "c", "т": "t", "у": "y",
+    "х": "x", "ѕ": "s", "ԁ": "d", "ԛ": "q", "ԝ": "w",
+    "А": "A", "В": "B", "Е": "E", "К": "K", "М": "M", "Н": "H", "О": "O",
+    "Р": "P", "С": "C", "Т": "T", "Х": "X", "Ѕ": "S", "І": "l", "Ј": "J",
+    # Greek
+    "α": "a", "ο": "o", "ρ": "p", "ν": "v", "ι": "i", "κ": "k", "τ": "t",
+    "Α": "A", "Β": "B", "Ε": "E", "Ζ": "Z", "Η": "H", "Ι": "l", "Κ": "K",
+    "Μ": "M", "Ν": "N", "Ο": "O", "Ρ": "P", "Τ": "T", "Υ": "Y", "Χ": "X",
+    # Latin
+    "ı": "i", "ſ": "f", "I": "l", "0": "O", "1": "l",
+    # 日本語
+    "ー": "一", "ｰ": "一", "－": "一", "カ": "力", "ロ": "口", "エ": "工",
+    "ニ": "二", "ハ": "八", "ト": "卜", "タ": "夕", "ヘ": "へ", "ベ": "べ",
+    "ペ": "ぺ", "〇": "O", "○": "O",
+}
+
+
+def ranges(pred):
+    res = []
+    start = None
+    for c in range(MAX + 1):
+        if c < MAX and pred(c):
+            if start is None:
+                start = c
+        elif start is not None:
+            res.append((start, c - 1))
+            start = None
+    return res
+
+
+def is_surrogate(c):
+    return 0xD800 <= c <= 0xDFFF
+
+
+def is_xid_start(c):
+    return not is_surrogate(c) and chr(c) != "_" and chr(c).isidentifier()
+
+
+def is_xid_continue(c):
+    return not is_surrogate(c) and ("a" + chr(c)).isidentifier()
+
+
+def canonical_decomposition(c):
+    d = unicodedata.decomposition(chr(c))
+    if not d or d.startswith("<"):
+        return None
+    return [int(x, 16) for x in d.split()]
+
+
+def full_decomposition(c):
+    d = canonical_decomposition(c)
+    if d is None:
+        return [c]
+    return [y for x in d for y in full_decomposition(x)]
+
+
+def is_hangul_syllable(c):
+    return 0xAC00 <= c <= 0xD7A3
+
+
+def ch(c):
+    return "'\\u{%X}'" % c
+
+
+def main():
+    out = sys.stdout
+    out.write("// このファイルは gen_unicode_tables.py で生成されている\n")
+    out.write("// Unicode %s\n\n" % unicodedata.unidata_version)
+
+    out.write("pub const UNICODE_VERSION: (u8, u8, u8) = (%s);\n\n" %
+              ", ".join(unicodedata.unidata_version.split(".")))
+
+    for name, pred in [("XID_START", is_xid_start), ("XID_CONTINUE", is_xid_continue)]:
+        out.write("pub const %s: &[(char, char)] = &[\n" % name)
+        for (a, b) in ranges(pred):
+            out.write("    (%s, %s),\n" % (ch(a), ch(b)))
+        out.write("];\n\n")
+
+    ccc = []
+    for c in range(MAX):
+        if is_surrogate(c):
+            continue
+        k = unicodedata.combining(chr(c))
+        if k == 0:
+            continue
+        if ccc and ccc[-1][1] == c - 1 and ccc[-1][2] == k:
+            ccc[-1] = (ccc[-1][0], c, k)
+        else:
+            ccc.append((c, c, k))
+    out.write("pub const COMBINING_CLASS: &[(char, char, u8)] = &[\n")
+    for (a, b, k) in ccc:
+        out.write("    (%s, %s, %d),\n" % (ch(a), ch(b), k))
+    out.write("];\n\n")
+
+    decomp = []
+    comp = []
+    for c in range(MAX):
+        if is_surrogate(c) or is_hangul_syllable(c):
+            continue
+        d = canonical_decomposition(c)
+        if d is None:
+            continue
+        decomp.append((c, full_decomposition(c)))
+        if len(d) == 2 and unicodedata.normalize("NFC", chr(d[0]) + chr(d[1])) == chr(c):
+            comp.append(((d[0], d[1]), c))
+    out.write("pub const DECOMPOSITION: &[(char, &[char])] = &[\n")
+    for (c, d) in decomp:
+        out.write("    (%s, &[%s]),\n" % (ch(c), ", ".join(ch(x) for x in d)))
+    out.write("];\n\n")
+
+    comp.sort()
+    out.write("pub const COMPOSITION: &[((char, char), char)] = &[\n")
+    for ((a, b), c) in comp:
+        out.write("    ((%s, %s), %s),\n" % (ch(a), ch(b), ch(c)))
+    out.write("];\n\n")
+
+    out.write("pub const CONFUSABLES: &[(char, char)] = &[\n")
+    for (a, b) in sorted(CONFUSABLES.items(), key=lambda x: ord(x[0])):
+        out.write("    (%s, %s),\n" % (ch(ord(a)), ch(ord(b))))
+    out.write("];\n")
+
+
+if __name__ == "__main__":
+    main()
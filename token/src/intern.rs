@@ -0,0 +1,130 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+// 識別子と文字列リテラルの番号
+// 文字列はプロセスが終わるまで解放しない。異なる文字列の数だけメモリを使い続ける
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sym(u32);
+
+// 最初の塊の大きさ。塊は倍々に大きくなる
+const FIRST_CHUNK: usize = 64;
+const CHUNKS: usize = 27;
+
+type Chunk = Box<[OnceLock<&'static str>]>;
+
+// 番号から文字列を引く表
+// 書き込みは追加だけで済むので、読むときはロックを取らない
+static STRINGS: [OnceLock<Chunk>; CHUNKS] = [const { OnceLock::new() }; CHUNKS];
+
+// 番号を塊の番号と塊の中の位置に分ける
+fn slot(id: u32) -> (usize, usize) {
+    let m = id as usize / FIRST_CHUNK + 1;
+    let k = (usize::BITS - 1 - m.leading_zeros()) as usize;
+    (k, id as usize - FIRST_CHUNK * ((1 << k) - 1))
+}
+
+#[derive(Default)]
+struct Interner {
+    map: HashMap<&'static str, Sym>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| Mutex::new(Interner::default()))
+}
+
+impl Sym {
+    pub fn intern(s: &str) -> Sym {
+        let mut interner = interner().lock().unwrap();
+        if let Some(&sym) = interner.map.get(s) {
+            return sym;
+        }
+        let sym = Sym(interner.map.len() as u32);
+        let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+        let (k, i) = slot(sym.0);
+        let chunk =
+            STRINGS[k].get_or_init(|| (0..FIRST_CHUNK << k).map(|_| OnceLock::new()).collect());
+        chunk[i].set(s).unwrap();
+        interner.map.insert(s, sym);
+        sym
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let (k, i) = slot(self.0);
+        STRINGS[k].get().and_then(|c| c[i].get()).unwrap()
+    }
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<&str> for Sym {
+    fn from(s: &str) -> Sym {
+        Sym::intern(s)
+    }
+}
+
+impl PartialOrd for Sym {
+    fn partial_cmp(&self, other: &Sym) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Sym {
+    fn cmp(&self, other: &Sym) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.as_str().cmp(other.as_str())
+        }
+    }
+}
+
+impl fmt::Debug for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern_test() {
+        let a = Sym::intern("変数");
+        let b = Sym::intern(&String::from("変数"));
+        let c = Sym::intern("x");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.as_str(), "変数");
+        assert_eq!(format!("{}", c), "x");
+        assert!(Sym::intern("a") < Sym::intern("b"));
+    }
+
+    #[test]
+    fn slot_test() {
+        assert_eq!(slot(0), (0, 0));
+        assert_eq!(slot(63), (0, 63));
+        assert_eq!(slot(64), (1, 0));
+        assert_eq!(slot(191), (1, 127));
+        assert_eq!(slot(192), (2, 0));
+        assert_eq!(slot(u32::MAX).0, CHUNKS - 1);
+        // 塊の境界をまたいでも引ける
+        let syms = (0..300)
+            .map(|i| Sym::intern(&format!("s{}", i)))
+            .collect::<Vec<_>>();
+        for (i, sym) in syms.iter().enumerate() {
+            assert_eq!(sym.as_str(), format!("s{}", i));
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::lexer::{Cursor, LexError, Lexer};
+use crate::token::Token;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+pub struct CharsCursor<I: Iterator<Item = char>> {
+    iter: I,
+    buf: VecDeque<char>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = char>> CharsCursor<I> {
+    pub fn new(iter: I) -> CharsCursor<I> {
+        CharsCursor {
+            iter,
+            buf: VecDeque::with_capacity(2),
+            pos: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Cursor for CharsCursor<I> {
+    fn peek_at(&mut self, i: usize) -> Option<char> {
+        while self.buf.len() <= i {
+            self.buf.push_back(self.iter.next()?);
+        }
+        Some(self.buf[i])
+    }
+
+    fn bump(&mut self) {
+        if self.buf.pop_front().is_some() || self.iter.next().is_some() {
+            self.pos += 1;
+        }
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+}
+
+const READ_BUF_SIZE: usize = 4096;
+
+pub struct ReaderCursor<R: Read> {
+    reader: R,
+    bytes: Box<[u8; READ_BUF_SIZE]>,
+    start: usize,
+    end: usize,
+    buf: VecDeque<char>,
+    pos: usize,
+    error: Option<LexError>,
+}
+
+impl<R: Read> ReaderCursor<R> {
+    pub fn new(reader: R) -> ReaderCursor<R> {
+        ReaderCursor {
+            reader,
+            bytes: Box::new([0; READ_BUF_SIZE]),
+            start: 0,
+            end: 0,
+            buf: VecDeque::with_capacity(2),
+            pos: 0,
+            error: None,
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.start == self.end {
+            loop {
+                match self.reader.read(&mut self.bytes[..]) {
+                    Ok(0) => return None,
+                    Ok(n) => {
+                        self.start = 0;
+                        self.end = n;
+                        break;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.error = Some(LexError::Io {
+                            pos: self.pos + self.buf.len(),
+                            kind: e.kind(),
+                        });
+                        return None;
+                    }
+                }
+            }
+        }
+        let b = self.bytes[self.start];
+        self.start += 1;
+        Some(b)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        if self.error.is_some() {
+            return None;
+        }
+        let b = self.next_byte()?;
+        let len = match b {
+            0x00..=0x7F => return Some(b as char),
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 0,
+        };
+        let mut seq = [b, 0, 0, 0];
+        let mut ok = len != 0;
+        for x in seq.iter_mut().take(len).skip(1) {
+            match self.next_byte() {
+                Some(c) => *x = c,
+                None => {
+                    ok = false;
+                    break;
+                }
+            }
+        }
+        let c = if ok {
+            std::str::from_utf8(&seq[..len])
+                .ok()
+                .and_then(|s| s.chars().next())
+        } else {
+            None
+        };
+        if c.is_none() && self.error.is_none() {
+            self.error = Some(LexError::InvalidUtf8 {
+                pos: self.pos + self.buf.len(),
+            });
+        }
+        c
+    }
+}
+
+impl<R: Read> Cursor for ReaderCursor<R> {
+    fn peek_at(&mut self, i: usize) -> Option<char> {
+        while self.buf.len() <= i {
+            let c = self.next_char()?;
+            self.buf.push_back(c);
+        }
+        Some(self.buf[i])
+    }
+
+    fn bump(&mut self) {
+        if self.buf.pop_front().is_some() || self.next_char().is_some() {
+            self.pos += 1;
+        }
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn take_error(&mut self) -> Option<LexError> {
+        self.error.take()
+    }
+}
+
+pub struct TokenIter<C: Cursor> {
+    lexer: Lexer<C>,
+    done: bool,
+}
+
+impl<I: Iterator<Item = char>> TokenIter<CharsCursor<I>> {
+    pub fn from_chars<T: IntoIterator<Item = char, IntoIter = I>>(
+        chars: T,
+    ) -> TokenIter<CharsCursor<I>> {
+        TokenIter::with_cursor(CharsCursor::new(chars.into_iter()))
+    }
+}
+
+impl<R: Read> TokenIter<ReaderCursor<R>> {
+    pub fn from_reader(reader: R) -> TokenIter<ReaderCursor<R>> {
+        TokenIter::with_cursor(ReaderCursor::new(reader))
+    }
+}
+
+impl<C: Cursor> TokenIter<C> {
+    pub fn with_cursor(cursor: C) -> TokenIter<C> {
+        TokenIter {
+            lexer: Lexer::with_cursor(cursor),
+            done: false,
+        }
+    }
+}
+
+impl<C: Cursor> Iterator for TokenIter<C> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.lexer.next_token();
+        if let Some(e) = self.lexer.cursor_mut().take_error() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        match res {
+            Ok(Some(token)) => Some(Ok(token)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<C: Cursor> std::iter::FusedIterator for TokenIter<C> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    const SRC: &str = "fun 挨拶(名前: string): string { \"こんにちは\" /* a /* b */ */ }\n// end";
+
+    #[test]
+    fn same_as_lex_test() {
+        let expected = lex(&SRC.chars().collect::<Vec<_>>()).unwrap();
+        let from_chars = TokenIter::from_chars(SRC.chars()).collect::<Result<Vec<_>, _>>();
+        assert_eq!(from_chars, Ok(expected.clone()));
+        let from_reader = TokenIter::from_reader(SRC.as_bytes()).collect::<Result<Vec<_>, _>>();
+        assert_eq!(from_reader, Ok(expected));
+    }
+
+    #[test]
+    fn stop_on_error_test() {
+        let mut iter = TokenIter::from_chars("a b # c".chars());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(iter.next(), Some(Err(LexError::new(4, Some('#')))));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn invalid_utf8_test() {
+        let res = TokenIter::from_reader(&b"ab \xE3\x81 c"[..]).collect::<Vec<_>>();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1], Err(LexError::InvalidUtf8 { pos: 3 }));
+    }
+
+    #[test]
+    fn large_input_test() {
+        let line = "let x = x + 1i64; /* 変数 */\n";
+        let n = 100_000;
+        let chars = std::iter::repeat_n(line, n).flat_map(|s| s.chars());
+        let mut count = 0;
+        for token in TokenIter::from_chars(chars) {
+            token.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 7 * n);
+    }
+}
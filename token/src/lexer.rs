@@ -0,0 +1,690 @@
+use crate::intern::Sym;
+use crate::token::{Comment, Keyword, Kind, Literal, NumLiteral, Symbol, Token};
+use crate::unicode;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexError {
+    Unexpected { pos: usize, found: Option<char> },
+    InvalidUtf8 { pos: usize },
+    Io { pos: usize, kind: io::ErrorKind },
+}
+
+impl LexError {
+    pub fn new(pos: usize, found: Option<char>) -> LexError {
+        LexError::Unexpected { pos, found }
+    }
+
+    pub fn pos(&self) -> usize {
+        match *self {
+            LexError::Unexpected { pos, .. } => pos,
+            LexError::InvalidUtf8 { pos } => pos,
+            LexError::Io { pos, .. } => pos,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::Unexpected {
+                pos,
+                found: Some(c),
+            } => write!(f, "unexpected {:?} at {}", c, pos),
+            LexError::Unexpected { pos, found: None } => {
+                write!(f, "unexpected end of input at {}", pos)
+            }
+            LexError::InvalidUtf8 { pos } => write!(f, "invalid UTF-8 at {}", pos),
+            LexError::Io { pos, kind } => write!(f, "I/O error {:?} at {}", kind, pos),
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Class {
+    Invalid,
+    Space,
+    Ident,
+    Digit,
+    Quote,
+    DoubleQuote,
+    Slash,
+    Symbol,
+}
+
+const CLASS: [Class; 128] = {
+    let mut table = [Class::Invalid; 128];
+    let mut i = 0;
+    while i < 128 {
+        let c = i as u8;
+        table[i] = match c {
+            b' ' | b'\n' | b'\t' => Class::Space,
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => Class::Ident,
+            b'0'..=b'9' => Class::Digit,
+            b'\'' => Class::Quote,
+            b'"' => Class::DoubleQuote,
+            b'/' => Class::Slash,
+            b'.' | b',' | b':' | b';' | b'(' | b')' | b'[' | b']' | b'{' | b'}' | b'!' | b'+'
+            | b'-' | b'*' | b'%' | b'&' | b'|' | b'^' | b'<' | b'>' | b'=' | b'?' | b'\\' => {
+                Class::Symbol
+            }
+            _ => Class::Invalid,
+        };
+        i += 1;
+    }
+    table
+};
+
+fn class(c: char) -> Class {
+    if c.is_ascii() {
+        CLASS[c as usize]
+    } else if unicode::is_ident_start(c) {
+        Class::Ident
+    } else {
+        Class::Invalid
+    }
+}
+
+pub fn keyword(s: &str) -> Option<Keyword> {
+    Some(match s {
+        "i32" => Keyword::I32,
+        "i64" => Keyword::I64,
+        "F32" => Keyword::F32,
+        "F64" => Keyword::F64,
+        "string" => Keyword::String,
+        "bool" => Keyword::Bool,
+        "char" => Keyword::Char,
+        "true" => Keyword::True,
+        "false" => Keyword::False,
+        "let" => Keyword::Let,
+        "if" => Keyword::If,
+        "while" => Keyword::While,
+        "return" => Keyword::Return,
+        "struct" => Keyword::Struct,
+        "fun" => Keyword::Fun,
+        "extern" => Keyword::Extern,
+        "for" => Keyword::For,
+        "else" => Keyword::Else,
+        "elif" => Keyword::Elif,
+        "break" => Keyword::Break,
+        "continue" => Keyword::Continue,
+        "as" => Keyword::As,
+        "bitcast" => Keyword::Bitcast,
+        "enum" => Keyword::Enum,
+        "union" => Keyword::Union,
+        "match" => Keyword::Match,
+        "const" => Keyword::Const,
+        "export" => Keyword::Export,
+        _ => return None,
+    })
+}
+
+// 先読みは2文字まで
+pub trait Cursor {
+    fn peek_at(&mut self, i: usize) -> Option<char>;
+    fn bump(&mut self);
+    fn pos(&self) -> usize;
+
+    fn take_error(&mut self) -> Option<LexError> {
+        None
+    }
+}
+
+pub struct SliceCursor<'a> {
+    src: &'a [char],
+    pos: usize,
+}
+
+impl<'a> SliceCursor<'a> {
+    pub fn new(src: &'a [char]) -> SliceCursor<'a> {
+        SliceCursor { src, pos: 0 }
+    }
+}
+
+impl<'a> Cursor for SliceCursor<'a> {
+    fn peek_at(&mut self, i: usize) -> Option<char> {
+        self.src.get(self.pos + i).cloned()
+    }
+
+    fn bump(&mut self) {
+        self.pos += 1;
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+}
+
+pub struct Lexer<C: Cursor> {
+    cursor: C,
+    comments: Option<Vec<Comment>>,
+}
+
+impl<'a> Lexer<SliceCursor<'a>> {
+    pub fn new(src: &'a [char]) -> Lexer<SliceCursor<'a>> {
+        Lexer::with_cursor(SliceCursor::new(src))
+    }
+}
+
+impl<C: Cursor> Lexer<C> {
+    pub fn with_cursor(cursor: C) -> Lexer<C> {
+        Lexer {
+            cursor,
+            comments: None,
+        }
+    }
+
+    // コメントの位置も記録する
+    pub fn keep_comments(mut self) -> Lexer<C> {
+        self.comments = Some(Vec::new());
+        self
+    }
+
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        self.comments.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn cursor(&self) -> &C {
+        &self.cursor
+    }
+
+    pub fn cursor_mut(&mut self) -> &mut C {
+        &mut self.cursor
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.cursor.peek_at(0)
+    }
+
+    fn peek_at(&mut self, i: usize) -> Option<char> {
+        self.cursor.peek_at(i)
+    }
+
+    fn bump(&mut self) {
+        self.cursor.bump();
+    }
+
+    fn pos(&self) -> usize {
+        self.cursor.pos()
+    }
+
+    fn error(&mut self) -> LexError {
+        LexError::new(self.pos(), self.peek())
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), LexError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    pub fn lex(mut self) -> Result<Vec<Token>, LexError> {
+        let mut tokens = Vec::new();
+        while let Some(token) = self.next_token()? {
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
+
+    pub fn next_token(&mut self) -> Result<Option<Token>, LexError> {
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+            let pos = self.pos();
+            let kind = match class(c) {
+                Class::Space => {
+                    self.bump();
+                    continue;
+                }
+                Class::Slash => match self.peek_at(1) {
+                    Some('/') => {
+                        self.line_comment();
+                        self.push_comment(pos, false);
+                        continue;
+                    }
+                    Some('*') => {
+                        self.block_comment()?;
+                        self.push_comment(pos, true);
+                        continue;
+                    }
+                    _ => Kind::Symbol(self.symbol(c)),
+                },
+                Class::Ident => {
+                    let s = self.ident_str();
+                    match keyword(&s) {
+                        Some(k) => Kind::Keyword(k),
+                        None => Kind::Ident(Sym::intern(&s)),
+                    }
+                }
+                Class::Symbol => Kind::Symbol(self.symbol(c)),
+                Class::Digit => Kind::Literal(Literal::Num(self.num_literal()?)),
+                Class::Quote => {
+                    self.bump();
+                    let c = self.literal_char('\'')?;
+                    self.expect('\'')?;
+                    Kind::Literal(Literal::Char(c))
+                }
+                Class::DoubleQuote => {
+                    self.bump();
+                    let mut s = String::new();
+                    while let Some(c) = self.peek() {
+                        if c == '"' {
+                            break;
+                        }
+                        s.push(self.literal_char('"')?);
+                    }
+                    self.expect('"')?;
+                    Kind::Literal(Literal::String(Sym::intern(&s)))
+                }
+                Class::Invalid => return Err(self.error()),
+            };
+            return Ok(Some(Token {
+                kind,
+                pos,
+                len: self.pos() - pos,
+            }));
+        }
+    }
+
+    fn push_comment(&mut self, pos: usize, block: bool) {
+        let len = self.pos() - pos;
+        if let Some(comments) = &mut self.comments {
+            comments.push(Comment { pos, len, block });
+        }
+    }
+
+    // 末尾の改行は空白として読む
+    fn line_comment(&mut self) {
+        self.bump();
+        self.bump();
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.bump();
+        }
+    }
+
+    fn block_comment(&mut self) -> Result<(), LexError> {
+        self.bump();
+        self.bump();
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some('/'), Some('*')) => self.block_comment()?,
+                (Some('*'), Some('/')) => {
+                    self.bump();
+                    self.bump();
+                    return Ok(());
+                }
+                (Some(_), _) => self.bump(),
+                (None, _) => return Err(self.error()),
+            }
+        }
+    }
+
+    fn ident_str(&mut self) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if !unicode::is_ident_continue(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        unicode::nfc(&s)
+    }
+
+    fn symbol(&mut self, c: char) -> Symbol {
+        self.bump();
+        match c {
+            '.' => Symbol::Dot,
+            ',' => Symbol::Comma,
+            ';' => Symbol::Semicolon,
+            '(' => Symbol::OpenParent,
+            ')' => Symbol::CloseParent,
+            '[' => Symbol::OpenBracket,
+            ']' => Symbol::CloseBracket,
+            '{' => Symbol::OpenBrace,
+            '}' => Symbol::CloseBrace,
+            '?' => Symbol::Question,
+            '\\' => Symbol::Backslash,
+            ':' => self.symbol_tail(&[(':', Symbol::DoubleColon)], Symbol::Colon),
+            '!' => self.symbol_tail(&[('=', Symbol::Ne)], Symbol::Not),
+            '+' => self.symbol_tail(&[('=', Symbol::AddAssign)], Symbol::Add),
+            '-' => self.symbol_tail(
+                &[('=', Symbol::SubAssign), ('>', Symbol::Arrow)],
+                Symbol::Sub,
+            ),
+            '*' => {
+                if self.eat('*') {
+                    self.symbol_tail(&[('=', Symbol::PowAssign)], Symbol::Pow)
+                } else {
+                    self.symbol_tail(&[('=', Symbol::MulAssign)], Symbol::Mul)
+                }
+            }
+            '/' => self.symbol_tail(&[('=', Symbol::DivAssign)], Symbol::Div),
+            '%' => self.symbol_tail(&[('=', Symbol::ModAssign)], Symbol::Mod),
+            '&' => self.symbol_tail(
+                &[('&', Symbol::And), ('=', Symbol::BitAndAssign)],
+                Symbol::BitAnd,
+            ),
+            '|' => self.symbol_tail(
+                &[('|', Symbol::Or), ('=', Symbol::BitOrAssign)],
+                Symbol::BitOr,
+            ),
+            '^' => self.symbol_tail(&[('=', Symbol::BitXorAssign)], Symbol::BitXor),
+            '<' => {
+                if self.eat('<') {
+                    self.symbol_tail(&[('=', Symbol::ShlAssign)], Symbol::Shl)
+                } else {
+                    self.symbol_tail(&[('=', Symbol::Lte)], Symbol::Lt)
+                }
+            }
+            '>' => {
+                if self.eat('>') {
+                    if self.eat('>') {
+                        self.symbol_tail(&[('=', Symbol::UShrAssign)], Symbol::UShr)
+                    } else {
+                        self.symbol_tail(&[('=', Symbol::ShrAssign)], Symbol::Shr)
+                    }
+                } else {
+                    self.symbol_tail(&[('=', Symbol::Gte)], Symbol::Gt)
+                }
+            }
+            '=' => self.symbol_tail(
+                &[('=', Symbol::Eq), ('>', Symbol::FatArrow)],
+                Symbol::Assign,
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    fn symbol_tail(&mut self, tails: &[(char, Symbol)], short: Symbol) -> Symbol {
+        for (c, sym) in tails {
+            if self.eat(*c) {
+                return sym.clone();
+            }
+        }
+        short
+    }
+
+    fn digits(&mut self) -> Result<String, LexError> {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        if s.is_empty() {
+            return Err(self.error());
+        }
+        Ok(s)
+    }
+
+    fn num_literal(&mut self) -> Result<NumLiteral, LexError> {
+        let s1 = self.digits()?;
+        let s2 = if self.eat('.') {
+            Some(self.digits()?)
+        } else {
+            None
+        };
+        let suffix = match self.peek() {
+            Some(c) if unicode::is_ident_start(c) => Some(self.ident_str()),
+            _ => None,
+        };
+
+        fn parse<T: std::str::FromStr>(s: &str, f: fn(T) -> NumLiteral) -> Result<NumLiteral, ()> {
+            s.parse::<T>().map(f).map_err(|_| ())
+        }
+
+        let res = match (s2, suffix.as_deref()) {
+            (Some(s2), None) | (Some(s2), Some("f64")) => {
+                parse(&format!("{}.{}", s1, s2), NumLiteral::F64)
+            }
+            (Some(s2), Some("f32")) => parse(&format!("{}.{}", s1, s2), NumLiteral::F32),
+            (None, None) | (None, Some("i32")) => parse(&s1, NumLiteral::I32),
+            (None, Some("i64")) => parse(&s1, NumLiteral::I64),
+            (None, Some("f32")) => parse(&s1, NumLiteral::F32),
+            (None, Some("f64")) => parse(&s1, NumLiteral::F64),
+            _ => Err(()),
+        };
+        res.map_err(|_| self.error())
+    }
+
+    fn hex_char(&mut self, len: usize) -> Result<char, LexError> {
+        let mut x = 0u32;
+        for _ in 0..len {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => {
+                    x = x * 16 + d;
+                    self.bump();
+                }
+                None => return Err(self.error()),
+            }
+        }
+        std::char::from_u32(x).ok_or_else(|| self.error())
+    }
+
+    fn literal_char(&mut self, lit: char) -> Result<char, LexError> {
+        match self.peek() {
+            Some('\\') => {
+                self.bump();
+                let c = self.peek().ok_or_else(|| self.error())?;
+                let res = match c {
+                    't' => '\t',
+                    'n' => '\n',
+                    'r' => '\r',
+                    '\\' => '\\',
+                    'x' => {
+                        self.bump();
+                        return self.hex_char(2);
+                    }
+                    'u' => {
+                        self.bump();
+                        return self.hex_char(4);
+                    }
+                    'U' => {
+                        self.bump();
+                        return self.hex_char(8);
+                    }
+                    c if c == lit => lit,
+                    _ => return Err(self.error()),
+                };
+                self.bump();
+                Ok(res)
+            }
+            Some(c) if c != lit => {
+                self.bump();
+                Ok(c)
+            }
+            _ => Err(self.error()),
+        }
+    }
+}
+
+pub fn lex(src: &[char]) -> Result<Vec<Token>, LexError> {
+    Lexer::new(src).lex()
+}
+
+pub fn lex_with_comments(src: &[char]) -> Result<(Vec<Token>, Vec<Comment>), LexError> {
+    let mut lexer = Lexer::new(src).keep_comments();
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok((tokens, lexer.take_comments()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer;
+    use parser::parser::Parser;
+    use parser::stream::Stream;
+
+    fn check(src: &str) {
+        let chars = src.chars().collect::<Vec<_>>();
+        let expected = lexer().parse(&mut Stream::new(chars.clone()));
+        let actual = lex(&chars);
+        match (expected, actual) {
+            (Ok(expected), Ok(actual)) => assert_eq!(expected, actual, "{:?}", src),
+            (Err(expected), Err(actual)) => assert_eq!(expected.pos(), actual.pos(), "{:?}", src),
+            (expected, actual) => panic!("{:?}: {:?} != {:?}", src, expected, actual),
+        }
+    }
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn choose<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+            &xs[(self.next() % xs.len() as u64) as usize]
+        }
+    }
+
+    const FRAGMENTS: &[&str] = &[
+        " ",
+        "\n",
+        "\t",
+        "\r",
+        "x",
+        "abc",
+        "_a1",
+        "変数",
+        "ｶ",
+        "e\u{301}",
+        "i32",
+        "F32",
+        "f32",
+        "while",
+        "fun",
+        "0",
+        "12",
+        "3.5",
+        "1.",
+        "7i64",
+        "2f32",
+        "1.5f32",
+        "9x",
+        "99999999999",
+        "'a'",
+        "'\\n'",
+        "'\\''",
+        "'\\\"'",
+        "''",
+        "'ab'",
+        "\"\"",
+        "\"a\\tb\"",
+        "\"\\x41\\u3042\"",
+        "\"\\U0001F600\"",
+        "\"\\uD800\"",
+        "\"\\q\"",
+        "\"open",
+        "//c\n",
+        "//",
+        "/**/",
+        "/*/**/*/",
+        "/* ",
+        "*/",
+        "/",
+        "*",
+        "**",
+        "!",
+        "!=",
+        "=",
+        "==",
+        "<",
+        "<=",
+        ">",
+        ">=",
+        "&",
+        "&&",
+        "|",
+        "||",
+        "^",
+        "+",
+        "-",
+        "%",
+        ".",
+        ",",
+        ":",
+        ";",
+        "(",
+        ")",
+        "[",
+        "]",
+        "{",
+        "}",
+        "#",
+        "・",
+        "\\",
+    ];
+
+    #[test]
+    fn corpus_test() {
+        check(include_str!("../../test"));
+        check("fun main(): i32 { let x = a / b; x }");
+        check("a/b");
+        check("a //x\n/ b");
+    }
+
+    // 両方とも同じ位置で失敗する
+    #[test]
+    fn error_test() {
+        for src in &["a # b", "x = \"abc", "1 /* 2", "'a", "f(・)"] {
+            assert!(lex(&src.chars().collect::<Vec<_>>()).is_err(), "{:?}", src);
+            check(src);
+        }
+    }
+
+    #[test]
+    fn comments_test() {
+        let src = "a // x\n/* /* y */ */ b /**/".chars().collect::<Vec<_>>();
+        let (tokens, comments) = lex_with_comments(&src).unwrap();
+        assert_eq!(tokens, lex(&src).unwrap());
+        let text = |c: &Comment| src[c.pos..c.pos + c.len].iter().collect::<String>();
+        assert_eq!(
+            comments.iter().map(text).collect::<Vec<_>>(),
+            vec!["// x", "/* /* y */ */", "/**/"]
+        );
+        assert_eq!(
+            comments.iter().map(|c| c.block).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+    }
+
+    #[test]
+    fn generated_test() {
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+        for _ in 0..5000 {
+            let n = rng.next() % 12;
+            let src = (0..n).map(|_| *rng.choose(FRAGMENTS)).collect::<String>();
+            check(&src);
+        }
+    }
+}
@@ -1,2 +1,7 @@
-pub mod token;
+pub mod intern;
+pub mod iter;
+pub mod lexer;
+pub mod lint;
 pub mod parser;
+pub mod token;
+pub mod unicode;
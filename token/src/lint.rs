@@ -0,0 +1,63 @@
+use crate::intern::Sym;
+use crate::token::{Kind, Token};
+use crate::unicode;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Confusable {
+    pub first: Token,
+    pub second: Token,
+}
+
+// 見た目が紛らわしい別々の名前の組を、後の名前が初めて現れた順に返す
+pub fn confusables<T: Copy>(names: impl IntoIterator<Item = (Sym, T)>) -> Vec<(T, T)> {
+    let mut seen = HashSet::new();
+    let mut by_skeleton: HashMap<String, Vec<T>> = HashMap::new();
+    let mut res = Vec::new();
+    for (name, x) in names {
+        if !seen.insert(name) {
+            continue;
+        }
+        let others = by_skeleton
+            .entry(unicode::skeleton(name.as_str()))
+            .or_default();
+        res.extend(others.iter().map(|&other| (other, x)));
+        others.push(x);
+    }
+    res
+}
+
+pub fn confusable_idents(tokens: &[Token]) -> Vec<Confusable> {
+    let idents = tokens.iter().filter_map(|tok| match &tok.kind {
+        Kind::Ident(name) => Some((*name, tok)),
+        _ => None,
+    });
+    confusables(idents)
+        .into_iter()
+        .map(|(first, second)| Confusable {
+            first: first.clone(),
+            second: second.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer;
+    use parser::parser::Parser;
+    use parser::stream::Stream;
+
+    #[test]
+    fn confusable_idents_test() {
+        let tokens = lexer()
+            .parse(&mut Stream::new(
+                "カウント 力ウント paypal pаypal paypal x".chars().collect(),
+            ))
+            .unwrap();
+        let res = confusable_idents(&tokens);
+        assert_eq!(res.len(), 2);
+        assert_eq!((res[0].first.pos, res[0].second.pos), (0, 5));
+        assert_eq!((res[1].first.pos, res[1].second.pos), (10, 17));
+    }
+}
@@ -1,11 +1,11 @@
-
-use crate::token::{Keyword, Kind, Literal, NumLiteral, Symbol, Token};
-use parser::stream::Stream;
+use crate::intern::Sym;
+use crate::lexer::keyword;
+use crate::token::{Kind, Literal, NumLiteral, Symbol, Token};
+use crate::unicode;
 use parser::{
     or,
     parser::{
-        any_one, eof, expect, fail, parser_func, token, tokens, val, Either, Fail, Parser,
-        ParserError, ParserResult, Val,
+        any_one, eof, expect, fail, parser_func, token, tokens, val, Either, Fail, Parser, Val,
     },
 };
 pub fn string(s: &str) -> impl Parser<Input = char, Output = String> {
@@ -26,6 +26,7 @@ pub fn line_comment() -> impl Parser<Input = char, Output = ()> {
 pub fn block_comment() -> impl Parser<Input = char, Output = ()> {
     parser_func(|st| {
         string("/*")
+            .attempt()
             .with(
                 parser_func(|st| match (st.peak(), st.peak_index(1)) {
                     (Some('/'), Some('*')) => block_comment().parse(st),
@@ -49,11 +50,11 @@ pub fn skip() -> impl Parser<Input = char, Output = ()> {
 }
 
 pub fn ident_str() -> impl Parser<Input = char, Output = String> {
-    expect::<char, _>(|&c| c.is_ascii_alphabetic())
-        .and(expect::<char, _>(|&c| c.is_ascii_alphanumeric() || c == '_').many())
+    expect::<char, _>(|&c| unicode::is_ident_start(c))
+        .and(expect::<char, _>(|&c| unicode::is_ident_continue(c)).many())
         .map(|(x, mut xs)| {
             xs.insert(0, x);
-            xs.into_iter().collect::<String>()
+            unicode::nfc(&xs.into_iter().collect::<String>())
         })
 }
 
@@ -74,7 +75,7 @@ pub fn num_literal() -> impl Parser<Input = char, Output = NumLiteral> {
         .and(token('.').and(num).optional())
         .and(ident_str().optional())
         .then(|((s1, dot_num), suffix)| {
-            let suffix = suffix.as_ref().map(|x| x.as_str());
+            let suffix = suffix.as_deref();
             if let Some((_, s2)) = dot_num {
                 let s = format!("{}.{}", s1, s2);
                 match suffix {
@@ -95,12 +96,12 @@ pub fn num_literal() -> impl Parser<Input = char, Output = NumLiteral> {
 }
 
 pub fn hex_char(len: usize) -> impl Parser<Input = char, Output = char> {
-    expect::<char, _>(|&x| x.is_ascii_digit() || ('a' <= x && x <= 'f') || ('A' <= x && x <= 'F'))
+    expect::<char, _>(|&x| x.is_ascii_hexdigit())
         .map(|x| x.to_ascii_lowercase())
         .many_n(len)
         .map(|x| {
             u32::from_str_radix(&x.into_iter().collect::<String>(), 16)
-                .map(|x| std::char::from_u32(x))
+                .map(std::char::from_u32)
                 .unwrap()
         })
         .then(|x| match x {
@@ -114,7 +115,7 @@ pub fn lexer() -> impl Parser<Input = char, Output = Vec<Token>> {
         .map(|_| None)
         .or(one_token().map(Some))
         .many()
-        .map(|x| x.into_iter().filter_map(|x| x).collect::<Vec<_>>())
+        .map(|x| x.into_iter().flatten().collect::<Vec<_>>())
         .skip(eof())
 }
 
@@ -138,7 +139,7 @@ pub fn kind() -> impl Parser<Input = char, Output = Kind> {
 pub fn literal() -> impl Parser<Input = char, Output = Literal> {
     or!(
         char_literal().map(Literal::Char),
-        string_literal().map(Literal::String),
+        string_literal().map(|s| Literal::String(Sym::intern(&s))),
         num_literal().map(Literal::Num)
     )
 }
@@ -174,28 +175,9 @@ pub fn string_literal() -> impl Parser<Input = char, Output = String> {
 }
 
 pub fn ident_or_keyword() -> impl Parser<Input = char, Output = Kind> {
-    parser_func(|st| {
-        let s = ident_str().parse(st)?;
-        Ok(match s.as_ref() {
-            "i32" => Kind::Keyword(Keyword::I32),
-            "i64" => Kind::Keyword(Keyword::I64),
-            "F32" => Kind::Keyword(Keyword::F32),
-            "F64" => Kind::Keyword(Keyword::F64),
-            "string" => Kind::Keyword(Keyword::String),
-            "bool" => Kind::Keyword(Keyword::Bool),
-            "char" => Kind::Keyword(Keyword::Char),
-            "true" => Kind::Keyword(Keyword::True),
-            "false" => Kind::Keyword(Keyword::False),
-            "let" => Kind::Keyword(Keyword::Let),
-            "if" => Kind::Keyword(Keyword::If),
-            "while" => Kind::Keyword(Keyword::While),
-            "return" => Kind::Keyword(Keyword::Return),
-            "struct" => Kind::Keyword(Keyword::Struct),
-            "fun" => Kind::Keyword(Keyword::Fun),
-            "extern" => Kind::Keyword(Keyword::Extern),
-            "for" => Kind::Keyword(Keyword::For),
-            s => Kind::Ident(s.to_string()),
-        })
+    ident_str().map(|s| match keyword(&s) {
+        Some(k) => Kind::Keyword(k),
+        None => Kind::Ident(Sym::intern(&s)),
     })
 }
 
@@ -203,6 +185,7 @@ pub fn symbol() -> impl Parser<Input = char, Output = Symbol> {
     or!(
         token('.').with(val(Symbol::Dot)),
         token(',').with(val(Symbol::Comma)),
+        string("::").with(val(Symbol::DoubleColon)).attempt(),
         token(':').with(val(Symbol::Colon)),
         token(';').with(val(Symbol::Semicolon)),
         token('(').with(val(Symbol::OpenParent)),
@@ -213,22 +196,68 @@ pub fn symbol() -> impl Parser<Input = char, Output = Symbol> {
         token('}').with(val(Symbol::CloseBrace)),
         string("!=").with(val(Symbol::Ne)).attempt(),
         token('!').with(val(Symbol::Not)),
+        string("+=").with(val(Symbol::AddAssign)).attempt(),
         token('+').with(val(Symbol::Add)),
+        string("-=").with(val(Symbol::SubAssign)).attempt(),
+        string("->").with(val(Symbol::Arrow)).attempt(),
         token('-').with(val(Symbol::Sub)),
+        string("**=").with(val(Symbol::PowAssign)).attempt(),
         string("**").with(val(Symbol::Pow)).attempt(),
+        string("*=").with(val(Symbol::MulAssign)).attempt(),
         token('*').with(val(Symbol::Mul)),
+        string("/=").with(val(Symbol::DivAssign)).attempt(),
         token('/').with(val(Symbol::Div)),
+        string("%=").with(val(Symbol::ModAssign)).attempt(),
         token('%').with(val(Symbol::Mod)),
         string("&&").with(val(Symbol::And)).attempt(),
+        string("&=").with(val(Symbol::BitAndAssign)).attempt(),
         token('&').with(val(Symbol::BitAnd)),
         string("||").with(val(Symbol::Or)).attempt(),
+        string("|=").with(val(Symbol::BitOrAssign)).attempt(),
         token('|').with(val(Symbol::BitOr)),
+        string("^=").with(val(Symbol::BitXorAssign)).attempt(),
         token('^').with(val(Symbol::BitXor)),
+        string("<<=").with(val(Symbol::ShlAssign)).attempt(),
+        string("<<").with(val(Symbol::Shl)).attempt(),
         string("<=").with(val(Symbol::Lte)).attempt(),
         token('<').with(val(Symbol::Lt)),
+        string(">>>=").with(val(Symbol::UShrAssign)).attempt(),
+        string(">>>").with(val(Symbol::UShr)).attempt(),
+        string(">>=").with(val(Symbol::ShrAssign)).attempt(),
+        string(">>").with(val(Symbol::Shr)).attempt(),
         string(">=").with(val(Symbol::Gte)).attempt(),
         token('>').with(val(Symbol::Gt)),
         string("==").with(val(Symbol::Eq)).attempt(),
-        token('=').with(val(Symbol::Assign))
+        string("=>").with(val(Symbol::FatArrow)).attempt(),
+        token('=').with(val(Symbol::Assign)),
+        token('?').with(val(Symbol::Question)),
+        token('\\').with(val(Symbol::Backslash))
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::Sym;
+    use parser::stream::Stream;
+
+    // 「/*」の途中で失敗したら戻って、1文字の「/」を割り算として読む
+    #[test]
+    fn lone_slash_test() {
+        let src = "a / b".chars().collect::<Vec<_>>();
+        let kinds = lexer()
+            .parse(&mut Stream::new(src))
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                Kind::Ident(Sym::intern("a")),
+                Kind::Symbol(Symbol::Div),
+                Kind::Ident(Sym::intern("b")),
+            ]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::intern::Sym;
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub kind: Kind,
@@ -5,10 +7,18 @@ pub struct Token {
     pub len: usize,
 }
 
+// 字句解析で読み飛ばしたコメントの位置(行コメントの長さは改行を含まない)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub pos: usize,
+    pub len: usize,
+    pub block: bool,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Kind {
     Keyword(Keyword),
-    Ident(String),
+    Ident(Sym),
     Literal(Literal),
     Symbol(Symbol),
 }
@@ -16,7 +26,7 @@ pub enum Kind {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
     Char(char),
-    String(String),
+    String(Sym),
     Num(NumLiteral),
 }
 
@@ -47,6 +57,17 @@ pub enum Keyword {
     Fun,
     Extern,
     For,
+    Else,
+    Elif,
+    Break,
+    Continue,
+    As,
+    Bitcast,
+    Enum,
+    Union,
+    Match,
+    Const,
+    Export,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -80,4 +101,24 @@ pub enum Symbol {
     Gt,
     Gte,
     Assign,
+    Shl,
+    Shr,
+    UShr,
+    AddAssign,
+    SubAssign,
+    MulAssign,
+    DivAssign,
+    ModAssign,
+    PowAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+    ShlAssign,
+    ShrAssign,
+    UShrAssign,
+    Arrow,
+    FatArrow,
+    DoubleColon,
+    Question,
+    Backslash,
 }
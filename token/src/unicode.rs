@@ -0,0 +1,212 @@
+mod tables;
+
+pub use tables::UNICODE_VERSION;
+
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_L_COUNT: u32 = 19;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = HANGUL_L_COUNT * HANGUL_N_COUNT;
+
+fn in_ranges(table: &[(char, char)], c: char) -> bool {
+    table
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                std::cmp::Ordering::Less
+            } else if c < lo {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+pub fn is_xid_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || (!c.is_ascii() && in_ranges(tables::XID_START, c))
+}
+
+pub fn is_xid_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || (!c.is_ascii() && in_ranges(tables::XID_CONTINUE, c))
+}
+
+pub fn is_ident_start(c: char) -> bool {
+    c == '_' || is_xid_start(c)
+}
+
+pub fn is_ident_continue(c: char) -> bool {
+    is_xid_continue(c)
+}
+
+pub fn combining_class(c: char) -> u8 {
+    if c.is_ascii() {
+        return 0;
+    }
+    tables::COMBINING_CLASS
+        .binary_search_by(|&(lo, hi, _)| {
+            if hi < c {
+                std::cmp::Ordering::Less
+            } else if c < lo {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .map(|i| tables::COMBINING_CLASS[i].2)
+        .unwrap_or(0)
+}
+
+fn decompose_char(c: char, res: &mut Vec<char>) {
+    let s = c as u32;
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&s) {
+        let i = s - HANGUL_S_BASE;
+        let l = HANGUL_L_BASE + i / HANGUL_N_COUNT;
+        let v = HANGUL_V_BASE + (i % HANGUL_N_COUNT) / HANGUL_T_COUNT;
+        let t = HANGUL_T_BASE + i % HANGUL_T_COUNT;
+        res.push(std::char::from_u32(l).unwrap());
+        res.push(std::char::from_u32(v).unwrap());
+        if t != HANGUL_T_BASE {
+            res.push(std::char::from_u32(t).unwrap());
+        }
+        return;
+    }
+
+    match tables::DECOMPOSITION.binary_search_by_key(&c, |&(x, _)| x) {
+        Ok(i) => res.extend_from_slice(tables::DECOMPOSITION[i].1),
+        Err(_) => res.push(c),
+    }
+}
+
+fn compose_pair(a: char, b: char) -> Option<char> {
+    let (a32, b32) = (a as u32, b as u32);
+    if (HANGUL_L_BASE..HANGUL_L_BASE + HANGUL_L_COUNT).contains(&a32)
+        && (HANGUL_V_BASE..HANGUL_V_BASE + HANGUL_V_COUNT).contains(&b32)
+    {
+        let l = a32 - HANGUL_L_BASE;
+        let v = b32 - HANGUL_V_BASE;
+        return std::char::from_u32(HANGUL_S_BASE + (l * HANGUL_V_COUNT + v) * HANGUL_T_COUNT);
+    }
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&a32)
+        && (a32 - HANGUL_S_BASE).is_multiple_of(HANGUL_T_COUNT)
+        && (HANGUL_T_BASE + 1..HANGUL_T_BASE + HANGUL_T_COUNT).contains(&b32)
+    {
+        return std::char::from_u32(a32 + (b32 - HANGUL_T_BASE));
+    }
+
+    tables::COMPOSITION
+        .binary_search_by_key(&(a, b), |&(x, _)| x)
+        .ok()
+        .map(|i| tables::COMPOSITION[i].1)
+}
+
+fn canonical_order(chars: &mut [char]) {
+    let mut i = 0;
+    while i < chars.len() {
+        if combining_class(chars[i]) == 0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && combining_class(chars[i]) != 0 {
+            i += 1;
+        }
+        chars[start..i].sort_by_key(|&c| combining_class(c));
+    }
+}
+
+pub fn nfd(s: &str) -> String {
+    if s.is_ascii() {
+        return s.to_string();
+    }
+    let mut chars = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose_char(c, &mut chars);
+    }
+    canonical_order(&mut chars);
+    chars.into_iter().collect()
+}
+
+pub fn nfc(s: &str) -> String {
+    if s.is_ascii() {
+        return s.to_string();
+    }
+    let chars = nfd(s).chars().collect::<Vec<_>>();
+    let mut res: Vec<char> = Vec::with_capacity(chars.len());
+    let mut starter: Option<usize> = None;
+    let mut last_class = 0;
+    for c in chars {
+        let class = combining_class(c);
+        if let Some(si) = starter {
+            let blocked = si + 1 != res.len() && last_class >= class;
+            if !blocked {
+                if let Some(x) = compose_pair(res[si], c) {
+                    res[si] = x;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(res.len());
+        }
+        last_class = class;
+        res.push(c);
+    }
+    res.into_iter().collect()
+}
+
+// UTS #39 の skeleton を簡略化したもの
+pub fn skeleton(s: &str) -> String {
+    let mapped = nfd(s)
+        .chars()
+        .map(|c| {
+            tables::CONFUSABLES
+                .binary_search_by_key(&c, |&(x, _)| x)
+                .map(|i| tables::CONFUSABLES[i].1)
+                .unwrap_or(c)
+        })
+        .collect::<String>();
+    nfd(&mapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xid_test() {
+        assert!(is_ident_start('a'));
+        assert!(is_ident_start('_'));
+        assert!(is_ident_start('あ'));
+        assert!(is_ident_start('変'));
+        assert!(!is_ident_start('1'));
+        assert!(!is_ident_start('・'));
+        assert!(is_ident_continue('1'));
+        assert!(is_ident_continue('ー'));
+        assert!(is_ident_continue('\u{3099}'));
+        assert!(!is_ident_continue('-'));
+        assert!(!is_ident_continue('　'));
+    }
+
+    #[test]
+    fn nfc_test() {
+        assert_eq!(nfc("abc"), "abc");
+        assert_eq!(nfc("e\u{301}"), "\u{e9}");
+        assert_eq!(nfc("\u{304b}\u{3099}"), "\u{304c}");
+        assert_eq!(nfc("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}");
+        assert_eq!(nfc("\u{212b}"), "\u{c5}");
+        assert_eq!(nfc("a\u{323}\u{302}"), "\u{1ead}");
+        assert_eq!(nfc("a\u{302}\u{323}"), "\u{1ead}");
+        assert_eq!(nfd("\u{ac01}"), "\u{1100}\u{1161}\u{11a8}");
+    }
+
+    #[test]
+    fn skeleton_test() {
+        assert_eq!(skeleton("pаypal"), skeleton("paypal"));
+        assert_eq!(skeleton("カ"), skeleton("力"));
+        assert_ne!(skeleton("foo"), skeleton("bar"));
+    }
+}
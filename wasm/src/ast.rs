@@ -1,27 +1,27 @@
 #[derive(Clone, Debug, PartialEq)]
-enum ValueType {
+pub enum ValueType {
     I32,
-    I63,
+    I64,
     F32,
     F64,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct BlockType(Option<ValueType>);
+pub struct BlockType(pub Option<ValueType>);
 
 #[derive(Clone, Debug, PartialEq)]
-enum ElemType {
+pub enum ElemType {
     AnyFunc,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct FuncType {
-    params: Vec<ValueType>,
-    result: Option<ValueType>,
+pub struct FuncType {
+    pub params: Vec<ValueType>,
+    pub result: Option<ValueType>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum LanguageType {
+pub enum LanguageType {
     ValueType(ValueType),
     ElemType(ElemType),
     FuncType(FuncType),
@@ -29,22 +29,22 @@ enum LanguageType {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalType {
-    content_type: ValueType,
-    mutability: bool,
+pub struct GlobalType {
+    pub content_type: ValueType,
+    pub mutability: bool,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct TableType {
-    element_type: ElemType,
-    limits: ResizableLimits,
+pub struct TableType {
+    pub element_type: ElemType,
+    pub limits: ResizableLimits,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct MemoryType(ResizableLimits);
+pub struct MemoryType(pub ResizableLimits);
 
 #[derive(Clone, Debug, PartialEq)]
-enum ExternalKind {
+pub enum ExternalKind {
     Function,
     Table,
     Memory,
@@ -52,7 +52,7 @@ enum ExternalKind {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum ExternalKindImport {
+pub enum ExternalKindImport {
     Function(usize),
     Table(TableType),
     Memory(MemoryType),
@@ -60,13 +60,13 @@ enum ExternalKindImport {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct ResizableLimits {
-    initial: i32,
-    maximum: Option<i32>,
+pub struct ResizableLimits {
+    pub initial: i32,
+    pub maximum: Option<i32>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum InitExpr {
+pub enum InitExpr {
     I32(i32),
     I64(i64),
     F32(f32),
@@ -75,87 +75,87 @@ enum InitExpr {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct TypeSection(Vec<FuncType>);
+pub struct TypeSection(pub Vec<FuncType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ImportEntry {
-    module: String,
-    field: String,
-    kind: ExternalKindImport,
+pub struct ImportEntry {
+    pub module: String,
+    pub field: String,
+    pub kind: ExternalKindImport,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct ImportSection(Vec<ImportEntry>);
+pub struct ImportSection(pub Vec<ImportEntry>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct FunctionSection(Vec<usize>);
+pub struct FunctionSection(pub Vec<usize>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct TableSection(Vec<TableType>);
+pub struct TableSection(pub Vec<TableType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct MemorySection(Vec<MemoryType>);
+pub struct MemorySection(pub Vec<MemoryType>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalSection(Vec<GlobalVariable>);
+pub struct GlobalSection(pub Vec<GlobalVariable>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct GlobalVariable(GlobalType, InitExpr);
+pub struct GlobalVariable(pub GlobalType, pub InitExpr);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ExportSection(Vec<ExportEntry>);
+pub struct ExportSection(pub Vec<ExportEntry>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ExportEntry {
-    field: String,
-    kind: ExternalKind,
-    index: usize,
+pub struct ExportEntry {
+    pub field: String,
+    pub kind: ExternalKind,
+    pub index: usize,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct StartSection(usize);
+pub struct StartSection(pub usize);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ElementSection(Vec<ElemSegment>);
+pub struct ElementSection(pub Vec<ElemSegment>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct ElemSegment {
-    offset: InitExpr,
-    elems: usize,
+pub struct ElemSegment {
+    pub offset: InitExpr,
+    pub elems: Vec<usize>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct CodeSection(Vec<FunctionBody>);
+pub struct CodeSection(pub Vec<FunctionBody>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct FunctionBody {
-    locals: Vec<LocalEntry>,
-    codes: Vec<OperatorCode>,
+pub struct FunctionBody {
+    pub locals: Vec<LocalEntry>,
+    pub codes: Vec<OperatorCode>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct LocalEntry {
-    count: usize,
-    typ: ValueType,
+pub struct LocalEntry {
+    pub count: usize,
+    pub typ: ValueType,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct DataSection(Vec<DataSegment>);
+pub struct DataSection(pub Vec<DataSegment>);
 
 #[derive(Clone, Debug, PartialEq)]
-struct DataSegment {
-    offset: InitExpr,
-    data: Vec<u8>,
+pub struct DataSegment {
+    pub offset: InitExpr,
+    pub data: Vec<u8>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct MemoryImmediate {
-    flags: u32,
-    offset: u32,
+pub struct MemoryImmediate {
+    pub flags: u32,
+    pub offset: u32,
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum OperatorCode {
+pub enum OperatorCode {
     Unreachable,
     Nop,
     Block(BlockType),
@@ -331,16 +331,16 @@ enum OperatorCode {
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
-struct WasmASTRoot {
-    type_section: Option<TypeSection>,
-    import_section: Option<ImportSection>,
-    function_section: Option<FunctionSection>,
-    table_section: Option<TableSection>,
-    memory_section: Option<MemorySection>,
-    global_section: Option<GlobalSection>,
-    export_section: Option<ExportSection>,
-    start_section: Option<StartSection>,
-    element_section: Option<ElementSection>,
-    code_section: Option<CodeSection>,
-    data_section: Option<DataSection>,
+pub struct WasmASTRoot {
+    pub type_section: Option<TypeSection>,
+    pub import_section: Option<ImportSection>,
+    pub function_section: Option<FunctionSection>,
+    pub table_section: Option<TableSection>,
+    pub memory_section: Option<MemorySection>,
+    pub global_section: Option<GlobalSection>,
+    pub export_section: Option<ExportSection>,
+    pub start_section: Option<StartSection>,
+    pub element_section: Option<ElementSection>,
+    pub code_section: Option<CodeSection>,
+    pub data_section: Option<DataSection>,
 }
@@ -1,18 +1,772 @@
+use crate::ast::*;
 use byteorder::{LittleEndian, WriteBytesExt};
 
+const MAGIC: u32 = 0x6d73_6100;
+const VERSION: u32 = 1;
 
-trait BinaryEncode {
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const TABLE_SECTION: u8 = 4;
+const MEMORY_SECTION: u8 = 5;
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const START_SECTION: u8 = 8;
+const ELEMENT_SECTION: u8 = 9;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+
+// MVPのバイナリ形式に書き出す
+pub trait BinaryEncode {
     fn encode(&self, bytes: &mut Vec<u8>);
 }
 
-fn encodeUint8(x: u8, bytes: &mut Vec<u8>) {
+fn encode_uint8(x: u8, bytes: &mut Vec<u8>) {
     bytes.write_u8(x).unwrap();
 }
 
-fn encodeUint16(x: u16, bytes: &mut Vec<u8>) {
-    bytes.write_u16::<LittleEndian>(x).unwrap();
+fn encode_uint32(x: u32, bytes: &mut Vec<u8>) {
+    bytes.write_u32::<LittleEndian>(x).unwrap();
 }
 
-fn encodeUint32(x: u32, bytes: &mut Vec<u8>) {
-    bytes.write_u32::<LittleEndian>(x).unwrap();
-}
\ No newline at end of file
+fn encode_uint64(x: u64, bytes: &mut Vec<u8>) {
+    bytes.write_u64::<LittleEndian>(x).unwrap();
+}
+
+fn encode_varuint32(x: u32, bytes: &mut Vec<u8>) {
+    leb128::write::unsigned(bytes, x as u64).unwrap();
+}
+
+fn encode_varint32(x: i32, bytes: &mut Vec<u8>) {
+    leb128::write::signed(bytes, x as i64).unwrap();
+}
+
+fn encode_varint64(x: i64, bytes: &mut Vec<u8>) {
+    leb128::write::signed(bytes, x).unwrap();
+}
+
+// 添字と個数はvaruint32
+fn encode_index(x: usize, bytes: &mut Vec<u8>) {
+    encode_varuint32(x as u32, bytes);
+}
+
+fn encode_indices(xs: &[usize], bytes: &mut Vec<u8>) {
+    encode_index(xs.len(), bytes);
+    for x in xs {
+        encode_index(*x, bytes);
+    }
+}
+
+fn encode_vec<T: BinaryEncode>(xs: &[T], bytes: &mut Vec<u8>) {
+    encode_index(xs.len(), bytes);
+    for x in xs {
+        x.encode(bytes);
+    }
+}
+
+fn encode_bytes(xs: &[u8], bytes: &mut Vec<u8>) {
+    encode_index(xs.len(), bytes);
+    bytes.extend_from_slice(xs);
+}
+
+// 中身を書き出してから長さを前に付ける
+fn encode_sized<F: FnOnce(&mut Vec<u8>)>(f: F, bytes: &mut Vec<u8>) {
+    let mut payload = Vec::new();
+    f(&mut payload);
+    encode_bytes(&payload, bytes);
+}
+
+fn encode_section<F: FnOnce(&mut Vec<u8>)>(id: u8, f: F, bytes: &mut Vec<u8>) {
+    encode_uint8(id, bytes);
+    encode_sized(f, bytes);
+}
+
+impl BinaryEncode for ValueType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(
+            match self {
+                ValueType::I32 => 0x7f,
+                ValueType::I64 => 0x7e,
+                ValueType::F32 => 0x7d,
+                ValueType::F64 => 0x7c,
+            },
+            bytes,
+        );
+    }
+}
+
+impl BinaryEncode for BlockType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match &self.0 {
+            Some(t) => t.encode(bytes),
+            None => encode_uint8(0x40, bytes),
+        }
+    }
+}
+
+impl BinaryEncode for ElemType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ElemType::AnyFunc => encode_uint8(0x70, bytes),
+        }
+    }
+}
+
+impl BinaryEncode for FuncType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(0x60, bytes);
+        encode_vec(&self.params, bytes);
+        match &self.result {
+            Some(t) => {
+                encode_uint8(1, bytes);
+                t.encode(bytes);
+            }
+            None => encode_uint8(0, bytes),
+        }
+    }
+}
+
+impl BinaryEncode for LanguageType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            LanguageType::ValueType(t) => t.encode(bytes),
+            LanguageType::ElemType(t) => t.encode(bytes),
+            LanguageType::FuncType(t) => t.encode(bytes),
+            LanguageType::BlockType(t) => t.encode(bytes),
+        }
+    }
+}
+
+impl BinaryEncode for GlobalType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.content_type.encode(bytes);
+        encode_uint8(self.mutability as u8, bytes);
+    }
+}
+
+impl BinaryEncode for TableType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.element_type.encode(bytes);
+        self.limits.encode(bytes);
+    }
+}
+
+impl BinaryEncode for MemoryType {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ExternalKind {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(
+            match self {
+                ExternalKind::Function => 0,
+                ExternalKind::Table => 1,
+                ExternalKind::Memory => 2,
+                ExternalKind::Global => 3,
+            },
+            bytes,
+        );
+    }
+}
+
+impl BinaryEncode for ExternalKindImport {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            ExternalKindImport::Function(t) => {
+                ExternalKind::Function.encode(bytes);
+                encode_index(*t, bytes);
+            }
+            ExternalKindImport::Table(t) => {
+                ExternalKind::Table.encode(bytes);
+                t.encode(bytes);
+            }
+            ExternalKindImport::Memory(t) => {
+                ExternalKind::Memory.encode(bytes);
+                t.encode(bytes);
+            }
+            ExternalKindImport::Global(t) => {
+                ExternalKind::Global.encode(bytes);
+                t.encode(bytes);
+            }
+        }
+    }
+}
+
+impl BinaryEncode for ResizableLimits {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint8(self.maximum.is_some() as u8, bytes);
+        encode_varuint32(self.initial as u32, bytes);
+        if let Some(maximum) = self.maximum {
+            encode_varuint32(maximum as u32, bytes);
+        }
+    }
+}
+
+impl BinaryEncode for InitExpr {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            InitExpr::I32(x) => OperatorCode::I32Const(*x).encode(bytes),
+            InitExpr::I64(x) => OperatorCode::I64Const(*x).encode(bytes),
+            InitExpr::F32(x) => OperatorCode::F32Const(*x).encode(bytes),
+            InitExpr::F64(x) => OperatorCode::F64Const(*x).encode(bytes),
+            InitExpr::Global(x) => OperatorCode::GetGlobal(*x).encode(bytes),
+        }
+        OperatorCode::End.encode(bytes);
+    }
+}
+
+impl BinaryEncode for TypeSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(TYPE_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for ImportEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_bytes(self.module.as_bytes(), bytes);
+        encode_bytes(self.field.as_bytes(), bytes);
+        self.kind.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ImportSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(IMPORT_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for FunctionSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(FUNCTION_SECTION, |b| encode_indices(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for TableSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(TABLE_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for MemorySection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(MEMORY_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for GlobalSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(GLOBAL_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for GlobalVariable {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+        self.1.encode(bytes);
+    }
+}
+
+impl BinaryEncode for ExportSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(EXPORT_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for ExportEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_bytes(self.field.as_bytes(), bytes);
+        self.kind.encode(bytes);
+        encode_index(self.index, bytes);
+    }
+}
+
+impl BinaryEncode for StartSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(START_SECTION, |b| encode_index(self.0, b), bytes);
+    }
+}
+
+impl BinaryEncode for ElementSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(ELEMENT_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+// MVPでは表は1つだけなので添字は常に0
+impl BinaryEncode for ElemSegment {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_index(0, bytes);
+        self.offset.encode(bytes);
+        encode_indices(&self.elems, bytes);
+    }
+}
+
+impl BinaryEncode for CodeSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(CODE_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+// codesは最後のEndまで含む
+impl BinaryEncode for FunctionBody {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_sized(
+            |b| {
+                encode_vec(&self.locals, b);
+                for op in &self.codes {
+                    op.encode(b);
+                }
+            },
+            bytes,
+        );
+    }
+}
+
+impl BinaryEncode for LocalEntry {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_index(self.count, bytes);
+        self.typ.encode(bytes);
+    }
+}
+
+impl BinaryEncode for DataSection {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_section(DATA_SECTION, |b| encode_vec(&self.0, b), bytes);
+    }
+}
+
+// 線形メモリも1つだけ
+impl BinaryEncode for DataSegment {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_index(0, bytes);
+        self.offset.encode(bytes);
+        encode_bytes(&self.data, bytes);
+    }
+}
+
+impl BinaryEncode for MemoryImmediate {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_varuint32(self.flags, bytes);
+        encode_varuint32(self.offset, bytes);
+    }
+}
+
+impl BinaryEncode for OperatorCode {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        use OperatorCode::*;
+        let op = match self {
+            Unreachable => 0x00,
+            Nop => 0x01,
+            Block(_) => 0x02,
+            Loop(_) => 0x03,
+            If(_) => 0x04,
+            Else => 0x05,
+            End => 0x0b,
+            Br(_) => 0x0c,
+            BrIf(_) => 0x0d,
+            BrTable { .. } => 0x0e,
+            Return => 0x0f,
+            Call(_) => 0x10,
+            CallIndirect(_) => 0x11,
+            Drop => 0x1a,
+            Select => 0x1b,
+            GetLocal(_) => 0x20,
+            SetLocal(_) => 0x21,
+            TeeLocal(_) => 0x22,
+            GetGlobal(_) => 0x23,
+            SetGlobal(_) => 0x24,
+            I32Load(_) => 0x28,
+            I64Load(_) => 0x29,
+            F32Load(_) => 0x2a,
+            F64Load(_) => 0x2b,
+            I32Load8s(_) => 0x2c,
+            I32Load8u(_) => 0x2d,
+            I32Load16s(_) => 0x2e,
+            I32Load16u(_) => 0x2f,
+            I64Load8s(_) => 0x30,
+            I64Load8u(_) => 0x31,
+            I64Load16s(_) => 0x32,
+            I64Load16u(_) => 0x33,
+            I64Load32s(_) => 0x34,
+            I64Load32u(_) => 0x35,
+            I32Store(_) => 0x36,
+            I64Store(_) => 0x37,
+            F32Store(_) => 0x38,
+            F64Store(_) => 0x39,
+            I32Store8(_) => 0x3a,
+            I32Store16(_) => 0x3b,
+            I64Store8(_) => 0x3c,
+            I64Store16(_) => 0x3d,
+            I64Store32(_) => 0x3e,
+            CurrentMemory => 0x3f,
+            GrowMemory => 0x40,
+            I32Const(_) => 0x41,
+            I64Const(_) => 0x42,
+            F32Const(_) => 0x43,
+            F64Const(_) => 0x44,
+            I32Eqz => 0x45,
+            I32Eq => 0x46,
+            I32Ne => 0x47,
+            I32Lts => 0x48,
+            I32Ltu => 0x49,
+            I32Gts => 0x4a,
+            I32Gtu => 0x4b,
+            I32Les => 0x4c,
+            I32Leu => 0x4d,
+            I32Ges => 0x4e,
+            I32Geu => 0x4f,
+            I64Eqz => 0x50,
+            I64Eq => 0x51,
+            I64Ne => 0x52,
+            I64Lts => 0x53,
+            I64Ltu => 0x54,
+            I64Gts => 0x55,
+            I64Gtu => 0x56,
+            I64Les => 0x57,
+            I64Leu => 0x58,
+            I64Ges => 0x59,
+            I64Geu => 0x5a,
+            F32Eq => 0x5b,
+            F32Ne => 0x5c,
+            F32Lt => 0x5d,
+            F32Gt => 0x5e,
+            F32Le => 0x5f,
+            F32Ge => 0x60,
+            F64Eq => 0x61,
+            F64Ne => 0x62,
+            F64Lt => 0x63,
+            F64Gt => 0x64,
+            F64Le => 0x65,
+            F64Ge => 0x66,
+            I32Clz => 0x67,
+            I32Ctz => 0x68,
+            I32Popcnt => 0x69,
+            I32Add => 0x6a,
+            I32Sub => 0x6b,
+            I32Mul => 0x6c,
+            I32Divs => 0x6d,
+            I32Divu => 0x6e,
+            I32Rems => 0x6f,
+            I32Remu => 0x70,
+            I32And => 0x71,
+            I32Or => 0x72,
+            I32Xor => 0x73,
+            I32Shl => 0x74,
+            I32Shrs => 0x75,
+            I32Shru => 0x76,
+            I32Rotl => 0x77,
+            I32Rotr => 0x78,
+            I64Clz => 0x79,
+            I64Ctz => 0x7a,
+            I64Popcnt => 0x7b,
+            I64Add => 0x7c,
+            I64Sub => 0x7d,
+            I64Mul => 0x7e,
+            I64Divs => 0x7f,
+            I64Divu => 0x80,
+            I64Rems => 0x81,
+            I64Remu => 0x82,
+            I64And => 0x83,
+            I64Or => 0x84,
+            I64Xor => 0x85,
+            I64Shl => 0x86,
+            I64Shrs => 0x87,
+            I64Shru => 0x88,
+            I64Rotl => 0x89,
+            I64Rotr => 0x8a,
+            F32Abs => 0x8b,
+            F32Neg => 0x8c,
+            F32Ceil => 0x8d,
+            F32Floor => 0x8e,
+            F32Trunc => 0x8f,
+            F32Nearest => 0x90,
+            F32Sqrt => 0x91,
+            F32Add => 0x92,
+            F32Sub => 0x93,
+            F32Mul => 0x94,
+            F32Div => 0x95,
+            F32Min => 0x96,
+            F32Max => 0x97,
+            F32Copysign => 0x98,
+            F64Abs => 0x99,
+            F64Neg => 0x9a,
+            F64Ceil => 0x9b,
+            F64Floor => 0x9c,
+            F64Trunc => 0x9d,
+            F64Nearest => 0x9e,
+            F64Sqrt => 0x9f,
+            F64Add => 0xa0,
+            F64Sub => 0xa1,
+            F64Mul => 0xa2,
+            F64Div => 0xa3,
+            F64Min => 0xa4,
+            F64Max => 0xa5,
+            F64Copysign => 0xa6,
+            I32WrapI64 => 0xa7,
+            I32TruncsF32 => 0xa8,
+            I32TrancuF32 => 0xa9,
+            I32TrancsF64 => 0xaa,
+            I32TrancuF64 => 0xab,
+            I64ExtendsI32 => 0xac,
+            I64ExtenduI32 => 0xad,
+            I64TruncsF32 => 0xae,
+            I64TrancuF32 => 0xaf,
+            I64TrancsF64 => 0xb0,
+            I64TrancuF64 => 0xb1,
+            F32ConvertsI32 => 0xb2,
+            F32ConvertuI32 => 0xb3,
+            F32ConvertsI64 => 0xb4,
+            F32ConvertuI64 => 0xb5,
+            F32DemoteF64 => 0xb6,
+            F64ConvertsI32 => 0xb7,
+            F64ConvertuI32 => 0xb8,
+            F64ConvertsI64 => 0xb9,
+            F64ConvertuI64 => 0xba,
+            F64PromoteF32 => 0xbb,
+            I32ReinterpretF32 => 0xbc,
+            I64ReinterpretF64 => 0xbd,
+            F32ReinterpretI32 => 0xbe,
+            F64ReinterpretI64 => 0xbf,
+        };
+        encode_uint8(op, bytes);
+        // 即値
+        match self {
+            Block(t) | Loop(t) | If(t) => t.encode(bytes),
+            Br(x) | BrIf(x) | Call(x) | GetLocal(x) | SetLocal(x) | TeeLocal(x) | GetGlobal(x)
+            | SetGlobal(x) => encode_index(*x, bytes),
+            // 分岐先の表の後に既定の分岐先を置く
+            BrTable { index, params } => {
+                encode_indices(params, bytes);
+                encode_index(*index, bytes);
+            }
+            // 予約された表の添字
+            CallIndirect(t) => {
+                encode_index(*t, bytes);
+                encode_uint8(0, bytes);
+            }
+            I32Load(m) | I64Load(m) | F32Load(m) | F64Load(m) | I32Load8s(m) | I32Load8u(m)
+            | I32Load16s(m) | I32Load16u(m) | I64Load8s(m) | I64Load8u(m) | I64Load16s(m)
+            | I64Load16u(m) | I64Load32s(m) | I64Load32u(m) | I32Store(m) | I64Store(m)
+            | F32Store(m) | F64Store(m) | I32Store8(m) | I32Store16(m) | I64Store8(m)
+            | I64Store16(m) | I64Store32(m) => m.encode(bytes),
+            // 予約された線形メモリの添字
+            CurrentMemory | GrowMemory => encode_uint8(0, bytes),
+            I32Const(x) => encode_varint32(*x, bytes),
+            I64Const(x) => encode_varint64(*x, bytes),
+            F32Const(x) => encode_uint32(x.to_bits(), bytes),
+            F64Const(x) => encode_uint64(x.to_bits(), bytes),
+            _ => {}
+        }
+    }
+}
+
+impl BinaryEncode for WasmASTRoot {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        encode_uint32(MAGIC, bytes);
+        encode_uint32(VERSION, bytes);
+        // 無いセクションは書き出さない
+        fn section<T: BinaryEncode>(s: &Option<T>, bytes: &mut Vec<u8>) {
+            if let Some(s) = s {
+                s.encode(bytes);
+            }
+        }
+        section(&self.type_section, bytes);
+        section(&self.import_section, bytes);
+        section(&self.function_section, bytes);
+        section(&self.table_section, bytes);
+        section(&self.memory_section, bytes);
+        section(&self.global_section, bytes);
+        section(&self.export_section, bytes);
+        section(&self.start_section, bytes);
+        section(&self.element_section, bytes);
+        section(&self.code_section, bytes);
+        section(&self.data_section, bytes);
+    }
+}
+
+impl WasmASTRoot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<T: BinaryEncode>(x: &T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        x.encode(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn operator_code_test() {
+        use OperatorCode::*;
+        let cases = vec![
+            (Block(BlockType(None)), vec![0x02, 0x40]),
+            (If(BlockType(Some(ValueType::F64))), vec![0x04, 0x7c]),
+            (Br(1), vec![0x0c, 0x01]),
+            (
+                BrTable {
+                    index: 2,
+                    params: vec![0, 1],
+                },
+                vec![0x0e, 0x02, 0x00, 0x01, 0x02],
+            ),
+            (Call(300), vec![0x10, 0xac, 0x02]),
+            (CallIndirect(1), vec![0x11, 0x01, 0x00]),
+            (
+                I64Load32u(MemoryImmediate {
+                    flags: 2,
+                    offset: 128,
+                }),
+                vec![0x35, 0x02, 0x80, 0x01],
+            ),
+            (GrowMemory, vec![0x40, 0x00]),
+            (I32Const(-1), vec![0x41, 0x7f]),
+            (I32Const(64), vec![0x41, 0xc0, 0x00]),
+            (I32Const(-128), vec![0x41, 0x80, 0x7f]),
+            (I64Const(i64::MIN), {
+                let mut v = vec![0x42];
+                v.extend(vec![0x80; 9]);
+                v.push(0x7f);
+                v
+            }),
+            (F32Const(1.0), vec![0x43, 0x00, 0x00, 0x80, 0x3f]),
+            (
+                F64Const(-2.0),
+                vec![0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0],
+            ),
+            (I32Geu, vec![0x4f]),
+            (F64ReinterpretI64, vec![0xbf]),
+        ];
+        for (op, expected) in cases {
+            assert_eq!(encode(&op), expected, "{:?}", op);
+        }
+    }
+
+    // (func (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)
+    #[test]
+    fn add_test() {
+        use OperatorCode::*;
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![ValueType::I32, ValueType::I32],
+                result: Some(ValueType::I32),
+            }])),
+            function_section: Some(FunctionSection(vec![0])),
+            export_section: Some(ExportSection(vec![ExportEntry {
+                field: "add".to_string(),
+                kind: ExternalKind::Function,
+                index: 0,
+            }])),
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: vec![],
+                codes: vec![GetLocal(0), GetLocal(1), I32Add, End],
+            }])),
+            ..WasmASTRoot::default()
+        };
+        #[rustfmt::skip]
+        let expected = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f,
+            0x03, 0x02, 0x01, 0x00,
+            0x07, 0x07, 0x01, 0x03, 0x61, 0x64, 0x64, 0x00, 0x00,
+            0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b,
+        ];
+        assert_eq!(root.to_bytes(), expected);
+    }
+
+    #[test]
+    fn sections_test() {
+        use OperatorCode::*;
+        let root = WasmASTRoot {
+            type_section: Some(TypeSection(vec![FuncType {
+                params: vec![],
+                result: None,
+            }])),
+            import_section: Some(ImportSection(vec![ImportEntry {
+                module: "env".to_string(),
+                field: "f".to_string(),
+                kind: ExternalKindImport::Function(0),
+            }])),
+            function_section: Some(FunctionSection(vec![0])),
+            table_section: Some(TableSection(vec![TableType {
+                element_type: ElemType::AnyFunc,
+                limits: ResizableLimits {
+                    initial: 1,
+                    maximum: Some(1),
+                },
+            }])),
+            memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 1,
+                maximum: None,
+            })])),
+            global_section: Some(GlobalSection(vec![GlobalVariable(
+                GlobalType {
+                    content_type: ValueType::I32,
+                    mutability: true,
+                },
+                InitExpr::I32(8),
+            )])),
+            export_section: Some(ExportSection(vec![ExportEntry {
+                field: "memory".to_string(),
+                kind: ExternalKind::Memory,
+                index: 0,
+            }])),
+            start_section: Some(StartSection(1)),
+            element_section: Some(ElementSection(vec![ElemSegment {
+                offset: InitExpr::I32(0),
+                elems: vec![1],
+            }])),
+            code_section: Some(CodeSection(vec![FunctionBody {
+                locals: vec![LocalEntry {
+                    count: 2,
+                    typ: ValueType::I64,
+                }],
+                codes: vec![Call(0), End],
+            }])),
+            data_section: Some(DataSection(vec![DataSegment {
+                offset: InitExpr::I32(8),
+                data: b"hi".to_vec(),
+            }])),
+        };
+        #[rustfmt::skip]
+        let expected = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+            0x02, 0x09, 0x01, 0x03, 0x65, 0x6e, 0x76, 0x01, 0x66, 0x00, 0x00,
+            0x03, 0x02, 0x01, 0x00,
+            0x04, 0x05, 0x01, 0x70, 0x01, 0x01, 0x01,
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x08, 0x0b,
+            0x07, 0x0a, 0x01, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00,
+            0x08, 0x01, 0x01,
+            0x09, 0x07, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x01, 0x01,
+            0x0a, 0x08, 0x01, 0x06, 0x01, 0x02, 0x7e, 0x10, 0x00, 0x0b,
+            0x0b, 0x08, 0x01, 0x00, 0x41, 0x08, 0x0b, 0x02, 0x68, 0x69,
+        ];
+        assert_eq!(root.to_bytes(), expected);
+    }
+
+    // 中身が128バイト以上のセクションは長さが2バイトになる
+    #[test]
+    fn section_size_test() {
+        let section = DataSection(vec![DataSegment {
+            offset: InitExpr::Global(0),
+            data: vec![0xaa; 200],
+        }]);
+        let bytes = encode(&section);
+        assert_eq!(
+            bytes[..9],
+            [0x0b, 0xcf, 0x01, 0x01, 0x00, 0x23, 0x00, 0x0b, 0xc8]
+        );
+        assert_eq!(bytes[9..11], [0x01, 0xaa]);
+        assert_eq!(bytes.len(), 3 + 207);
+    }
+}
@@ -0,0 +1,1013 @@
+use crate::ast::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const PAGE_SIZE: usize = 65536;
+const MAX_PAGES: u32 = 65536;
+// 呼び出しの深さの上限
+const MAX_DEPTH: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Val {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Val {
+    pub fn zero(t: &ValueType) -> Val {
+        match t {
+            ValueType::I32 => Val::I32(0),
+            ValueType::I64 => Val::I64(0),
+            ValueType::F32 => Val::F32(0.0),
+            ValueType::F64 => Val::F64(0.0),
+        }
+    }
+
+    // 検証済みのモジュールでは型が合う
+    fn i32(self) -> i32 {
+        match self {
+            Val::I32(x) => x,
+            v => panic!("expected i32: {:?}", v),
+        }
+    }
+
+    fn i64(self) -> i64 {
+        match self {
+            Val::I64(x) => x,
+            v => panic!("expected i64: {:?}", v),
+        }
+    }
+
+    fn f32(self) -> f32 {
+        match self {
+            Val::F32(x) => x,
+            v => panic!("expected f32: {:?}", v),
+        }
+    }
+
+    fn f64(self) -> f64 {
+        match self {
+            Val::F64(x) => x,
+            v => panic!("expected f64: {:?}", v),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    MemoryOutOfBounds,
+    DivisionByZero,
+    IntegerOverflow,
+    InvalidConversion,
+    UndefinedElement,
+    IndirectCallTypeMismatch,
+    StackOverflow,
+    UnknownImport(String, String),
+    UnknownExport(String),
+}
+
+// 線形メモリと引数を受け取る
+pub type HostFunc = Box<dyn FnMut(&mut [u8], &[Val]) -> Option<Val>>;
+
+#[derive(Default)]
+pub struct Imports {
+    funcs: HashMap<(String, String), HostFunc>,
+}
+
+impl Imports {
+    pub fn new() -> Imports {
+        Imports::default()
+    }
+
+    pub fn register<F: FnMut(&mut [u8], &[Val]) -> Option<Val> + 'static>(
+        &mut self,
+        module: &str,
+        field: &str,
+        f: F,
+    ) {
+        self.funcs
+            .insert((module.to_string(), field.to_string()), Box::new(f));
+    }
+}
+
+// 構造化制御命令の対応するElseとEndの位置
+struct Code<'a> {
+    body: &'a FunctionBody,
+    ends: HashMap<usize, usize>,
+    elses: HashMap<usize, usize>,
+}
+
+impl<'a> Code<'a> {
+    fn new(body: &'a FunctionBody) -> Code<'a> {
+        let mut ends = HashMap::new();
+        let mut elses = HashMap::new();
+        let mut starts = Vec::new();
+        for (pc, op) in body.codes.iter().enumerate() {
+            match op {
+                OperatorCode::Block(_) | OperatorCode::Loop(_) | OperatorCode::If(_) => {
+                    starts.push(pc)
+                }
+                OperatorCode::Else => {
+                    if let Some(&start) = starts.last() {
+                        elses.insert(start, pc);
+                    }
+                }
+                OperatorCode::End => {
+                    if let Some(start) = starts.pop() {
+                        ends.insert(start, pc);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Code { body, ends, elses }
+    }
+}
+
+enum Callee<'a> {
+    Host(HostFunc),
+    Wasm(Rc<Code<'a>>),
+}
+
+#[derive(Clone, Copy)]
+struct Label {
+    is_loop: bool,
+    start: usize,
+    end: usize,
+    // 分岐で持ち出す値の個数
+    arity: usize,
+    height: usize,
+}
+
+fn init_value(e: &InitExpr, globals: &[Val]) -> Val {
+    match e {
+        InitExpr::I32(x) => Val::I32(*x),
+        InitExpr::I64(x) => Val::I64(*x),
+        InitExpr::F32(x) => Val::F32(*x),
+        InitExpr::F64(x) => Val::F64(*x),
+        InitExpr::Global(i) => globals[*i],
+    }
+}
+
+// NaNと範囲外はトラップする
+fn trunc(x: f64, min: f64, max: f64) -> Result<f64, Trap> {
+    if x.is_nan() {
+        return Err(Trap::InvalidConversion);
+    }
+    let x = x.trunc();
+    if x < min || x >= max {
+        Err(Trap::IntegerOverflow)
+    } else {
+        Ok(x)
+    }
+}
+
+// wasmのmin/maxはNaNを伝播し、-0を+0より小さいとみなす
+fn fmin(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.min(b)
+    }
+}
+
+fn fmax(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_positive() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.max(b)
+    }
+}
+
+macro_rules! unary {
+    ($stack:expr, $get:ident, |$a:ident| $e:expr) => {{
+        let $a = $stack.pop().unwrap().$get();
+        $stack.push($e);
+    }};
+}
+
+macro_rules! binary {
+    ($stack:expr, $get:ident, |$a:ident, $b:ident| $e:expr) => {{
+        let $b = $stack.pop().unwrap().$get();
+        let $a = $stack.pop().unwrap().$get();
+        $stack.push($e);
+    }};
+}
+
+fn bool(b: bool) -> Val {
+    Val::I32(b as i32)
+}
+
+fn i32_div(a: i32, b: i32) -> Result<i32, Trap> {
+    match b {
+        0 => Err(Trap::DivisionByZero),
+        _ => a.checked_div(b).ok_or(Trap::IntegerOverflow),
+    }
+}
+
+fn i64_div(a: i64, b: i64) -> Result<i64, Trap> {
+    match b {
+        0 => Err(Trap::DivisionByZero),
+        _ => a.checked_div(b).ok_or(Trap::IntegerOverflow),
+    }
+}
+
+// 制御命令とメモリ命令以外
+fn numeric(op: &OperatorCode, stack: &mut Vec<Val>) -> Result<(), Trap> {
+    use OperatorCode::*;
+    match op {
+        I32Const(x) => stack.push(Val::I32(*x)),
+        I64Const(x) => stack.push(Val::I64(*x)),
+        F32Const(x) => stack.push(Val::F32(*x)),
+        F64Const(x) => stack.push(Val::F64(*x)),
+        I32Eqz => unary!(stack, i32, |a| bool(a == 0)),
+        I32Eq => binary!(stack, i32, |a, b| bool(a == b)),
+        I32Ne => binary!(stack, i32, |a, b| bool(a != b)),
+        I32Lts => binary!(stack, i32, |a, b| bool(a < b)),
+        I32Ltu => binary!(stack, i32, |a, b| bool((a as u32) < b as u32)),
+        I32Gts => binary!(stack, i32, |a, b| bool(a > b)),
+        I32Gtu => binary!(stack, i32, |a, b| bool(a as u32 > b as u32)),
+        I32Les => binary!(stack, i32, |a, b| bool(a <= b)),
+        I32Leu => binary!(stack, i32, |a, b| bool(a as u32 <= b as u32)),
+        I32Ges => binary!(stack, i32, |a, b| bool(a >= b)),
+        I32Geu => binary!(stack, i32, |a, b| bool(a as u32 >= b as u32)),
+        I64Eqz => unary!(stack, i64, |a| bool(a == 0)),
+        I64Eq => binary!(stack, i64, |a, b| bool(a == b)),
+        I64Ne => binary!(stack, i64, |a, b| bool(a != b)),
+        I64Lts => binary!(stack, i64, |a, b| bool(a < b)),
+        I64Ltu => binary!(stack, i64, |a, b| bool((a as u64) < b as u64)),
+        I64Gts => binary!(stack, i64, |a, b| bool(a > b)),
+        I64Gtu => binary!(stack, i64, |a, b| bool(a as u64 > b as u64)),
+        I64Les => binary!(stack, i64, |a, b| bool(a <= b)),
+        I64Leu => binary!(stack, i64, |a, b| bool(a as u64 <= b as u64)),
+        I64Ges => binary!(stack, i64, |a, b| bool(a >= b)),
+        I64Geu => binary!(stack, i64, |a, b| bool(a as u64 >= b as u64)),
+        F32Eq => binary!(stack, f32, |a, b| bool(a == b)),
+        F32Ne => binary!(stack, f32, |a, b| bool(a != b)),
+        F32Lt => binary!(stack, f32, |a, b| bool(a < b)),
+        F32Gt => binary!(stack, f32, |a, b| bool(a > b)),
+        F32Le => binary!(stack, f32, |a, b| bool(a <= b)),
+        F32Ge => binary!(stack, f32, |a, b| bool(a >= b)),
+        F64Eq => binary!(stack, f64, |a, b| bool(a == b)),
+        F64Ne => binary!(stack, f64, |a, b| bool(a != b)),
+        F64Lt => binary!(stack, f64, |a, b| bool(a < b)),
+        F64Gt => binary!(stack, f64, |a, b| bool(a > b)),
+        F64Le => binary!(stack, f64, |a, b| bool(a <= b)),
+        F64Ge => binary!(stack, f64, |a, b| bool(a >= b)),
+        I32Clz => unary!(stack, i32, |a| Val::I32(a.leading_zeros() as i32)),
+        I32Ctz => unary!(stack, i32, |a| Val::I32(a.trailing_zeros() as i32)),
+        I32Popcnt => unary!(stack, i32, |a| Val::I32(a.count_ones() as i32)),
+        I32Add => binary!(stack, i32, |a, b| Val::I32(a.wrapping_add(b))),
+        I32Sub => binary!(stack, i32, |a, b| Val::I32(a.wrapping_sub(b))),
+        I32Mul => binary!(stack, i32, |a, b| Val::I32(a.wrapping_mul(b))),
+        I32Divs => binary!(stack, i32, |a, b| Val::I32(i32_div(a, b)?)),
+        I32Divu => binary!(stack, i32, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I32((a as u32 / b as u32) as i32),
+        }),
+        I32Rems => binary!(stack, i32, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I32(a.wrapping_rem(b)),
+        }),
+        I32Remu => binary!(stack, i32, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I32((a as u32 % b as u32) as i32),
+        }),
+        I32And => binary!(stack, i32, |a, b| Val::I32(a & b)),
+        I32Or => binary!(stack, i32, |a, b| Val::I32(a | b)),
+        I32Xor => binary!(stack, i32, |a, b| Val::I32(a ^ b)),
+        I32Shl => binary!(stack, i32, |a, b| Val::I32(a.wrapping_shl(b as u32))),
+        I32Shrs => binary!(stack, i32, |a, b| Val::I32(a.wrapping_shr(b as u32))),
+        I32Shru => binary!(stack, i32, |a, b| Val::I32(
+            (a as u32).wrapping_shr(b as u32) as i32
+        )),
+        I32Rotl => binary!(stack, i32, |a, b| Val::I32(a.rotate_left(b as u32 % 32))),
+        I32Rotr => binary!(stack, i32, |a, b| Val::I32(a.rotate_right(b as u32 % 32))),
+        I64Clz => unary!(stack, i64, |a| Val::I64(a.leading_zeros() as i64)),
+        I64Ctz => unary!(stack, i64, |a| Val::I64(a.trailing_zeros() as i64)),
+        I64Popcnt => unary!(stack, i64, |a| Val::I64(a.count_ones() as i64)),
+        I64Add => binary!(stack, i64, |a, b| Val::I64(a.wrapping_add(b))),
+        I64Sub => binary!(stack, i64, |a, b| Val::I64(a.wrapping_sub(b))),
+        I64Mul => binary!(stack, i64, |a, b| Val::I64(a.wrapping_mul(b))),
+        I64Divs => binary!(stack, i64, |a, b| Val::I64(i64_div(a, b)?)),
+        I64Divu => binary!(stack, i64, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I64((a as u64 / b as u64) as i64),
+        }),
+        I64Rems => binary!(stack, i64, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I64(a.wrapping_rem(b)),
+        }),
+        I64Remu => binary!(stack, i64, |a, b| match b {
+            0 => return Err(Trap::DivisionByZero),
+            _ => Val::I64((a as u64 % b as u64) as i64),
+        }),
+        I64And => binary!(stack, i64, |a, b| Val::I64(a & b)),
+        I64Or => binary!(stack, i64, |a, b| Val::I64(a | b)),
+        I64Xor => binary!(stack, i64, |a, b| Val::I64(a ^ b)),
+        I64Shl => binary!(stack, i64, |a, b| Val::I64(a.wrapping_shl(b as u32))),
+        I64Shrs => binary!(stack, i64, |a, b| Val::I64(a.wrapping_shr(b as u32))),
+        I64Shru => binary!(stack, i64, |a, b| Val::I64(
+            (a as u64).wrapping_shr(b as u32) as i64
+        )),
+        I64Rotl => binary!(stack, i64, |a, b| Val::I64(a.rotate_left((b % 64) as u32))),
+        I64Rotr => binary!(stack, i64, |a, b| Val::I64(a.rotate_right((b % 64) as u32))),
+        F32Abs => unary!(stack, f32, |a| Val::F32(a.abs())),
+        F32Neg => unary!(stack, f32, |a| Val::F32(-a)),
+        F32Ceil => unary!(stack, f32, |a| Val::F32(a.ceil())),
+        F32Floor => unary!(stack, f32, |a| Val::F32(a.floor())),
+        F32Trunc => unary!(stack, f32, |a| Val::F32(a.trunc())),
+        F32Nearest => unary!(stack, f32, |a| Val::F32(a.round_ties_even())),
+        F32Sqrt => unary!(stack, f32, |a| Val::F32(a.sqrt())),
+        F32Add => binary!(stack, f32, |a, b| Val::F32(a + b)),
+        F32Sub => binary!(stack, f32, |a, b| Val::F32(a - b)),
+        F32Mul => binary!(stack, f32, |a, b| Val::F32(a * b)),
+        F32Div => binary!(stack, f32, |a, b| Val::F32(a / b)),
+        F32Min => binary!(stack, f32, |a, b| Val::F32(fmin(a as f64, b as f64) as f32)),
+        F32Max => binary!(stack, f32, |a, b| Val::F32(fmax(a as f64, b as f64) as f32)),
+        F32Copysign => binary!(stack, f32, |a, b| Val::F32(a.copysign(b))),
+        F64Abs => unary!(stack, f64, |a| Val::F64(a.abs())),
+        F64Neg => unary!(stack, f64, |a| Val::F64(-a)),
+        F64Ceil => unary!(stack, f64, |a| Val::F64(a.ceil())),
+        F64Floor => unary!(stack, f64, |a| Val::F64(a.floor())),
+        F64Trunc => unary!(stack, f64, |a| Val::F64(a.trunc())),
+        F64Nearest => unary!(stack, f64, |a| Val::F64(a.round_ties_even())),
+        F64Sqrt => unary!(stack, f64, |a| Val::F64(a.sqrt())),
+        F64Add => binary!(stack, f64, |a, b| Val::F64(a + b)),
+        F64Sub => binary!(stack, f64, |a, b| Val::F64(a - b)),
+        F64Mul => binary!(stack, f64, |a, b| Val::F64(a * b)),
+        F64Div => binary!(stack, f64, |a, b| Val::F64(a / b)),
+        F64Min => binary!(stack, f64, |a, b| Val::F64(fmin(a, b))),
+        F64Max => binary!(stack, f64, |a, b| Val::F64(fmax(a, b))),
+        F64Copysign => binary!(stack, f64, |a, b| Val::F64(a.copysign(b))),
+        I32WrapI64 => unary!(stack, i64, |a| Val::I32(a as i32)),
+        I32TruncsF32 => {
+            unary!(stack, f32, |a| Val::I32(
+                trunc(a as f64, -2147483648.0, 2147483648.0)? as i32
+            ))
+        }
+        I32TrancuF32 => unary!(stack, f32, |a| Val::I32(
+            trunc(a as f64, 0.0, 4294967296.0)? as u32 as i32
+        )),
+        I32TrancsF64 => unary!(stack, f64, |a| Val::I32(
+            trunc(a, -2147483648.0, 2147483648.0)? as i32
+        )),
+        I32TrancuF64 => unary!(stack, f64, |a| Val::I32(
+            trunc(a, 0.0, 4294967296.0)? as u32 as i32
+        )),
+        I64ExtendsI32 => unary!(stack, i32, |a| Val::I64(a as i64)),
+        I64ExtenduI32 => unary!(stack, i32, |a| Val::I64(a as u32 as i64)),
+        I64TruncsF32 => unary!(stack, f32, |a| Val::I64(trunc(
+            a as f64,
+            -9223372036854775808.0,
+            9223372036854775808.0
+        )? as i64)),
+        I64TrancuF32 => {
+            unary!(stack, f32, |a| Val::I64(
+                trunc(a as f64, 0.0, 18446744073709551616.0)? as u64 as i64
+            ))
+        }
+        I64TrancsF64 => {
+            unary!(stack, f64, |a| Val::I64(
+                trunc(a, -9223372036854775808.0, 9223372036854775808.0)? as i64
+            ))
+        }
+        I64TrancuF64 => unary!(stack, f64, |a| Val::I64(
+            trunc(a, 0.0, 18446744073709551616.0)? as u64 as i64
+        )),
+        F32ConvertsI32 => unary!(stack, i32, |a| Val::F32(a as f32)),
+        F32ConvertuI32 => unary!(stack, i32, |a| Val::F32(a as u32 as f32)),
+        F32ConvertsI64 => unary!(stack, i64, |a| Val::F32(a as f32)),
+        F32ConvertuI64 => unary!(stack, i64, |a| Val::F32(a as u64 as f32)),
+        F32DemoteF64 => unary!(stack, f64, |a| Val::F32(a as f32)),
+        F64ConvertsI32 => unary!(stack, i32, |a| Val::F64(a as f64)),
+        F64ConvertuI32 => unary!(stack, i32, |a| Val::F64(a as u32 as f64)),
+        F64ConvertsI64 => unary!(stack, i64, |a| Val::F64(a as f64)),
+        F64ConvertuI64 => unary!(stack, i64, |a| Val::F64(a as u64 as f64)),
+        F64PromoteF32 => unary!(stack, f32, |a| Val::F64(a as f64)),
+        I32ReinterpretF32 => unary!(stack, f32, |a| Val::I32(a.to_bits() as i32)),
+        I64ReinterpretF64 => unary!(stack, f64, |a| Val::I64(a.to_bits() as i64)),
+        F32ReinterpretI32 => unary!(stack, i32, |a| Val::F32(f32::from_bits(a as u32))),
+        F64ReinterpretI64 => unary!(stack, i64, |a| Val::F64(f64::from_bits(a as u64))),
+        op => unreachable!("{:?}", op),
+    }
+    Ok(())
+}
+
+pub struct Instance<'a> {
+    types: &'a [FuncType],
+    // インポートした関数が先
+    funcs: Vec<(usize, Callee<'a>)>,
+    exports: &'a [ExportEntry],
+    table: Vec<Option<usize>>,
+    globals: Vec<Val>,
+    memory: Vec<u8>,
+    max_pages: u32,
+    depth: usize,
+}
+
+impl<'a> Instance<'a> {
+    // データとエレメントを配置してからstart関数を実行する
+    pub fn new(module: &'a WasmASTRoot, mut imports: Imports) -> Result<Instance<'a>, Trap> {
+        let types = module.type_section.as_ref().map_or(&[][..], |s| &s.0[..]);
+        let mut funcs = Vec::new();
+        for entry in module.import_section.iter().flat_map(|s| &s.0) {
+            if let ExternalKindImport::Function(t) = entry.kind {
+                let key = (entry.module.clone(), entry.field.clone());
+                match imports.funcs.remove(&key) {
+                    Some(f) => funcs.push((t, Callee::Host(f))),
+                    None => return Err(Trap::UnknownImport(key.0, key.1)),
+                }
+            }
+        }
+        let sigs = module.function_section.iter().flat_map(|s| &s.0);
+        let bodies = module.code_section.iter().flat_map(|s| &s.0);
+        for (&t, body) in sigs.zip(bodies) {
+            funcs.push((t, Callee::Wasm(Rc::new(Code::new(body)))));
+        }
+
+        let mut globals = Vec::new();
+        for GlobalVariable(_, init) in module.global_section.iter().flat_map(|s| &s.0) {
+            let v = init_value(init, &globals);
+            globals.push(v);
+        }
+
+        let limits = module
+            .memory_section
+            .as_ref()
+            .and_then(|s| s.0.first())
+            .map(|MemoryType(limits)| limits);
+        let memory = vec![0; limits.map_or(0, |l| l.initial as usize) * PAGE_SIZE];
+        let max_pages = limits
+            .and_then(|l| l.maximum)
+            .map_or(MAX_PAGES, |m| m as u32);
+
+        let size = module
+            .table_section
+            .as_ref()
+            .and_then(|s| s.0.first())
+            .map_or(0, |t| t.limits.initial as usize);
+        let mut table = vec![None; size];
+        for seg in module.element_section.iter().flat_map(|s| &s.0) {
+            let offset = init_value(&seg.offset, &globals).i32() as u32 as usize;
+            if offset + seg.elems.len() > table.len() {
+                return Err(Trap::UndefinedElement);
+            }
+            for (i, &f) in seg.elems.iter().enumerate() {
+                table[offset + i] = Some(f);
+            }
+        }
+
+        let mut instance = Instance {
+            types,
+            funcs,
+            exports: module.export_section.as_ref().map_or(&[][..], |s| &s.0[..]),
+            table,
+            globals,
+            memory,
+            max_pages,
+            depth: 0,
+        };
+        for seg in module.data_section.iter().flat_map(|s| &s.0) {
+            let offset = init_value(&seg.offset, &instance.globals).i32() as u32 as usize;
+            match instance.memory.get_mut(offset..offset + seg.data.len()) {
+                Some(dst) => dst.copy_from_slice(&seg.data),
+                None => return Err(Trap::MemoryOutOfBounds),
+            }
+        }
+        if let Some(StartSection(f)) = module.start_section {
+            instance.call(f, Vec::new())?;
+        }
+        Ok(instance)
+    }
+
+    pub fn invoke(&mut self, name: &str, args: &[Val]) -> Result<Option<Val>, Trap> {
+        let f = self
+            .exports
+            .iter()
+            .find(|e| e.field == name && e.kind == ExternalKind::Function)
+            .ok_or_else(|| Trap::UnknownExport(name.to_string()))?
+            .index;
+        self.call(f, args.to_vec())
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn globals(&self) -> &[Val] {
+        &self.globals
+    }
+
+    fn call(&mut self, f: usize, args: Vec<Val>) -> Result<Option<Val>, Trap> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Trap::StackOverflow);
+        }
+        let t = self.funcs[f].0;
+        let code = match &mut self.funcs[f].1 {
+            Callee::Host(host) => return Ok(host(&mut self.memory, &args)),
+            Callee::Wasm(code) => code.clone(),
+        };
+        let mut locals = args;
+        for entry in &code.body.locals {
+            locals.extend(std::iter::repeat_n(Val::zero(&entry.typ), entry.count));
+        }
+        let arity = self.types[t].result.is_some() as usize;
+        self.depth += 1;
+        let res = self.exec(&code, locals, arity);
+        self.depth -= 1;
+        res
+    }
+
+    fn addr(&self, base: Val, imm: &MemoryImmediate, size: usize) -> Result<usize, Trap> {
+        let addr = base.i32() as u32 as u64 + imm.offset as u64;
+        if addr + size as u64 > self.memory.len() as u64 {
+            Err(Trap::MemoryOutOfBounds)
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    fn load<const N: usize>(&self, base: Val, imm: &MemoryImmediate) -> Result<[u8; N], Trap> {
+        let addr = self.addr(base, imm, N)?;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.memory[addr..addr + N]);
+        Ok(bytes)
+    }
+
+    fn store(&mut self, base: Val, imm: &MemoryImmediate, bytes: &[u8]) -> Result<(), Trap> {
+        let addr = self.addr(base, imm, bytes.len())?;
+        self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn memory_op(&mut self, op: &OperatorCode, stack: &mut Vec<Val>) -> Result<(), Trap> {
+        use OperatorCode::*;
+        match op {
+            I32Load(m) | I64Load(m) | F32Load(m) | F64Load(m) | I32Load8s(m) | I32Load8u(m)
+            | I32Load16s(m) | I32Load16u(m) | I64Load8s(m) | I64Load8u(m) | I64Load16s(m)
+            | I64Load16u(m) | I64Load32s(m) | I64Load32u(m) => {
+                let base = stack.pop().unwrap();
+                let v = match op {
+                    I32Load(_) => Val::I32(i32::from_le_bytes(self.load(base, m)?)),
+                    I64Load(_) => Val::I64(i64::from_le_bytes(self.load(base, m)?)),
+                    F32Load(_) => Val::F32(f32::from_le_bytes(self.load(base, m)?)),
+                    F64Load(_) => Val::F64(f64::from_le_bytes(self.load(base, m)?)),
+                    I32Load8s(_) => Val::I32(i8::from_le_bytes(self.load(base, m)?) as i32),
+                    I32Load8u(_) => Val::I32(u8::from_le_bytes(self.load(base, m)?) as i32),
+                    I32Load16s(_) => Val::I32(i16::from_le_bytes(self.load(base, m)?) as i32),
+                    I32Load16u(_) => Val::I32(u16::from_le_bytes(self.load(base, m)?) as i32),
+                    I64Load8s(_) => Val::I64(i8::from_le_bytes(self.load(base, m)?) as i64),
+                    I64Load8u(_) => Val::I64(u8::from_le_bytes(self.load(base, m)?) as i64),
+                    I64Load16s(_) => Val::I64(i16::from_le_bytes(self.load(base, m)?) as i64),
+                    I64Load16u(_) => Val::I64(u16::from_le_bytes(self.load(base, m)?) as i64),
+                    I64Load32s(_) => Val::I64(i32::from_le_bytes(self.load(base, m)?) as i64),
+                    _ => Val::I64(u32::from_le_bytes(self.load(base, m)?) as i64),
+                };
+                stack.push(v);
+            }
+            I32Store(m) | I64Store(m) | F32Store(m) | F64Store(m) | I32Store8(m)
+            | I32Store16(m) | I64Store8(m) | I64Store16(m) | I64Store32(m) => {
+                let v = stack.pop().unwrap();
+                let base = stack.pop().unwrap();
+                match op {
+                    I32Store(_) => self.store(base, m, &v.i32().to_le_bytes())?,
+                    I64Store(_) => self.store(base, m, &v.i64().to_le_bytes())?,
+                    F32Store(_) => self.store(base, m, &v.f32().to_le_bytes())?,
+                    F64Store(_) => self.store(base, m, &v.f64().to_le_bytes())?,
+                    I32Store8(_) => self.store(base, m, &[v.i32() as u8])?,
+                    I32Store16(_) => self.store(base, m, &(v.i32() as u16).to_le_bytes())?,
+                    I64Store8(_) => self.store(base, m, &[v.i64() as u8])?,
+                    I64Store16(_) => self.store(base, m, &(v.i64() as u16).to_le_bytes())?,
+                    _ => self.store(base, m, &(v.i64() as u32).to_le_bytes())?,
+                }
+            }
+            CurrentMemory => stack.push(Val::I32((self.memory.len() / PAGE_SIZE) as i32)),
+            GrowMemory => {
+                let delta = stack.pop().unwrap().i32() as u32;
+                let pages = (self.memory.len() / PAGE_SIZE) as u32;
+                match pages.checked_add(delta) {
+                    Some(n) if n <= self.max_pages => {
+                        self.memory.resize(n as usize * PAGE_SIZE, 0);
+                        stack.push(Val::I32(pages as i32));
+                    }
+                    _ => stack.push(Val::I32(-1)),
+                }
+            }
+            op => return numeric(op, stack),
+        }
+        Ok(())
+    }
+
+    fn call_op(&mut self, f: usize, stack: &mut Vec<Val>) -> Result<(), Trap> {
+        let n = self.types[self.funcs[f].0].params.len();
+        let args = stack.split_off(stack.len() - n);
+        if let Some(v) = self.call(f, args)? {
+            stack.push(v);
+        }
+        Ok(())
+    }
+
+    // 分岐先のラベルまで値スタックとラベルを巻き戻す
+    fn branch(labels: &mut Vec<Label>, stack: &mut Vec<Val>, depth: usize) -> usize {
+        let i = labels.len() - 1 - depth;
+        let label = labels[i];
+        let arity = if label.is_loop { 0 } else { label.arity };
+        let values = stack.split_off(stack.len() - arity);
+        stack.truncate(label.height);
+        stack.extend(values);
+        if label.is_loop {
+            labels.truncate(i + 1);
+            label.start + 1
+        } else {
+            labels.truncate(i);
+            label.end + 1
+        }
+    }
+
+    fn exec(
+        &mut self,
+        code: &Code,
+        mut locals: Vec<Val>,
+        arity: usize,
+    ) -> Result<Option<Val>, Trap> {
+        use OperatorCode::*;
+        let codes = &code.body.codes;
+        let mut stack: Vec<Val> = Vec::new();
+        // 関数本体を囲むラベル
+        let mut labels = vec![Label {
+            is_loop: false,
+            start: 0,
+            end: codes.len(),
+            arity,
+            height: 0,
+        }];
+        let mut pc = 0;
+        while pc < codes.len() {
+            let op = &codes[pc];
+            pc += 1;
+            match op {
+                Unreachable => return Err(Trap::Unreachable),
+                Nop => {}
+                Block(t) | Loop(t) | If(t) => {
+                    let start = pc - 1;
+                    let end = code.ends[&start];
+                    if let If(_) = op {
+                        if stack.pop().unwrap().i32() == 0 {
+                            pc = code.elses.get(&start).map_or(end, |&e| e + 1);
+                        }
+                    }
+                    labels.push(Label {
+                        is_loop: matches!(op, Loop(_)),
+                        start,
+                        end,
+                        arity: t.0.is_some() as usize,
+                        height: stack.len(),
+                    });
+                }
+                // then節の終わりからEndへ飛ぶ
+                Else => pc = labels.last().unwrap().end,
+                End => {
+                    labels.pop();
+                }
+                Br(n) => pc = Self::branch(&mut labels, &mut stack, *n),
+                BrIf(n) => {
+                    if stack.pop().unwrap().i32() != 0 {
+                        pc = Self::branch(&mut labels, &mut stack, *n);
+                    }
+                }
+                BrTable { index, params } => {
+                    let i = stack.pop().unwrap().i32() as u32 as usize;
+                    let n = *params.get(i).unwrap_or(index);
+                    pc = Self::branch(&mut labels, &mut stack, n);
+                }
+                Return => {
+                    let depth = labels.len() - 1;
+                    pc = Self::branch(&mut labels, &mut stack, depth);
+                }
+                Call(f) => self.call_op(*f, &mut stack)?,
+                CallIndirect(t) => {
+                    let i = stack.pop().unwrap().i32() as u32 as usize;
+                    let f = self
+                        .table
+                        .get(i)
+                        .cloned()
+                        .flatten()
+                        .ok_or(Trap::UndefinedElement)?;
+                    if self.types[self.funcs[f].0] != self.types[*t] {
+                        return Err(Trap::IndirectCallTypeMismatch);
+                    }
+                    self.call_op(f, &mut stack)?;
+                }
+                Drop => {
+                    stack.pop();
+                }
+                Select => {
+                    let c = stack.pop().unwrap().i32();
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(if c != 0 { a } else { b });
+                }
+                GetLocal(i) => stack.push(locals[*i]),
+                SetLocal(i) => locals[*i] = stack.pop().unwrap(),
+                TeeLocal(i) => locals[*i] = *stack.last().unwrap(),
+                GetGlobal(i) => stack.push(self.globals[*i]),
+                SetGlobal(i) => self.globals[*i] = stack.pop().unwrap(),
+                op => self.memory_op(op, &mut stack)?,
+            }
+        }
+        Ok(if arity == 1 { stack.pop() } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use OperatorCode::*;
+
+    fn module(types: Vec<FuncType>, funcs: Vec<(usize, Vec<OperatorCode>)>) -> WasmASTRoot {
+        WasmASTRoot {
+            type_section: Some(TypeSection(types)),
+            function_section: Some(FunctionSection(funcs.iter().map(|f| f.0).collect())),
+            memory_section: Some(MemorySection(vec![MemoryType(ResizableLimits {
+                initial: 1,
+                maximum: Some(2),
+            })])),
+            export_section: Some(ExportSection(
+                (0..funcs.len())
+                    .map(|i| ExportEntry {
+                        field: format!("f{}", i),
+                        kind: ExternalKind::Function,
+                        index: i,
+                    })
+                    .collect(),
+            )),
+            code_section: Some(CodeSection(
+                funcs
+                    .into_iter()
+                    .map(|(_, codes)| FunctionBody {
+                        locals: vec![LocalEntry {
+                            count: 1,
+                            typ: ValueType::I32,
+                        }],
+                        codes,
+                    })
+                    .collect(),
+            )),
+            ..WasmASTRoot::default()
+        }
+    }
+
+    fn i32_to_i32() -> FuncType {
+        FuncType {
+            params: vec![ValueType::I32],
+            result: Some(ValueType::I32),
+        }
+    }
+
+    fn mem() -> MemoryImmediate {
+        MemoryImmediate {
+            flags: 2,
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn control_test() {
+        let m = module(
+            vec![i32_to_i32()],
+            vec![
+                // 再帰による階乗
+                (
+                    0,
+                    vec![
+                        GetLocal(0),
+                        I32Eqz,
+                        If(BlockType(Some(ValueType::I32))),
+                        I32Const(1),
+                        Else,
+                        GetLocal(0),
+                        GetLocal(0),
+                        I32Const(1),
+                        I32Sub,
+                        Call(0),
+                        I32Mul,
+                        End,
+                        End,
+                    ],
+                ),
+                // ループで1..=nの和
+                (
+                    0,
+                    vec![
+                        Block(BlockType(None)),
+                        Loop(BlockType(None)),
+                        GetLocal(0),
+                        I32Eqz,
+                        BrIf(1),
+                        GetLocal(1),
+                        GetLocal(0),
+                        I32Add,
+                        SetLocal(1),
+                        GetLocal(0),
+                        I32Const(1),
+                        I32Sub,
+                        SetLocal(0),
+                        Br(0),
+                        End,
+                        End,
+                        GetLocal(1),
+                        End,
+                    ],
+                ),
+                // br_tableと途中のreturn
+                (
+                    0,
+                    vec![
+                        Block(BlockType(None)),
+                        Block(BlockType(None)),
+                        GetLocal(0),
+                        BrTable {
+                            index: 1,
+                            params: vec![0],
+                        },
+                        End,
+                        I32Const(10),
+                        Return,
+                        End,
+                        I32Const(20),
+                        End,
+                    ],
+                ),
+            ],
+        );
+        let mut inst = Instance::new(&m, Imports::new()).unwrap();
+        assert_eq!(inst.invoke("f0", &[Val::I32(5)]), Ok(Some(Val::I32(120))));
+        assert_eq!(inst.invoke("f1", &[Val::I32(10)]), Ok(Some(Val::I32(55))));
+        assert_eq!(inst.invoke("f2", &[Val::I32(0)]), Ok(Some(Val::I32(10))));
+        assert_eq!(inst.invoke("f2", &[Val::I32(7)]), Ok(Some(Val::I32(20))));
+        assert_eq!(inst.invoke("f0", &[Val::I32(-1)]), Err(Trap::StackOverflow));
+    }
+
+    #[test]
+    fn memory_test() {
+        let mut m = module(
+            vec![i32_to_i32()],
+            vec![
+                (
+                    0,
+                    vec![
+                        GetLocal(0),
+                        I32Const(-2),
+                        I32Store16(mem()),
+                        GetLocal(0),
+                        I32Load16s(mem()),
+                        GetLocal(0),
+                        I32Load8u(mem()),
+                        I32Add,
+                        End,
+                    ],
+                ),
+                (0, vec![GetLocal(0), GrowMemory, Drop, CurrentMemory, End]),
+            ],
+        );
+        m.data_section = Some(DataSection(vec![DataSegment {
+            offset: InitExpr::I32(8),
+            data: vec![1, 2, 3, 4],
+        }]));
+        let mut inst = Instance::new(&m, Imports::new()).unwrap();
+        assert_eq!(&inst.memory()[8..12], &[1, 2, 3, 4]);
+        assert_eq!(inst.invoke("f0", &[Val::I32(16)]), Ok(Some(Val::I32(252))));
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(65535)]),
+            Err(Trap::MemoryOutOfBounds)
+        );
+        assert_eq!(inst.invoke("f1", &[Val::I32(1)]), Ok(Some(Val::I32(2))));
+        // 上限を超えると-1を返し、ページ数は変わらない
+        assert_eq!(inst.invoke("f1", &[Val::I32(1)]), Ok(Some(Val::I32(2))));
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(65535)]),
+            Ok(Some(Val::I32(252)))
+        );
+    }
+
+    #[test]
+    fn trap_test() {
+        let m = module(
+            vec![i32_to_i32()],
+            vec![
+                (0, vec![I32Const(i32::MIN), GetLocal(0), I32Divs, End]),
+                (0, vec![I32Const(i32::MIN), GetLocal(0), I32Rems, End]),
+                (
+                    0,
+                    vec![
+                        GetLocal(0),
+                        F64ConvertsI32,
+                        F64Const(1e10),
+                        F64Mul,
+                        I32TrancsF64,
+                        End,
+                    ],
+                ),
+                (0, vec![Unreachable, End]),
+            ],
+        );
+        let mut inst = Instance::new(&m, Imports::new()).unwrap();
+        assert_eq!(inst.invoke("f0", &[Val::I32(0)]), Err(Trap::DivisionByZero));
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(-1)]),
+            Err(Trap::IntegerOverflow)
+        );
+        assert_eq!(inst.invoke("f1", &[Val::I32(-1)]), Ok(Some(Val::I32(0))));
+        assert_eq!(inst.invoke("f2", &[Val::I32(0)]), Ok(Some(Val::I32(0))));
+        assert_eq!(
+            inst.invoke("f2", &[Val::I32(1)]),
+            Err(Trap::IntegerOverflow)
+        );
+        assert_eq!(inst.invoke("f3", &[Val::I32(0)]), Err(Trap::Unreachable));
+        assert_eq!(
+            inst.invoke("f4", &[]),
+            Err(Trap::UnknownExport("f4".to_string()))
+        );
+    }
+
+    #[test]
+    fn import_table_test() {
+        let void = FuncType {
+            params: vec![],
+            result: Some(ValueType::I32),
+        };
+        let mut m = module(
+            vec![i32_to_i32(), void],
+            vec![
+                (0, vec![GetLocal(0), GetLocal(0), CallIndirect(0), End]),
+                (0, vec![GetLocal(0), I32Const(1), I32Add, End]),
+                (1, vec![I32Const(3), End]),
+            ],
+        );
+        m.import_section = Some(ImportSection(vec![ImportEntry {
+            module: "host".to_string(),
+            field: "double".to_string(),
+            kind: ExternalKindImport::Function(0),
+        }]));
+        // インポートで関数のインデックスがずれる
+        for e in &mut m.export_section.as_mut().unwrap().0 {
+            e.index += 1;
+        }
+        m.table_section = Some(TableSection(vec![TableType {
+            element_type: ElemType::AnyFunc,
+            limits: ResizableLimits {
+                initial: 4,
+                maximum: None,
+            },
+        }]));
+        m.element_section = Some(ElementSection(vec![ElemSegment {
+            offset: InitExpr::I32(0),
+            elems: vec![0, 2, 3],
+        }]));
+        assert_eq!(
+            Instance::new(&m, Imports::new()).err(),
+            Some(Trap::UnknownImport(
+                "host".to_string(),
+                "double".to_string()
+            ))
+        );
+
+        let mut imports = Imports::new();
+        imports.register("host", "double", |_, args| match args {
+            [Val::I32(x)] => Some(Val::I32(x * 2)),
+            _ => None,
+        });
+        let mut inst = Instance::new(&m, imports).unwrap();
+        assert_eq!(inst.invoke("f0", &[Val::I32(0)]), Ok(Some(Val::I32(0))));
+        assert_eq!(inst.invoke("f0", &[Val::I32(1)]), Ok(Some(Val::I32(2))));
+        assert_eq!(inst.invoke("f1", &[Val::I32(20)]), Ok(Some(Val::I32(21))));
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(2)]),
+            Err(Trap::IndirectCallTypeMismatch)
+        );
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(3)]),
+            Err(Trap::UndefinedElement)
+        );
+        assert_eq!(
+            inst.invoke("f0", &[Val::I32(9)]),
+            Err(Trap::UndefinedElement)
+        );
+    }
+}
@@ -1,2 +1,3 @@
 pub mod ast;
-pub mod encode;
\ No newline at end of file
+pub mod encode;
+pub mod interp;